@@ -0,0 +1,54 @@
+//! Compare applying a chain of single-qubit gates one by one with applying
+//! their composition with `StateVector::apply_gate_sequence()`.
+//!
+//! Run with `cargo bench --bench gate_sequence`.
+
+use std::f64::consts::{FRAC_PI_2, PI};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use qasmsim::statevector::StateVector;
+
+const QUBIT_WIDTH: usize = 20;
+const CHAIN_LENGTH: usize = 16;
+const REPETITIONS: usize = 5;
+
+fn best_of<F: FnMut()>(mut run: F) -> Duration {
+    (0..REPETITIONS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .expect("at least one repetition")
+}
+
+fn main() {
+    let gates: Vec<(f64, f64, f64)> = (0..CHAIN_LENGTH)
+        .map(|index| match index % 3 {
+            0 => (FRAC_PI_2, 0.0, PI),
+            1 => (0.1 * index as f64, 0.2, -0.3),
+            _ => (0.0, 0.0, PI / index as f64),
+        })
+        .collect();
+    let mut state = StateVector::new(QUBIT_WIDTH);
+
+    let one_by_one = best_of(|| {
+        for &(theta, phi, lambda) in &gates {
+            state.u(theta, phi, lambda, black_box(QUBIT_WIDTH / 2));
+        }
+    });
+    let composed = best_of(|| {
+        state.apply_gate_sequence(black_box(&gates), black_box(QUBIT_WIDTH / 2));
+    });
+
+    println!(
+        "{} gates on {} qubits: one by one {:?}, composed {:?} ({:.1}x)",
+        CHAIN_LENGTH,
+        QUBIT_WIDTH,
+        one_by_one,
+        composed,
+        one_by_one.as_secs_f64() / composed.as_secs_f64()
+    );
+}
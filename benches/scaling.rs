@@ -0,0 +1,99 @@
+//! Measure how the time and the memory of a simulation grow with the number
+//! of qubits, for empty circuits, which only allocate and reset the state,
+//! and for circuits applying `h` to every qubit.
+//!
+//! Fails, as a regression, if simulating the empty 20-qubit circuit takes
+//! longer than a second. Run with `cargo bench --bench scaling`, optionally
+//! passing the maximum number of qubits, as in
+//! `cargo bench --bench scaling -- 20`, on machines without enough memory
+//! for the larger state-vectors: 16 bytes per amplitude, 4 GiB for 28
+//! qubits. Pass `--csv` to print the measurements as comma-separated values,
+//! for plotting time and memory against the number of qubits.
+//!
+//! The benchmark does not use `criterion` since the crate is not available
+//! to the offline builds of qasmsim, so, like the other benchmarks, it runs
+//! without a harness and keeps the best of a few repetitions. Each row plays
+//! the role of a benchmark in a `criterion` group parametrized by the number
+//! of qubits, so porting it once the dependency is available only requires
+//! replacing `best_of()` with `BenchmarkGroup::bench_with_input()`.
+
+use std::env;
+use std::process;
+use std::time::{Duration, Instant};
+
+const QUBIT_WIDTHS: [usize; 5] = [10, 15, 20, 25, 28];
+const REPETITIONS: usize = 3;
+const REGRESSION_WIDTH: usize = 20;
+const REGRESSION_THRESHOLD: Duration = Duration::from_secs(1);
+
+fn best_of(source: &str) -> (Duration, usize) {
+    (0..REPETITIONS)
+        .map(|_| {
+            let start = Instant::now();
+            let execution = qasmsim::run(source, None).expect("the circuit runs");
+            (start.elapsed(), execution.peak_memory_bytes())
+        })
+        .min()
+        .expect("at least one repetition")
+}
+
+fn empty_circuit(qubit_width: usize) -> String {
+    format!("OPENQASM 2.0;\nqreg q[{}];\nreset q;\n", qubit_width)
+}
+
+fn hadamard_circuit(qubit_width: usize) -> String {
+    format!(
+        "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[{}];\nh q;\n",
+        qubit_width
+    )
+}
+
+fn main() {
+    let max_width = env::args()
+        .skip(1)
+        .find_map(|argument| argument.parse().ok())
+        .unwrap_or(usize::MAX);
+    let csv = env::args().any(|argument| argument == "--csv");
+
+    if csv {
+        println!("qubits,empty_ns,hadamard_ns,memory_bytes");
+    } else {
+        println!(
+            "{:>6} | {:>14} | {:>14} | {:>12}",
+            "qubits", "empty", "h on all", "memory (MB)"
+        );
+    }
+    let mut regression = None;
+    for qubit_width in QUBIT_WIDTHS.into_iter().filter(|&n| n <= max_width) {
+        let (empty, memory) = best_of(&empty_circuit(qubit_width));
+        let (hadamard, _) = best_of(&hadamard_circuit(qubit_width));
+        if csv {
+            println!(
+                "{},{},{},{}",
+                qubit_width,
+                empty.as_nanos(),
+                hadamard.as_nanos(),
+                memory
+            );
+        } else {
+            println!(
+                "{:>6} | {:>14?} | {:>14?} | {:>12.3}",
+                qubit_width,
+                empty,
+                hadamard,
+                memory as f64 / 1_000_000.0
+            );
+        }
+        if qubit_width == REGRESSION_WIDTH && empty > REGRESSION_THRESHOLD {
+            regression = Some(empty);
+        }
+    }
+
+    if let Some(elapsed) = regression {
+        eprintln!(
+            "regression: simulating {} qubits took {:?}, more than {:?}",
+            REGRESSION_WIDTH, elapsed, REGRESSION_THRESHOLD
+        );
+        process::exit(1);
+    }
+}
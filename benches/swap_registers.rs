@@ -0,0 +1,51 @@
+//! Compare swapping two registers with one `swap` gate per pair of qubits
+//! with permuting the amplitudes once with `StateVector::swap_registers()`.
+//!
+//! Run with `cargo bench --bench swap_registers`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use qasmsim::statevector::StateVector;
+
+const QUBIT_WIDTH: usize = 20;
+const REPETITIONS: usize = 5;
+
+fn best_of<F: FnMut()>(mut run: F) -> Duration {
+    (0..REPETITIONS)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .min()
+        .expect("at least one repetition")
+}
+
+fn main() {
+    let register_a: Vec<usize> = (0..QUBIT_WIDTH / 2).collect();
+    let register_b: Vec<usize> = (QUBIT_WIDTH / 2..QUBIT_WIDTH).collect();
+    let mut state = StateVector::new(QUBIT_WIDTH);
+
+    let one_by_one = best_of(|| {
+        for (&a, &b) in register_a.iter().zip(&register_b) {
+            state.cnot(black_box(a), b);
+            state.cnot(b, a);
+            state.cnot(a, b);
+        }
+    });
+    let permuted = best_of(|| {
+        state
+            .swap_registers(black_box(&register_a), black_box(&register_b))
+            .expect("registers of the same length");
+    });
+
+    println!(
+        "{} swaps on {} qubits: one by one {:?}, permuted {:?} ({:.1}x)",
+        register_a.len(),
+        QUBIT_WIDTH,
+        one_by_one,
+        permuted,
+        one_by_one.as_secs_f64() / permuted.as_secs_f64()
+    );
+}
@@ -0,0 +1,46 @@
+//! Compare the hit rate of the cache of `U` matrices with the default and an
+//! enlarged capacity while sweeping more angles than the default capacity
+//! fits.
+//!
+//! Run with `cargo bench --bench u_cache`.
+
+use std::time::Instant;
+
+use qasmsim::statevector::{
+    set_u_cache_capacity, u_cache_stats, StateVector, DEFAULT_U_CACHE_CAPACITY,
+};
+
+const QUBIT_WIDTH: usize = 4;
+const ANGLE_COUNT: usize = 64;
+const SWEEPS: usize = 100;
+
+fn sweep(capacity: usize) {
+    set_u_cache_capacity(capacity);
+    let mut state = StateVector::new(QUBIT_WIDTH);
+    let start = Instant::now();
+    for _ in 0..SWEEPS {
+        for step in 0..ANGLE_COUNT {
+            let angle = step as f64 / ANGLE_COUNT as f64;
+            state.u(angle, -angle, 0.5 * angle, step % QUBIT_WIDTH);
+        }
+    }
+    let elapsed = start.elapsed();
+    let stats = u_cache_stats();
+    println!(
+        "capacity {:>4}: hit rate {:.3} ({} hits, {} misses) in {:?}",
+        capacity,
+        stats.hit_rate(),
+        stats.hits,
+        stats.misses,
+        elapsed
+    );
+}
+
+fn main() {
+    println!(
+        "{} sweeps of {} angles on {} qubits",
+        SWEEPS, ANGLE_COUNT, QUBIT_WIDTH
+    );
+    sweep(DEFAULT_U_CACHE_CAPACITY);
+    sweep(ANGLE_COUNT);
+}
@@ -0,0 +1,185 @@
+//! Count the bytes allocated on the heap to measure the peak memory used by
+//! a simulation.
+//!
+//! The library does not replace the global allocator. A binary wanting the
+//! peak memory of its simulations installs [`TrackingAllocator`] itself:
+//!
+//! ```
+//! use qasmsim::alloc_tracking::TrackingAllocator;
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+//!
+//! fn main() -> Result<(), qasmsim::QasmSimError<'static>> {
+//!     let execution = qasmsim::run("OPENQASM 2.0; qreg q[10];", None)?;
+//!     assert!(execution.times().peak_memory_bytes().unwrap() > 0);
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The allocator forwards to the system allocator and keeps the number of
+//! bytes in use. Counters are global, so allocations from other threads
+//! count too.
+//!
+//! [`TrackingAllocator`]: ./struct.TrackingAllocator.html
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// Maximum number of measurements running at the same time.
+const SLOTS: usize = 64;
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+/// Bitmap of the slots in [`WATERMARKS`] in use by a measurement.
+static ACTIVE: AtomicU64 = AtomicU64::new(0);
+
+/// Highest number of bytes in use since each active measurement started.
+static WATERMARKS: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+
+/// A global allocator counting the bytes in use, for measuring the peak
+/// memory of simulations.
+///
+/// Install it with the `#[global_allocator]` attribute in the binary; see the
+/// [module documentation] for an example.
+///
+/// [module documentation]: ./index.html
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrackingAllocator;
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer = System.alloc(layout);
+        if !pointer.is_null() {
+            grow(layout.size());
+        }
+        pointer
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let pointer = System.alloc_zeroed(layout);
+        if !pointer.is_null() {
+            grow(layout.size());
+        }
+        pointer
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        System.dealloc(pointer, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_pointer = System.realloc(pointer, layout, new_size);
+        if !new_pointer.is_null() {
+            CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+            grow(new_size);
+        }
+        new_pointer
+    }
+}
+
+fn grow(size: usize) {
+    if !INSTALLED.load(Ordering::Relaxed) {
+        INSTALLED.store(true, Ordering::Relaxed);
+    }
+    let current = CURRENT.fetch_add(size, Ordering::Relaxed) + size;
+    let mut active = ACTIVE.load(Ordering::Acquire);
+    while active != 0 {
+        let slot = active.trailing_zeros() as usize;
+        WATERMARKS[slot].fetch_max(current, Ordering::Relaxed);
+        active &= active - 1;
+    }
+}
+
+/// Take a free slot in [`WATERMARKS`], starting at `baseline`, and return its
+/// index.
+fn acquire_slot(baseline: usize) -> Option<usize> {
+    let mut active = ACTIVE.load(Ordering::Relaxed);
+    loop {
+        let slot = (!active).trailing_zeros() as usize;
+        if slot >= SLOTS {
+            return None;
+        }
+        WATERMARKS[slot].store(baseline, Ordering::Relaxed);
+        match ACTIVE.compare_exchange_weak(
+            active,
+            active | (1 << slot),
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => return Some(slot),
+            Err(actual) => active = actual,
+        }
+    }
+}
+
+/// Run `block` and return its result along with the highest number of bytes
+/// in use while running, over those in use before.
+///
+/// Measurements running at the same time do not interfere with each other.
+/// The peak is `None` if [`TrackingAllocator`] is not the global allocator,
+/// or if there are too many measurements running at the same time.
+pub(crate) fn measure_peak<T>(block: impl FnOnce() -> T) -> (T, Option<usize>) {
+    let baseline = CURRENT.load(Ordering::Relaxed);
+    let slot = match acquire_slot(baseline) {
+        Some(slot) if INSTALLED.load(Ordering::Relaxed) => slot,
+        Some(slot) => {
+            ACTIVE.fetch_and(!(1 << slot), Ordering::AcqRel);
+            return (block(), None);
+        }
+        None => return (block(), None),
+    };
+    let result = block();
+    let peak = WATERMARKS[slot]
+        .load(Ordering::Relaxed)
+        .saturating_sub(baseline);
+    ACTIVE.fetch_and(!(1 << slot), Ordering::AcqRel);
+    (result, Some(peak))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[global_allocator]
+    static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+    // Other tests allocate at the same time, so the sizes leave a wide margin
+    // and the tests allocating `LARGE` bytes do not run at the same time.
+    const LARGE: usize = 1 << 26;
+    const SMALL: usize = 1 << 10;
+
+    static SERIAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    #[test]
+    fn test_peak_is_relative_to_the_baseline() {
+        let _serial = SERIAL.lock().unwrap();
+        let held = vec![0u8; LARGE];
+        let (_, peak) = measure_peak(|| vec![0u8; SMALL]);
+        assert!(peak.unwrap() < LARGE);
+        drop(held);
+    }
+
+    #[test]
+    fn test_previous_peaks_do_not_count() {
+        let _serial = SERIAL.lock().unwrap();
+        let (_, _) = measure_peak(|| vec![0u8; LARGE]);
+        let (_, peak) = measure_peak(|| vec![0u8; SMALL]);
+        assert!(peak.unwrap() < LARGE);
+    }
+
+    #[test]
+    fn test_nested_measurements_do_not_interfere() {
+        let _serial = SERIAL.lock().unwrap();
+        let ((_, inner), outer) = measure_peak(|| {
+            let held = vec![0u8; LARGE];
+            let inner = measure_peak(|| vec![0u8; SMALL]);
+            drop(held);
+            inner
+        });
+        assert!(inner.unwrap() < LARGE);
+        assert!(outer.unwrap() >= LARGE / 2);
+    }
+}
@@ -1,23 +1,51 @@
 use std::collections::HashMap;
+use std::error;
+use std::fmt;
 
 use crate::error::QasmSimError;
-use crate::grammar::{ast, parse_program};
+use crate::grammar::{
+    ast, lexer::Location, lint::Lint, openqasm3_warnings, parse_lenient, parse_program,
+    parse_program_body, parse_program_with_extensions, symbols::SymbolTable,
+};
 use crate::interpreter;
 use crate::interpreter::runtime::RuntimeError;
-use crate::linker::Linker;
+use crate::linker::{declaration_line, declared_gate_name, same_declaration, Linker};
+pub use crate::linker::{register_library, GateLibrary};
+use crate::options::SimulationOptions;
 use crate::qe;
+use crate::random::ThreadRandom;
 use crate::semantics;
+use crate::statevector::StateVector;
 
 pub type Result<'src, T> = std::result::Result<T, QasmSimError<'src>>;
 
+/// A program whose `include` statements have been replaced by the
+/// definitions in the included libraries, ready to be simulated.
+///
+/// See [`OpenQasmProgram`] for the methods to inspect the program and to
+/// turn it back into source code.
+///
+/// [`OpenQasmProgram`]: ./grammar/ast/struct.OpenQasmProgram.html
+pub type LinkedProgram = ast::OpenQasmProgram;
+
+type Registers = Vec<(String, usize)>;
+
 /// Return the default linker which includes the [`qelib1.inc`] library.
 ///
 /// [`qelib1.inc`]: https://github.com/Qiskit/openqasm/blob/master/examples/generic/qelib1.inc
 fn default_linker() -> Linker {
-    Linker::with_embedded(HashMap::from_iter(vec![(
-        "qelib1.inc".to_owned(),
-        qe::QELIB1.to_owned(),
-    )]))
+    linker_with_libraries(&HashMap::new())
+}
+
+/// Return a linker which includes the [`qelib1.inc`] library and `libraries`,
+/// mapping paths to library sources. A path in `libraries` takes precedence
+/// over the embedded `qelib1.inc`.
+///
+/// [`qelib1.inc`]: https://github.com/Qiskit/openqasm/blob/master/examples/generic/qelib1.inc
+fn linker_with_libraries(libraries: &HashMap<String, String>) -> Linker {
+    let mut embedded = HashMap::from_iter(vec![("qelib1.inc".to_owned(), qe::QELIB1.to_owned())]);
+    embedded.extend(libraries.clone());
+    Linker::with_embedded(embedded)
 }
 
 /// Return the AST of `input` and link external sources with `linker`.
@@ -43,10 +71,12 @@ fn default_linker() -> Linker {
 ///     h q[0];
 ///     cx q[0], q[1];
 /// "#)?;
+/// assert_eq!(ast.qubit_width(), 2);
+/// assert!(ast.gate_names().contains(&"h"));
 /// # use qasmsim::QasmSimError;
 /// # Ok::<(), qasmsim::QasmSimError>(())
 /// ```
-pub fn parse_and_link(input: &str) -> Result<'_, ast::OpenQasmProgram> {
+pub fn parse_and_link(input: &str) -> Result<'_, LinkedProgram> {
     let linker = default_linker();
     let program = parse_program(input)?;
     linker
@@ -54,18 +84,230 @@ pub fn parse_and_link(input: &str) -> Result<'_, ast::OpenQasmProgram> {
         .map_err(|err| QasmSimError::from((input, err)))
 }
 
-type GateSignature = (String, Vec<String>, Vec<String>);
+/// Return the AST of `input`, accepting the syntax extensions to OPENQASM
+/// 2.0, and link external sources with `linker`.
+///
+/// # Errors
+///
+/// The function fails in the same cases [`parse_and_link()`] does. It also
+/// fails with an [`IndexOutOfBounds`] error if a bit-level conditional tests
+/// a bit outside of its classical register.
+///
+/// See [`parse_program_with_extensions()`] for the list of extensions.
+///
+/// [`parse_and_link()`]: ./fn.parse_and_link.html
+/// [`parse_program_with_extensions()`]: ./grammar/fn.parse_program_with_extensions.html
+/// [`IndexOutOfBounds`]: ./error/enum.QasmSimError.html#variant.IndexOutOfBounds
+pub fn parse_and_link_with_extensions(input: &str) -> Result<'_, LinkedProgram> {
+    let linker = default_linker();
+    let program = parse_program_with_extensions(input)?;
+    let linked = linker
+        .link(program)
+        .map_err(|err| QasmSimError::from((input, err)))?;
+    semantics::extract_semantics(&linked)
+        .map_err(|err| QasmSimError::from((input, RuntimeError::from(err))))?;
+    Ok(linked)
+}
+
+/// Return the AST of `input`, parsed according to `options`, and link external
+/// sources along with the warnings about nonstandard syntax, and partially
+/// supported OPENQASM 3 features, found in `input`.
+/// The paths in `libraries`, mapping to library sources, are available to the
+/// `include` statements.
+pub(crate) fn parse_and_link_with_options<'src>(
+    input: &'src str,
+    options: &SimulationOptions,
+    libraries: &HashMap<String, String>,
+) -> Result<'src, (ast::OpenQasmProgram, Vec<Lint>)> {
+    let (program, warnings) = if options.strict {
+        let program = if options.extensions {
+            parse_program_with_extensions(input)?
+        } else {
+            parse_program(input)?
+        };
+        let warnings = openqasm3_warnings(input, &program);
+        (program, warnings)
+    } else {
+        parse_lenient(input, options.extensions)?
+    };
+    let linked = linker_with_libraries(libraries)
+        .link(program)
+        .map_err(|err| QasmSimError::from((input, err)))?;
+    Ok((linked, warnings))
+}
+
+/// The state of an interactive session simulating a program body after
+/// another with [`simulate_body()`].
+///
+/// A session starts empty, with no registers, no gates but the ones in
+/// `qelib1.inc` if included, and a state-vector of zero qubits.
+///
+/// [`simulate_body()`]: ./fn.simulate_body.html
+#[derive(Debug, Clone)]
+
+pub struct Session {
+    program: LinkedProgram,
+    statevector: StateVector,
+    memory: HashMap<String, (u128, usize, usize)>,
+    written: HashMap<String, u128>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            program: ast::OpenQasmProgram {
+                version: "2.0".into(),
+                program: Vec::new(),
+            },
+            statevector: StateVector::new(0),
+            memory: HashMap::new(),
+            written: HashMap::new(),
+        }
+    }
+}
+
+impl Session {
+    /// Create an empty session.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Return the statements simulated so far, as a linked program.
+    pub fn program(&self) -> &LinkedProgram {
+        &self.program
+    }
+
+    /// Return the state-vector after the statements simulated so far.
+    pub fn statevector(&self) -> &StateVector {
+        &self.statevector
+    }
+}
+
+/// Simulate `body`, a list of OPENQASM statements with no `OPENQASM 2.0;`
+/// header, in the context of `session` and return the computation.
+///
+/// Calls thread their state through `session`:
+///
+/// - The body can use the registers and gates declared by the bodies
+///   simulated before, and declare new ones. Including a library already
+///   included has no effect.
+/// - The simulation starts from the state-vector and the values of the
+///   classical registers the previous body ended with. Qubits declared in
+///   `body` are added in the zero state, as the most significant ones, and
+///   classical registers declared in `body` start at 0.
+/// - The computation holds the state after `body`, but the gate count only
+///   counts the gates in `body`.
+/// - If the simulation fails, `session` is left as it was, so the body can
+///   be fixed and fed again.
+///
+/// Measurements are simulated once, as in [`simulate()`].
+///
+/// # Errors
+///
+/// The function fails in the same cases [`run()`] does. The lines of the
+/// errors refer to `body`.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::{simulate_body, Session};
+///
+/// let mut session = Session::new();
+/// simulate_body(&mut session, r#"
+///     include "qelib1.inc";
+///     qreg q[2];
+///     h q[0];
+/// "#)?;
+/// let computation = simulate_body(&mut session, "cx q[0], q[1];")?;
+/// let probabilities = computation.probabilities();
+/// assert!((probabilities[0b00] - 0.5).abs() < 1e-9);
+/// assert!((probabilities[0b11] - 0.5).abs() < 1e-9);
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// [`simulate()`]: ./fn.simulate.html
+/// [`run()`]: ./fn.run.html
+pub fn simulate_body<'src>(
+    session: &mut Session,
+    body: &'src str,
+) -> Result<'src, interpreter::Computation> {
+    let statements = parse_program_body(body)?;
+    let linked = default_linker()
+        .link(ast::OpenQasmProgram {
+            version: session.program.version.clone(),
+            program: statements,
+        })
+        .map_err(|err| QasmSimError::from((body, err)))?;
+
+    let mut program = session.program.clone();
+    let start = program.program.len();
+    for span in linked.program {
+        let known = declared_gate_name(&span.node).is_some()
+            && program.program[..start]
+                .iter()
+                .any(|previous| same_declaration(&previous.node, &span.node));
+        if !known {
+            program.program.push(span);
+        }
+    }
+
+    let computation = interpreter::runtime::resume(
+        &program,
+        start,
+        &session.statevector,
+        &session.memory,
+        &session.written,
+        &mut ThreadRandom,
+    )
+    .map_err(|err| QasmSimError::from((body, err)))?;
+
+    // The locations of the statements refer to `body`, which will not be
+    // the source of the errors of the next bodies.
+    for span in &mut program.program[start..] {
+        span.boundaries = (Location::new(), Location::new());
+    }
+    session.program = program;
+    session.statevector = computation.statevector().clone();
+    session.memory = computation.memory().clone();
+    session.written = computation.written_bits().clone();
+    Ok(computation)
+}
+
+/// The description of a gate returned by [`get_gate_info()`].
+///
+/// [`get_gate_info()`]: ./fn.get_gate_info.html
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+
+pub struct GateInfo {
+    /// Name of the gate.
+    pub name: String,
+    /// Names of the formal real parameters.
+    pub real_parameters: Vec<String>,
+    /// Names of the formal quantum registers.
+    pub quantum_parameters: Vec<String>,
+    /// Documentation of the gate, or an empty string if the gate is not
+    /// documented.
+    pub docstring: String,
+    /// Path of the library declaring the gate, as written in the `include`
+    /// statement (e.g. `"qelib1.inc"`), or `"<source>"` if the gate is
+    /// declared in the source code itself.
+    pub origin: String,
+    /// Line (1-based) of the declaration of the gate in its origin.
+    pub lineno: usize,
+}
 
 /// Return the signature and documentation of the gate `gate_name` if it is
-/// defined in the source code `input`.
+/// defined in the source code `input`, along with where the gate is
+/// declared: the library or the source code, and the line in it.
 ///
 /// # Errors
 ///
 /// The function can fail if `gate_name` is an opaque gate, or if it is not
 /// found in the source. In that case it will return an `Err` wrapping
 /// a special case for the [`QasmSimError::UndefinedGate`] variant with
-/// `source` set to `""`, `lineno` set to `0` and `symbol_name` set to
-/// `gate_name`.
+/// `source` set to `""`, `lineno` set to `0`, `symbol_name` set to
+/// `gate_name` and `suggestion` set to `None`.
 ///
 /// [`QasmSimError::UndefinedGate`]: ./error/enum.QasmSimError.html#variant.UndefinedGate
 ///
@@ -76,24 +318,21 @@ type GateSignature = (String, Vec<String>, Vec<String>);
 /// ```
 /// use qasmsim::get_gate_info;
 ///
-/// let (docstring, (name, real_params, quantum_params)) = get_gate_info(r#"
+/// let info = get_gate_info(r#"
 ///     OPENQASM 2.0;
 ///     // 3-parameter 2-pulse single qubit gate
 ///     gate u3(theta,phi,lambda) q { U(theta,phi,lambda) q; }
 /// "#, "u3")?;
 ///
 /// assert_eq!(
-///     docstring,
+///     info.docstring,
 ///     String::from(" 3-parameter 2-pulse single qubit gate\n")
 /// );
 ///
-/// assert_eq!(
-///     name,
-///     String::from("u3")
-/// );
+/// assert_eq!(info.name, String::from("u3"));
 ///
 /// assert_eq!(
-///     real_params,
+///     info.real_parameters,
 ///     vec![
 ///         String::from("theta"),
 ///         String::from("phi"),
@@ -101,35 +340,24 @@ type GateSignature = (String, Vec<String>, Vec<String>);
 ///     ]
 /// );
 ///
-/// assert_eq!(
-///     quantum_params,
-///     vec![
-///         String::from("q"),
-///     ]
-/// );
+/// assert_eq!(info.quantum_parameters, vec![String::from("q")]);
+///
+/// assert_eq!(info.origin, String::from("<source>"));
+/// assert_eq!(info.lineno, 4);
 ///
 /// # use qasmsim::QasmSimError;
 /// # Ok::<(), qasmsim::QasmSimError>(())
-pub fn get_gate_info<'src>(
-    input: &'src str,
-    gate_name: &str,
-) -> Result<'src, (String, GateSignature)> {
-    let linked = parse_and_link(input)?;
+pub fn get_gate_info<'src>(input: &'src str, gate_name: &str) -> Result<'src, GateInfo> {
+    let linker = default_linker();
+    let program = parse_program(input)?;
+    let (linked, origins) = linker
+        .link_with_origins(program)
+        .map_err(|err| QasmSimError::from((input, err)))?;
     // TODO: Implement conversion from SemanticError to QasmSimError directly
     // without converting to RuntimeError first.
     let semantics = semantics::extract_semantics(&linked)
         .map_err(|err| QasmSimError::from((input, RuntimeError::from(err))))?;
 
-    let docstring =
-        semantics
-            .symbol_docstrings
-            .get(gate_name)
-            .ok_or(QasmSimError::UndefinedGate {
-                source: "",
-                lineno: 0,
-                symbol_name: String::from(gate_name),
-            })?;
-
     let macro_def =
         semantics
             .macro_definitions
@@ -138,20 +366,421 @@ pub fn get_gate_info<'src>(
                 source: "",
                 lineno: 0,
                 symbol_name: String::from(gate_name),
+                suggestion: None,
             })?;
 
-    Ok((
-        docstring.to_string(),
-        (
-            macro_def.0.clone(),
-            macro_def.1.clone(),
-            macro_def.2.clone(),
+    let docstring = semantics
+        .symbol_docstrings
+        .get(gate_name)
+        .map(String::as_str)
+        .unwrap_or_default();
+
+    let (origin, lineno) = match origins.get(gate_name) {
+        Some(libpath) => (
+            libpath.clone(),
+            linker
+                .declaration_line(libpath, gate_name)
+                .expect("the library declares the gate"),
         ),
-    ))
+        None => (
+            String::from("<source>"),
+            declaration_line(input, gate_name).expect("the source declares the gate"),
+        ),
+    };
+
+    Ok(GateInfo {
+        name: macro_def.0.clone(),
+        real_parameters: macro_def.1.clone(),
+        quantum_parameters: macro_def.2.clone(),
+        docstring: docstring.to_string(),
+        origin,
+        lineno,
+    })
+}
+
+/// Replace the declaration of the gate `gate_name` in `program` with
+/// `replacement`, a [`Statement::GateDecl`] declaring the same gate, usually
+/// for providing a concrete implementation of an opaque gate. The
+/// replacement keeps the span of the original declaration.
+///
+/// # Errors
+///
+/// Fail with [`SubstitutionError::NotAGateDeclaration`] if `replacement` is
+/// not a gate declaration, with [`SubstitutionError::NameMismatch`] if it
+/// declares a gate other than `gate_name`, and with
+/// [`SubstitutionError::GateNotFound`] if `program` does not declare
+/// `gate_name`. The program is left untouched on error.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::{parse_and_link, simulate, substitute_gate};
+/// use qasmsim::grammar::parse_statement;
+///
+/// let mut program = parse_and_link(r#"
+///     OPENQASM 2.0;
+///     opaque gate flip q;
+///     qreg q[1];
+///     flip q[0];
+/// "#)?;
+/// let definition = parse_statement("gate flip q { U(pi, 0, pi) q; }")?;
+/// substitute_gate(&mut program, "flip", definition)?;
+///
+/// let computation = simulate(&program)?;
+/// assert_eq!(computation.probabilities()[1], 1.0);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// [`Statement::GateDecl`]: ./grammar/ast/enum.Statement.html#variant.GateDecl
+/// [`SubstitutionError::NotAGateDeclaration`]: ./error/enum.SubstitutionError.html#variant.NotAGateDeclaration
+/// [`SubstitutionError::NameMismatch`]: ./error/enum.SubstitutionError.html#variant.NameMismatch
+/// [`SubstitutionError::GateNotFound`]: ./error/enum.SubstitutionError.html#variant.GateNotFound
+pub fn substitute_gate(
+    program: &mut ast::OpenQasmProgram,
+    gate_name: &str,
+    replacement: ast::Statement,
+) -> std::result::Result<(), SubstitutionError> {
+    match &replacement {
+        ast::Statement::GateDecl { signature, .. } if signature.0 != gate_name => {
+            return Err(SubstitutionError::NameMismatch {
+                expected: gate_name.into(),
+                found: signature.0.to_string(),
+            });
+        }
+        ast::Statement::GateDecl { .. } => (),
+        _ => return Err(SubstitutionError::NotAGateDeclaration),
+    }
+    let declaration = program.program.iter_mut().find(|span| match &*span.node {
+        ast::Statement::GateDecl { signature, .. } => signature.0 == gate_name,
+        ast::Statement::OpaqueGateDecl { signature, .. } => signature.0 == gate_name,
+        _ => false,
+    });
+    match declaration {
+        None => Err(SubstitutionError::GateNotFound(gate_name.into())),
+        Some(span) => {
+            *span.node = replacement;
+            Ok(())
+        }
+    }
+}
+
+/// Represent the errors replacing a gate declaration with
+/// [`substitute_gate()`].
+///
+/// [`substitute_gate()`]: ../fn.substitute_gate.html
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+
+pub enum SubstitutionError {
+    /// The replacement is not a gate declaration.
+    NotAGateDeclaration,
+    /// The replacement declares a gate with another name.
+    NameMismatch {
+        /// Name of the gate to replace.
+        expected: String,
+        /// Name of the gate the replacement declares.
+        found: String,
+    },
+    /// The program does not declare the gate to replace.
+    GateNotFound(String),
+}
+
+impl fmt::Display for SubstitutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubstitutionError::NotAGateDeclaration => {
+                write!(f, "the replacement is not a gate declaration")
+            }
+            SubstitutionError::NameMismatch { expected, found } => write!(
+                f,
+                "the replacement declares gate `{}` instead of `{}`",
+                found, expected
+            ),
+            SubstitutionError::GateNotFound(name) => {
+                write!(f, "the program does not declare gate `{}`", name)
+            }
+        }
+    }
+}
+
+impl error::Error for SubstitutionError {}
+
+/// Gates of `qelib1.inc` which are their own inverse.
+const SELF_INVERSE_GATES: [&str; 11] = [
+    "x", "y", "z", "h", "cx", "cy", "cz", "ch", "swap", "ccx", "cswap",
+];
+
+/// Pairs of gates of `qelib1.inc` which are the inverse of each other.
+const INVERSE_GATE_PAIRS: [(&str, &str); 2] = [("s", "sdg"), ("t", "tdg")];
+
+/// Maximum difference between the parameters of two `U` gates for them to
+/// be considered the inverse of each other.
+const INVERSE_PARAMETER_EPSILON: f64 = 1e-12;
+
+/// Return a copy of `program` without the pairs of adjacent gate
+/// applications undoing each other.
+///
+/// Two gate applications are adjacent if they act on the same arguments
+/// and no statement in between acts on any of their qubits. The pass
+/// cancels `U(θ, φ, λ)` followed by `U(-θ, -λ, -φ)`, `CX` followed by `CX`
+/// and, when declared as in `qelib1.inc`, the gates which are their own
+/// inverse, such as `h` or `cx`, and the pairs `s`, `sdg` and `t`, `tdg`.
+/// Cancelling a pair can make other gates adjacent, which are cancelled as
+/// well. Conditionals are never cancelled and no gate is cancelled across
+/// them.
+///
+/// The optimized program simulates as the original one.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::{optimize_circuit, parse_and_link};
+///
+/// let program = parse_and_link(r#"
+///     OPENQASM 2.0;
+///     include "qelib1.inc";
+///     qreg q[2];
+///     h q[0];
+///     t q[1];
+///     x q[0];
+///     x q[0];
+///     tdg q[1];
+///     h q[0];
+/// "#)?;
+/// let optimized = optimize_circuit(&program);
+/// assert!(optimized.to_qasm().ends_with("qreg q[2];\n"));
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+pub fn optimize_circuit(program: &ast::OpenQasmProgram) -> ast::OpenQasmProgram {
+    let inverses = qelib1_inverses(program);
+    let mut optimized: Vec<ast::Span<ast::Statement>> = Vec::new();
+    for span in &program.program {
+        if let ast::Statement::QuantumOperation(ast::QuantumOperation::Unitary(unitary)) =
+            &*span.node
+        {
+            if let Some(index) = cancelling_operation(&optimized, unitary, &inverses) {
+                optimized.remove(index);
+                continue;
+            }
+        }
+        optimized.push(span.clone());
+    }
+    ast::OpenQasmProgram {
+        version: program.version.clone(),
+        program: optimized,
+    }
+}
+
+/// Map the names of the gates of `qelib1.inc` declared in `program` as in
+/// the library to the names of their inverses.
+fn qelib1_inverses(program: &ast::OpenQasmProgram) -> HashMap<String, String> {
+    let library = crate::grammar::parse_library(qe::QELIB1).expect("qelib1.inc is valid");
+    let pairs = SELF_INVERSE_GATES
+        .iter()
+        .map(|name| (*name, *name))
+        .chain(INVERSE_GATE_PAIRS.iter().copied())
+        .chain(INVERSE_GATE_PAIRS.iter().map(|(a, b)| (*b, *a)));
+    let as_in_library = |name: &str| {
+        let original = library
+            .definitions
+            .iter()
+            .find(|span| declared_gate_name(&span.node) == Some(name));
+        let mut declarations = program
+            .program
+            .iter()
+            .filter(|span| declared_gate_name(&span.node) == Some(name))
+            .peekable();
+        match original {
+            Some(original) => {
+                declarations.peek().is_some()
+                    && declarations.all(|span| same_declaration(&span.node, &original.node))
+            }
+            None => false,
+        }
+    };
+    pairs
+        .filter(|(name, inverse)| as_in_library(name) && as_in_library(inverse))
+        .map(|(name, inverse)| (name.to_owned(), inverse.to_owned()))
+        .collect()
+}
+
+/// Return the index of the statement in `statements` cancelling with
+/// `unitary` if applied right after them.
+fn cancelling_operation(
+    statements: &[ast::Span<ast::Statement>],
+    unitary: &ast::UnitaryOperation,
+    inverses: &HashMap<String, String>,
+) -> Option<usize> {
+    for (index, span) in statements.iter().enumerate().rev() {
+        let qubits = match &*span.node {
+            ast::Statement::QuantumOperation(ast::QuantumOperation::Unitary(operation)) => {
+                &operation.2[..]
+            }
+            ast::Statement::QuantumOperation(ast::QuantumOperation::Measure(qubit, _))
+            | ast::Statement::QuantumOperation(ast::QuantumOperation::Reset(qubit)) => {
+                std::slice::from_ref(qubit)
+            }
+            ast::Statement::Barrier(ast::BarrierPragma(qubits)) => &qubits[..],
+            ast::Statement::Conditional(..) | ast::Statement::ConditionalBlock(..) => return None,
+            _ => continue,
+        };
+        if !qubits
+            .iter()
+            .any(|qubit| unitary.2.iter().any(|other| overlap(qubit, other)))
+        {
+            continue;
+        }
+        return match &*span.node {
+            ast::Statement::QuantumOperation(ast::QuantumOperation::Unitary(previous))
+                if previous.2 == unitary.2 && are_inverse(previous, unitary, inverses) =>
+            {
+                Some(index)
+            }
+            _ => None,
+        };
+    }
+    None
+}
+
+/// Return whether the arguments `a` and `b` share some qubit.
+fn overlap(a: &ast::Argument, b: &ast::Argument) -> bool {
+    match (a, b) {
+        (ast::Argument::Item(a, i), ast::Argument::Item(b, j)) => a == b && i == j,
+        (ast::Argument::Id(a) | ast::Argument::Item(a, _), ast::Argument::Id(b))
+        | (ast::Argument::Id(a), ast::Argument::Item(b, _)) => a == b,
+    }
+}
+
+/// Return whether applying `second` right after `first` is the identity,
+/// regardless of their arguments.
+fn are_inverse(
+    first: &ast::UnitaryOperation,
+    second: &ast::UnitaryOperation,
+    inverses: &HashMap<String, String>,
+) -> bool {
+    match (first.0.as_str(), second.0.as_str()) {
+        ("U", "U") => {
+            let bindings = HashMap::new();
+            let solver = interpreter::expression_solver::ExpressionSolver::new(&bindings);
+            let solve = |params: &[ast::Spanned<ast::Expression>]| {
+                params
+                    .iter()
+                    .map(|param| solver.solve(param).ok())
+                    .collect::<Option<Vec<_>>>()
+            };
+            match (solve(&first.1), solve(&second.1)) {
+                (Some(a), Some(b)) => [(a[0], -b[0]), (a[1], -b[2]), (a[2], -b[1])]
+                    .iter()
+                    .all(|(x, y)| (x - y).abs() <= INVERSE_PARAMETER_EPSILON),
+                _ => false,
+            }
+        }
+        ("CX", "CX") => true,
+        (first_name, second_name) => {
+            first.1.is_empty()
+                && second.1.is_empty()
+                && inverses.get(first_name).map(String::as_str) == Some(second_name)
+        }
+    }
+}
+
+/// Return the declarations of registers and gates in `input` and the places
+/// where they are used.
+///
+/// Syntax extensions and nonstandard syntax are accepted and the program does
+/// not need to be valid for simulation. If some library cannot be included,
+/// the symbols are collected from the program without the library.
+///
+/// # Errors
+///
+/// The function fails if the source code cannot be parsed.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::symbols;
+///
+/// let table = symbols(r#"
+/// OPENQASM 2.0;
+/// include "qelib1.inc";
+/// qreg q[1];
+/// h q[0];
+/// "#)?;
+/// let reference = table.references.iter().rev().find(|r| r.name == "h").unwrap();
+/// let declaration = &table.declarations[reference.declaration.unwrap()];
+/// assert_eq!(declaration.name, "h");
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+pub fn symbols(input: &str) -> Result<'_, SymbolTable> {
+    let (program, _) = parse_lenient(input, true)?;
+    let program = default_linker().link(program.clone()).unwrap_or(program);
+    Ok(crate::grammar::symbols::symbols(&program))
+}
+
+/// Return the names and sizes of the quantum and the classical registers
+/// declared in the linked `program`, in order of declaration.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::{parse_and_link, program_registers};
+///
+/// let program = parse_and_link(r#"
+/// OPENQASM 2.0;
+/// qreg q[2];
+/// creg c[1];
+/// "#)?;
+/// let (quantum, classical) = program_registers(&program);
+/// assert_eq!(quantum, vec![("q".to_string(), 2)]);
+/// assert_eq!(classical, vec![("c".to_string(), 1)]);
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+pub fn program_registers(program: &LinkedProgram) -> (Registers, Registers) {
+    let mut quantum = Vec::new();
+    let mut classical = Vec::new();
+    for span in &program.program {
+        match &*span.node {
+            ast::Statement::QRegDecl(name, size) => quantum.push((name.to_string(), *size)),
+            ast::Statement::CRegDecl(name, size) => classical.push((name.to_string(), *size)),
+            _ => (),
+        }
+    }
+    (quantum, classical)
+}
+
+/// Return the version of the OPENQASM specification whose standard library,
+/// `qelib1.inc`, is embedded in qasmsim.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::get_builtin_library_version;
+///
+/// assert_eq!(get_builtin_library_version(), "2.0");
+/// ```
+pub fn get_builtin_library_version() -> &'static str {
+    crate::qe::QELIB1_VERSION
 }
 
 pub use interpreter::runtime::simulate;
 
 pub use interpreter::runtime::simulate_with_shots;
 
-pub use interpreter::runtime::simulate_with_mode;
+pub use interpreter::runtime::simulate_with_options;
+
+pub use interpreter::runtime::simulate_with_rng;
+
+pub use interpreter::runtime::simulate_traced;
+
+pub use interpreter::runtime::simulate_replay;
+
+pub use interpreter::runtime::sample;
+
+pub use interpreter::runtime::simulate_weighted;
+
+pub use interpreter::runtime::simulate_postselected;
+
+pub use interpreter::flattener::{compile, decompose_to_qasm, PrimitiveOp};
@@ -1,16 +1,48 @@
 use std::collections::HashMap;
 use std::convert;
+use std::fmt;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use crate::{api, statevector::StateVector};
+use crate::{
+    api,
+    statevector::{Complex, State, StateVector},
+};
 
-use crate::error::QasmSimError;
-use crate::interpreter::{Computation, Histogram};
+use crate::error::{QasmSimError, RuntimeError};
+use crate::grammar::lint::Lint;
+use crate::interpreter::{self, runtime, Computation, FinalState, Histogram, ShotRecord};
+use crate::options::{SimulationMode, SimulationOptions};
+use crate::random::{RandomSource, SeededRandom, ThreadRandom};
 
+pub use api::compile;
+pub use api::decompose_to_qasm;
+pub use api::get_builtin_library_version;
 pub use api::get_gate_info;
+pub use api::optimize_circuit;
 pub use api::parse_and_link;
+pub use api::parse_and_link_with_extensions;
+pub use api::program_registers;
+pub use api::register_library;
 pub use api::simulate;
-pub use api::simulate_with_mode;
+pub use api::simulate_body;
+pub use api::simulate_postselected;
+pub use api::simulate_replay;
+pub use api::simulate_traced;
+pub use api::simulate_weighted;
+pub use api::simulate_with_options;
+pub use api::simulate_with_rng;
 pub use api::simulate_with_shots;
+pub use api::substitute_gate;
+pub use api::symbols;
+pub use api::GateInfo;
+pub use api::GateLibrary;
+pub use api::LinkedProgram;
+pub use api::PrimitiveOp;
+pub use api::Session;
 
 macro_rules! measure {
     ($block:expr) => {{
@@ -22,12 +54,28 @@ macro_rules! measure {
     }};
 }
 
-/// Register the milliseconds spent in parsing the program and simulating.
+/// Run `block` and return its result along with the peak of heap memory, in
+/// bytes, allocated while running, if the `memory-tracking` feature is
+/// enabled and the tracking allocator is installed.
+fn measure_peak_memory<T>(block: impl FnOnce() -> T) -> (T, Option<usize>) {
+    #[cfg(feature = "memory-tracking")]
+    {
+        crate::alloc_tracking::measure_peak(block)
+    }
+    #[cfg(not(feature = "memory-tracking"))]
+    {
+        (block(), None)
+    }
+}
+
+/// Register the milliseconds spent in parsing the program and simulating,
+/// and the peak of memory allocated while simulating if measured.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 
 pub struct ExecutionTimes {
     parsing_time: u128,
     simulation_time: u128,
+    peak_memory_bytes: Option<usize>,
 }
 
 impl ExecutionTimes {
@@ -36,9 +84,41 @@ impl ExecutionTimes {
         ExecutionTimes {
             parsing_time,
             simulation_time,
+            peak_memory_bytes: None,
         }
     }
 
+    /// Same as [`new()`] but also registering the peak of heap memory, in
+    /// bytes, allocated while simulating.
+    ///
+    /// [`new()`]: #method.new
+    pub fn with_peak_memory(
+        parsing_time: u128,
+        simulation_time: u128,
+        peak_memory_bytes: usize,
+    ) -> Self {
+        ExecutionTimes {
+            peak_memory_bytes: Some(peak_memory_bytes),
+            ..ExecutionTimes::new(parsing_time, simulation_time)
+        }
+    }
+
+    /// Return the peak of heap memory, in bytes, allocated while simulating.
+    ///
+    /// Unlike [`Execution::peak_memory_bytes()`], which estimates the memory
+    /// of the simulator state, this is measured by counting the allocations,
+    /// including those of other threads running at the same time. It is only
+    /// measured by [`QasmSim::run()`] and the functions using it, with the
+    /// `memory-tracking` feature enabled and [`TrackingAllocator`] installed
+    /// as the global allocator; otherwise it is `None`.
+    ///
+    /// [`Execution::peak_memory_bytes()`]: ./struct.Execution.html#method.peak_memory_bytes
+    /// [`QasmSim::run()`]: ./struct.QasmSim.html#method.run
+    /// [`TrackingAllocator`]: ./alloc_tracking/struct.TrackingAllocator.html
+    pub fn peak_memory_bytes(&self) -> Option<usize> {
+        self.peak_memory_bytes
+    }
+
     /// Return the time spent in parsing the program and converting it to an AST.
     pub fn simulation_time(&self) -> u128 {
         self.simulation_time
@@ -74,16 +154,22 @@ impl From<(u128, u128)> for ExecutionTimes {
 /// [`run()`]: ./fn.run.html
 /// [`Computation`]: ./struct.Computation.html
 /// [time statistics]: ./struct.ExecutionTimes.html
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 
 pub struct Execution {
-    statevector: StateVector,
-    probabilities: Vec<f64>,
-    memory: HashMap<String, (u64, usize, usize)>,
+    state: FinalState,
+    memory: HashMap<String, (u128, usize, usize)>,
     histogram: Option<Histogram>,
-    sequences: Option<Vec<String>>,
+    sequences: Option<Vec<ShotRecord>>,
     times: ExecutionTimes,
     stats: Option<HashMap<String, usize>>,
+    written_bits: HashMap<String, u128>,
+    max_entanglement: Option<f64>,
+    quantum_registers: HashMap<String, (usize, usize)>,
+    gate_count: HashMap<String, usize>,
+    peak_memory_bytes: usize,
+    density_matrix: Option<Vec<Vec<Complex>>>,
+    warnings: Vec<Lint>,
 }
 
 impl Execution {
@@ -91,35 +177,65 @@ impl Execution {
     pub fn new(
         statevector: StateVector,
         probabilities: Vec<f64>,
-        memory: HashMap<String, (u64, usize, usize)>,
+        memory: HashMap<String, (u128, usize, usize)>,
         histogram: Option<Histogram>,
-        sequences: Option<Vec<String>>,
+        sequences: Option<Vec<ShotRecord>>,
         times: ExecutionTimes,
         stats: Option<HashMap<String, usize>>,
     ) -> Self {
         Execution {
-            statevector,
-            probabilities,
+            state: FinalState::new(statevector.into()).with_probabilities(probabilities),
             memory,
             histogram,
             sequences,
             times,
             stats,
+            written_bits: HashMap::new(),
+            max_entanglement: None,
+            quantum_registers: HashMap::new(),
+            gate_count: HashMap::new(),
+            peak_memory_bytes: 0,
+            density_matrix: None,
+            warnings: Vec::new(),
         }
     }
 
+    pub(crate) fn with_warnings(mut self, warnings: Vec<Lint>) -> Self {
+        self.warnings = warnings;
+        self
+    }
+
+    /// Return the state of the quantum system in the representation used
+    /// for simulating. See [`Computation::state()`].
+    ///
+    /// [`Computation::state()`]: ./struct.Computation.html#method.state
+    pub fn state(&self) -> &State {
+        self.state.state()
+    }
+
     /// Return the statevector of the quantum system.
+    ///
+    /// If the simulation did not use the dense backend in double precision,
+    /// the state-vector is built on the first call. See
+    /// [`Computation::statevector()`].
+    ///
+    /// [`Computation::statevector()`]: ./struct.Computation.html#method.statevector
     pub fn statevector(&self) -> &StateVector {
-        &self.statevector
+        self.state.statevector()
     }
 
     /// Return the probabilities associated with the state-vector.
+    ///
+    /// Unless given to [`new()`], probabilities are computed on the first
+    /// call.
+    ///
+    /// [`new()`]: #method.new
     pub fn probabilities(&self) -> &Vec<f64> {
-        &self.probabilities
+        self.state.probabilities()
     }
 
     /// Return an associative map with classical names and the classical outcomes.
-    pub fn memory(&self) -> &HashMap<String, (u64, usize, usize)> {
+    pub fn memory(&self) -> &HashMap<String, (u128, usize, usize)> {
         &self.memory
     }
 
@@ -128,24 +244,238 @@ impl Execution {
         &self.histogram
     }
 
-    /// Return the sequences when simulating with several shots.
-    pub fn sequences(&self) -> &Option<Vec<String>> {
+    /// Return the values of the classical registers at the end of each shot,
+    /// in order, when simulating with several shots.
+    ///
+    /// There are sequences only when simulating in
+    /// [`SimulationMode::Sequence`]; the other modes return `None`, and
+    /// simulating with shots but no mode, as [`run()`] does, returns an empty
+    /// list.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::{options::SimulationMode, run_mode};
+    ///
+    /// let execution = run_mode(r#"
+    /// OPENQASM 2.0;
+    /// qreg q[1];
+    /// creg c[1];
+    /// U(pi, 0, pi) q[0];
+    /// measure q -> c;
+    /// "#, Some(3), SimulationMode::Sequence)?;
+    /// let sequences = execution.sequences().as_ref().unwrap();
+    /// assert_eq!(sequences.len(), 3);
+    /// assert!(sequences.iter().all(|shot| shot["c"] == 1));
+    /// # use qasmsim::QasmSimError;
+    /// # Ok::<(), QasmSimError>(())
+    /// ```
+    ///
+    /// [`SimulationMode::Sequence`]: ./options/enum.SimulationMode.html#variant.Sequence
+    /// [`run()`]: ./fn.run.html
+    pub fn sequences(&self) -> &Option<Vec<ShotRecord>> {
         &self.sequences
     }
 
+    /// Return the values of the classical registers at the end of the shot
+    /// `index`, if there are [`sequences()`] and the shot exists.
+    ///
+    /// [`sequences()`]: #method.sequences
+    pub fn shot(&self, index: usize) -> Option<&ShotRecord> {
+        self.sequences.as_ref()?.get(index)
+    }
+
+    /// Concatenate the binary representation of the registers in `shot`, in
+    /// the same format as the keys of [`stats()`], or return `None` if some
+    /// register is missing from `shot`.
+    ///
+    /// [`stats()`]: #method.stats
+    pub(crate) fn format_shot(&self, shot: &ShotRecord) -> Option<String> {
+        self.register_order()
+            .iter()
+            .map(|name| {
+                let width = self.memory.get(name)?.1;
+                Some(format!("{:0width$b}", shot.get(name)?, width = width))
+            })
+            .collect()
+    }
+
     /// Return the time spent in parsing and performing the simulation.
     pub fn times(&self) -> &ExecutionTimes {
         &self.times
     }
 
     /// Return the statistics of the simulation.
+    ///
+    /// Keys are the concatenation of the binary representation of every
+    /// classical register, in the order returned by [`register_order()`].
+    ///
+    /// [`register_order()`]: #method.register_order
     pub fn stats(&self) -> &Option<HashMap<String, usize>> {
         &self.stats
     }
 
+    /// Return the names of the classical registers in the order they
+    /// contribute to the keys of [`stats()`].
+    ///
+    /// Registers are laid out from the last declared to the first, so the
+    /// first declared register occupies the least significant bits, mirroring
+    /// how qubits are laid out in the state-vector.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::run;
+    ///
+    /// let execution = run(r#"
+    /// OPENQASM 2.0;
+    /// creg c[1];
+    /// creg d[2];
+    /// "#, Some(1))?;
+    /// assert_eq!(execution.register_order(), vec!["d", "c"]);
+    /// # use qasmsim::QasmSimError;
+    /// # Ok::<(), QasmSimError>(())
+    /// ```
+    ///
+    /// [`stats()`]: #method.stats
+    pub fn register_order(&self) -> Vec<String> {
+        let mut registers: Vec<_> = self.memory.iter().collect();
+        registers.sort_by_key(|(_, (_, _, location))| std::cmp::Reverse(*location));
+        registers
+            .into_iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Return an associative map with classical names and a mask of the bits
+    /// written by some measurement, in any of the shots when simulating with
+    /// shots.
+    pub fn written_bits(&self) -> &HashMap<String, u128> {
+        &self.written_bits
+    }
+
+    /// Return the maximum entanglement entropy, in bits, reached during the
+    /// simulation if it was tracked.
+    pub fn max_entanglement(&self) -> Option<f64> {
+        self.max_entanglement
+    }
+
+    /// Return an associative map with quantum register names and the index
+    /// of their first qubit in the state-vector along with their size.
+    pub fn quantum_registers(&self) -> &HashMap<String, (usize, usize)> {
+        &self.quantum_registers
+    }
+
+    /// Return an associative map with gate names and the number of times
+    /// they were applied during the simulation. See
+    /// [`Computation::gate_application_count()`] for how applications are
+    /// counted.
+    ///
+    /// [`Computation::gate_application_count()`]: ./struct.Computation.html#method.gate_application_count
+    pub fn gate_application_count(&self) -> &HashMap<String, usize> {
+        &self.gate_count
+    }
+
+    /// Return an estimate of the peak number of bytes taken by the
+    /// simulation. See [`Computation::peak_memory_bytes()`] for what is
+    /// accounted.
+    ///
+    /// [`Computation::peak_memory_bytes()`]: ./struct.Computation.html#method.peak_memory_bytes
+    pub fn peak_memory_bytes(&self) -> usize {
+        self.peak_memory_bytes
+    }
+
+    /// Return the reduced density matrix of the qubits requested with
+    /// [`SimulationOptions::density_qubits`], averaged over the shots, if it
+    /// was requested.
+    ///
+    /// [`SimulationOptions::density_qubits`]: ./options/struct.SimulationOptions.html#structfield.density_qubits
+    pub fn density_matrix(&self) -> Option<&Vec<Vec<Complex>>> {
+        self.density_matrix.as_ref()
+    }
+
+    /// Return the warnings about nonstandard syntax, and partially supported
+    /// OPENQASM 3 features, found while parsing the program.
+    pub fn warnings(&self) -> &[Lint] {
+        &self.warnings
+    }
+
+    /// Return a copy of the execution keeping only the classical registers
+    /// named in `names` in the memory, the histogram, the statistics and the
+    /// sequences. The outcomes in the statistics are shortened to the bits of
+    /// the kept registers.
+    pub(crate) fn retain_registers(&self, names: &[String]) -> Execution {
+        let is_kept = |name: &String| names.contains(name);
+        let mut bit_ranges = Vec::new();
+        let mut offset = 0;
+        for name in self.register_order() {
+            let size = self.memory[&name].1;
+            if is_kept(&name) {
+                bit_ranges.push(offset..offset + size);
+            }
+            offset += size;
+        }
+        let shorten = |outcome: &String| -> String {
+            bit_ranges
+                .iter()
+                .map(|range| &outcome[range.clone()])
+                .collect()
+        };
+
+        let mut execution = self.clone();
+        execution.memory.retain(|name, _| is_kept(name));
+        execution.written_bits.retain(|name, _| is_kept(name));
+        if let Some(histogram) = execution.histogram.as_mut() {
+            histogram.retain(|name, _| is_kept(name));
+        }
+        if let Some(stats) = execution.stats.as_mut() {
+            let mut shortened = HashMap::new();
+            for (outcome, count) in stats.iter() {
+                *shortened.entry(shorten(outcome)).or_insert(0) += count;
+            }
+            *stats = shortened;
+        }
+        if let Some(sequences) = execution.sequences.as_mut() {
+            for shot in sequences.iter_mut() {
+                shot.retain(|name, _| is_kept(name));
+            }
+        }
+        execution
+    }
+
     /// Return the expectation value of the simulation.
     pub fn expectation(&self) -> Vec<f64> {
-        self.statevector.expectation_values()
+        self.statevector().expectation_values()
+    }
+}
+
+/// Probabilities are left out of the comparison: they follow from the
+/// state-vector and may not have been computed yet.
+impl PartialEq for Execution {
+    fn eq(&self, other: &Self) -> bool {
+        self.state == other.state
+            && self.memory == other.memory
+            && self.histogram == other.histogram
+            && self.sequences == other.sequences
+            && self.times == other.times
+            && self.stats == other.stats
+            && self.written_bits == other.written_bits
+            && self.max_entanglement == other.max_entanglement
+            && self.quantum_registers == other.quantum_registers
+            && self.gate_count == other.gate_count
+            && self.peak_memory_bytes == other.peak_memory_bytes
+            && self.density_matrix == other.density_matrix
+            && self.warnings == other.warnings
+    }
+}
+
+impl fmt::Display for Execution {
+    /// Write the same summary as [`Computation`] does. Times are not
+    /// included.
+    ///
+    /// [`Computation`]: ./struct.Computation.html
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        interpreter::write_summary(f, self.state(), &self.memory, &self.stats)
     }
 }
 
@@ -153,16 +483,204 @@ impl convert::From<(Computation, u128, u128)> for Execution {
     fn from(value: (Computation, u128, u128)) -> Self {
         let (computation, parsing_time, simulation_time) = value;
         Execution {
-            statevector: computation.statevector().clone(),
-            probabilities: computation.probabilities().to_vec(),
             memory: computation.memory().clone(),
             histogram: computation.histogram().clone(),
             sequences: computation.sequences().clone(),
-            times: ExecutionTimes {
-                parsing_time,
-                simulation_time,
-            },
+            times: ExecutionTimes::new(parsing_time, simulation_time),
             stats: computation.stats().clone(),
+            written_bits: computation.written_bits().clone(),
+            max_entanglement: computation.max_entanglement(),
+            quantum_registers: computation.quantum_registers().clone(),
+            gate_count: computation.gate_application_count().clone(),
+            peak_memory_bytes: computation.peak_memory_bytes(),
+            density_matrix: computation.density_matrix().cloned(),
+            warnings: Vec::new(),
+            // Moved after cloning the rest, so the state is not copied.
+            state: computation.into_final_state(),
+        }
+    }
+}
+
+/// Builder for configuring and running simulations. This is the preferred
+/// entry point for simulating from library code: the free functions such as
+/// [`run()`] or [`run_mode()`] are shortcuts for common configurations.
+///
+/// Unless configured with [`with_options()`], programs are parsed strictly,
+/// rejecting nonstandard syntax and the syntax extensions, as [`run()`] does.
+///
+/// [`run()`]: ./fn.run.html
+/// [`run_mode()`]: ./fn.run_mode.html
+/// [`with_options()`]: #method.with_options
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::HashMap;
+/// use qasmsim::QasmSim;
+///
+/// let includes = HashMap::from([(
+///     "flip.inc".to_string(),
+///     "gate flip a { x a; }".to_string(),
+/// )]);
+/// let execution = QasmSim::new()
+///     .with_shots(100)
+///     .with_seed(42)
+///     .with_includes(includes)
+///     .run(r#"
+/// OPENQASM 2.0;
+/// include "qelib1.inc";
+/// include "flip.inc";
+/// qreg q[1];
+/// creg c[1];
+/// flip q[0];
+/// measure q -> c;
+/// "#)?;
+/// assert_eq!(execution.histogram().as_ref().unwrap()["c"].0, vec![(1, 100)]);
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+
+pub struct QasmSim {
+    shots: Option<usize>,
+    seed: Option<u64>,
+    mode: Option<SimulationMode>,
+    includes: HashMap<String, String>,
+    options: SimulationOptions,
+}
+
+impl Default for QasmSim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl QasmSim {
+    /// Create a builder simulating once, with no seed and strict parsing.
+    pub fn new() -> Self {
+        QasmSim {
+            shots: None,
+            seed: None,
+            mode: None,
+            includes: HashMap::new(),
+            options: SimulationOptions {
+                strict: true,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Set the number of simulations.
+    pub fn with_shots(mut self, shots: usize) -> Self {
+        self.shots = Some(shots);
+        self
+    }
+
+    /// Set the seed of the random number generator used for measurements,
+    /// making the simulation reproducible.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Set the mode of the return value when simulating with shots.
+    pub fn with_mode(mut self, mode: SimulationMode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Make the libraries in `includes`, mapping paths to library sources,
+    /// available to the `include` statements. A path in `includes` takes
+    /// precedence over the embedded `qelib1.inc`.
+    pub fn with_includes(mut self, includes: HashMap<String, String>) -> Self {
+        self.includes = includes;
+        self
+    }
+
+    /// Set the options for parsing and simulating, replacing those set by
+    /// previous calls to [`with_options()`] or [`with_max_qubits()`].
+    ///
+    /// [`with_options()`]: #method.with_options
+    /// [`with_max_qubits()`]: #method.with_max_qubits
+    pub fn with_options(mut self, options: SimulationOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Fail instead of simulating programs declaring more than `max_qubits`
+    /// qubits.
+    pub fn with_max_qubits(mut self, max_qubits: usize) -> Self {
+        self.options.max_qubits = Some(max_qubits);
+        self
+    }
+
+    /// Simulate on `pad_qubits` qubits, keeping the qubits not declared by
+    /// the program in |0⟩, so the state vector has `2^pad_qubits`
+    /// amplitudes.
+    pub fn with_pad_qubits(mut self, pad_qubits: usize) -> Self {
+        self.options.pad_qubits = Some(pad_qubits);
+        self
+    }
+
+    /// Parse and simulate the `input` OPENQASM program.
+    ///
+    /// Unless parsing strictly, nonstandard syntax is accepted and reported
+    /// in [`Execution::warnings()`].
+    ///
+    /// [`Execution::warnings()`]: ./struct.Execution.html#method.warnings
+    ///
+    /// # Errors
+    ///
+    /// The function fails in the same cases [`run()`] does, and with
+    /// [`QasmSimError::TooManyQubits`] if the program exceeds the maximum
+    /// number of qubits.
+    ///
+    /// [`run()`]: ./fn.run.html
+    /// [`QasmSimError::TooManyQubits`]: ./error/enum.QasmSimError.html#variant.TooManyQubits
+    pub fn run<'src>(&self, input: &'src str) -> api::Result<'src, Execution> {
+        let (parsed, parsing_time) =
+            measure!({ api::parse_and_link_with_options(input, &self.options, &self.includes) });
+        let (linked, warnings) = parsed?;
+        let ((out, simulation_time), peak_memory_bytes) =
+            measure_peak_memory(|| measure!({ self.simulate(&linked) }));
+        let out = out.map_err(|err| QasmSimError::from((input, err)));
+        let mut execution = Execution::from((out?, parsing_time, simulation_time));
+        execution.times.peak_memory_bytes = peak_memory_bytes;
+        Ok(execution.with_warnings(warnings))
+    }
+
+    /// Simulate the already linked `program`. The libraries set with
+    /// [`with_includes()`] are ignored since the program is already linked.
+    ///
+    /// [`with_includes()`]: #method.with_includes
+    ///
+    /// # Errors
+    ///
+    /// The function fails in the same cases [`simulate()`] does, with
+    /// [`RuntimeError::TooManyQubits`] if the program exceeds the maximum
+    /// number of qubits.
+    ///
+    /// [`simulate()`]: ./fn.simulate.html
+    /// [`RuntimeError::TooManyQubits`]: ./error/enum.RuntimeError.html#variant.TooManyQubits
+    pub fn simulate(&self, program: &LinkedProgram) -> Result<Computation, RuntimeError> {
+        let mode = self.mode;
+        match self.seed {
+            None => runtime::simulate_in_mode(
+                program,
+                self.shots,
+                mode,
+                &self.options,
+                &mut ThreadRandom,
+                None,
+            ),
+            Some(seed) => runtime::simulate_in_mode(
+                program,
+                self.shots,
+                mode,
+                &self.options,
+                &mut SeededRandom::new(seed),
+                None,
+            ),
         }
     }
 }
@@ -193,26 +711,389 @@ impl convert::From<(Computation, u128, u128)> for Execution {
 /// # Ok::<(), QasmSimError>(())
 /// ```
 pub fn run(input: &str, shots: Option<usize>) -> api::Result<'_, Execution> {
+    with_shots(QasmSim::new(), shots).run(input)
+}
+
+/// Parse and simulate the `input` OPENQASM program with optional `shots`,
+/// giving up if the simulation takes longer than `timeout`.
+///
+/// The simulation runs in a background thread. After a timeout, the
+/// simulation is cancelled before its next statement or shot, and the
+/// function returns once the thread has stopped.
+///
+/// # Errors
+///
+/// The function fails in the same cases [`run()`] does, and with
+/// [`QasmSimError::SimulationTimeout`] if the simulation exceeds `timeout`.
+///
+/// # Panics
+///
+/// The function panics with the same payload if the simulation panics.
+///
+/// [`run()`]: ./fn.run.html
+/// [`QasmSimError::SimulationTimeout`]: ./error/enum.QasmSimError.html#variant.SimulationTimeout
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use qasmsim::run_with_timeout;
+///
+/// let execution = run_with_timeout(r#"
+/// OPENQASM 2.0;
+/// include "qelib1.inc";
+/// qreg q[2];
+/// h q[0];
+/// "#, Some(10), Duration::from_secs(10))?;
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+pub fn run_with_timeout(
+    input: &str,
+    shots: Option<usize>,
+    timeout: Duration,
+) -> api::Result<'_, Execution> {
     let (linked, parsing_time) = measure!({ parse_and_link(input) });
-    let (out, simulation_time) = measure!({
-        match shots {
-            None => simulate(&linked?),
-            Some(shots) => simulate_with_shots(&linked?, shots),
+    let linked = linked?;
+    let (sender, receiver) = mpsc::channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let start = Instant::now();
+    let worker = thread::spawn({
+        let cancelled = Arc::clone(&cancelled);
+        move || {
+            let out = runtime::simulate_in_mode(
+                &linked,
+                shots,
+                None,
+                &SimulationOptions::default(),
+                &mut ThreadRandom,
+                Some(&cancelled),
+            );
+            // The receiver is gone if the simulation timed out.
+            let _ = sender.send(out);
         }
     });
+    let out = wait_for(&receiver, worker, timeout, start, &cancelled)?;
+    let simulation_time = start.elapsed().as_millis();
     let out = out.map_err(|err| QasmSimError::from((input, err)));
     Ok(Execution::from((out?, parsing_time, simulation_time)))
 }
 
-/// Parse and simulate the `input` OPENQASM program with `shots` and `mode`.
-pub fn run_mode(input: &str, shots: Option<usize>, mode: String) -> api::Result<'_, Execution> {
-    let (linked, parsing_time) = measure!({ parse_and_link(input) });
-    let (out, simulation_time) = measure!({
-        match shots {
-            None => simulate(&linked?),
-            Some(shots) => simulate_with_mode(&linked?, shots, mode),
+/// Wait up to `timeout` for the `worker` thread, started at `start`, to send
+/// its result through `receiver`, resuming the panic of the worker if it
+/// panics before sending it. On timeout, set `cancelled` and wait for the
+/// worker to stop.
+fn wait_for<T>(
+    receiver: &mpsc::Receiver<T>,
+    worker: JoinHandle<()>,
+    timeout: Duration,
+    start: Instant,
+    cancelled: &AtomicBool,
+) -> api::Result<'static, T> {
+    match receiver.recv_timeout(timeout) {
+        Ok(out) => Ok(out),
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            let elapsed_ms = start.elapsed().as_millis();
+            cancelled.store(true, Ordering::Relaxed);
+            // The result of the worker, cancelled or not, is discarded.
+            let _ = worker.join();
+            Err(QasmSimError::SimulationTimeout { elapsed_ms })
         }
+        Err(mpsc::RecvTimeoutError::Disconnected) => match worker.join() {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(()) => unreachable!("the worker sends its result before finishing"),
+        },
+    }
+}
+
+/// Parse and simulate the `input` OPENQASM program with optional `shots`,
+/// configured by `options`.
+///
+/// Unless `options.strict` is set, nonstandard syntax is accepted and
+/// reported in [`Execution::warnings()`].
+///
+/// [`Execution::warnings()`]: ./struct.Execution.html#method.warnings
+///
+/// # Errors
+///
+/// The function fails in the same cases [`run()`] does.
+///
+/// [`run()`]: ./fn.run.html
+pub fn run_with_options<'src>(
+    input: &'src str,
+    shots: Option<usize>,
+    options: &SimulationOptions,
+) -> api::Result<'src, Execution> {
+    with_shots(QasmSim::new(), shots)
+        .with_options(options.clone())
+        .run(input)
+}
+
+/// Parse and simulate the `input` OPENQASM program `shots` times and return
+/// the individual outcome of each shot as a map relating classical register
+/// names with their values.
+///
+/// # Errors
+///
+/// The function fails in the same cases [`run()`] does.
+///
+/// [`run()`]: ./fn.run.html
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::run_and_sample;
+///
+/// let samples = run_and_sample(r#"
+/// OPENQASM 2.0;
+/// include "qelib1.inc";
+/// qreg q[2];
+/// creg c[2];
+/// h q[0];
+/// cx q[0], q[1];
+/// measure q -> c;
+/// "#, 10)?;
+///
+/// assert_eq!(samples.len(), 10);
+/// assert!(samples.iter().all(|shot| shot["c"] == 0 || shot["c"] == 3));
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+pub fn run_and_sample(input: &str, shots: usize) -> api::Result<'_, Vec<HashMap<String, u128>>> {
+    let linked = parse_and_link(input)?;
+    api::sample(&linked, shots).map_err(|err| QasmSimError::from((input, err)))
+}
+
+/// Parse the `input` OPENQASM program and return an iterator simulating it
+/// once per item, yielding the classical memory at the end of each shot as
+/// a map relating classical register names with their values.
+///
+/// Unlike [`run_and_sample()`], shots are simulated lazily, when the
+/// iterator is advanced, and not kept, so memory does not grow with the
+/// number of shots. The iterator is endless; bound it with adapters such as
+/// `take()`.
+///
+/// With a `seed`, the outcomes of the measurements are drawn from a
+/// [`SeededRandom`] source created once and advancing shot after shot, so
+/// the same seed produces the same sequence of shots.
+///
+/// # Errors
+///
+/// If the program fails to parse or to simulate, the iterator yields the
+/// [`QasmSimError`] and ends.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::streaming_shots;
+///
+/// let source = r#"
+/// OPENQASM 2.0;
+/// include "qelib1.inc";
+/// qreg q[2];
+/// creg c[2];
+/// h q[0];
+/// cx q[0], q[1];
+/// measure q -> c;
+/// "#;
+///
+/// let ones = streaming_shots(source, Some(7))
+///     .take(100)
+///     .map(|shot| shot.unwrap())
+///     .filter(|shot| shot["c"] == 3)
+///     .count();
+/// assert!(ones > 0 && ones < 100);
+///
+/// let first: Vec<_> = streaming_shots(source, Some(7)).take(5).collect();
+/// let again: Vec<_> = streaming_shots(source, Some(7)).take(5).collect();
+/// assert_eq!(first, again);
+/// ```
+///
+/// [`run_and_sample()`]: ./fn.run_and_sample.html
+/// [`SeededRandom`]: ./random/struct.SeededRandom.html
+/// [`QasmSimError`]: ./error/enum.QasmSimError.html
+pub fn streaming_shots(
+    input: &str,
+    seed: Option<u64>,
+) -> impl Iterator<Item = api::Result<'_, HashMap<String, u128>>> {
+    let rng: Box<dyn RandomSource> = match seed {
+        None => Box::new(ThreadRandom),
+        Some(seed) => Box::new(SeededRandom::new(seed)),
+    };
+    let shots = parse_and_link(input).and_then(|linked| {
+        runtime::Shots::new(linked, rng).map_err(|err| QasmSimError::from((input, err)))
     });
-    let out = out.map_err(|err| QasmSimError::from((input, err)));
-    Ok(Execution::from((out?, parsing_time, simulation_time)))
+    let (mut shots, mut setup_error) = match shots {
+        Ok(shots) => (Some(shots), None),
+        Err(error) => (None, Some(error)),
+    };
+    std::iter::from_fn(move || {
+        if let Some(error) = setup_error.take() {
+            return Some(Err(error));
+        }
+        let shot = shots.as_mut()?.next()?;
+        Some(shot.map_err(|err| QasmSimError::from((input, err))))
+    })
+}
+
+/// Parse and simulate the `input` OPENQASM program with optional `shots`,
+/// keeping the results of the shots required by `mode`.
+///
+/// With [`SimulationMode::Sequence`], the outcome of every shot is available
+/// through [`Execution::sequences()`]. With any other mode, the outcomes are
+/// aggregated in [`Execution::histogram()`] and [`Execution::stats()`], and
+/// the min and max modes only change which outcome the printers report. The
+/// mode is ignored when simulating without shots.
+///
+/// # Errors
+///
+/// The function fails in the same cases as [`run()`].
+///
+/// # Examples
+///
+/// Aggregating the outcomes:
+///
+/// ```
+/// use qasmsim::{options::SimulationMode, run_mode};
+///
+/// let source = r#"
+/// OPENQASM 2.0;
+/// qreg q[1];
+/// creg c[1];
+/// U(pi, 0, pi) q[0];
+/// measure q -> c;
+/// "#;
+/// let execution = run_mode(source, Some(10), SimulationMode::Aggregation)?;
+/// assert_eq!(execution.stats().as_ref().unwrap()["1"], 10);
+/// assert_eq!(execution.sequences(), &None);
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// Keeping the outcome of each shot:
+///
+/// ```
+/// use qasmsim::{options::SimulationMode, run_mode};
+///
+/// let source = r#"
+/// OPENQASM 2.0;
+/// qreg q[1];
+/// creg c[1];
+/// U(pi, 0, pi) q[0];
+/// measure q -> c;
+/// "#;
+/// let execution = run_mode(source, Some(2), SimulationMode::Sequence)?;
+/// assert_eq!(execution.shot(1).unwrap()["c"], 1);
+/// assert_eq!(execution.shot(2), None);
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// [`SimulationMode::Sequence`]: ./options/enum.SimulationMode.html#variant.Sequence
+/// [`Execution::sequences()`]: ./struct.Execution.html#method.sequences
+/// [`Execution::histogram()`]: ./struct.Execution.html#method.histogram
+/// [`Execution::stats()`]: ./struct.Execution.html#method.stats
+/// [`run()`]: ./fn.run.html
+pub fn run_mode(
+    input: &str,
+    shots: Option<usize>,
+    mode: SimulationMode,
+) -> api::Result<'_, Execution> {
+    with_shots(QasmSim::new(), shots).with_mode(mode).run(input)
+}
+
+fn with_shots(simulator: QasmSim, shots: Option<usize>) -> QasmSim {
+    match shots {
+        None => simulator,
+        Some(shots) => simulator.with_shots(shots),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "the simulation failed")]
+    fn test_wait_for_resumes_the_panic_of_the_worker() {
+        let (sender, receiver) = mpsc::channel::<()>();
+        let worker = thread::spawn(move || {
+            let _sender = sender;
+            panic!("the simulation failed");
+        });
+        let _ = wait_for(
+            &receiver,
+            worker,
+            Duration::from_secs(10),
+            Instant::now(),
+            &AtomicBool::new(false),
+        );
+    }
+
+    #[test]
+    fn test_wait_for_times_out() {
+        let (sender, receiver) = mpsc::channel::<()>();
+        let worker = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            let _ = sender.send(());
+        });
+        assert!(matches!(
+            wait_for(
+                &receiver,
+                worker,
+                Duration::from_millis(10),
+                Instant::now(),
+                &AtomicBool::new(false)
+            ),
+            Err(QasmSimError::SimulationTimeout { .. })
+        ));
+    }
+
+    #[test]
+    fn test_timeout_stops_the_simulation() {
+        // The measurement in the middle forces simulating every shot, which
+        // would take much longer than the test allows.
+        let program = parse_and_link(
+            "
+            OPENQASM 2.0;
+            include \"qelib1.inc\";
+            qreg q[2];
+            creg c[2];
+            h q[0];
+            measure q[0] -> c[0];
+            cx q[0], q[1];
+            measure q[1] -> c[1];
+            ",
+        )
+        .unwrap();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+        let worker = thread::spawn({
+            let cancelled = Arc::clone(&cancelled);
+            move || {
+                let out = runtime::simulate_in_mode(
+                    &program,
+                    Some(usize::MAX),
+                    None,
+                    &SimulationOptions::default(),
+                    &mut ThreadRandom,
+                    Some(&cancelled),
+                );
+                let _ = sender.send(out);
+            }
+        });
+        let start = Instant::now();
+        assert!(matches!(
+            wait_for(
+                &receiver,
+                worker,
+                Duration::from_millis(50),
+                start,
+                &cancelled
+            ),
+            Err(QasmSimError::SimulationTimeout { .. })
+        ));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(matches!(receiver.recv(), Ok(Err(RuntimeError::Cancelled))));
+    }
 }
@@ -0,0 +1,372 @@
+//! This module contains a builder for constructing OPENQASM programs without
+//! writing the source code by hand.
+
+use std::fmt::Write;
+
+use crate::api;
+use crate::arch::native::{run, Execution};
+use crate::error::QasmSimError;
+
+/// A quantum circuit built gate by gate.
+///
+/// The circuit has a single quantum register `q` and keeps the OPENQASM
+/// source it stands for, which includes `qelib1.inc`. Classical registers are
+/// named `c0`, `c1`... in the order they are added, and classical bits are
+/// numbered across all of them, in that same order.
+///
+/// # Panics
+///
+/// Builder methods panic if given a qubit or a classical bit out of range.
+///
+/// # Examples
+///
+/// Prepare and measure a Bell pair:
+///
+/// ```
+/// use qasmsim::Circuit;
+///
+/// let mut circuit = Circuit::new(2);
+/// let register = circuit.add_classical(2);
+/// circuit.h(0).cx(0, 1).measure(0, 0).measure(1, 1);
+///
+/// let execution = circuit.run(Some(100)).unwrap();
+/// let histogram = execution.histogram().as_ref().unwrap();
+/// assert!(histogram[&register]
+///     .0
+///     .iter()
+///     .all(|(value, _)| *value == 0 || *value == 3));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+
+pub struct Circuit {
+    qubits: usize,
+    classical_registers: Vec<usize>,
+    source: String,
+}
+
+impl Circuit {
+    /// Create an empty circuit on `n_qubits` qubits.
+    ///
+    /// # Panics
+    ///
+    /// The function panics if `n_qubits` is 0.
+    pub fn new(n_qubits: usize) -> Self {
+        assert!(n_qubits > 0, "a circuit needs at least one qubit");
+        Circuit {
+            qubits: n_qubits,
+            classical_registers: Vec::new(),
+            source: format!(
+                "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[{}];\n",
+                n_qubits
+            ),
+        }
+    }
+
+    /// Return the number of qubits of the circuit.
+    pub fn qubits(&self) -> usize {
+        self.qubits
+    }
+
+    /// Return the number of classical bits of all the registers.
+    pub fn classical_bits(&self) -> usize {
+        self.classical_registers.iter().sum()
+    }
+
+    /// Add a classical register of `n_bits` bits and return its name.
+    ///
+    /// # Panics
+    ///
+    /// The function panics if `n_bits` is 0.
+    pub fn add_classical(&mut self, n_bits: usize) -> String {
+        assert!(n_bits > 0, "a classical register needs at least one bit");
+        let name = format!("c{}", self.classical_registers.len());
+        self.classical_registers.push(n_bits);
+        writeln!(self.source, "creg {}[{}];", name, n_bits).unwrap();
+        name
+    }
+
+    /// Apply a Hadamard gate to `qubit`.
+    pub fn h(&mut self, qubit: usize) -> &mut Self {
+        self.check_qubit(qubit);
+        writeln!(self.source, "h q[{}];", qubit).unwrap();
+        self
+    }
+
+    /// Apply a controlled-NOT gate to `target`, controlled by `control`.
+    ///
+    /// # Panics
+    ///
+    /// The function panics if `control` and `target` are the same qubit.
+    pub fn cx(&mut self, control: usize, target: usize) -> &mut Self {
+        self.check_qubit(control);
+        self.check_qubit(target);
+        assert_ne!(control, target, "control and target must differ");
+        writeln!(self.source, "cx q[{}], q[{}];", control, target).unwrap();
+        self
+    }
+
+    /// Apply the built-in `U(theta, phi, lambda)` gate to `target`.
+    pub fn u(&mut self, theta: f64, phi: f64, lambda: f64, target: usize) -> &mut Self {
+        self.check_qubit(target);
+        writeln!(
+            self.source,
+            "U({:.17e}, {:.17e}, {:.17e}) q[{}];",
+            theta, phi, lambda, target
+        )
+        .unwrap();
+        self
+    }
+
+    /// Measure `qubit` into the classical bit `classical_bit`, counting bits
+    /// across all the classical registers.
+    pub fn measure(&mut self, qubit: usize, classical_bit: usize) -> &mut Self {
+        self.check_qubit(qubit);
+        let (register, bit) = self.locate_bit(classical_bit);
+        writeln!(
+            self.source,
+            "measure q[{}] -> c{}[{}];",
+            qubit, register, bit
+        )
+        .unwrap();
+        self
+    }
+
+    /// Return the OPENQASM source of the circuit.
+    pub fn to_qasm(&self) -> String {
+        self.source.clone()
+    }
+
+    /// Simulate the circuit with optional `shots`, as [`run()`] does.
+    ///
+    /// [`run()`]: ./fn.run.html
+    ///
+    /// # Errors
+    ///
+    /// The function fails in the same cases [`run()`] does, although the
+    /// source generated by the builder is always valid.
+    pub fn run(&self, shots: Option<usize>) -> api::Result<'_, Execution> {
+        run(&self.source, shots)
+    }
+
+    fn check_qubit(&self, qubit: usize) {
+        assert!(
+            qubit < self.qubits,
+            "qubit {} is out of range for a circuit of {} qubits",
+            qubit,
+            self.qubits
+        );
+    }
+
+    fn locate_bit(&self, classical_bit: usize) -> (usize, usize) {
+        let mut bit = classical_bit;
+        for (register, size) in self.classical_registers.iter().enumerate() {
+            if bit < *size {
+                return (register, bit);
+            }
+            bit -= size;
+        }
+        panic!(
+            "classical bit {} is out of range for {} classical bits",
+            classical_bit,
+            self.classical_bits()
+        );
+    }
+}
+
+/// Generate an OPENQASM program preparing the state whose amplitudes are
+/// proportional to `data`, using only `U` and `CX` gates.
+///
+/// The program declares a register `q` of `log2(data.len())` qubits and
+/// follows the state preparation of Möttönen et al., rotating each qubit,
+/// from the most significant to the least, with a rotation uniformly
+/// controlled by the qubits already prepared.
+///
+/// # Errors
+///
+/// The function fails with [`QasmSimError::InvalidStateVector`] if the
+/// length of `data` is not a power of two greater than 1, or if `data` has
+/// values that are not finite or all its values are 0.
+///
+/// [`QasmSimError::InvalidStateVector`]: ./error/enum.QasmSimError.html#variant.InvalidStateVector
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::amplitude_encode;
+///
+/// let source = amplitude_encode(&[3.0, 0.0, 0.0, -4.0])?;
+/// let execution = qasmsim::run(&source, None).unwrap();
+/// let statevector = execution.statevector();
+/// assert!((statevector.amplitude_at(0).unwrap().re - 0.6).abs() < 1e-10);
+/// assert!((statevector.amplitude_at(3).unwrap().re + 0.8).abs() < 1e-10);
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+pub fn amplitude_encode(data: &[f64]) -> Result<String, QasmSimError<'static>> {
+    let invalid = |reason: String| Err(QasmSimError::InvalidStateVector { reason });
+    if data.len() < 2 || !data.len().is_power_of_two() {
+        return invalid(format!(
+            "the number of values, {}, is not a power of two greater than 1",
+            data.len()
+        ));
+    }
+    if let Some(index) = data.iter().position(|value| !value.is_finite()) {
+        return invalid(format!("the value at {} is not finite", index));
+    }
+    if data.iter().all(|value| *value == 0.0) {
+        return invalid("all the values are 0".into());
+    }
+
+    let qubits = data.len().trailing_zeros() as usize;
+    let mut source = format!("OPENQASM 2.0;\nqreg q[{}];\n", qubits);
+    for target in (0..qubits).rev() {
+        let angles = rotation_angles(data, target);
+        let controls: Vec<usize> = (target + 1..qubits).collect();
+        write_uniformly_controlled_ry(&mut source, &angles, &controls, target);
+    }
+    Ok(source)
+}
+
+/// Return the angles of the rotations of the `target` qubit, one per value
+/// of the qubits more significant than `target`. Amplitudes only keep their
+/// signs in the rotations of qubit 0, the rest of the rotations split norms.
+fn rotation_angles(data: &[f64], target: usize) -> Vec<f64> {
+    let block = 1 << target;
+    let norm = |start: usize| {
+        data[start..start + block]
+            .iter()
+            .map(|value| value * value)
+            .sum::<f64>()
+            .sqrt()
+    };
+    (0..data.len() >> (target + 1))
+        .map(|control_value| {
+            let start = control_value << (target + 1);
+            if target == 0 {
+                2.0 * data[start + 1].atan2(data[start])
+            } else {
+                2.0 * norm(start + block).atan2(norm(start))
+            }
+        })
+        .collect()
+}
+
+/// Write a rotation around the Y axis of `target` by `angles[c]` when the
+/// `controls` qubits encode `c`, as alternating rotations and `CX` gates.
+fn write_uniformly_controlled_ry(
+    source: &mut String,
+    angles: &[f64],
+    controls: &[usize],
+    target: usize,
+) {
+    let count = angles.len();
+    let gray = |i: usize| i ^ (i >> 1);
+    for i in 0..count {
+        // Solve the angles of the rotations for the sign patterns the `CX`
+        // gates produce, following the Gray code order.
+        let theta = angles
+            .iter()
+            .enumerate()
+            .map(|(j, angle)| {
+                if (j & gray(i)).count_ones().is_multiple_of(2) {
+                    *angle
+                } else {
+                    -*angle
+                }
+            })
+            .sum::<f64>()
+            / count as f64;
+        writeln!(source, "U({:.17e}, 0, 0) q[{}];", theta, target).unwrap();
+        if count > 1 {
+            let changed_bit = (gray(i) ^ gray((i + 1) % count)).trailing_zeros() as usize;
+            writeln!(source, "CX q[{}], q[{}];", controls[changed_bit], target).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+    use crate::complex::Complex;
+    use crate::statevector::{assert_approx_eq, StateVector};
+
+    #[test]
+    fn test_builder_generates_qasm() {
+        let mut circuit = Circuit::new(2);
+        circuit.add_classical(1);
+        circuit.add_classical(1);
+        circuit.h(0).cx(0, 1).u(0.0, 0.0, 0.0, 1).measure(1, 1);
+        assert_eq!(
+            circuit.to_qasm(),
+            indoc!(
+                r#"
+                OPENQASM 2.0;
+                include "qelib1.inc";
+                qreg q[2];
+                creg c0[1];
+                creg c1[1];
+                h q[0];
+                cx q[0], q[1];
+                U(0.00000000000000000e0, 0.00000000000000000e0, 0.00000000000000000e0) q[1];
+                measure q[1] -> c1[0];
+                "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_run_simulates_the_circuit() {
+        let mut circuit = Circuit::new(3);
+        circuit
+            .u(std::f64::consts::PI, 0.0, std::f64::consts::PI, 0)
+            .cx(0, 2);
+        let execution = circuit.run(None).unwrap();
+        let mut bases = vec![Complex::new(0.0, 0.0); 8];
+        bases[5] = Complex::new(1.0, 0.0);
+        let expected = StateVector::from_complex_bases_unchecked(bases);
+        assert_approx_eq(execution.statevector(), &expected);
+    }
+
+    #[test]
+    fn test_amplitude_encoding_prepares_the_normalized_data() {
+        let data = [0.5, -1.0, 0.0, 2.0, 0.25, 0.0, -3.0, 1.5];
+        let source = amplitude_encode(&data).unwrap();
+        assert!(source
+            .lines()
+            .skip(2)
+            .all(|line| { line.starts_with("U(") || line.starts_with("CX ") }));
+
+        let norm = data.iter().map(|value| value * value).sum::<f64>().sqrt();
+        let expected = StateVector::from_complex_bases_unchecked(
+            data.iter()
+                .map(|value| Complex::new(value / norm, 0.0))
+                .collect(),
+        );
+        let execution = run(&source, None).unwrap();
+        assert_approx_eq(execution.statevector(), &expected);
+    }
+
+    #[test]
+    fn test_amplitude_encoding_rejects_invalid_data() {
+        let reason = |data: &[f64]| match amplitude_encode(data) {
+            Err(QasmSimError::InvalidStateVector { reason }) => reason,
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert_eq!(
+            reason(&[1.0, 0.0, 0.0]),
+            "the number of values, 3, is not a power of two greater than 1"
+        );
+        assert_eq!(reason(&[1.0, f64::NAN]), "the value at 1 is not finite");
+        assert_eq!(reason(&[0.0, 0.0]), "all the values are 0");
+    }
+
+    #[test]
+    #[should_panic(expected = "classical bit 2 is out of range for 2 classical bits")]
+    fn test_measure_out_of_range_panics() {
+        let mut circuit = Circuit::new(1);
+        circuit.add_classical(2);
+        circuit.measure(0, 2);
+    }
+}
@@ -1,5 +1,173 @@
+use float_cmp::{ApproxEq, F64Margin};
+
 /// Alias for the float-64-based complex.
+///
+/// Since it is an alias and not a new type, `Complex` is interchangeable with
+/// `num::Complex<f64>` (`num_complex::Complex<f64>`) and implements the
+/// `num_traits` traits such as `Zero`, `One` and `Num` with no conversion.
+///
+/// # Examples
+///
+/// ```
+/// use num::{Num, One, Zero};
+/// use qasmsim::statevector::Complex;
+///
+/// let zero = Complex::zero();
+/// let one = Complex::one();
+/// let parsed = Complex::from_str_radix("1+0i", 10).unwrap();
+/// assert_eq!(zero + one, parsed);
+///
+/// let foreign: num::Complex<f64> = parsed;
+/// let back: Complex = foreign;
+/// assert_eq!(back, one);
+/// ```
+///
+/// The constructors and operations of `num::Complex` are available as well:
+///
+/// - `Complex::new(re, im)` builds a number from its components.
+/// - `Complex::from(re)` builds a real number.
+/// - `Complex::from_polar(r, theta)` builds the number of modulus `r` and
+///   argument `theta`, in radians.
+/// - `Complex::i()` returns the imaginary unit.
+/// - `z.conj()`, `z.norm()`, `z.norm_sqr()` and `z.arg()` return the
+///   conjugate, the modulus, the squared modulus and the argument of `z`.
+///
+/// Use them to build the amplitudes of a [`StateVector`]. For instance, the
+/// state `1/sqrt(2)(|0⟩ + i|1⟩)`:
+///
+/// ```
+/// use std::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_2};
+/// use qasmsim::statevector::{Complex, StateVector};
+///
+/// let amplitudes = vec![
+///     Complex::from(FRAC_1_SQRT_2),
+///     Complex::i() * FRAC_1_SQRT_2,
+/// ];
+/// let state = StateVector::from_amplitudes_with_validation(amplitudes)?;
+///
+/// let polar = Complex::from_polar(FRAC_1_SQRT_2, FRAC_PI_2);
+/// assert!((state.amplitude_at(1).unwrap() - polar).norm() < 1e-12);
+/// assert_eq!(state.amplitude_at(1).unwrap().conj(), Complex::new(0.0, -FRAC_1_SQRT_2));
+/// assert!((state.probabilities()[1] - 0.5).abs() < 1e-12);
+/// # Ok::<(), qasmsim::QasmSimError>(())
+/// ```
+///
+/// [`StateVector`]: ./struct.StateVector.html
 pub type Complex = num::Complex<f64>;
-/// The margin withing two floats are considered the same is the same for each
-/// component of a complex number.
-pub type ComplexMargin = float_cmp::F64Margin;
+/// The margin within which two complex numbers are considered the same.
+///
+/// Two complex numbers are approximately equal if both their real and
+/// imaginary components are. Two floats are approximately equal if their
+/// difference is no higher than `epsilon`, if they are no more than `ulps`
+/// representable floats apart, or if their difference is no higher than
+/// `relative` times the greatest of their magnitudes.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::ComplexMargin;
+/// use qasmsim::statevector::Complex;
+///
+/// let margin = ComplexMargin::absolute_relative(1e-12, 1e-6);
+/// assert!(margin.approx_eq(1e6, 1e6 + 0.5));
+/// assert!(margin.approx_eq(Complex::new(0.0, 1.0), Complex::new(1e-13, 1.0)));
+/// assert!(!margin.approx_eq(0.0, 1e-9));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+
+pub struct ComplexMargin {
+    /// Maximum absolute difference between the components.
+    pub epsilon: f64,
+    /// Maximum distance between the components in units in the last place.
+    pub ulps: i64,
+    /// Maximum difference between the components relative to the greatest
+    /// of their magnitudes.
+    pub relative: f64,
+}
+
+impl ComplexMargin {
+    /// Create a margin allowing an absolute difference of `epsilon`.
+    pub fn epsilon(epsilon: f64) -> Self {
+        ComplexMargin {
+            epsilon,
+            ulps: 0,
+            relative: 0.0,
+        }
+    }
+
+    /// Create a margin allowing the components to be `ulps` representable
+    /// floats apart.
+    pub fn ulps(ulps: u32) -> Self {
+        ComplexMargin {
+            epsilon: 0.0,
+            ulps: ulps.into(),
+            relative: 0.0,
+        }
+    }
+
+    /// Create a margin allowing an absolute difference of `absolute` or a
+    /// difference of `relative` times the greatest of the magnitudes.
+    pub fn absolute_relative(absolute: f64, relative: f64) -> Self {
+        ComplexMargin {
+            epsilon: absolute,
+            ulps: 0,
+            relative,
+        }
+    }
+
+    /// Return `true` if `a` and `b` are the same within this margin. Both
+    /// `f64` values and complex numbers can be compared.
+    pub fn approx_eq<T: Into<Complex>>(&self, a: T, b: T) -> bool {
+        let (a, b) = (a.into(), b.into());
+        self.approx_eq_f64(a.re, b.re) && self.approx_eq_f64(a.im, b.im)
+    }
+
+    fn approx_eq_f64(&self, a: f64, b: f64) -> bool {
+        let margin = F64Margin {
+            epsilon: self.epsilon,
+            ulps: self.ulps,
+        };
+        a.approx_eq(b, margin) || (a - b).abs() <= self.relative * a.abs().max(b.abs())
+    }
+}
+
+impl Default for ComplexMargin {
+    fn default() -> Self {
+        F64Margin::default().into()
+    }
+}
+
+impl From<F64Margin> for ComplexMargin {
+    fn from(margin: F64Margin) -> Self {
+        ComplexMargin {
+            epsilon: margin.epsilon,
+            ulps: margin.ulps,
+            relative: 0.0,
+        }
+    }
+}
+
+impl From<(f64, i64)> for ComplexMargin {
+    fn from((epsilon, ulps): (f64, i64)) -> Self {
+        F64Margin { epsilon, ulps }.into()
+    }
+}
+
+/// Return `true` if the two values, either `f64` or complex numbers, differ
+/// by no more than an absolute `epsilon` in each of their components.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::approx_eq_eps;
+/// use qasmsim::statevector::Complex;
+///
+/// assert!(approx_eq_eps!(0.1 + 0.2, 0.3, 1e-12));
+/// assert!(!approx_eq_eps!(Complex::new(0.0, 1.0), Complex::new(0.0, 1.1), 1e-3));
+/// ```
+#[macro_export]
+macro_rules! approx_eq_eps {
+    ($a:expr, $b:expr, $eps:expr) => {
+        $crate::ComplexMargin::epsilon($eps).approx_eq($a, $b)
+    };
+}
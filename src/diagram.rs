@@ -0,0 +1,277 @@
+//! Draw OPENQASM programs as circuit diagrams in the [DOT] language of
+//! GraphViz.
+//!
+//! Each qubit is a wire laid out from left to right and every operation
+//! becomes a node on the wires of the qubits it acts on. The output can be
+//! rendered with `dot -Tsvg`.
+//!
+//! [DOT]: https://graphviz.org/doc/info/lang.html
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::grammar::ast::{
+    Argument, Expression, OpenQasmProgram, QuantumOperation, Spanned, Statement, UnitaryOperation,
+};
+use crate::interpreter::expression_solver::ExpressionSolver;
+
+/// Return the circuit diagram of `program` in the DOT language.
+///
+/// Gates are drawn as boxes, `CX` controls as dots joined to their targets
+/// by a vertical edge, and measurements as double-bordered boxes. Only the
+/// statements in the program body are drawn: gates are not expanded into
+/// their definitions.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::diagram::circuit_to_dot;
+/// use qasmsim::parse_and_link;
+///
+/// let program = parse_and_link(r#"
+/// OPENQASM 2.0;
+/// include "qelib1.inc";
+/// qreg q[2];
+/// creg c[2];
+/// h q[0];
+/// cx q[0], q[1];
+/// measure q -> c;
+/// "#)?;
+/// let dot = circuit_to_dot(&program);
+/// assert!(dot.starts_with("digraph circuit {"));
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+pub fn circuit_to_dot(program: &OpenQasmProgram) -> String {
+    let mut diagram = Diagram::default();
+    for span in &program.program {
+        diagram.draw_statement(&span.node);
+    }
+    diagram.finish()
+}
+
+#[derive(Debug, Default)]
+struct Diagram {
+    body: String,
+    register_sizes: HashMap<String, usize>,
+    last_nodes: HashMap<(String, usize), String>,
+    node_count: usize,
+}
+
+impl Diagram {
+    fn draw_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::QRegDecl(name, size) => {
+                self.register_sizes.insert(name.to_string(), *size);
+                for index in 0..*size {
+                    let node =
+                        self.new_node(&format!("label=\"{}[{}]\", shape=plaintext", name, index));
+                    self.last_nodes.insert((name.to_string(), index), node);
+                }
+            }
+            Statement::CRegDecl(name, size) => {
+                self.register_sizes.insert(name.to_string(), *size);
+            }
+            Statement::QuantumOperation(operation) => self.draw_operation(operation, ""),
+            Statement::Conditional(Argument::Id(register), test, operation) => {
+                self.draw_operation(operation, &format!("if {}=={}: ", register, test));
+            }
+            Statement::ConditionalBlock(Argument::Id(register), test, operations) => {
+                for operation in operations {
+                    self.draw_operation(operation, &format!("if {}=={}: ", register, test));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn draw_operation(&mut self, operation: &QuantumOperation, prefix: &str) {
+        match operation {
+            QuantumOperation::Unitary(unitary) => {
+                for qubits in self.broadcast(&unitary.2) {
+                    self.draw_unitary(unitary, &qubits, prefix);
+                }
+            }
+            QuantumOperation::Measure(source, target) => {
+                let pairs = self.broadcast(&[source.clone(), target.clone()]);
+                for pair in pairs {
+                    let label = format!("{}measure → {}[{}]", prefix, pair[1].0, pair[1].1);
+                    self.draw_on_wire(&pair[0], &box_attributes(&label, 2));
+                }
+            }
+            QuantumOperation::Reset(target) => {
+                for qubits in self.broadcast(std::slice::from_ref(target)) {
+                    let label = format!("{}reset", prefix);
+                    self.draw_on_wire(&qubits[0], &box_attributes(&label, 1));
+                }
+            }
+        }
+    }
+
+    fn draw_unitary(
+        &mut self,
+        unitary: &UnitaryOperation,
+        qubits: &[(String, usize)],
+        prefix: &str,
+    ) {
+        let is_cnot = (unitary.0 == "CX" || unitary.0 == "cx") && qubits.len() == 2;
+        let nodes: Vec<String> = if is_cnot {
+            vec![
+                self.draw_on_wire(&qubits[0], "label=\"\", shape=point, width=0.15"),
+                self.draw_on_wire(&qubits[1], "label=\"+\", shape=circle"),
+            ]
+        } else {
+            let label = format!("{}{}{}", prefix, unitary.0, parameters(&unitary.1));
+            qubits
+                .iter()
+                .map(|qubit| self.draw_on_wire(qubit, &box_attributes(&label, 1)))
+                .collect()
+        };
+        if nodes.len() > 1 {
+            let _ = writeln!(self.body, "  {{ rank=same; {}; }}", nodes.join("; "));
+            for pair in nodes.windows(2) {
+                let _ = writeln!(
+                    self.body,
+                    "  {} -> {} [dir=none, constraint=false];",
+                    pair[0], pair[1]
+                );
+            }
+        }
+    }
+
+    /// Add a node on the wire of `qubit`, connected to the previous node on
+    /// the same wire, and return its id.
+    fn draw_on_wire(&mut self, qubit: &(String, usize), attributes: &str) -> String {
+        let node = self.new_node(attributes);
+        if let Some(previous) = self.last_nodes.insert(qubit.clone(), node.clone()) {
+            let _ = writeln!(self.body, "  {} -> {} [arrowhead=none];", previous, node);
+        }
+        node
+    }
+
+    fn new_node(&mut self, attributes: &str) -> String {
+        let node = format!("n{}", self.node_count);
+        self.node_count += 1;
+        let _ = writeln!(self.body, "  {} [{}];", node, attributes);
+        node
+    }
+
+    /// Expand the register arguments in `arguments` into as many lists of
+    /// individual bits as the size of the registers.
+    fn broadcast(&self, arguments: &[Argument]) -> Vec<Vec<(String, usize)>> {
+        let size = arguments
+            .iter()
+            .find_map(|argument| match argument {
+                Argument::Id(name) => self.register_sizes.get(name.as_str()).copied(),
+                Argument::Item(_, _) => None,
+            })
+            .unwrap_or(1);
+        (0..size)
+            .map(|index| {
+                arguments
+                    .iter()
+                    .map(|argument| match argument {
+                        Argument::Id(name) => (name.to_string(), index),
+                        Argument::Item(name, item) => (name.to_string(), *item),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn finish(self) -> String {
+        format!(
+            "digraph circuit {{\n  rankdir=LR;\n  node [fontname=\"monospace\"];\n{}}}\n",
+            self.body
+        )
+    }
+}
+
+fn box_attributes(label: &str, peripheries: usize) -> String {
+    format!(
+        "label=\"{}\", shape=box, peripheries={}",
+        label.replace('"', "\\\""),
+        peripheries
+    )
+}
+
+pub(crate) fn parameters(expressions: &[Spanned<Expression>]) -> String {
+    if expressions.is_empty() {
+        return String::new();
+    }
+    let bindings = HashMap::new();
+    let solver = ExpressionSolver::new(&bindings);
+    let values: Vec<String> = expressions
+        .iter()
+        .map(|expression| match solver.solve(expression) {
+            Ok(value) => format!("{:.3}", value),
+            Err(_) => String::from("?"),
+        })
+        .collect();
+    format!("({})", values.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use crate::api::parse_and_link;
+
+    use super::*;
+
+    #[test]
+    fn test_circuit_to_dot() {
+        let source = indoc!(
+            "
+        OPENQASM 2.0;
+        include \"qelib1.inc\";
+        qreg q[2];
+        creg c[2];
+        h q[0];
+        cx q[0], q[1];
+        measure q -> c;
+        "
+        );
+        let program = parse_and_link(source).unwrap();
+        assert_eq!(
+            circuit_to_dot(&program),
+            indoc!(
+                "
+            digraph circuit {
+              rankdir=LR;
+              node [fontname=\"monospace\"];
+              n0 [label=\"q[0]\", shape=plaintext];
+              n1 [label=\"q[1]\", shape=plaintext];
+              n2 [label=\"h\", shape=box, peripheries=1];
+              n0 -> n2 [arrowhead=none];
+              n3 [label=\"\", shape=point, width=0.15];
+              n2 -> n3 [arrowhead=none];
+              n4 [label=\"+\", shape=circle];
+              n1 -> n4 [arrowhead=none];
+              { rank=same; n3; n4; }
+              n3 -> n4 [dir=none, constraint=false];
+              n5 [label=\"measure → c[0]\", shape=box, peripheries=2];
+              n3 -> n5 [arrowhead=none];
+              n6 [label=\"measure → c[1]\", shape=box, peripheries=2];
+              n4 -> n6 [arrowhead=none];
+            }
+            "
+            )
+        );
+    }
+
+    #[test]
+    fn test_parameters_and_conditionals_are_labeled() {
+        let source = indoc!(
+            "
+        OPENQASM 2.0;
+        qreg q[1];
+        creg c[1];
+        if (c==1) U(pi, 0, pi/2) q[0];
+        "
+        );
+        let program = parse_and_link(source).unwrap();
+        let dot = circuit_to_dot(&program);
+        assert!(dot.contains("label=\"if c==1: U(3.142, 0.000, 1.571)\""));
+    }
+}
@@ -3,12 +3,15 @@
 
 #[macro_use]
 pub(crate) mod humanize;
+pub mod report;
 
 use std::convert;
 use std::error;
 use std::fmt;
+use std::io;
 
 use self::humanize::humanize_error;
+pub use crate::api::SubstitutionError;
 use crate::grammar::lexer::{self, Location, Tok};
 pub use crate::interpreter::runtime::RuntimeError;
 pub use crate::linker::LinkerError;
@@ -68,6 +71,37 @@ pub type SrcAndErr<'src, E> = (&'src str, E);
 pub enum QasmSimError<'src> {
     /// A generic unknown error.
     UnknownError(String),
+    /// The name of a simulation mode is none of `aggregation`, `sequence`,
+    /// `min` or `max`.
+    InvalidMode(String),
+    /// The number of shots is not at least 1.
+    InvalidShots {
+        /// Number of shots requested.
+        shots: usize,
+    },
+    /// The simulation took longer than allowed.
+    SimulationTimeout {
+        /// Milliseconds elapsed before giving up on the simulation.
+        elapsed_ms: u128,
+    },
+    /// The result of a simulation lacks the data required for printing it
+    /// with the given options, such as a histogram when printing shots.
+    MalformedResult {
+        /// Description of the problem.
+        reason: String,
+    },
+    /// The amplitudes given for building a state-vector do not describe a
+    /// valid quantum state.
+    InvalidStateVector {
+        /// Description of the problem.
+        reason: String,
+    },
+    /// The qubits given for an operation on a state-vector are not valid
+    /// qubits of it.
+    InvalidQubits {
+        /// Description of the problem.
+        reason: String,
+    },
     /// Found an invalid token at some position.
     InvalidToken {
         /// Line source.
@@ -113,6 +147,53 @@ pub enum QasmSimError<'src> {
         /// A list of expected tokens.
         expected: Vec<String>,
     },
+    /// Found a language feature that is not supported yet.
+    UnsupportedFeature {
+        /// Line source.
+        source: &'src str,
+        /// Line number.
+        lineno: usize,
+        /// Position inside the line (0-based) where the feature starts.
+        startpos: usize,
+        /// Position inside the line (0-based) where the feature ends.
+        endpos: Option<usize>,
+        /// Name of the feature.
+        feature: String,
+    },
+    /// Found a conditional inside a conditional block, which is not
+    /// supported by the conditional block extension.
+    NestedConditional {
+        /// Line source.
+        source: &'src str,
+        /// Line number.
+        lineno: usize,
+        /// Position inside the line (0-based) where the nested `if` starts.
+        startpos: usize,
+        /// Position inside the line (0-based) where the nested `if` ends.
+        endpos: Option<usize>,
+    },
+    /// Found an integer literal too large for its use.
+    IntegerOutOfRange {
+        /// Line source.
+        source: &'src str,
+        /// Line number.
+        lineno: usize,
+        /// Position inside the line (0-based) where the literal starts.
+        startpos: usize,
+        /// Position inside the line (0-based) where the literal ends.
+        endpos: Option<usize>,
+        /// Literal found.
+        literal: String,
+    },
+    /// Found a block comment `/*` with no matching `*/`.
+    UnterminatedComment {
+        /// Line source.
+        source: &'src str,
+        /// Line number.
+        lineno: usize,
+        /// Position inside the line (0-based) where the comment starts.
+        startpos: usize,
+    },
     /// Found a redefinition of a register.
     RedefinitionError {
         /// Line source.
@@ -124,6 +205,35 @@ pub enum QasmSimError<'src> {
         /// Line number where the register was originally declared.
         previous_lineno: usize,
     },
+    /// Found a division by zero in a parameter expression.
+    DivisionByZero {
+        /// Line source.
+        source: &'src str,
+        /// Line number.
+        lineno: usize,
+    },
+    /// Found a classical register wider than supported.
+    RegisterTooWide {
+        /// Line source.
+        source: &'src str,
+        /// Name of the register.
+        symbol_name: String,
+        /// Line number.
+        lineno: usize,
+        /// Declared size of the register.
+        size: usize,
+        /// Maximum size supported.
+        max_size: usize,
+    },
+    /// Found a register declared with size 0.
+    EmptyRegister {
+        /// Line source.
+        source: &'src str,
+        /// Name of the register.
+        symbol_name: String,
+        /// Line number.
+        lineno: usize,
+    },
     /// The unability of linking a library.
     LibraryNotFound {
         /// Line source.
@@ -133,6 +243,44 @@ pub enum QasmSimError<'src> {
         /// Line number.
         lineno: usize,
     },
+    /// Two included libraries declare the same gate differently.
+    GateRedefinition {
+        /// Line source.
+        source: &'src str,
+        /// Line number.
+        lineno: usize,
+        /// Name of the gate.
+        symbol_name: String,
+        /// Path of the library declaring the gate again.
+        libpath: String,
+        /// Path of the library declaring the gate first.
+        previous_libpath: String,
+    },
+    /// A registered library declares a gate the program declares
+    /// differently.
+    RegisteredGateRedefinition {
+        /// Line source.
+        source: &'src str,
+        /// Line number.
+        lineno: usize,
+        /// Name of the gate.
+        symbol_name: String,
+    },
+    /// An included library is not valid OPENQASM.
+    InvalidLibrary {
+        /// Line source.
+        source: &'src str,
+        /// Line number.
+        lineno: usize,
+        /// Path to the included library.
+        libpath: String,
+        /// Line of the library where parsing fails.
+        library_lineno: usize,
+        /// Column of the library where parsing fails.
+        library_column: usize,
+        /// Description of the parsing error.
+        reason: String,
+    },
     /// Use of register index that does not fit the register size.
     IndexOutOfBounds {
         /// Line source.
@@ -181,6 +329,48 @@ pub enum QasmSimError<'src> {
         lineno: usize,
         /// Name of the unknown gate.
         symbol_name: String,
+        /// A hint about how to fix the error, if any.
+        suggestion: Option<String>,
+    },
+    /// Use of a classical register in a parameter expression, which requires
+    /// enabling the extensions.
+    ClassicalRegisterInExpression {
+        /// Line source.
+        source: &'src str,
+        /// Line number.
+        lineno: usize,
+        /// Name of the classical register.
+        symbol_name: String,
+    },
+    /// The program declares more qubits than allowed.
+    TooManyQubits {
+        /// Line source.
+        source: &'src str,
+        /// Line number.
+        lineno: usize,
+        /// Number of qubits declared in the program.
+        qubits: usize,
+        /// Maximum number of qubits allowed.
+        max_qubits: usize,
+    },
+    /// Application of a controlled gate whose control and target are the
+    /// same qubit.
+    RepeatedQubit {
+        /// Line source.
+        source: &'src str,
+        /// Line number.
+        lineno: usize,
+        /// Name of the gate.
+        symbol_name: String,
+    },
+    /// Application of an opaque gate, which cannot be simulated.
+    OpaqueGateNotSimulatable {
+        /// Line source.
+        source: &'src str,
+        /// Line number.
+        lineno: usize,
+        /// Name of the opaque gate.
+        symbol_name: String,
     },
     /// Found an unexpected type of value.
     TypeMismatch {
@@ -216,12 +406,210 @@ impl fmt::Display for QasmSimError<'_> {
 
 impl error::Error for QasmSimError<'_> {}
 
+impl QasmSimError<'_> {
+    /// Return the position and the description of the error in a
+    /// machine-readable form, for editors to underline the code causing the
+    /// error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::error::Severity;
+    /// use qasmsim::run;
+    ///
+    /// let error = run("OPENQASM 2.0;\nqreg q[1];\nCX q[0], q[0]\n", None)
+    ///     .expect_err("missing semicolon");
+    /// let diagnostic = error.to_diagnostic();
+    /// assert_eq!(diagnostic.severity, Severity::Error);
+    /// assert_eq!((diagnostic.line, diagnostic.column), (3, 14));
+    /// assert_eq!(diagnostic.message, "expected \";\", found EOF");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        humanize::diagnostic(self)
+    }
+
+    /// Detach the error from the source code, for storing it beyond the
+    /// lifetime of the source.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::error::OwnedQasmSimError;
+    ///
+    /// fn run_file(source: String) -> Result<(), OwnedQasmSimError> {
+    ///     qasmsim::run(&source, None).map_err(|error| error.into_owned())?;
+    ///     Ok(())
+    /// }
+    ///
+    /// let error = run_file("qreg q[1];".to_string()).expect_err("no version");
+    /// assert_eq!(error.diagnostic().line, 1);
+    /// ```
+    pub fn into_owned(self) -> OwnedQasmSimError {
+        OwnedQasmSimError::from(self)
+    }
+}
+
+/// How important a [`Diagnostic`] is.
+///
+/// [`Diagnostic`]: ./struct.Diagnostic.html
+#[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+
+pub enum Severity {
+    /// The program cannot be simulated.
+    Error,
+    /// The program can be simulated but probably not as intended.
+    Warning,
+}
+
+/// A machine-readable description of a problem in the source code, as
+/// returned by [`QasmSimError::to_diagnostic()`].
+///
+/// Lines and columns are 1-based and columns count bytes from the beginning
+/// of the line. Errors unrelated to a position in the source code, such as
+/// timeouts, have `line`, `column` and `length` set to 0.
+///
+/// [`QasmSimError::to_diagnostic()`]: ./enum.QasmSimError.html#method.to_diagnostic
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+
+pub struct Diagnostic {
+    /// Line of the problem.
+    pub line: usize,
+    /// Column where the problematic code starts.
+    pub column: usize,
+    /// Number of bytes of the problematic code.
+    pub length: usize,
+    /// Description of the problem.
+    pub message: String,
+    /// Suggestion for fixing the problem, if any.
+    pub help: Option<String>,
+    /// How important the problem is.
+    pub severity: Severity,
+}
+
+/// A [`QasmSimError`] detached from the source code it refers to, so it can
+/// be stored, sent to other threads or boxed as a
+/// `Box<dyn Error + Send + Sync>`.
+///
+/// The error keeps the human description and the [`Diagnostic`] of the
+/// original error. When built from the pairing of the source with a
+/// [`LinkerError`], [`SemanticError`] or [`RuntimeError`], the error also
+/// keeps the latter, available through [`source()`].
+///
+/// # Examples
+///
+/// ```
+/// use std::error::Error;
+/// use qasmsim::{parse_and_link, simulate};
+/// use qasmsim::error::OwnedQasmSimError;
+///
+/// let source = "OPENQASM 2.0;\nqreg q[2];\nCX q[1], q[2];\n";
+/// let program = parse_and_link(source).unwrap();
+/// let runtime_error = simulate(&program).expect_err("index out of bounds");
+/// let error: Box<dyn Error + Send + Sync> =
+///     Box::new(OwnedQasmSimError::from((source, runtime_error)));
+/// assert!(error.to_string().contains("index out of bounds"));
+/// assert!(error.source().is_some());
+/// ```
+///
+/// [`QasmSimError`]: ./enum.QasmSimError.html
+/// [`Diagnostic`]: ./struct.Diagnostic.html
+/// [`LinkerError`]: ../linker/enum.LinkerError.html
+/// [`SemanticError`]: ./enum.SemanticError.html
+/// [`RuntimeError`]: ./enum.RuntimeError.html
+/// [`source()`]: https://doc.rust-lang.org/std/error/trait.Error.html#method.source
+#[derive(Debug)]
+
+pub struct OwnedQasmSimError {
+    message: String,
+    diagnostic: Diagnostic,
+    cause: Option<Box<dyn error::Error + Send + Sync>>,
+}
+
+impl OwnedQasmSimError {
+    /// Return the machine-readable description of the error.
+    pub fn diagnostic(&self) -> &Diagnostic {
+        &self.diagnostic
+    }
+
+    fn with_cause<E>(error: QasmSimError<'_>, cause: E) -> Self
+    where
+        E: error::Error + Send + Sync + 'static,
+    {
+        OwnedQasmSimError {
+            cause: Some(Box::new(cause)),
+            ..OwnedQasmSimError::from(error)
+        }
+    }
+}
+
+impl fmt::Display for OwnedQasmSimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl error::Error for OwnedQasmSimError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.cause
+            .as_deref()
+            .map(|cause| cause as &(dyn error::Error + 'static))
+    }
+}
+
+impl From<QasmSimError<'_>> for OwnedQasmSimError {
+    fn from(error: QasmSimError<'_>) -> Self {
+        OwnedQasmSimError {
+            message: error.to_string(),
+            diagnostic: error.to_diagnostic(),
+            cause: None,
+        }
+    }
+}
+
+impl From<SrcAndErr<'_, LinkerError>> for OwnedQasmSimError {
+    fn from(source_and_error: SrcAndErr<'_, LinkerError>) -> Self {
+        let (input, error) = source_and_error;
+        OwnedQasmSimError::with_cause(QasmSimError::from((input, error.clone())), error)
+    }
+}
+
+impl From<SrcAndErr<'_, SemanticError>> for OwnedQasmSimError {
+    fn from(source_and_error: SrcAndErr<'_, SemanticError>) -> Self {
+        let (input, error) = source_and_error;
+        let runtime_error = RuntimeError::from(error.clone());
+        OwnedQasmSimError::with_cause(QasmSimError::from((input, runtime_error)), error)
+    }
+}
+
+impl From<SrcAndErr<'_, RuntimeError>> for OwnedQasmSimError {
+    fn from(source_and_error: SrcAndErr<'_, RuntimeError>) -> Self {
+        let (input, error) = source_and_error;
+        OwnedQasmSimError::with_cause(QasmSimError::from((input, error.clone())), error)
+    }
+}
+
 impl convert::From<String> for QasmSimError<'_> {
     fn from(err: String) -> Self {
         QasmSimError::UnknownError(err)
     }
 }
 
+impl convert::From<fmt::Error> for QasmSimError<'_> {
+    fn from(_: fmt::Error) -> Self {
+        QasmSimError::UnknownError(String::from("cannot write the output"))
+    }
+}
+
+impl convert::From<io::Error> for QasmSimError<'_> {
+    fn from(err: io::Error) -> Self {
+        QasmSimError::UnknownError(format!("cannot write the output: {}", err))
+    }
+}
+
 impl<'src> From<SrcAndErr<'src, ParseError>> for QasmSimError<'src> {
     fn from(src_and_err: SrcAndErr<'src, ParseError>) -> Self {
         let (input, error) = src_and_err;
@@ -248,6 +636,47 @@ impl<'src> From<SrcAndErr<'src, ParseError>> for QasmSimError<'src> {
                     expected,
                 }
             }
+            ParseError::UnrecognizedToken {
+                token: (location, Tok::Unsupported { repr }, endlocation),
+                ..
+            } => {
+                let (source, lineno, startpos, endpos) =
+                    extract_line(location.0, Some(endlocation.0), input);
+                QasmSimError::UnsupportedFeature {
+                    source,
+                    lineno,
+                    startpos,
+                    endpos,
+                    feature: repr,
+                }
+            }
+            ParseError::UnrecognizedToken {
+                token: (location, Tok::If, endlocation),
+                expected,
+            } if expected.is_empty() => {
+                let (source, lineno, startpos, endpos) =
+                    extract_line(location.0, Some(endlocation.0), input);
+                QasmSimError::NestedConditional {
+                    source,
+                    lineno,
+                    startpos,
+                    endpos,
+                }
+            }
+            ParseError::UnrecognizedToken {
+                token: (location, Tok::Int { repr }, endlocation),
+                expected,
+            } if expected.is_empty() => {
+                let (source, lineno, startpos, endpos) =
+                    extract_line(location.0, Some(endlocation.0), input);
+                QasmSimError::IntegerOutOfRange {
+                    source,
+                    lineno,
+                    startpos,
+                    endpos,
+                    literal: repr,
+                }
+            }
             ParseError::UnrecognizedToken { token, expected } => {
                 let location = token.0;
                 let endlocation = token.2;
@@ -276,6 +705,19 @@ impl<'src> From<SrcAndErr<'src, ParseError>> for QasmSimError<'src> {
                     expected: Vec::new(),
                 }
             }
+            ParseError::User { error: lexer_error }
+                if input[lexer_error.location.0..].starts_with("/*") =>
+            {
+                // The lexer only fails at the start of a block comment when
+                // the comment is not terminated.
+                let (source, lineno, startpos, _) =
+                    extract_line(lexer_error.location.0, None, input);
+                QasmSimError::UnterminatedComment {
+                    source,
+                    lineno,
+                    startpos,
+                }
+            }
             ParseError::User { error: lexer_error } => {
                 let location = lexer_error.location;
                 let (source, lineno, startpos, endpos) = extract_line(location.0, None, input);
@@ -298,6 +740,17 @@ impl<'src> From<SrcAndErr<'src, RuntimeError>> for QasmSimError<'src> {
         let (input, error) = source_and_error;
         match error {
             RuntimeError::Other => QasmSimError::UnknownError(format!("{:?}", error)),
+            RuntimeError::TraceMismatch { .. }
+            | RuntimeError::RandomSourceExhausted
+            | RuntimeError::Cancelled
+            | RuntimeError::InvalidTrace { .. }
+            | RuntimeError::InvalidDensityQubits { .. }
+            | RuntimeError::InvalidEntanglementCut { .. }
+            | RuntimeError::InvalidPostselection { .. }
+            | RuntimeError::PaddingTooNarrow { .. } => {
+                QasmSimError::UnknownError(format!("{}", error))
+            }
+            RuntimeError::InvalidShots { shots } => QasmSimError::InvalidShots { shots },
             RuntimeError::RegisterSizeMismatch {
                 location,
                 symbol_name,
@@ -327,12 +780,60 @@ impl<'src> From<SrcAndErr<'src, RuntimeError>> for QasmSimError<'src> {
             RuntimeError::UndefinedGate {
                 location,
                 symbol_name,
+                suggestion,
             } => {
                 let (source, lineno, _, _) = extract_line(location.0, None, input);
                 QasmSimError::UndefinedGate {
                     source,
                     lineno,
                     symbol_name,
+                    suggestion,
+                }
+            }
+            RuntimeError::OpaqueGateNotSimulatable {
+                location,
+                symbol_name,
+            } => {
+                let (source, lineno, _, _) = extract_line(location.0, None, input);
+                QasmSimError::OpaqueGateNotSimulatable {
+                    source,
+                    lineno,
+                    symbol_name,
+                }
+            }
+            RuntimeError::RepeatedQubit {
+                location,
+                symbol_name,
+            } => {
+                let (source, lineno, _, _) = extract_line(location.0, None, input);
+                QasmSimError::RepeatedQubit {
+                    source,
+                    lineno,
+                    symbol_name,
+                }
+            }
+            RuntimeError::ClassicalRegisterInExpression {
+                location,
+                symbol_name,
+            } => {
+                let (source, lineno, _, _) = extract_line(location.0, None, input);
+                QasmSimError::ClassicalRegisterInExpression {
+                    source,
+                    lineno,
+                    symbol_name,
+                }
+            }
+            RuntimeError::TooManyQubits {
+                location,
+                qubits,
+                max_qubits,
+            } => {
+                let (source, lineno, _, _) = extract_line(location.0, None, input);
+                QasmSimError::TooManyQubits {
+                    source,
+                    lineno,
+                    qubits,
+                    max_qubits,
                 }
             }
             RuntimeError::WrongNumberOfParameters {
@@ -395,6 +896,51 @@ impl<'src> From<SrcAndErr<'src, RuntimeError>> for QasmSimError<'src> {
                         previous_lineno,
                     }
                 }
+                SemanticError::DivisionByZero { location } => {
+                    let (source, lineno, _, _) = extract_line(location.0, None, input);
+                    QasmSimError::DivisionByZero { source, lineno }
+                }
+                SemanticError::RegisterTooWide {
+                    symbol_name,
+                    location,
+                    size,
+                    max_size,
+                } => {
+                    let (source, lineno, _, _) = extract_line(location.0, None, input);
+                    QasmSimError::RegisterTooWide {
+                        source,
+                        symbol_name,
+                        lineno,
+                        size,
+                        max_size,
+                    }
+                }
+                SemanticError::EmptyRegister {
+                    symbol_name,
+                    location,
+                } => {
+                    let (source, lineno, _, _) = extract_line(location.0, None, input);
+                    QasmSimError::EmptyRegister {
+                        source,
+                        symbol_name,
+                        lineno,
+                    }
+                }
+                SemanticError::IndexOutOfBounds {
+                    symbol_name,
+                    location,
+                    index,
+                    size,
+                } => {
+                    let (source, lineno, _, _) = extract_line(location.0, None, input);
+                    QasmSimError::IndexOutOfBounds {
+                        source,
+                        symbol_name,
+                        lineno,
+                        size,
+                        index,
+                    }
+                }
             },
         }
     }
@@ -412,11 +958,54 @@ impl<'src> From<SrcAndErr<'src, LinkerError>> for QasmSimError<'src> {
                     lineno,
                 }
             }
+            LinkerError::GateRedefinition {
+                location,
+                symbol_name,
+                libpath,
+                previous_libpath,
+            } => {
+                let (source, lineno, _, _) = extract_line(location.0, None, input);
+                QasmSimError::GateRedefinition {
+                    source,
+                    lineno,
+                    symbol_name,
+                    libpath,
+                    previous_libpath,
+                }
+            }
+            LinkerError::RegisteredGateRedefinition {
+                location,
+                symbol_name,
+            } => {
+                let (source, lineno, _, _) = extract_line(location.0, None, input);
+                QasmSimError::RegisteredGateRedefinition {
+                    source,
+                    lineno,
+                    symbol_name,
+                }
+            }
+            LinkerError::InvalidLibrary {
+                location,
+                libpath,
+                library_lineno,
+                library_column,
+                reason,
+            } => {
+                let (source, lineno, _, _) = extract_line(location.0, None, input);
+                QasmSimError::InvalidLibrary {
+                    source,
+                    lineno,
+                    libpath,
+                    library_lineno,
+                    library_column,
+                    reason,
+                }
+            }
         }
     }
 }
 
-fn extract_line(
+pub(crate) fn extract_line(
     offset: usize,
     endoffset: Option<usize>,
     doc: &str,
@@ -428,32 +1017,32 @@ fn extract_line(
         doc.len()
     );
 
-    let mut linecount = 1;
-    let mut start = 0;
-    let mut end = start + 1;
-    for (idx, character) in doc.chars().enumerate() {
-        if idx < offset && character == '\n' {
-            start = idx + 1;
-            linecount += 1;
-        }
-        if idx >= offset {
-            end = idx + 1;
-            if character == '\n' {
-                break;
-            }
-        }
-    }
-
-    if end < start {
-        end = doc.len();
-    }
+    let offset = floor_char_boundary(doc, offset);
+    let preceding = &doc[..offset];
+    let linecount = preceding.matches('\n').count() + 1;
+    let start = preceding.rfind('\n').map_or(0, |index| index + 1);
+    let end = doc[offset..]
+        .find('\n')
+        .map_or(doc.len(), |index| offset + index + 1);
 
-    let startpos = offset - start;
-    let endpos = endoffset.map(|endoffset| endoffset - start);
+    // Positions in the line are counted in characters, not in bytes.
+    let startpos = doc[start..offset].chars().count();
+    let endpos = endoffset.map(|endoffset| {
+        let endoffset = floor_char_boundary(doc, endoffset.clamp(start, doc.len()));
+        doc[start..endoffset].chars().count()
+    });
 
     (&doc[start..end], linecount, startpos, endpos)
 }
 
+/// Return the greatest character boundary of `doc` not after `offset`.
+fn floor_char_boundary(doc: &str, mut offset: usize) -> usize {
+    while !doc.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
 #[cfg(test)]
 mod test_into_doc_coords {
     use indoc::indoc;
@@ -480,4 +1069,13 @@ mod test_into_doc_coords {
       test_middle_of_source: 7, None => ("line 2\n", 2, 0, None),
       test_last_character: 20, None => ("line 3", 3, 6, None)
     );
+
+    test_get_line_src!("",
+      test_empty_source: 0, None => ("", 1, 0, None)
+    );
+
+    test_get_line_src!("// ñañaña\nfoo q[0];",
+      test_after_multibyte_characters: 13, Some(16) => ("foo q[0];", 2, 0, Some(3)),
+      test_inside_multibyte_characters: 4, None => ("// ñañaña\n", 1, 3, None)
+    );
 }
@@ -1,6 +1,6 @@
 use std::fmt::{self, Write};
 
-use crate::error::QasmSimError;
+use crate::error::{Diagnostic, QasmSimError, Severity};
 
 macro_rules! lazy_humanize {
     ($err:expr, $($variant:path),*) => {{
@@ -92,6 +92,59 @@ fn human_description(error: &QasmSimError) -> Option<HumanDescription> {
                 help,
             })
         }
+        QasmSimError::UnsupportedFeature {
+            source,
+            lineno,
+            startpos,
+            endpos,
+            feature,
+        } => Some(HumanDescription {
+            msg: format!("`{}` is not yet supported", feature),
+            lineno: *lineno,
+            startpos: *startpos,
+            endpos: *endpos,
+            linesrc: (*source).into(),
+            help: Some("only OPENQASM 3 declarations and measurements are supported".into()),
+        }),
+        QasmSimError::NestedConditional {
+            source,
+            lineno,
+            startpos,
+            endpos,
+        } => Some(HumanDescription {
+            msg: "conditionals cannot be nested inside a conditional block".into(),
+            lineno: *lineno,
+            startpos: *startpos,
+            endpos: *endpos,
+            linesrc: (*source).into(),
+            help: Some("move the nested `if` out of the block".into()),
+        }),
+        QasmSimError::IntegerOutOfRange {
+            source,
+            lineno,
+            startpos,
+            endpos,
+            literal,
+        } => Some(HumanDescription {
+            msg: format!("integer literal `{}` is too large", literal),
+            lineno: *lineno,
+            startpos: *startpos,
+            endpos: *endpos,
+            linesrc: (*source).into(),
+            help: None,
+        }),
+        QasmSimError::UnterminatedComment {
+            source,
+            lineno,
+            startpos,
+        } => Some(HumanDescription {
+            msg: "unterminated block comment".into(),
+            lineno: *lineno,
+            startpos: *startpos,
+            endpos: Some(*startpos + 2),
+            linesrc: (*source).into(),
+            help: Some("close the comment with `*/`".into()),
+        }),
         QasmSimError::RedefinitionError {
             source,
             lineno,
@@ -108,6 +161,43 @@ fn human_description(error: &QasmSimError) -> Option<HumanDescription> {
                 *previous_lineno
             )),
         }),
+        QasmSimError::DivisionByZero { source, lineno } => Some(HumanDescription {
+            msg: "attempt to divide by zero".into(),
+            lineno: *lineno,
+            startpos: 0,
+            endpos: None,
+            linesrc: (*source).into(),
+            help: None,
+        }),
+        QasmSimError::RegisterTooWide {
+            source,
+            symbol_name,
+            lineno,
+            size,
+            max_size,
+        } => Some(HumanDescription {
+            msg: format!(
+                "classical register `{}` has {} bits but at most {} are supported",
+                symbol_name, size, max_size
+            ),
+            lineno: *lineno,
+            startpos: 0,
+            endpos: None,
+            linesrc: (*source).into(),
+            help: None,
+        }),
+        QasmSimError::EmptyRegister {
+            source,
+            symbol_name,
+            lineno,
+        } => Some(HumanDescription {
+            msg: format!("register `{}` has size 0", symbol_name),
+            lineno: *lineno,
+            startpos: 0,
+            endpos: None,
+            linesrc: (*source).into(),
+            help: Some("registers must have at least one element".into()),
+        }),
         QasmSimError::IndexOutOfBounds {
             symbol_name,
             source,
@@ -218,13 +308,78 @@ fn human_description(error: &QasmSimError) -> Option<HumanDescription> {
             source,
             symbol_name,
             lineno,
+            suggestion,
         } => Some(HumanDescription {
             msg: format!("cannot find gate `{}` in this scope", symbol_name),
             linesrc: (*source).into(),
             lineno: *lineno,
             startpos: 0,
             endpos: None,
-            help: None,
+            help: suggestion.clone(),
+        }),
+        QasmSimError::ClassicalRegisterInExpression {
+            source,
+            lineno,
+            symbol_name,
+        } => Some(HumanDescription {
+            msg: format!(
+                "cannot use the classical register `{}` in a parameter expression",
+                symbol_name
+            ),
+            linesrc: (*source).into(),
+            lineno: *lineno,
+            startpos: 0,
+            endpos: None,
+            help: Some(
+                "OPENQASM 2.0 does not allow it; enable the extensions to use the value of the register"
+                    .into(),
+            ),
+        }),
+        QasmSimError::TooManyQubits {
+            source,
+            lineno,
+            qubits,
+            max_qubits,
+        } => Some(HumanDescription {
+            msg: format!(
+                "the program declares {} qubits but the limit is {}",
+                qubits, max_qubits
+            ),
+            linesrc: (*source).into(),
+            lineno: *lineno,
+            startpos: 0,
+            endpos: None,
+            help: Some("reduce the size of the quantum registers or raise the limit".into()),
+        }),
+        QasmSimError::OpaqueGateNotSimulatable {
+            source,
+            symbol_name,
+            lineno,
+        } => Some(HumanDescription {
+            msg: format!("cannot simulate opaque gate `{}`", symbol_name),
+            linesrc: (*source).into(),
+            lineno: *lineno,
+            startpos: 0,
+            endpos: None,
+            help: Some(format!(
+                "substitute `{}` with a gate definition or treat opaque gates as identity",
+                symbol_name
+            )),
+        }),
+        QasmSimError::RepeatedQubit {
+            source,
+            symbol_name,
+            lineno,
+        } => Some(HumanDescription {
+            msg: format!(
+                "the control and the target of `{}` are the same qubit",
+                symbol_name
+            ),
+            linesrc: (*source).into(),
+            lineno: *lineno,
+            startpos: 0,
+            endpos: None,
+            help: Some("apply the gate to two different qubits".into()),
         }),
         QasmSimError::LibraryNotFound {
             source,
@@ -238,6 +393,59 @@ fn human_description(error: &QasmSimError) -> Option<HumanDescription> {
             endpos: None,
             help: None,
         }),
+        QasmSimError::GateRedefinition {
+            source,
+            lineno,
+            symbol_name,
+            libpath,
+            previous_libpath,
+        } => Some(HumanDescription {
+            msg: format!(
+                "library `{}` redefines gate `{}`, already defined in `{}`",
+                libpath, symbol_name, previous_libpath
+            ),
+            linesrc: (*source).into(),
+            lineno: *lineno,
+            startpos: 0,
+            endpos: None,
+            help: Some(format!(
+                "rename the gate in one of the libraries or define it as in `{}`",
+                previous_libpath
+            )),
+        }),
+        QasmSimError::RegisteredGateRedefinition {
+            source,
+            lineno,
+            symbol_name,
+        } => Some(HumanDescription {
+            msg: format!(
+                "a registered library declares gate `{}` differently",
+                symbol_name
+            ),
+            linesrc: (*source).into(),
+            lineno: *lineno,
+            startpos: 0,
+            endpos: None,
+            help: Some("rename the gate in the program or in the library".into()),
+        }),
+        QasmSimError::InvalidLibrary {
+            source,
+            lineno,
+            libpath,
+            library_lineno,
+            library_column,
+            reason,
+        } => Some(HumanDescription {
+            msg: format!(
+                "cannot parse library `{}`: {} at line {}, column {}",
+                libpath, reason, library_lineno, library_column
+            ),
+            linesrc: (*source).into(),
+            lineno: *lineno,
+            startpos: 0,
+            endpos: None,
+            help: Some(format!("fix the syntax of `{}`", libpath)),
+        }),
         _ => None,
     }
 }
@@ -245,6 +453,30 @@ fn human_description(error: &QasmSimError) -> Option<HumanDescription> {
 pub fn humanize_error<W: Write>(buffer: &mut W, error: &QasmSimError) -> fmt::Result {
     match error {
         QasmSimError::UnknownError(msg) => write!(buffer, "{}", msg),
+        QasmSimError::SimulationTimeout { elapsed_ms } => writeln!(
+            buffer,
+            "error: simulation timed out after {} ms",
+            elapsed_ms
+        ),
+        QasmSimError::InvalidStateVector { reason } => {
+            writeln!(buffer, "error: invalid state-vector: {}", reason)
+        }
+        QasmSimError::InvalidQubits { reason } => {
+            writeln!(buffer, "error: invalid qubits: {}", reason)
+        }
+        QasmSimError::MalformedResult { reason } => {
+            writeln!(buffer, "error: malformed result: {}", reason)
+        }
+        QasmSimError::InvalidMode(mode) => writeln!(
+            buffer,
+            "error: invalid mode `{}`, expected `aggregation`, `sequence`, `min` or `max`",
+            mode
+        ),
+        QasmSimError::InvalidShots { shots } => writeln!(
+            buffer,
+            "error: invalid number of shots `{}`, expected at least 1",
+            shots
+        ),
         _ => {
             let description: HumanDescription =
                 human_description(error).expect("some human description");
@@ -253,6 +485,39 @@ pub fn humanize_error<W: Write>(buffer: &mut W, error: &QasmSimError) -> fmt::Re
     }
 }
 
+pub(crate) fn diagnostic(error: &QasmSimError) -> Diagnostic {
+    match human_description(error) {
+        Some(description) => Diagnostic {
+            line: description.lineno,
+            column: description.startpos + 1,
+            length: description
+                .endpos
+                .map_or(1, |endpos| endpos - description.startpos),
+            message: description.msg,
+            help: description.help,
+            severity: Severity::Error,
+        },
+        None => {
+            let mut buffer = String::new();
+            humanize_error(&mut buffer, error).expect("writing to a string");
+            let message = buffer.trim();
+            Diagnostic {
+                line: 0,
+                column: 0,
+                length: 0,
+                message: message.strip_prefix("error: ").unwrap_or(message).into(),
+                help: None,
+                severity: Severity::Error,
+            }
+        }
+    }
+}
+
+/// Return the line of the source code where `error` happens, if any.
+pub(crate) fn source_line(error: &QasmSimError) -> Option<String> {
+    human_description(error).map(|description| description.linesrc)
+}
+
 fn humanize<W: Write>(buffer: &mut W, descripition: &HumanDescription) -> fmt::Result {
     let HumanDescription {
         msg,
@@ -0,0 +1,179 @@
+//! Render errors as reports naming the file and the position of the
+//! problem, in the style of the Rust compiler.
+//!
+//! Unlike the `Display` of [`QasmSimError`], reports include the path of the
+//! file and the line and column of the problem, so they are suited for
+//! terminals running a simulator on files.
+//!
+//! [`QasmSimError`]: ../enum.QasmSimError.html
+
+use std::fmt::Write;
+
+use super::humanize;
+use crate::error::QasmSimError;
+
+/// Render `error`, happening in the file at `path`, as a report with the
+/// message, the `path:line:column` of the problem, the offending line with
+/// the problematic code underlined and a hint, if any.
+///
+/// The source code is not required since errors keep the line where they
+/// happen. Errors unrelated to a position in the source code only include
+/// the message and the path.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::error::report::render;
+///
+/// let error = qasmsim::run("OPENQASM 2.0;\nqreg q[1];\nCX q[0], q[0]\n", None)
+///     .expect_err("missing semicolon");
+/// assert_eq!(
+///     render("test.qasm", &error),
+///     concat!(
+///         "error: expected \";\", found EOF\n",
+///         " --> test.qasm:3:14\n",
+///         "  |\n",
+///         "3 | CX q[0], q[0]\n",
+///         "  |              ^\n",
+///         "  = help: consider adding \";\" here\n",
+///     )
+/// );
+/// ```
+pub fn render(path: &str, error: &QasmSimError) -> String {
+    let diagnostic = error.to_diagnostic();
+    let mut report = String::new();
+    writeln!(report, "error: {}", diagnostic.message).unwrap();
+    let line = match humanize::source_line(error) {
+        Some(line) => line,
+        None => {
+            writeln!(report, " --> {}", path).unwrap();
+            return report;
+        }
+    };
+
+    let gutter = " ".repeat(diagnostic.line.to_string().len());
+    writeln!(
+        report,
+        "{} --> {}:{}:{}",
+        &gutter[1..],
+        path,
+        diagnostic.line,
+        diagnostic.column
+    )
+    .unwrap();
+    writeln!(report, "{} |", gutter).unwrap();
+    writeln!(report, "{} | {}", diagnostic.line, line.trim_end()).unwrap();
+    writeln!(
+        report,
+        "{} | {}{}",
+        gutter,
+        " ".repeat(diagnostic.column - 1),
+        "^".repeat(diagnostic.length.max(1))
+    )
+    .unwrap();
+    if let Some(help) = diagnostic.help {
+        writeln!(report, "{} = help: {}", gutter, help).unwrap();
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use indoc::indoc;
+
+    use super::*;
+    use crate::QasmSim;
+
+    #[test]
+    fn test_report_of_a_syntax_error() {
+        let source = indoc!(
+            "
+            OPENQASM 2.0;
+            qreg q[2];
+            creg c[2]
+            creg d[2];
+            "
+        );
+        let error = crate::run(source, None).unwrap_err();
+        assert_eq!(
+            render("bell.qasm", &error),
+            indoc!(
+                r#"
+                error: expected ";", found "keyword `creg`"
+                 --> bell.qasm:4:1
+                  |
+                4 | creg d[2];
+                  | ^^^^
+                  = help: consider adding ";" before this
+                "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_report_of_a_semantic_error() {
+        let source = indoc!(
+            "
+            OPENQASM 2.0;
+            qreg q[2];
+            qreg q[1];
+            "
+        );
+        let error = crate::run(source, None).unwrap_err();
+        assert_eq!(
+            render("twice.qasm", &error),
+            indoc!(
+                "
+                error: cannot declare symbol `q` twice
+                 --> twice.qasm:3:1
+                  |
+                3 | qreg q[1];
+                  | ^
+                  = help: first declaration happens in line 2
+                "
+            )
+        );
+    }
+
+    #[test]
+    fn test_report_of_a_runtime_error_inside_an_include() {
+        let mut includes = HashMap::new();
+        includes.insert("lib.inc".to_string(), "gate g a { h a; }\n".to_string());
+        let source = indoc!(
+            r#"
+            OPENQASM 2.0;
+            include "lib.inc";
+            qreg q[1];
+            g q[0];
+            "#
+        );
+        let error = QasmSim::new()
+            .with_includes(includes)
+            .run(source)
+            .unwrap_err();
+        assert_eq!(
+            render("main.qasm", &error),
+            indoc!(
+                r#"
+                error: cannot find gate `h` in this scope
+                 --> main.qasm:4:1
+                  |
+                4 | g q[0];
+                  | ^
+                  = help: gate 'h' is defined in "qelib1.inc"; add `include "qelib1.inc";`
+                "#
+            )
+        );
+    }
+
+    #[test]
+    fn test_report_of_an_error_without_position() {
+        let error = QasmSimError::SimulationTimeout { elapsed_ms: 10 };
+        assert_eq!(
+            render("slow.qasm", &error),
+            "error: simulation timed out after 10 ms\n --> slow.qasm\n"
+        );
+    }
+}
@@ -14,25 +14,33 @@ use lalrpop_util::lalrpop_mod;
 
 pub mod ast;
 pub mod lexer;
+pub mod lint;
+mod modifiers;
+pub mod symbols;
 lalrpop_mod!(
     #[allow(clippy::all)]
     open_qasm2,
     "/grammar/open_qasm2.rs"
 );
 
-use self::ast::{Expression, OpenQasmLibrary, OpenQasmProgram, Span, Statement};
-use self::lexer::Lexer;
+use self::ast::{
+    Expression, OpenQasmLibrary, OpenQasmProgram, QuantumOperation, Span, Spanned, Statement,
+};
+use self::lexer::{Lexer, Tok};
+use self::lint::{Lint, LintSeverity};
 use crate::error::QasmSimError;
 
+pub use self::modifiers::expand_modifiers;
+
 macro_rules! parse_functions {
     ($($(#[$attr:meta])* $vis:vis fn $funcname:ident ($param:ident) -> $rettype:ty => $parser:ty;)*) => {
         $(
             $(#[$attr])* $vis fn $funcname(
                 $param: &str
-            ) -> Result<$rettype, QasmSimError> {
+            ) -> Result<$rettype, QasmSimError<'_>> {
                 let lexer = Lexer::new($param);
                 let parser = <$parser>::new();
-                parser.parse(lexer).map_err(|err| ($param, err).into())
+                parser.parse(false, lexer).map_err(|err| ($param, err).into())
             }
         )*
     };
@@ -48,26 +56,28 @@ parse_functions! {
     /// ```
     /// # use qasmsim::{QasmSimError, grammar::parse_expression};
     /// use qasmsim::grammar::ast::{Expression, OpCode};
+    /// use qasmsim::grammar::lexer::Location;
     ///
     /// let expr_ast = parse_expression("pi/2")?;
     ///
-    /// assert_eq!(expr_ast, Expression::Op(
+    /// assert_eq!(expr_ast.boundaries, (Location(0), Location(4)));
+    /// assert_eq!(expr_ast.node, Expression::Op(
     ///     OpCode::Div,
-    ///     Box::new(Expression::Pi),
-    ///     Box::new(Expression::Real(2.0))
+    ///     Box::new(Expression::Pi.into()),
+    ///     Box::new(Expression::Real(2.0).into())
     /// ));
     /// # Ok::<(), QasmSimError>(())
     /// ```
     ///
     /// [`Expression`]: ./ast/enum.Expression.html
-    pub fn parse_expression(source) -> Expression => open_qasm2::ExprParser;
+    pub fn parse_expression(source) -> Spanned<Expression> => open_qasm2::ExprParser;
 
     /// Parse `source` into a [`OpenQasmLib`] AST.
     ///
     /// The main difference between this method and [`parse_program()`] is that
     /// a library can only contain gate definitions and other statements are
-    /// forbidden. Also, the list of statements in a program is colated to
-    /// the source code via [`Span`]. Definitions in a library are not.
+    /// forbidden. As the statements in a program, the definitions are
+    /// colated to the source code via [`Span`].
     ///
     /// # Examples
     ///
@@ -92,29 +102,31 @@ parse_functions! {
     /// }")?;
     ///
     /// assert_eq!(library_ast, OpenQasmLibrary{
-    ///     definitions: vec![
-    ///         Statement::GateDecl {
+    ///     definitions: vec![Span {
+    ///         boundaries: (Location(1), Location(32)),
+    ///         node: Box::new(Statement::GateDecl {
     ///             signature: (
-    ///                 "idle".to_string(),
+    ///                 "idle".into(),
     ///                 vec![],
-    ///                 vec!["q".to_string()],
+    ///                 vec!["q".into()],
     ///                 vec![
     ///                     GateOperation::Unitary(
     ///                         UnitaryOperation(
-    ///                             "U".to_string(),
+    ///                             "U".into(),
     ///                             vec![
-    ///                                 Expression::Real(0.0),
-    ///                                 Expression::Real(0.0),
-    ///                                 Expression::Real(0.0)
+    ///                                 Expression::Real(0.0).into(),
+    ///                                 Expression::Real(0.0).into(),
+    ///                                 Expression::Real(0.0).into()
     ///                             ],
-    ///                             vec![Argument::Id("q".to_string())]
+    ///                             vec![Argument::Id("q".into())]
     ///                         )
-    ///                     )
+    ///                     ).into()
     ///                 ]
     ///             ),
     ///             docstring: None
-    ///         }
-    ///     ]
+    ///         }),
+    ///         origin: None
+    ///     }]
     /// });
     /// # Ok::<(), QasmSimError>(())
     /// ```
@@ -161,25 +173,26 @@ parse_functions! {
     ///         boundaries: (Location(15), Location(46)),
     ///         node: Box::new(Statement::GateDecl {
     ///             signature: (
-    ///                 "idle".to_string(),
+    ///                 "idle".into(),
     ///                 vec![],
-    ///                 vec!["q".to_string()],
+    ///                 vec!["q".into()],
     ///                 vec![
     ///                     GateOperation::Unitary(
     ///                         UnitaryOperation(
-    ///                             "U".to_string(),
+    ///                             "U".into(),
     ///                             vec![
-    ///                                 Expression::Real(0.0),
-    ///                                 Expression::Real(0.0),
-    ///                                 Expression::Real(0.0)
+    ///                                 Expression::Real(0.0).into(),
+    ///                                 Expression::Real(0.0).into(),
+    ///                                 Expression::Real(0.0).into()
     ///                             ],
-    ///                             vec![Argument::Id("q".to_string())]
+    ///                             vec![Argument::Id("q".into())]
     ///                         )
-    ///                     )
+    ///                     ).into()
     ///                 ]
     ///             ),
     ///             docstring: None
-    ///         })
+    ///         }),
+    ///         origin: None
     ///     }]
     /// });
     /// # Ok::<(), QasmSimError>(())
@@ -219,25 +232,26 @@ parse_functions! {
     ///     boundaries: (Location(0), Location(29)),
     ///     node: Box::new(Statement::GateDecl {
     ///         signature: (
-    ///             "idle".to_string(),
+    ///             "idle".into(),
     ///             vec![],
-    ///             vec!["q".to_string()],
+    ///             vec!["q".into()],
     ///             vec![
     ///                 GateOperation::Unitary(
     ///                     UnitaryOperation(
-    ///                         "U".to_string(),
+    ///                         "U".into(),
     ///                         vec![
-    ///                             Expression::Real(0.0),
-    ///                             Expression::Real(0.0),
-    ///                             Expression::Real(0.0)
+    ///                             Expression::Real(0.0).into(),
+    ///                             Expression::Real(0.0).into(),
+    ///                             Expression::Real(0.0).into()
     ///                         ],
-    ///                         vec![Argument::Id("q".to_string())]
+    ///                         vec![Argument::Id("q".into())]
     ///                     )
-    ///                 )
+    ///                 ).into()
     ///             ]
     ///         ),
     ///         docstring: None
-    ///     })
+    ///     }),
+    ///     origin: None
     /// }]);
     /// # Ok::<(), QasmSimError>(())
     /// ```
@@ -271,21 +285,21 @@ parse_functions! {
     ///
     /// assert_eq!(statement_ast, Statement::GateDecl {
     ///     signature: (
-    ///         "idle".to_string(),
+    ///         "idle".into(),
     ///         vec![],
-    ///         vec!["q".to_string()],
+    ///         vec!["q".into()],
     ///         vec![
     ///             GateOperation::Unitary(
     ///                 UnitaryOperation(
-    ///                     "U".to_string(),
+    ///                     "U".into(),
     ///                     vec![
-    ///                         Expression::Real(0.0),
-    ///                         Expression::Real(0.0),
-    ///                         Expression::Real(0.0)
+    ///                         Expression::Real(0.0).into(),
+    ///                         Expression::Real(0.0).into(),
+    ///                         Expression::Real(0.0).into()
     ///                     ],
-    ///                     vec![Argument::Id("q".to_string())]
+    ///                     vec![Argument::Id("q".into())]
     ///                 )
-    ///             )
+    ///             ).into()
     ///         ]
     ///     ),
     ///     docstring: None
@@ -303,19 +317,169 @@ parse_functions! {
     pub fn parse_statement(source) -> Statement => open_qasm2::StatementParser;
 }
 
+/// Parse `source` into a [`OpenQasmProgram`] AST, accepting the syntax
+/// extensions to OPENQASM 2.0:
+///
+/// - Conditionals on single bits of a classical register such as
+///   `if (c[1]==1) x q[0];`.
+/// - Conditionals guarding a block of quantum operations such as
+///   `if (c==1) { x q[0]; z q[1]; }`. Blocks cannot contain other
+///   conditionals.
+///
+/// With no extensions in use, the result is the same as that of
+/// [`parse_program()`].
+///
+/// # Examples
+///
+/// ```
+/// # use qasmsim::{QasmSimError, grammar::parse_program_with_extensions};
+/// let program_ast = parse_program_with_extensions(r"
+/// OPENQASM 2.0;
+/// qreg q[1];
+/// creg c[2];
+/// if (c[1]==1) U(pi, 0, pi) q[0];
+/// ")?;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// [`OpenQasmProgram`]: ./ast/struct.OpenQasmProgram.html
+/// [`parse_program()`]: ./fn.parse_program.html
+pub fn parse_program_with_extensions(source: &str) -> Result<OpenQasmProgram, QasmSimError<'_>> {
+    let lexer = Lexer::new(source);
+    let parser = open_qasm2::OpenQasmProgramParser::new();
+    parser
+        .parse(true, lexer)
+        .map_err(|err| (source, err).into())
+}
+
+/// Parse `source` into a [`OpenQasmProgram`] AST, accepting some nonstandard
+/// syntax commonly found in the wild, and return the AST along with a
+/// warning for each nonstandard construct. The nonstandard syntax includes:
+///
+/// - A missing `OPENQASM 2.0;` header, assuming version 2.0.
+/// - Paths between angle brackets in `include` statements such as
+///   `include <qelib1.inc>;`.
+///
+/// OPENQASM 3 programs also get a warning for each OPENQASM 3 feature in use
+/// beyond the `qubit` and `bit` declarations, such as `c = measure q;`.
+///
+/// Use [`parse_program()`] to reject nonstandard syntax.
+///
+/// # Examples
+///
+/// ```
+/// # use qasmsim::{QasmSimError, grammar::parse_program_lenient};
+/// let (program_ast, warnings) = parse_program_lenient(r"
+/// qreg q[1];
+/// U(pi, 0, pi) q[0];
+/// ")?;
+///
+/// assert_eq!(program_ast.version, "2.0");
+/// assert_eq!(warnings[0].message, "missing `OPENQASM 2.0;` header");
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// [`OpenQasmProgram`]: ./ast/struct.OpenQasmProgram.html
+/// [`parse_program()`]: ./fn.parse_program.html
+pub fn parse_program_lenient(
+    source: &str,
+) -> Result<(OpenQasmProgram, Vec<Lint>), QasmSimError<'_>> {
+    parse_lenient(source, false)
+}
+
+pub(crate) fn parse_lenient(
+    source: &str,
+    extensions: bool,
+) -> Result<(OpenQasmProgram, Vec<Lint>), QasmSimError<'_>> {
+    let has_header = matches!(
+        Lexer::new_lenient(source).next(),
+        Some(Ok((_, Tok::QASMHeader, _)))
+    );
+    let program = if has_header {
+        let parser = open_qasm2::OpenQasmProgramParser::new();
+        parser
+            .parse(extensions, Lexer::new_lenient(source))
+            .map_err(|err| (source, err))?
+    } else {
+        let parser = open_qasm2::ProgramBodyParser::new();
+        let body = parser
+            .parse(extensions, Lexer::new_lenient(source))
+            .map_err(|err| (source, err))?;
+        OpenQasmProgram {
+            version: String::from("2.0"),
+            program: body,
+        }
+    };
+
+    let mut warnings = Vec::new();
+    if !has_header {
+        warnings.push(Lint {
+            severity: LintSeverity::Warning,
+            message: String::from("missing `OPENQASM 2.0;` header"),
+            span: program.program[0].clone(),
+        });
+    }
+    for span in &program.program {
+        let (start, end) = span.boundaries;
+        if let Statement::Include(_) = &*span.node {
+            if source[start.0..end.0].contains('<') {
+                warnings.push(Lint {
+                    severity: LintSeverity::Warning,
+                    message: String::from("`include` paths should be enclosed in double quotes"),
+                    span: span.clone(),
+                });
+            }
+        }
+    }
+    warnings.extend(openqasm3_warnings(source, &program));
+    Ok((program, warnings))
+}
+
+/// Return a warning for each use of the OPENQASM 3 syntax, beyond the
+/// `qubit` and `bit` declarations, in an OPENQASM 3 `program` parsed from
+/// `source`. Only measurements assigned with `=` are accepted; the rest of
+/// OPENQASM 3 features fail to parse.
+pub(crate) fn openqasm3_warnings(source: &str, program: &OpenQasmProgram) -> Vec<Lint> {
+    if !program.version.starts_with('3') {
+        return Vec::new();
+    }
+    program
+        .program
+        .iter()
+        .filter(|span| {
+            let (start, end) = span.boundaries;
+            let measures = matches!(
+                &*span.node,
+                Statement::QuantumOperation(QuantumOperation::Measure(..))
+                    | Statement::Conditional(_, _, QuantumOperation::Measure(..))
+            );
+            measures && !source[start.0..end.0].contains("->")
+        })
+        .map(|span| Lint {
+            severity: LintSeverity::Warning,
+            message: String::from(
+                "OPENQASM 3 measurement assignments are only partially supported; \
+                 simulated as `measure qubit -> bit`",
+            ),
+            span: span.clone(),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use indoc::indoc;
 
     use crate::grammar::lexer::Lexer;
-    use crate::grammar::open_qasm2;
     use crate::grammar::{ast::*, lexer::Location};
+    use crate::grammar::{open_qasm2, parse_program_lenient};
 
     macro_rules! span {
         ($left:expr, $node:expr, $right:expr) => {
             Span {
                 boundaries: (Location($left), Location($right)),
                 node: Box::new($node),
+                origin: None,
             }
         };
     }
@@ -331,14 +495,14 @@ mod tests {
         );
         let lexer = Lexer::new(source);
         let parser = open_qasm2::OpenQasmProgramParser::new();
-        let tree = parser.parse(lexer).unwrap();
+        let tree = parser.parse(false, lexer).unwrap();
         assert_eq!(
             tree,
             OpenQasmProgram {
                 version: "2.0".to_string(),
                 program: vec![
-                    span!(14, Statement::QRegDecl("q".to_string(), 2), 24),
-                    span!(25, Statement::CRegDecl("c".to_string(), 2), 35)
+                    span!(14, Statement::QRegDecl("q".into(), 2), 24),
+                    span!(25, Statement::CRegDecl("c".into(), 2), 35)
                 ]
             }
         );
@@ -351,11 +515,11 @@ mod tests {
     ";
         let lexer = Lexer::new(source);
         let parser = open_qasm2::StatementParser::new();
-        let tree = parser.parse(lexer).unwrap();
+        let tree = parser.parse(false, lexer).unwrap();
         assert_eq!(
             tree,
             Statement::GateDecl {
-                signature: ("id".to_string(), vec![], vec!["q".to_string()], vec![]),
+                signature: ("id".into(), vec![], vec!["q".into()], vec![]),
                 docstring: None,
             }
         );
@@ -368,11 +532,11 @@ mod tests {
     ";
         let lexer = Lexer::new(source);
         let parser = open_qasm2::StatementParser::new();
-        let tree = parser.parse(lexer).unwrap();
+        let tree = parser.parse(false, lexer).unwrap();
         assert_eq!(
             tree,
             Statement::GateDecl {
-                signature: ("id".to_string(), vec![], vec!["q".to_string()], vec![]),
+                signature: ("id".into(), vec![], vec!["q".into()], vec![]),
                 docstring: None,
             }
         );
@@ -387,19 +551,20 @@ mod tests {
     ";
         let lexer = Lexer::new(source);
         let parser = open_qasm2::StatementParser::new();
-        let tree = parser.parse(lexer).unwrap();
+        let tree = parser.parse(false, lexer).unwrap();
         assert_eq!(
             tree,
             Statement::GateDecl {
                 signature: (
-                    "cx".to_string(),
+                    "cx".into(),
                     vec![],
-                    vec!["c".to_string(), "t".to_string()],
+                    vec!["c".into(), "t".into()],
                     vec![GateOperation::Unitary(UnitaryOperation(
-                        "CX".to_owned(),
+                        "CX".into(),
                         vec![],
-                        vec![Argument::Id("c".to_owned()), Argument::Id("t".to_owned())]
-                    ))]
+                        vec![Argument::Id("c".into()), Argument::Id("t".into())]
+                    ))
+                    .into()]
                 ),
                 docstring: None
             }
@@ -415,23 +580,24 @@ mod tests {
     ";
         let lexer = Lexer::new(source);
         let parser = open_qasm2::StatementParser::new();
-        let tree = parser.parse(lexer).unwrap();
+        let tree = parser.parse(false, lexer).unwrap();
         assert_eq!(
             tree,
             Statement::GateDecl {
                 signature: (
-                    "u".to_string(),
-                    vec!["theta".to_string(), "phi".to_string(), "lambda".to_string()],
-                    vec!["q".to_string()],
+                    "u".into(),
+                    vec!["theta".into(), "phi".into(), "lambda".into()],
+                    vec!["q".into()],
                     vec![GateOperation::Unitary(UnitaryOperation(
-                        "U".to_owned(),
+                        "U".into(),
                         vec![
-                            Expression::Id("theta".to_owned()),
-                            Expression::Id("phi".to_owned()),
-                            Expression::Id("lambda".to_owned()),
+                            Expression::Id("theta".to_owned()).into(),
+                            Expression::Id("phi".to_owned()).into(),
+                            Expression::Id("lambda".to_owned()).into(),
                         ],
-                        vec![Argument::Id("q".to_owned())]
-                    ))]
+                        vec![Argument::Id("q".into())]
+                    ))
+                    .into()]
                 ),
                 docstring: None
             }
@@ -447,19 +613,20 @@ mod tests {
     ";
         let lexer = Lexer::new(source);
         let parser = open_qasm2::StatementParser::new();
-        let tree = parser.parse(lexer).unwrap();
+        let tree = parser.parse(false, lexer).unwrap();
         assert_eq!(
             tree,
             Statement::GateDecl {
                 signature: (
-                    "rz".to_string(),
-                    vec!["phi".to_string()],
-                    vec!["a".to_string()],
+                    "rz".into(),
+                    vec!["phi".into()],
+                    vec!["a".into()],
                     vec![GateOperation::Unitary(UnitaryOperation(
-                        "u1".to_string(),
-                        vec![Expression::Id("phi".to_string())],
-                        vec![Argument::Id("a".to_string())]
-                    ))]
+                        "u1".into(),
+                        vec![Expression::Id("phi".to_string()).into()],
+                        vec![Argument::Id("a".into())]
+                    ))
+                    .into()]
                 ),
                 docstring: None
             }
@@ -473,21 +640,22 @@ mod tests {
     ";
         let lexer = Lexer::new(source);
         let parser = open_qasm2::StatementParser::new();
-        let tree = parser.parse(lexer).unwrap();
+        let tree = parser.parse(false, lexer).unwrap();
         assert_eq!(
             tree,
             Statement::QuantumOperation(QuantumOperation::Unitary(UnitaryOperation(
-                "U".to_owned(),
+                "U".into(),
                 vec![
                     Expression::Op(
                         OpCode::Div,
-                        Box::new(Expression::Pi),
-                        Box::new(Expression::Real(2.0))
-                    ),
-                    Expression::Real(0.0),
-                    Expression::Pi,
+                        Box::new(Expression::Pi.into()),
+                        Box::new(Expression::Real(2.0).into())
+                    )
+                    .into(),
+                    Expression::Real(0.0).into(),
+                    Expression::Pi.into(),
                 ],
-                vec![Argument::Id("q".to_owned())]
+                vec![Argument::Id("q".into())]
             )))
         );
     }
@@ -499,26 +667,36 @@ mod tests {
     ";
         let lexer = Lexer::new(source);
         let parser = open_qasm2::ExprParser::new();
-        let tree = parser.parse(lexer).unwrap();
+        let tree = parser.parse(false, lexer).unwrap();
         assert_eq!(
             tree,
             Expression::Op(
                 OpCode::Add,
-                Box::new(Expression::Minus(Box::new(Expression::Pi))),
-                Box::new(Expression::Op(
-                    OpCode::Div,
-                    Box::new(Expression::Op(
-                        OpCode::Mul,
-                        Box::new(Expression::Op(
-                            OpCode::Sub,
-                            Box::new(Expression::Real(1.0)),
-                            Box::new(Expression::Real(2.0))
-                        )),
-                        Box::new(Expression::Real(3.0))
-                    )),
-                    Box::new(Expression::Real(4.0))
-                ))
+                Box::new(Expression::Minus(Box::new(Expression::Pi.into())).into()),
+                Box::new(
+                    Expression::Op(
+                        OpCode::Div,
+                        Box::new(
+                            Expression::Op(
+                                OpCode::Mul,
+                                Box::new(
+                                    Expression::Op(
+                                        OpCode::Sub,
+                                        Box::new(Expression::Real(1.0).into()),
+                                        Box::new(Expression::Real(2.0).into())
+                                    )
+                                    .into()
+                                ),
+                                Box::new(Expression::Real(3.0).into())
+                            )
+                            .into()
+                        ),
+                        Box::new(Expression::Real(4.0).into())
+                    )
+                    .into()
+                )
             )
+            .into()
         );
     }
 
@@ -533,18 +711,18 @@ mod tests {
         );
         let lexer = Lexer::new(source);
         let parser = open_qasm2::ProgramBodyParser::new();
-        let tree = parser.parse(lexer).unwrap();
+        let tree = parser.parse(false, lexer).unwrap();
         assert_eq!(
             tree,
             vec![
-                span!(0, Statement::QRegDecl("q".to_string(), 1), 10),
-                span!(11, Statement::CRegDecl("c".to_string(), 1), 21),
+                span!(0, Statement::QRegDecl("q".into(), 1), 10),
+                span!(11, Statement::CRegDecl("c".into(), 1), 21),
                 span!(
                     22,
                     Statement::QuantumOperation(QuantumOperation::Unitary(UnitaryOperation(
-                        "h".to_string(),
+                        "h".into(),
                         vec![],
-                        vec![Argument::Id("q".to_string())]
+                        vec![Argument::Id("q".into())]
                     ))),
                     26
                 )
@@ -565,34 +743,32 @@ mod tests {
         );
         let lexer = Lexer::new(source);
         let parser = open_qasm2::ProgramBodyParser::new();
-        let tree = parser.parse(lexer).unwrap();
+        let tree = parser.parse(false, lexer).unwrap();
         assert_eq!(
             tree,
             vec![
-                span!(0, Statement::QRegDecl("q".to_string(), 1), 10),
-                span!(11, Statement::CRegDecl("c".to_string(), 1), 21),
+                span!(0, Statement::QRegDecl("q".into(), 1), 10),
+                span!(11, Statement::CRegDecl("c".into(), 1), 21),
                 span!(
                     22,
                     Statement::QuantumOperation(QuantumOperation::Unitary(UnitaryOperation(
-                        "h".to_string(),
+                        "h".into(),
                         vec![],
-                        vec![Argument::Id("q".to_string())]
+                        vec![Argument::Id("q".into())]
                     ))),
                     26
                 ),
                 span!(
                     27,
                     Statement::QuantumOperation(QuantumOperation::Measure(
-                        Argument::Id("q".to_string()),
-                        Argument::Id("c".to_string())
+                        Argument::Id("q".into()),
+                        Argument::Id("c".into())
                     )),
                     42
                 ),
                 span!(
                     43,
-                    Statement::QuantumOperation(QuantumOperation::Reset(Argument::Id(
-                        "q".to_string()
-                    ))),
+                    Statement::QuantumOperation(QuantumOperation::Reset(Argument::Id("q".into()))),
                     51
                 )
             ]
@@ -614,7 +790,7 @@ mod tests {
         );
         let lexer = Lexer::new(source);
         let parser = open_qasm2::OpenQasmProgramParser::new();
-        let tree = parser.parse(lexer).unwrap();
+        let tree = parser.parse(false, lexer).unwrap();
         assert_eq!(
             tree,
             OpenQasmProgram {
@@ -622,7 +798,7 @@ mod tests {
                 program: vec![span!(
                     41,
                     Statement::GateDecl {
-                        signature: (String::from("id"), vec![], vec![String::from("q")], vec![]),
+                        signature: ("id".into(), vec![], vec!["q".into()], vec![]),
                         docstring: Some(" Comment 3\n".to_string())
                     },
                     66
@@ -649,7 +825,7 @@ mod tests {
         );
         let lexer = Lexer::new(source);
         let parser = open_qasm2::OpenQasmProgramParser::new();
-        let tree = parser.parse(lexer).unwrap();
+        let tree = parser.parse(false, lexer).unwrap();
         assert_eq!(
             tree,
             OpenQasmProgram {
@@ -657,7 +833,7 @@ mod tests {
                 program: vec![span!(
                     41,
                     Statement::GateDecl {
-                        signature: (String::from("id"), vec![], vec![String::from("q")], vec![]),
+                        signature: ("id".into(), vec![], vec!["q".into()], vec![]),
                         docstring: Some(" Comment 3\n Comment 4\n".to_string())
                     },
                     97
@@ -666,6 +842,123 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_block_comments() {
+        let source = indoc!(
+            "
+    /* Comment 1 /* still comment 1 */
+    OPENQASM 2.0;
+    /*
+     * Comment 2
+     * Comment 3
+     */
+    gate id q { /* Comment 4 */ }
+    "
+        );
+        let lexer = Lexer::new(source);
+        let parser = open_qasm2::OpenQasmProgramParser::new();
+        let tree = parser.parse(false, lexer).unwrap();
+        assert_eq!(
+            tree,
+            OpenQasmProgram {
+                version: "2.0".to_string(),
+                program: vec![span!(
+                    49,
+                    Statement::GateDecl {
+                        signature: ("id".into(), vec![], vec!["q".into()], vec![]),
+                        docstring: Some(" Comment 2\n Comment 3\n".to_string())
+                    },
+                    111
+                ),]
+            }
+        );
+    }
+
+    #[test]
+    fn test_crlf_line_endings() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    // Comment 1
+    gate id q {}
+    /* Comment 2
+     */
+    qreg q[1];
+    id q[0];
+    "
+        );
+        let crlf_source = source.replace('\n', "\r\n");
+        let nodes = |source: &str| -> Vec<Statement> {
+            super::parse_program(source)
+                .unwrap()
+                .program
+                .into_iter()
+                .map(|span| *span.node)
+                .collect()
+        };
+        assert_eq!(nodes(&crlf_source), nodes(source));
+    }
+
+    #[test]
+    fn test_statement_at() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    qreg q[2];
+    U(pi, 0, pi) q[0];
+    CX q[0], q[1];
+    "
+        );
+        let program = super::parse_program(source).unwrap();
+        let middle = source.find("U(pi").unwrap() + 5;
+        let statement = program.statement_at(middle).expect("some statement");
+        assert!(matches!(
+            statement,
+            Statement::QuantumOperation(QuantumOperation::Unitary(UnitaryOperation(name, _, _)))
+                if name == "U"
+        ));
+        assert_eq!(Location(middle).line_column(source), (3, 5));
+        assert_eq!(program.statement_at(source.len()), None);
+    }
+
+    #[test]
+    fn test_to_qasm_round_trip() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[2];
+    // Documentation of
+    // the gate
+    gate rot(theta, phi) a, b {
+      U(-(theta + phi) / 2, 2 ^ (phi - 1), sin(theta) * 0.5) a;
+      barrier a, b;
+      CX a, b;
+    }
+    opaque gate oracle(k) a;
+    rot(pi, -pi / 4) q[0], q[1];
+    barrier q;
+    if (c==1) reset q[0];
+    measure q -> c;
+    "
+        );
+        let nodes = |program: OpenQasmProgram| -> Vec<Statement> {
+            program.program.into_iter().map(|span| *span.node).collect()
+        };
+        let program = super::parse_program(source).unwrap();
+        let printed = program.to_qasm();
+        let reparsed = super::parse_program(&printed).unwrap();
+        assert_eq!(reparsed.version, program.version);
+        assert_eq!(nodes(reparsed), nodes(program.clone()));
+        assert_eq!(program.qubit_width(), 2);
+        assert_eq!(program.gate_names(), vec!["rot", "oracle"]);
+
+        let linked = crate::api::parse_and_link(source).unwrap();
+        let relinked = crate::api::parse_and_link(&linked.to_qasm()).unwrap();
+        assert_eq!(nodes(relinked), nodes(linked));
+    }
+
     #[test]
     fn test_conditional_application() {
         let source = "
@@ -673,21 +966,161 @@ mod tests {
     ";
         let lexer = Lexer::new(source);
         let parser = open_qasm2::StatementParser::new();
-        let tree = parser.parse(lexer).unwrap();
+        let tree = parser.parse(false, lexer).unwrap();
         assert_eq!(
             tree,
             Statement::Conditional(
-                Argument::Id(String::from("c")),
-                5_u64,
+                Argument::Id("c".into()),
+                5_u128,
                 QuantumOperation::Unitary(UnitaryOperation(
-                    String::from("cx"),
+                    "cx".into(),
                     vec![],
-                    vec![
-                        Argument::Id(String::from("c")),
-                        Argument::Id(String::from("t"))
-                    ]
+                    vec![Argument::Id("c".into()), Argument::Id("t".into())]
                 ))
             )
         );
     }
+
+    #[test]
+    fn test_conditional_with_prefixed_literals() {
+        let parser = open_qasm2::StatementParser::new();
+        for (source, value) in [
+            ("if (c==0xFF) x q;", 255_u128),
+            ("if (c==0b101) x q;", 5_u128),
+        ] {
+            let tree = parser.parse(false, Lexer::new(source)).unwrap();
+            assert_eq!(
+                tree,
+                Statement::Conditional(
+                    Argument::Id("c".into()),
+                    value,
+                    QuantumOperation::Unitary(UnitaryOperation(
+                        "x".into(),
+                        vec![],
+                        vec![Argument::Id("q".into())]
+                    ))
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_lenient_parsing_of_angle_brackets_include() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    include <qelib1.inc>;
+    "
+        );
+        let (tree, warnings) = parse_program_lenient(source).unwrap();
+        assert_eq!(
+            tree.program,
+            vec![span!(14, Statement::Include("qelib1.inc".into()), 35)]
+        );
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].message,
+            "`include` paths should be enclosed in double quotes"
+        );
+        assert!(crate::grammar::parse_program(source).is_err());
+    }
+
+    #[test]
+    fn test_lenient_parsing_of_standard_program_has_no_warnings() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    "
+        );
+        let (tree, warnings) = parse_program_lenient(source).unwrap();
+        assert_eq!(tree, crate::grammar::parse_program(source).unwrap());
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_spans_of_identifiers_and_expressions() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    qreg q[2];
+    U(pi/2, 0, theta) q[1];
+    measure q[0] -> c[0];
+    "
+        );
+        let text = |boundaries: (Location, Location)| &source[boundaries.0 .0..boundaries.1 .0];
+        let tree = crate::grammar::parse_program(source).unwrap();
+
+        let declaration = &tree.program[0];
+        assert_eq!(text(declaration.boundaries), "qreg q[2];");
+        match &*declaration.node {
+            Statement::QRegDecl(name, _) => assert_eq!(text(name.boundaries), "q"),
+            other => panic!("unexpected statement {:?}", other),
+        }
+
+        let unitary = &tree.program[1];
+        assert_eq!(text(unitary.boundaries), "U(pi/2, 0, theta) q[1];");
+        assert_eq!(unitary.boundaries.0.line_column(source), (3, 0));
+        let (name, parameters, arguments) = match &*unitary.node {
+            Statement::QuantumOperation(QuantumOperation::Unitary(UnitaryOperation(
+                name,
+                parameters,
+                arguments,
+            ))) => (name, parameters, arguments),
+            other => panic!("unexpected statement {:?}", other),
+        };
+        assert_eq!(text(name.boundaries), "U");
+        assert_eq!(text(parameters[0].boundaries), "pi/2");
+        assert_eq!(text(parameters[2].boundaries), "theta");
+        match &parameters[0].node {
+            Expression::Op(_, left, right) => {
+                assert_eq!(text(left.boundaries), "pi");
+                assert_eq!(text(right.boundaries), "2");
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+        match &arguments[0] {
+            Argument::Item(register, 1) => assert_eq!(text(register.boundaries), "q"),
+            other => panic!("unexpected argument {:?}", other),
+        }
+
+        let measurement = &tree.program[2];
+        assert_eq!(text(measurement.boundaries), "measure q[0] -> c[0];");
+        match &*measurement.node {
+            Statement::QuantumOperation(QuantumOperation::Measure(_, Argument::Item(name, 0))) => {
+                assert_eq!(text(name.boundaries), "c")
+            }
+            other => panic!("unexpected statement {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_ast_serialization_round_trip() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    qreg q[2];
+    creg c[2];
+    U(pi/2, 0, pi) q[0];
+    if (c==1) measure q[1] -> c[1];
+    "
+        );
+        let tree = crate::grammar::parse_program(source).unwrap();
+        let json = serde_json::to_string(&tree).unwrap();
+        let deserialized: OpenQasmProgram = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, tree);
+        let parameters = |program: &OpenQasmProgram| match &*program.program[2].node {
+            Statement::QuantumOperation(QuantumOperation::Unitary(UnitaryOperation(
+                _,
+                parameters,
+                _,
+            ))) => parameters
+                .iter()
+                .map(|parameter| parameter.boundaries)
+                .collect::<Vec<_>>(),
+            other => panic!("unexpected statement {:?}", other),
+        };
+        assert_eq!(parameters(&deserialized), parameters(&tree));
+    }
 }
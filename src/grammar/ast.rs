@@ -3,10 +3,17 @@
 //!
 //! # Notes
 //!
-//! Although OPENQASM 2.0 is stable enough, this module is not. Providing better
-//! errors would require an extensive use of the `Span` structure beyond
-//! statements, and adding new features to the language would require the
-//! modification os certain layouts.
+//! Although OPENQASM 2.0 is stable enough, this module is not. Statements are
+//! localized with the `Span` structure and the identifiers and expressions
+//! inside them with `Spanned`, but adding new features to the language would
+//! require the modification os certain layouts.
+
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt::{self, Write};
+use std::hash::{Hash, Hasher};
+use std::ops;
 
 use crate::grammar::lexer::Location;
 
@@ -45,10 +52,11 @@ use crate::grammar::lexer::Location;
 ///             boundaries: (Location(14), Location(24)),
 ///             node: Box::new(
 ///                 Statement::QRegDecl(
-///                     "q".to_string(),
+///                     "q".into(),
 ///                     1
 ///                 )
-///             )
+///             ),
+///             origin: None
 ///         },
 ///         Span {
 ///             boundaries: (Location(25), Location(45)),
@@ -56,27 +64,35 @@ use crate::grammar::lexer::Location;
 ///                 Statement::QuantumOperation(
 ///                     QuantumOperation::Unitary(
 ///                         UnitaryOperation(
-///                             "U".to_string(),
+///                             "U".into(),
 ///                             vec![
 ///                                 Expression::Op(
 ///                                     OpCode::Div,
-///                                     Box::new(Expression::Pi),
-///                                     Box::new(Expression::Int(2))
-///                                 ),
-///                                 Expression::Int(0),
-///                                 Expression::Pi
+///                                     Box::new(Expression::Pi.into()),
+///                                     Box::new(Expression::Int(2).into())
+///                                 ).into(),
+///                                 Expression::Int(0).into(),
+///                                 Expression::Pi.into()
 ///                             ],
 ///                             vec![
-///                                 Argument::Item("q".to_string(), 0)
+///                                 Argument::Item("q".into(), 0)
 ///                             ]
 ///                         )
 ///                     )
 ///                 )
-///             )
+///             ),
+///             origin: None
 ///         }
 ///     ]
 /// };
 /// ```
+///
+/// Identifiers, expressions and the operations inside blocks are
+/// [`Spanned`] nodes. Converting them with `into()` leaves them with empty
+/// boundaries.
+///
+/// [`Spanned`]: ./struct.Spanned.html
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 
 pub struct OpenQasmProgram {
@@ -87,6 +103,562 @@ pub struct OpenQasmProgram {
     pub program: Vec<Span<Statement>>,
 }
 
+impl OpenQasmProgram {
+    /// Return the first statement whose span contains the character at
+    /// `offset`, if any.
+    ///
+    /// After linking, the definitions embedded from a library share the span
+    /// of the `include` statement they replace, so the first of them is
+    /// returned for an offset inside the `include` statement.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::grammar::{ast::Statement, parse_program};
+    ///
+    /// let source = "OPENQASM 2.0;\nqreg q[1];\ncreg c[1];";
+    /// let program = parse_program(source)?;
+    /// let offset = source.find("creg").unwrap();
+    /// assert_eq!(
+    ///     program.statement_at(offset),
+    ///     Some(&Statement::CRegDecl("c".into(), 1))
+    /// );
+    /// assert_eq!(program.statement_at(0), None);
+    /// # use qasmsim::QasmSimError;
+    /// # Ok::<(), QasmSimError>(())
+    /// ```
+    pub fn statement_at(&self, offset: usize) -> Option<&Statement> {
+        self.program
+            .iter()
+            .find(|span| span.boundaries.0 .0 <= offset && offset < span.boundaries.1 .0)
+            .map(|span| &*span.node)
+    }
+
+    /// Return the number of qubits declared in the program.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::grammar::parse_program;
+    ///
+    /// let program = parse_program("OPENQASM 2.0;\nqreg q[2];\nqreg r[3];")?;
+    /// assert_eq!(program.qubit_width(), 5);
+    /// # use qasmsim::QasmSimError;
+    /// # Ok::<(), QasmSimError>(())
+    /// ```
+    pub fn qubit_width(&self) -> usize {
+        self.program
+            .iter()
+            .map(|span| match &*span.node {
+                Statement::QRegDecl(_, size) => *size,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Return the names of the gates declared in the program, opaque gates
+    /// included, in order of declaration.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::grammar::parse_program;
+    ///
+    /// let program = parse_program(
+    ///     "OPENQASM 2.0;\ngate x a { U(pi, 0, pi) a; }\nopaque gate oracle a;"
+    /// )?;
+    /// assert_eq!(program.gate_names(), vec!["x", "oracle"]);
+    /// # use qasmsim::QasmSimError;
+    /// # Ok::<(), QasmSimError>(())
+    /// ```
+    pub fn gate_names(&self) -> Vec<&str> {
+        self.program
+            .iter()
+            .filter_map(|span| match &*span.node {
+                Statement::GateDecl { signature, .. } => Some(signature.0.as_str()),
+                Statement::OpaqueGateDecl { signature, .. } => Some(signature.0.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Return the OPENQASM source code of the program.
+    ///
+    /// Parsing the result produces the same statements, although not the same
+    /// spans since the original formatting and comments, other than
+    /// docstrings, are not preserved.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::grammar::parse_program;
+    ///
+    /// let program = parse_program("OPENQASM 2.0; qreg q[1]; U(pi/2, 0, pi) q[0];")?;
+    /// assert_eq!(
+    ///     program.to_qasm(),
+    ///     "OPENQASM 2.0;\nqreg q[1];\nU(pi / 2, 0, pi) q[0];\n"
+    /// );
+    /// # use qasmsim::QasmSimError;
+    /// # Ok::<(), QasmSimError>(())
+    /// ```
+    pub fn to_qasm(&self) -> String {
+        let mut source = format!("OPENQASM {};\n", self.version);
+        for span in &self.program {
+            write_statement(&mut source, &span.node);
+        }
+        source
+    }
+
+    /// Return the inverse of the program, which undoes the operations of the
+    /// program when applied after it.
+    ///
+    /// The inverse keeps the declarations of the program and applies the
+    /// inverse of each operation in reverse order. The inverse of
+    /// `U(θ, φ, λ)` is `U(-θ, -λ, -φ)`, `CX` and barriers are their own
+    /// inverses and, for every gate `g` in use, a gate `g_inv` with the
+    /// inverse of the body of `g` is declared after `g`. Conditionals are
+    /// kept with their operations inverted.
+    ///
+    /// The program must be linked so that the definitions of all the gates
+    /// in use are available.
+    ///
+    /// # Errors
+    ///
+    /// The function fails with an [`InversionError`] if the program measures
+    /// or resets qubits, or applies opaque or undefined gates.
+    ///
+    /// [`InversionError`]: ./enum.InversionError.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::grammar::parse_program;
+    ///
+    /// let program = parse_program("OPENQASM 2.0; qreg q[2]; U(pi/2, 0, pi) q[0]; CX q[0], q[1];")?;
+    /// assert_eq!(
+    ///     program.inverse()?.to_qasm(),
+    ///     "OPENQASM 2.0;\nqreg q[2];\nCX q[0], q[1];\nU(-(pi / 2), -pi, -0) q[0];\n"
+    /// );
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn inverse(&self) -> Result<OpenQasmProgram, InversionError> {
+        let mut inverter = Inverter::new(self);
+        let mut operations = Vec::new();
+        for span in self.program.iter().rev() {
+            let location = span.boundaries.0;
+            let node = match &*span.node {
+                Statement::Barrier(barrier) => Statement::Barrier(barrier.clone()),
+                Statement::QuantumOperation(operation) => {
+                    Statement::QuantumOperation(inverter.invert_operation(operation, location)?)
+                }
+                Statement::Conditional(register, value, operation) => Statement::Conditional(
+                    register.clone(),
+                    *value,
+                    inverter.invert_operation(operation, location)?,
+                ),
+                Statement::ConditionalBlock(register, value, block) => {
+                    let block = block
+                        .iter()
+                        .rev()
+                        .map(|operation| {
+                            Ok(Spanned {
+                                boundaries: operation.boundaries,
+                                node: inverter.invert_operation(operation, location)?,
+                            })
+                        })
+                        .collect::<Result<_, _>>()?;
+                    Statement::ConditionalBlock(register.clone(), *value, block)
+                }
+                _ => continue,
+            };
+            operations.push(Span {
+                boundaries: span.boundaries,
+                node: Box::new(node),
+                origin: span.origin.clone(),
+            });
+        }
+
+        let inverse_gates = inverter.invert_gates()?;
+        let mut program = Vec::new();
+        for span in &self.program {
+            match &*span.node {
+                Statement::Barrier(_)
+                | Statement::QuantumOperation(_)
+                | Statement::Conditional(..)
+                | Statement::ConditionalBlock(..) => (),
+                Statement::GateDecl { signature, .. } => {
+                    program.push(span.clone());
+                    if let Some(inverse) = inverse_gates.get(signature.0.as_str()) {
+                        program.push(Span {
+                            boundaries: span.boundaries,
+                            node: Box::new(inverse.clone()),
+                            origin: span.origin.clone(),
+                        });
+                    }
+                }
+                _ => program.push(span.clone()),
+            }
+        }
+        program.extend(operations);
+        Ok(OpenQasmProgram {
+            version: self.version.clone(),
+            program,
+        })
+    }
+}
+
+/// Represent the reasons for a program not to be invertible.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+
+pub enum InversionError {
+    /// Measurements and resets are not unitary so they have no inverse.
+    NonUnitaryOperation {
+        /// Location of the statement with the operation.
+        location: Location,
+        /// Name of the operation.
+        symbol_name: String,
+    },
+    /// Opaque gates have no body to invert.
+    OpaqueGate {
+        /// Location of the statement applying the gate.
+        location: Location,
+        /// Name of the opaque gate.
+        symbol_name: String,
+    },
+    /// The gate is not declared in the program.
+    UndefinedGate {
+        /// Location of the statement applying the gate.
+        location: Location,
+        /// Name of the undefined gate.
+        symbol_name: String,
+    },
+}
+
+impl fmt::Display for InversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match lazy_humanize! {
+            self,
+            InversionError::NonUnitaryOperation,
+            InversionError::OpaqueGate,
+            InversionError::UndefinedGate
+        } {
+            Some(message) => message,
+            None => unreachable!(),
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl error::Error for InversionError {}
+
+/// Keep track of the gates whose inverses are needed while inverting a
+/// program.
+#[allow(clippy::type_complexity)]
+struct Inverter<'program> {
+    gates: HashMap<
+        &'program str,
+        (
+            &'program [Spanned<String>],
+            &'program [Spanned<String>],
+            Location,
+        ),
+    >,
+    bodies: HashMap<&'program str, &'program [Spanned<GateOperation>]>,
+    opaque_gates: HashSet<&'program str>,
+    inverse_names: HashMap<String, String>,
+    pending: Vec<String>,
+}
+
+impl<'program> Inverter<'program> {
+    fn new(program: &'program OpenQasmProgram) -> Self {
+        let mut gates = HashMap::new();
+        let mut bodies = HashMap::new();
+        let mut opaque_gates = HashSet::new();
+        for span in &program.program {
+            match &*span.node {
+                Statement::GateDecl {
+                    signature: (name, params, args, body),
+                    ..
+                } => {
+                    gates.insert(
+                        name.as_str(),
+                        (params.as_slice(), args.as_slice(), span.boundaries.0),
+                    );
+                    bodies.insert(name.as_str(), body.as_slice());
+                }
+                Statement::OpaqueGateDecl { signature, .. } => {
+                    opaque_gates.insert(signature.0.as_str());
+                }
+                _ => (),
+            }
+        }
+        Inverter {
+            gates,
+            bodies,
+            opaque_gates,
+            inverse_names: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    fn invert_operation(
+        &mut self,
+        operation: &QuantumOperation,
+        location: Location,
+    ) -> Result<QuantumOperation, InversionError> {
+        match operation {
+            QuantumOperation::Unitary(unitary) => Ok(QuantumOperation::Unitary(
+                self.invert_unitary(unitary, location)?,
+            )),
+            QuantumOperation::Measure(..) => Err(InversionError::NonUnitaryOperation {
+                location,
+                symbol_name: "measure".into(),
+            }),
+            QuantumOperation::Reset(_) => Err(InversionError::NonUnitaryOperation {
+                location,
+                symbol_name: "reset".into(),
+            }),
+        }
+    }
+
+    fn invert_unitary(
+        &mut self,
+        UnitaryOperation(name, params, args): &UnitaryOperation,
+        location: Location,
+    ) -> Result<UnitaryOperation, InversionError> {
+        match name.as_str() {
+            "U" => {
+                let minus = |param: &Spanned<Expression>| Spanned {
+                    boundaries: param.boundaries,
+                    node: Expression::Minus(Box::new(param.clone())),
+                };
+                let params = vec![minus(&params[0]), minus(&params[2]), minus(&params[1])];
+                Ok(UnitaryOperation(name.clone(), params, args.clone()))
+            }
+            "CX" => Ok(UnitaryOperation(name.clone(), params.clone(), args.clone())),
+            _ => {
+                if self.opaque_gates.contains(name.as_str()) {
+                    return Err(InversionError::OpaqueGate {
+                        location,
+                        symbol_name: name.to_string(),
+                    });
+                }
+                if !self.gates.contains_key(name.as_str()) {
+                    return Err(InversionError::UndefinedGate {
+                        location,
+                        symbol_name: name.to_string(),
+                    });
+                }
+                let inverse_name = Spanned {
+                    boundaries: name.boundaries,
+                    node: self.inverse_name(name),
+                };
+                Ok(UnitaryOperation(inverse_name, params.clone(), args.clone()))
+            }
+        }
+    }
+
+    /// Return the name of the inverse of `name`, scheduling the declaration
+    /// of the inverse gate if it is the first time it is needed.
+    fn inverse_name(&mut self, name: &str) -> String {
+        if let Some(inverse_name) = self.inverse_names.get(name) {
+            return inverse_name.clone();
+        }
+        let mut inverse_name = format!("{}_inv", name);
+        while self.gates.contains_key(inverse_name.as_str())
+            || self.opaque_gates.contains(inverse_name.as_str())
+        {
+            inverse_name.push('_');
+        }
+        self.inverse_names
+            .insert(name.to_owned(), inverse_name.clone());
+        self.pending.push(name.to_owned());
+        inverse_name
+    }
+
+    /// Return the declarations of the inverse gates needed so far, and of the
+    /// gates they need in turn, indexed by the name of the original gate.
+    fn invert_gates(&mut self) -> Result<HashMap<String, Statement>, InversionError> {
+        let mut declarations = HashMap::new();
+        while let Some(name) = self.pending.pop() {
+            let (params, args, location) = self.gates[name.as_str()];
+            let body = self.bodies[name.as_str()];
+            let inverse_body = body
+                .iter()
+                .rev()
+                .map(|operation| {
+                    let node = match &operation.node {
+                        GateOperation::Unitary(unitary) => {
+                            GateOperation::Unitary(self.invert_unitary(unitary, location)?)
+                        }
+                        GateOperation::Barrier(barrier) => GateOperation::Barrier(barrier.clone()),
+                    };
+                    Ok(Spanned {
+                        boundaries: operation.boundaries,
+                        node,
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            let declaration = Statement::GateDecl {
+                signature: (
+                    self.inverse_names[&name].as_str().into(),
+                    params.to_vec(),
+                    args.to_vec(),
+                    inverse_body,
+                ),
+                docstring: None,
+            };
+            declarations.insert(name, declaration);
+        }
+        Ok(declarations)
+    }
+}
+
+pub(crate) fn write_statement(source: &mut String, statement: &Statement) {
+    match statement {
+        Statement::QRegDecl(name, size) => {
+            let _ = writeln!(source, "qreg {}[{}];", name, size);
+        }
+        Statement::CRegDecl(name, size) => {
+            let _ = writeln!(source, "creg {}[{}];", name, size);
+        }
+        Statement::GateDecl {
+            signature: (name, params, args, body),
+            docstring,
+        } => {
+            write_docstring(source, docstring);
+            let _ = writeln!(source, "gate {} {{", gate_signature(name, params, args));
+            for operation in body {
+                source.push_str("  ");
+                match &operation.node {
+                    GateOperation::Unitary(unitary) => write_unitary(source, unitary),
+                    GateOperation::Barrier(barrier) => write_barrier(source, barrier),
+                }
+            }
+            source.push_str("}\n");
+        }
+        Statement::OpaqueGateDecl {
+            signature: (name, params, args),
+            docstring,
+        } => {
+            write_docstring(source, docstring);
+            let _ = writeln!(
+                source,
+                "opaque gate {};",
+                gate_signature(name, params, args)
+            );
+        }
+        Statement::Include(path) => {
+            let _ = writeln!(source, "include \"{}\";", path);
+        }
+        Statement::Barrier(barrier) => write_barrier(source, barrier),
+        Statement::QuantumOperation(operation) => write_operation(source, operation),
+        Statement::Conditional(register, value, operation) => {
+            let _ = write!(source, "if ({}=={}) ", argument(register), value);
+            write_operation(source, operation);
+        }
+        Statement::ConditionalBlock(register, value, operations) => {
+            let _ = writeln!(source, "if ({}=={}) {{", argument(register), value);
+            for operation in operations {
+                source.push_str("  ");
+                write_operation(source, operation);
+            }
+            source.push_str("}\n");
+        }
+    }
+}
+
+fn write_docstring(source: &mut String, docstring: &Option<String>) {
+    if let Some(docstring) = docstring {
+        for line in docstring.lines() {
+            let _ = writeln!(source, "//{}", line);
+        }
+    }
+}
+
+fn gate_signature(name: &str, params: &[Spanned<String>], args: &[Spanned<String>]) -> String {
+    if params.is_empty() {
+        format!("{} {}", name, args.join(", "))
+    } else {
+        format!("{}({}) {}", name, params.join(", "), args.join(", "))
+    }
+}
+
+fn write_operation(source: &mut String, operation: &QuantumOperation) {
+    match operation {
+        QuantumOperation::Unitary(unitary) => write_unitary(source, unitary),
+        QuantumOperation::Measure(from, to) => {
+            let _ = writeln!(source, "measure {} -> {};", argument(from), argument(to));
+        }
+        QuantumOperation::Reset(target) => {
+            let _ = writeln!(source, "reset {};", argument(target));
+        }
+    }
+}
+
+fn write_unitary(source: &mut String, UnitaryOperation(name, params, args): &UnitaryOperation) {
+    source.push_str(name);
+    if !params.is_empty() {
+        let params: Vec<String> = params.iter().map(|param| expression(param, 0)).collect();
+        let _ = write!(source, "({})", params.join(", "));
+    }
+    let args: Vec<String> = args.iter().map(argument).collect();
+    let _ = writeln!(source, " {};", args.join(", "));
+}
+
+fn write_barrier(source: &mut String, BarrierPragma(args): &BarrierPragma) {
+    let args: Vec<String> = args.iter().map(argument).collect();
+    let _ = writeln!(source, "barrier {};", args.join(", "));
+}
+
+fn argument(argument: &Argument) -> String {
+    match argument {
+        Argument::Id(name) => name.to_string(),
+        Argument::Item(name, index) => format!("{}[{}]", name, index),
+    }
+}
+
+/// Return the source of `expression`, in parenthesis if its precedence is
+/// lower than `precedence`.
+fn expression(expression: &Expression, precedence: u8) -> String {
+    let (own_precedence, source) = match expression {
+        Expression::Pi => (4, String::from("pi")),
+        Expression::Id(name) => (4, name.clone()),
+        Expression::Real(value) => (4, value.to_string()),
+        Expression::Int(value) => (4, value.to_string()),
+        Expression::Op(opcode, left, right) => {
+            let (own_precedence, symbol) = match opcode {
+                OpCode::Add => (1, "+"),
+                OpCode::Sub => (1, "-"),
+                OpCode::Mul => (2, "*"),
+                OpCode::Div => (2, "/"),
+                OpCode::Pow => (3, "^"),
+            };
+            let left = self::expression(left, own_precedence);
+            let right = self::expression(right, own_precedence + 1);
+            (own_precedence, format!("{} {} {}", left, symbol, right))
+        }
+        Expression::Function(funccode, argument) => {
+            let name = match funccode {
+                FuncCode::Sin => "sin",
+                FuncCode::Cos => "cos",
+                FuncCode::Tan => "tan",
+                FuncCode::Exp => "exp",
+                FuncCode::Ln => "ln",
+                FuncCode::Sqrt => "sqrt",
+            };
+            (4, format!("{}({})", name, self::expression(argument, 0)))
+        }
+        Expression::Minus(operand) => (4, format!("-{}", self::expression(operand, 4))),
+    };
+    if own_precedence < precedence {
+        format!("({})", source)
+    } else {
+        source
+    }
+}
+
 /// Represent a OPENQASM library. OPENQASM libraries can contain gate
 /// declarations only.
 ///
@@ -105,6 +677,7 @@ pub struct OpenQasmProgram {
 /// ```
 /// use qasmsim::grammar::ast::{
 ///     OpenQasmLibrary,
+///     Span,
 ///     Statement,
 ///     GateOperation,
 ///     UnitaryOperation,
@@ -116,42 +689,48 @@ pub struct OpenQasmProgram {
 ///
 /// let library = OpenQasmLibrary {
 ///     definitions: vec![
-///         Statement::GateDecl {
-///             signature: (
-///                 "h".to_string(),
-///                 vec![],
-///                 vec!["q".to_string()],
-///                 vec![
-///                     GateOperation::Unitary(
-///                         UnitaryOperation(
-///                            "U".to_string(),
-///                             vec![
-///                                 Expression::Op(
-///                                     OpCode::Div,
-///                                     Box::new(Expression::Pi),
-///                                     Box::new(Expression::Int(2))
-///                                 ),
-///                                 Expression::Int(0),
-///                                 Expression::Pi
-///                             ],
-///                             vec![
-///                                 Argument::Item("q".to_string(), 0)
-///                             ]
-///                         )
-///                     )
-///                 ]
-///             ),
-///             docstring: None
+///         Span {
+///             boundaries: (Location(0), Location(37)),
+///             node: Box::new(Statement::GateDecl {
+///                 signature: (
+///                     "h".into(),
+///                     vec![],
+///                     vec!["q".into()],
+///                     vec![
+///                         GateOperation::Unitary(
+///                             UnitaryOperation(
+///                                "U".into(),
+///                                 vec![
+///                                     Expression::Op(
+///                                         OpCode::Div,
+///                                         Box::new(Expression::Pi.into()),
+///                                         Box::new(Expression::Int(2).into())
+///                                     ).into(),
+///                                     Expression::Int(0).into(),
+///                                     Expression::Pi.into()
+///                                 ],
+///                                 vec![
+///                                     Argument::Item("q".into(), 0)
+///                                 ]
+///                             )
+///                         ).into()
+///                     ]
+///                 ),
+///                 docstring: None
+///             }),
+///             origin: None
 ///         }
 ///     ]
 /// };
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 
 pub struct OpenQasmLibrary {
-    /// List of gate declarations. Although the type allows for the contruction
-    /// of a library with arbitrary statements, this would not constitute a
-    /// valid OPENQASM library and the linker would panic at runtime.
-    pub definitions: Vec<Statement>,
+    /// List of gate declarations, with their boundaries in the library
+    /// source. Although the type allows for the contruction of a library
+    /// with arbitrary statements, this would not constitute a valid OPENQASM
+    /// library and the linker would panic at runtime.
+    pub definitions: Vec<Span<Statement>>,
 }
 
 // TODO: This should not be part of the grammar. It is a directive for
@@ -174,7 +753,7 @@ pub struct OpenQasmLibrary {
 /// ```
 /// use qasmsim::grammar::ast::{BarrierPragma, Argument};
 ///
-/// let barrier = BarrierPragma(vec![Argument::Id("q".to_string())]);
+/// let barrier = BarrierPragma(vec![Argument::Id("q".into())]);
 /// ```
 ///
 /// A `BarrierPragma` cannot compound a valid [`OpenQasmProgram`]. It needs
@@ -182,6 +761,7 @@ pub struct OpenQasmLibrary {
 ///
 /// [`OpenQasmProgram`]: ./struct.OpenQasmProgram.html
 /// [`Statement`]: ./enum.Statement.html
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 
 pub struct BarrierPragma(pub Vec<Argument>);
@@ -206,7 +786,7 @@ pub struct BarrierPragma(pub Vec<Argument>);
 /// use qasmsim::grammar::ast::{Statement, BarrierPragma, Argument};
 ///
 /// let barrier_stmt = Statement::Barrier(
-///     BarrierPragma(vec![Argument::Id("q".to_string())])
+///     BarrierPragma(vec![Argument::Id("q".into())])
 /// );
 /// ```
 ///
@@ -216,31 +796,37 @@ pub struct BarrierPragma(pub Vec<Argument>);
 /// [span]: ./struct.Span.html
 /// [`OpenQasmProgram`]: ./struct.OpenQasmProgram.html
 #[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
-
+#[allow(clippy::type_complexity)]
 pub enum Statement {
     /// Quantum register declaration with name and size.
-    QRegDecl(String, usize),
+    QRegDecl(Spanned<String>, usize),
     /// Classical register declaration with name and size.
-    CRegDecl(String, usize),
+    CRegDecl(Spanned<String>, usize),
     /// Quantum gate declaration with signature and docstring, if any.
     GateDecl {
         /// The signature includes the name, list of formal real parameters,
         /// list of formal quantum registers, and a list of [`GateOperation`]
         /// representing the body of the gate.
-        signature: (String, Vec<String>, Vec<String>, Vec<GateOperation>),
+        signature: (
+            Spanned<String>,
+            Vec<Spanned<String>>,
+            Vec<Spanned<String>>,
+            Vec<Spanned<GateOperation>>,
+        ),
         /// A string representing documentation related to the gate.
         docstring: Option<String>,
     },
     /// Include statement for linking with gate libraries.
-    Include(String),
+    Include(Spanned<String>),
     /// A wrapper for the barrier pragma.
     Barrier(BarrierPragma),
     /// Opaque gate declaration with signature and docstring, if any.
     OpaqueGateDecl {
         /// The signature inlcudes the name and formal lists of real parameters
         /// and quantum registers. Opaque declarations have no body.
-        signature: (String, Vec<String>, Vec<String>),
+        signature: (Spanned<String>, Vec<Spanned<String>>, Vec<Spanned<String>>),
         /// A string representing documentation related to the gate.
         docstring: Option<String>,
     },
@@ -249,10 +835,16 @@ pub enum Statement {
     /// A wrapper for making a quantum operation to simulate just if certain
     /// equality condition holds. The wrapper takes the left-side of the
     /// comparison, the right side, and the operation to perform.
-    Conditional(Argument, u64, QuantumOperation),
+    Conditional(Argument, u128, QuantumOperation),
+    /// Same as [`Conditional`] but guarding a block of operations which are
+    /// applied in order if the condition holds. The condition is evaluated
+    /// once, before applying the first operation.
+    ///
+    /// [`Conditional`]: #variant.Conditional
+    ConditionalBlock(Argument, u128, Vec<Spanned<QuantumOperation>>),
 }
 
-/// Relates a node with the fragment of source code where the node appears.
+/// Relates a statement with the fragment of source code where it appears.
 ///
 /// # Examples
 ///
@@ -269,29 +861,183 @@ pub enum Statement {
 /// use qasmsim::grammar::ast::{Span, Statement};
 /// use qasmsim::grammar::lexer::Location;
 ///
-/// let barrier_span = Span {
+/// let qreg_span = Span {
 ///     boundaries: (Location(14), Location(25)),
 ///     node: Box::new(
 ///         Statement::QRegDecl(
-///             "q".to_string(),
-///             1
+///             "q".into(),
+///             10
 ///         )
-///     )
+///     ),
+///     origin: None
 /// };
 /// ```
 ///
 /// Boundaries run from characters 14 to 25 corresponding to the starting-0
 /// character index of the source code.
 ///
-/// Right, now, only statements are tied to spans making impossible to
-/// accurately localize inner AST nodes.
-#[derive(Debug, Clone, PartialEq)]
+/// The nodes inside the statement are localized with [`Spanned`]. For the
+/// statements the linker embeds from a library, the boundaries are those of
+/// the `include` statement and [`origin`] tells the library and the
+/// boundaries in the library source. The boundaries of the inner nodes of
+/// these statements refer to the library source too.
+///
+/// [`Spanned`]: ./struct.Spanned.html
+/// [`origin`]: #structfield.origin
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
 
 pub struct Span<S> {
     /// Pair of source locations where the AST node can be found.
     pub boundaries: (Location, Location),
     /// Boxed AST node.
     pub node: Box<S>,
+    /// The library the node comes from, if the linker embedded it from an
+    /// included library.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub origin: Option<Origin>,
+}
+
+/// The place in a library where a statement embedded by the linker is
+/// declared.
+///
+/// # Examples
+///
+/// ```
+/// let source = "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[1];";
+/// let program = qasmsim::parse_and_link(source)?;
+/// let first_gate = &program.program[0];
+/// assert_eq!(first_gate.boundaries.0.line_column(source), (2, 0));
+///
+/// let origin = first_gate.origin.as_ref().unwrap();
+/// assert_eq!(origin.libpath, "qelib1.inc");
+/// assert!(origin.boundaries.0 < origin.boundaries.1);
+/// assert_eq!(program.program.last().unwrap().origin, None);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+
+pub struct Origin {
+    /// Path of the library, as written in the `include` statement.
+    pub libpath: String,
+    /// Pair of locations in the library source where the statement can be
+    /// found.
+    pub boundaries: (Location, Location),
+}
+
+/// Relates a node inside a statement, such as an identifier, an expression,
+/// or an operation in a block, with the fragment of source code where the
+/// node appears.
+///
+/// Unlike [`Span`], the node is not boxed and two spanned nodes are equal if
+/// the nodes are equal, regardless of their boundaries. This way, ASTs built
+/// programmatically compare equal to the parsed ones with the same
+/// structure. Converting a node into a `Spanned` node with `into()` gives
+/// empty boundaries at the beginning of the source.
+///
+/// A spanned node dereferences to the node.
+///
+/// # Examples
+///
+/// In the following program:
+///
+/// ```qasm
+/// OPENQASM 2.0;
+/// qreg q[10];
+/// ```
+///
+/// The name of the register is:
+///
+/// ```
+/// use qasmsim::grammar::ast::Spanned;
+/// use qasmsim::grammar::lexer::Location;
+///
+/// let name = Spanned {
+///     boundaries: (Location(19), Location(20)),
+///     node: "q".to_string()
+/// };
+/// assert_eq!(name, "q");
+/// assert_eq!(name.len(), 1);
+/// ```
+///
+/// [`Span`]: ./struct.Span.html
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Default)]
+
+pub struct Spanned<T> {
+    /// Pair of source locations where the AST node can be found.
+    pub boundaries: (Location, Location),
+    /// AST node.
+    pub node: T,
+}
+
+impl<T> Spanned<T> {
+    /// Return the node discarding its boundaries.
+    pub fn into_node(self) -> T {
+        self.node
+    }
+}
+
+impl<T> From<T> for Spanned<T> {
+    fn from(node: T) -> Self {
+        Spanned {
+            boundaries: (Location::new(), Location::new()),
+            node,
+        }
+    }
+}
+
+impl From<&str> for Spanned<String> {
+    fn from(node: &str) -> Self {
+        Spanned::from(node.to_owned())
+    }
+}
+
+impl<T> ops::Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+impl Borrow<str> for Spanned<String> {
+    fn borrow(&self) -> &str {
+        &self.node
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+impl<T: Eq> Eq for Spanned<T> {}
+
+impl PartialEq<str> for Spanned<String> {
+    fn eq(&self, other: &str) -> bool {
+        self.node == other
+    }
+}
+
+impl PartialEq<&str> for Spanned<String> {
+    fn eq(&self, other: &&str) -> bool {
+        self.node == *other
+    }
+}
+
+impl<T: Hash> Hash for Spanned<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.node.hash(state)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Spanned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.node.fmt(f)
+    }
 }
 
 /// Any of the statements that can appear inside a gate definition.
@@ -305,6 +1051,7 @@ pub struct Span<S> {
 ///
 /// [`OpenQasmLibrary`]: ./struct.OpenQasmLibrary.html
 #[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 
 pub enum GateOperation {
@@ -322,6 +1069,7 @@ pub enum GateOperation {
 ///
 /// [`OpenQasmProgram`]: ./struct.OpenQasmProgram.html
 #[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 
 pub enum QuantumOperation {
@@ -345,9 +1093,14 @@ pub enum QuantumOperation {
 ///
 /// [`OpenQasmProgram`]: ./struct.OpenQasmProgram.html
 /// [unitary]: https://en.wikipedia.org/wiki/Unitary_operator
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 
-pub struct UnitaryOperation(pub String, pub Vec<Expression>, pub Vec<Argument>);
+pub struct UnitaryOperation(
+    pub Spanned<String>,
+    pub Vec<Spanned<Expression>>,
+    pub Vec<Argument>,
+);
 
 /// Any of the operators that can appear in an expression.
 ///
@@ -358,6 +1111,7 @@ pub struct UnitaryOperation(pub String, pub Vec<Expression>, pub Vec<Argument>);
 ///
 /// [`OpenQasmLibrary`]: ./struct.OpenQasmLibrary.html
 #[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 
 pub enum OpCode {
@@ -375,6 +1129,7 @@ pub enum OpCode {
 
 /// Any of the functions that can appear in an expression.
 #[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 
 pub enum FuncCode {
@@ -400,6 +1155,7 @@ pub enum FuncCode {
 ///
 /// [`OpenQasmLibrary`]: ./struct.OpenQasmLibrary.html
 #[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 
 pub enum Expression {
@@ -412,11 +1168,11 @@ pub enum Expression {
     /// An integer number.
     Int(u64),
     /// A binary operation.
-    Op(OpCode, Box<Expression>, Box<Expression>),
+    Op(OpCode, Box<Spanned<Expression>>, Box<Spanned<Expression>>),
     /// A call to a function.
-    Function(FuncCode, Box<Expression>),
+    Function(FuncCode, Box<Spanned<Expression>>),
     /// A negation of an expression.
-    Minus(Box<Expression>),
+    Minus(Box<Spanned<Expression>>),
 }
 
 /// A reference to a register or register component.
@@ -436,18 +1192,19 @@ pub enum Expression {
 /// use qasmsim::grammar::ast::{BarrierPragma, Argument};
 ///
 /// let on_the_whole_register = BarrierPragma(
-///     vec![Argument::Id("q".to_string())]
+///     vec![Argument::Id("q".into())]
 /// );
 /// let on_the_first_qubit = BarrierPragma(
-///     vec![Argument::Item("q".to_string(), 0)]
+///     vec![Argument::Item("q".into(), 0)]
 /// );
 /// ```
 #[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 
 pub enum Argument {
     /// An entire register like `q`.
-    Id(String),
+    Id(Spanned<String>),
     /// One of the bits/qubits of a register `q[0]`.
-    Item(String, usize),
+    Item(Spanned<String>, usize),
 }
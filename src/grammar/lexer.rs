@@ -28,6 +28,7 @@ use regex::Regex;
 ///
 /// Location::new_at(19);
 /// ```
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
 
 pub struct Location(pub usize);
@@ -42,6 +43,31 @@ impl Location {
     pub fn new_at(position: usize) -> Self {
         Location(position)
     }
+
+    /// Return the line number (1-based) and the position inside the line
+    /// (0-based) of the location in `source`.
+    ///
+    /// Locations past the end of `source` are taken as its end, and locations
+    /// inside a multi-byte character as the start of the character.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::grammar::lexer::Location;
+    ///
+    /// let source = "OPENQASM 2.0;\nqreg q[2];";
+    /// assert_eq!(Location(19).line_column(source), (2, 5));
+    /// ```
+    pub fn line_column(&self, source: &str) -> (usize, usize) {
+        let mut end = self.0.min(source.len());
+        while !source.is_char_boundary(end) {
+            end -= 1;
+        }
+        let preceding = &source[..end];
+        let lineno = preceding.matches('\n').count() + 1;
+        let linestart = preceding.rfind('\n').map_or(0, |index| index + 1);
+        (lineno, preceding[linestart..].chars().count())
+    }
 }
 
 impl fmt::Display for Location {
@@ -164,6 +190,17 @@ pub enum Tok {
     Barrier,
     /// The key-word `if`.
     If,
+    /// The assignment symbol `=` (OPENQASM 3 only).
+    Assign,
+    /// The key-word `qubit` (OPENQASM 3 only).
+    Qubit,
+    /// The key-word `bit` (OPENQASM 3 only).
+    Bit,
+    /// An OPENQASM 3 key-word for a feature not supported yet, such as `for`.
+    Unsupported {
+        /// The key-word as it appears in the source code.
+        repr: String,
+    },
     /// The QASM header `OPENQASM`.
     QASMHeader,
     /// The version of OPENQASM as `X.Y`.
@@ -178,7 +215,8 @@ pub enum Tok {
         /// Identifier value.
         repr: String,
     },
-    /// An integer number.
+    /// An integer number, in decimal, hexadecimal (`0x` prefix) or binary
+    /// (`0b` prefix) notation.
     Int {
         /// String representation of the interger as it appears in the
         /// source code.
@@ -269,6 +307,10 @@ impl fmt::Display for Tok {
             Tok::Reset => "keyword `reset`".into(),
             Tok::Barrier => "keyword `barrier`".into(),
             Tok::If => "keyword `if`".into(),
+            Tok::Assign => "=".into(),
+            Tok::Qubit => "keyword `qubit`".into(),
+            Tok::Bit => "keyword `bit`".into(),
+            Tok::Unsupported { repr } => format!("keyword `{}`", &repr),
             Tok::QASMHeader => "qasm header `OPENQASM`".into(),
             Tok::Version { repr } => format!("open qasm version `{}`", &repr),
             Tok::Id { repr } => format!("identifier `{}`", &repr),
@@ -302,6 +344,86 @@ fn keywords() -> HashMap<String, Tok> {
     kw
 }
 
+/// Return the value of the integer literal `repr`, taking into account the
+/// `0x` and `0b` prefixes.
+pub(crate) fn int_value(repr: &str) -> Result<u128, std::num::ParseIntError> {
+    if let Some(digits) = repr.strip_prefix("0x") {
+        u128::from_str_radix(digits, 16)
+    } else if let Some(digits) = repr.strip_prefix("0b") {
+        u128::from_str_radix(digits, 2)
+    } else {
+        repr.parse()
+    }
+}
+
+/// Return the value of the integer literal `repr`, spanning from `left` to
+/// `right`, or a parsing error for the literal if the value does not fit in
+/// a `T`.
+///
+/// The error is an unrecognized `Tok::Int` token with no expected tokens,
+/// which is reported as [`QasmSimError::IntegerOutOfRange`].
+///
+/// [`QasmSimError::IntegerOutOfRange`]: ../../error/enum.QasmSimError.html#variant.IntegerOutOfRange
+pub(crate) fn int_literal<T: TryFrom<u128>>(
+    left: Location,
+    repr: String,
+    right: Location,
+) -> Result<T, lalrpop_util::ParseError<Location, Tok, LexicalError<Location>>> {
+    match int_value(&repr)
+        .ok()
+        .and_then(|value| T::try_from(value).ok())
+    {
+        Some(value) => Ok(value),
+        None => Err(lalrpop_util::ParseError::UnrecognizedToken {
+            token: (left, Tok::Int { repr }, right),
+            expected: Vec::new(),
+        }),
+    }
+}
+
+/// OPENQASM 3 key-words for features not supported yet.
+const UNSUPPORTED_OPENQASM3_KEYWORDS: &[&str] = &[
+    "for", "while", "def", "defcal", "return", "break", "continue", "switch", "let", "const",
+    "input", "output", "extern", "box", "delay", "int", "uint", "float", "angle", "bool",
+    "complex", "array",
+];
+
+/// Add the OPENQASM 3 key-words to `keywords`.
+fn add_openqasm3_keywords(keywords: &mut HashMap<String, Tok>) {
+    keywords.insert(String::from("qubit"), Tok::Qubit);
+    keywords.insert(String::from("bit"), Tok::Bit);
+    for keyword in UNSUPPORTED_OPENQASM3_KEYWORDS {
+        keywords.insert(
+            String::from(*keyword),
+            Tok::Unsupported {
+                repr: String::from(*keyword),
+            },
+        );
+    }
+}
+
+/// Return the documentation in the `content` of a block comment, with the
+/// leading `*` decoration of each line removed, in the same format as the
+/// documentation extracted from line comments.
+fn block_comment_docstring(content: &str) -> String {
+    let lines: Vec<&str> = content
+        .lines()
+        .map(|line| {
+            let undecorated = line.trim_start().strip_prefix('*').unwrap_or(line);
+            undecorated.trim_end()
+        })
+        .collect();
+    let first = lines.iter().position(|line| !line.is_empty());
+    let last = lines.iter().rposition(|line| !line.is_empty());
+    match (first, last) {
+        (Some(first), Some(last)) => lines[first..=last]
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect(),
+        _ => String::new(),
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum Mode {
     Base,
@@ -323,6 +445,8 @@ pub(crate) struct Lexer<'input> {
     errored: bool,
     docstring: Option<(Location, String, Location)>,
     delayed_token: Option<(Location, Tok, Location)>,
+    lenient: bool,
+    openqasm3: bool,
 }
 
 impl<'input> Lexer<'input> {
@@ -338,6 +462,17 @@ impl<'input> Lexer<'input> {
             errored: false,
             docstring: None,
             delayed_token: None,
+            lenient: false,
+            openqasm3: false,
+        }
+    }
+
+    /// Same as `new()` but the lexer also accepts nonstandard tokens such as
+    /// paths between angle brackets (`<qelib1.inc>`), emitted as strings.
+    pub fn new_lenient(input: &'input str) -> Self {
+        Lexer {
+            lenient: true,
+            ..Lexer::new(input)
         }
     }
 
@@ -387,10 +522,17 @@ impl<'input> Lexer<'input> {
     }
 
     fn advance_offset(&mut self, count: usize) {
-        for _ in 0..count {
+        self.skip_to(self.offset + count);
+    }
+
+    fn skip_to(&mut self, offset: usize) {
+        while let Some((index, _)) = self.chars.peek() {
+            if *index >= offset {
+                break;
+            }
             self.chars.next();
         }
-        self.offset += count;
+        self.offset = offset;
     }
 
     fn location(&self, offset: usize) -> Location {
@@ -410,17 +552,21 @@ impl<'input> Iterator for Lexer<'input> {
     #[allow(clippy::trivial_regex)]
     fn next(&mut self) -> Option<Self::Item> {
         lazy_static! {
-            static ref NEW_LINE: Regex = Regex::new(r"^\n").unwrap();
+            static ref NEW_LINE: Regex = Regex::new(r"^\r?\n").unwrap();
             static ref ALL_THE_LINE: Regex = Regex::new(r"^[^\n]*").unwrap();
             // TODO: Should be \s - \n, this will not match other forms of Unicode blank space.
-            static ref BLANK: Regex = Regex::new(r"^[ \t]+").unwrap();
+            static ref BLANK: Regex = Regex::new(r"^[ \t\r]+").unwrap();
+            static ref BLOCK_COMMENT_START: Regex = Regex::new(r"^/\*").unwrap();
             static ref GATE: Regex = Regex::new(r"^(CX|U)\b").unwrap();
             static ref OPENQASM: Regex = Regex::new(r"^OPENQASM\b").unwrap();
-            static ref VERSION: Regex = Regex::new(r"^([0-9]+\.[0-9]+)").unwrap();
+            static ref VERSION: Regex = Regex::new(r"^([0-9]+\.[0-9]+|3)").unwrap();
             static ref ID: Regex = Regex::new(r"^([a-z][A-Za-z0-9_]*)").unwrap();
-            static ref INTEGER: Regex = Regex::new(r"^([1-9]+[0-9]*|0)").unwrap();
+            static ref INTEGER: Regex =
+                Regex::new(r"^(0x[0-9A-Fa-f]+|0b[01]+|[1-9]+[0-9]*|0)").unwrap();
             static ref REAL: Regex =
                 Regex::new(r"^([0-9]+\.[0-9]*|[0-9]*\.[0-9]+)([eE][+-]?([0-9]+))?").unwrap();
+            static ref ASSIGN: Regex = Regex::new(r"^=").unwrap();
+            static ref ANGLE_STRING: Regex = Regex::new(r"^<[^>\n]*>").unwrap();
             static ref SYMBOL: Regex = Regex::new(r"^(->|==|//|[+\-\*/\^\[\]\{\}\(\);,])").unwrap();
         }
 
@@ -432,7 +578,7 @@ impl<'input> Iterator for Lexer<'input> {
             // it happens when emitting `DocStr` and `Gate`.
 
             // #[mode(EmitDelayedToken)]
-            match self.mode.get(0) {
+            match self.mode.front() {
                 Some(Mode::EmitDelayedToken) => {
                     if self.delayed_token.is_none() {
                         unreachable!("Trying to return a non existend delayed gate.");
@@ -455,15 +601,15 @@ impl<'input> Iterator for Lexer<'input> {
             // TODO: Finally they come the regular lexer actions per active mode.
 
             // TODO: Should transform this into
-            // `match self.mode.get(0) { ... }` to generalize the stacked lexer
+            // `match self.mode.front() { ... }` to generalize the stacked lexer
             // structure and start recognizing syntax patterns to extract into
             // macros.
             if let Some(new_line) = self.try_pattern(&NEW_LINE) {
                 self.lineno += 1;
                 self.lineoffset = self.offset;
-                match self.mode.get(0) {
+                match self.mode.front() {
                     Some(Mode::Comment) => {
-                        self.extend_docstring(&new_line);
+                        self.extend_docstring("\n");
                         self.update_docstring_end(self.location(start + new_line.len()));
                         self.mode.pop_front();
                     }
@@ -475,7 +621,7 @@ impl<'input> Iterator for Lexer<'input> {
             }
 
             // #[modes(Base, Version)]
-            match self.mode.get(0) {
+            match self.mode.front() {
                 Some(Mode::Base) | Some(Mode::Version) => {
                     if let Some(_blank) = self.try_pattern(&BLANK) {
                         continue;
@@ -485,7 +631,7 @@ impl<'input> Iterator for Lexer<'input> {
             }
 
             // #[modes(Base)]
-            match self.mode.get(0) {
+            match self.mode.front() {
                 Some(Mode::Base) => {
                     if let Some((_, c)) = self.chars.peek() {
                         if *c == '"' {
@@ -494,12 +640,24 @@ impl<'input> Iterator for Lexer<'input> {
                             continue;
                         }
                     }
+                    if self.lenient {
+                        if let Some(repr) = self.try_pattern(&ANGLE_STRING) {
+                            let end = start + repr.len();
+                            return Some(Ok((
+                                self.location(start),
+                                Tok::Str {
+                                    repr: String::from(&repr[1..repr.len() - 1]),
+                                },
+                                self.location(end),
+                            )));
+                        }
+                    }
                 }
                 _ => (),
             }
 
             // #[modes(Str)]
-            match self.mode.get(0) {
+            match self.mode.front() {
                 Some(Mode::Str) => {
                     loop {
                         match self.chars.next() {
@@ -529,10 +687,10 @@ impl<'input> Iterator for Lexer<'input> {
             }
 
             // #[modes(Comment)]
-            match self.mode.get(0) {
+            match self.mode.front() {
                 Some(Mode::Comment) => {
                     if let Some(content) = self.try_pattern(&ALL_THE_LINE) {
-                        self.extend_docstring(&content);
+                        self.extend_docstring(content.strip_suffix('\r').unwrap_or(&content));
                         self.update_docstring_end(self.location(start + content.len()));
                         continue;
                     }
@@ -540,6 +698,42 @@ impl<'input> Iterator for Lexer<'input> {
                 _ => (),
             }
 
+            // #[modes(Base, Version)]
+            match self.mode.front() {
+                Some(Mode::Base) | Some(Mode::Version) => {
+                    if let Some(_comment_start) = self.try_pattern(&BLOCK_COMMENT_START) {
+                        let content_start = self.offset;
+                        let content_end = match self.input[content_start..].find("*/") {
+                            None => {
+                                self.errored = true;
+                                return Some(Err(LexicalError {
+                                    location: self.location(start),
+                                }));
+                            }
+                            Some(length) => content_start + length,
+                        };
+                        let content = &self.input[content_start..content_end];
+                        self.lineno += content.matches('\n').count();
+                        self.skip_to(content_end + 2);
+                        let mut end = self.offset;
+                        // A line break right after the comment belongs to the
+                        // comment so it does not interrupt the docstring.
+                        if let Some(new_line) = self.try_pattern(&NEW_LINE) {
+                            self.lineno += 1;
+                            self.lineoffset = self.offset;
+                            end += new_line.len();
+                        }
+                        if !self.is_building_docstring() {
+                            self.start_docstring(self.location(start));
+                        }
+                        self.extend_docstring(&block_comment_docstring(content));
+                        self.update_docstring_end(self.location(end));
+                        continue;
+                    }
+                }
+                _ => (),
+            }
+
             // #[modes(all)]
             if let Some(repr) = self.try_pattern(&OPENQASM) {
                 self.mode.push_front(Mode::Version);
@@ -590,7 +784,7 @@ impl<'input> Iterator for Lexer<'input> {
             }
 
             // #[modes(Base)]
-            match self.mode.get(0) {
+            match self.mode.front() {
                 Some(Mode::Base) => {
                     if let Some(repr) = self.try_pattern(&REAL) {
                         let end = start + repr.len();
@@ -605,11 +799,15 @@ impl<'input> Iterator for Lexer<'input> {
             }
 
             // #[modes(Version)]
-            match self.mode.get(0) {
+            match self.mode.front() {
                 Some(Mode::Version) => {
                     if let Some(repr) = self.try_pattern(&VERSION) {
                         let end = start + repr.len();
                         self.mode.pop_front();
+                        if repr == "3" || repr.starts_with("3.") {
+                            self.openqasm3 = true;
+                            add_openqasm3_keywords(&mut self.keywords);
+                        }
                         return Some(Ok((
                             self.location(start),
                             Tok::Version { repr },
@@ -650,6 +848,13 @@ impl<'input> Iterator for Lexer<'input> {
                     "->" => Tok::Arrow,
                     "==" => Tok::Equal,
                     "//" => {
+                        // A comment following other tokens in the same line
+                        // does not document what comes next.
+                        if !self.input[self.lineoffset..start].trim().is_empty() {
+                            self.flush_docstring();
+                            self.try_pattern(&ALL_THE_LINE);
+                            continue;
+                        }
                         if !self.is_building_docstring() {
                             self.start_docstring(self.location(start));
                         }
@@ -661,6 +866,14 @@ impl<'input> Iterator for Lexer<'input> {
                 return Some(Ok((self.location(start), token, self.location(end))));
             }
 
+            // #[modes(all)]
+            if self.openqasm3 {
+                if let Some(symbol) = self.try_pattern(&ASSIGN) {
+                    let end = start + symbol.len();
+                    return Some(Ok((self.location(start), Tok::Assign, self.location(end))));
+                }
+            }
+
             self.errored = true;
             return Some(Err(LexicalError {
                 location: self.location(start),
@@ -740,6 +953,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_prefixed_integer_literals() {
+        let source = "0xFF 0b101 0x";
+        let lexer = Lexer::new(source);
+        assert_eq!(
+            lexer.collect::<Vec<_>>(),
+            vec![
+                Ok((
+                    Location(0),
+                    Tok::Int {
+                        repr: String::from("0xFF")
+                    },
+                    Location(4)
+                )),
+                Ok((
+                    Location(5),
+                    Tok::Int {
+                        repr: String::from("0b101")
+                    },
+                    Location(10)
+                )),
+                Ok((
+                    Location(11),
+                    Tok::Int {
+                        repr: String::from("0")
+                    },
+                    Location(12)
+                )),
+                Ok((
+                    Location(12),
+                    Tok::Id {
+                        repr: String::from("x")
+                    },
+                    Location(13)
+                )),
+            ]
+        );
+        assert_eq!(int_value("0xFF"), Ok(255));
+        assert_eq!(int_value("0b101"), Ok(5));
+        assert_eq!(int_value("42"), Ok(42));
+    }
+
+    #[test]
+    fn test_angle_brackets_only_in_lenient_mode() {
+        let source = "<qelib1.inc>";
+        assert_eq!(
+            Lexer::new_lenient(source).collect::<Vec<_>>(),
+            vec![Ok((
+                Location(0),
+                Tok::Str {
+                    repr: String::from("qelib1.inc")
+                },
+                Location(12)
+            ))]
+        );
+        assert_eq!(
+            Lexer::new(source).collect::<Vec<_>>(),
+            vec![Err(LexicalError::new_at(Location(0)))]
+        );
+    }
+
     #[test]
     fn test_some_blankspace() {
         let source = "
@@ -924,6 +1198,114 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_trailing_comments_are_not_docstring() {
+        let source = "q; // Not documentation\ngate";
+        let lexer = Lexer::new(source);
+        assert_eq!(
+            lexer.collect::<Vec<_>>(),
+            vec![
+                Ok((
+                    Location(0),
+                    Tok::Id {
+                        repr: String::from("q")
+                    },
+                    Location(1)
+                )),
+                Ok((Location(1), Tok::Semi, Location(2))),
+                Ok((Location(24), Tok::Gate, Location(28)))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_line_column() {
+        let source = "OPENQASM 2.0;\r\nqreg q[2];\nU(0, 0, 0) q[0];";
+        assert_eq!(Location(0).line_column(source), (1, 0));
+        assert_eq!(Location(12).line_column(source), (1, 12));
+        assert_eq!(Location(15).line_column(source), (2, 0));
+        assert_eq!(Location(37).line_column(source), (3, 11));
+    }
+
+    #[test]
+    fn test_line_column_inside_a_multibyte_character() {
+        let source = "// π/2\nh q;";
+        assert_eq!(Location(4).line_column(source), (1, 3));
+        assert_eq!(Location(5).line_column(source), (1, 4));
+        assert_eq!(Location(6).line_column(source), (1, 5));
+        assert_eq!(Location(100).line_column(source), (2, 4));
+    }
+
+    #[test]
+    fn test_crlf_line_endings() {
+        let source = "// Documentation of the\r\n// id gate\r\ngate\r\nid";
+        let lexer = Lexer::new(source);
+        assert_eq!(
+            lexer.collect::<Vec<_>>(),
+            vec![
+                Ok((
+                    Location(0),
+                    Tok::DocStr {
+                        repr: String::from(" Documentation of the\n id gate\n")
+                    },
+                    Location(37)
+                )),
+                Ok((Location(37), Tok::Gate, Location(41))),
+                Ok((Location(43), Tok::Id { repr: "id".into() }, Location(45)))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_comments_are_skipped() {
+        let source = "a /* b /* c */ d /*\n\n*/e";
+        let mut lexer = Lexer::new(source);
+        let tokens: Vec<_> = lexer.by_ref().collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Ok((Location(0), Tok::Id { repr: "a".into() }, Location(1))),
+                Ok((Location(15), Tok::Id { repr: "d".into() }, Location(16))),
+                Ok((Location(23), Tok::Id { repr: "e".into() }, Location(24)))
+            ]
+        );
+        assert_eq!(lexer.lineno, 3);
+    }
+
+    #[test]
+    fn test_block_comments_right_before_gate_token_are_docstring() {
+        let source = "/*\n * Documentation of the\n * id gate\n */\ngate";
+        let lexer = Lexer::new(source);
+        assert_eq!(
+            lexer.collect::<Vec<_>>(),
+            vec![
+                Ok((
+                    Location(0),
+                    Tok::DocStr {
+                        repr: String::from(" Documentation of the\n id gate\n")
+                    },
+                    Location(42)
+                )),
+                Ok((Location(42), Tok::Gate, Location(46)))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let source = "a /* b\n c";
+        let lexer = Lexer::new(source);
+        assert_eq!(
+            lexer.collect::<Vec<_>>(),
+            vec![
+                Ok((Location(0), Tok::Id { repr: "a".into() }, Location(1))),
+                Err(LexicalError {
+                    location: Location(2)
+                })
+            ]
+        );
+    }
+
     mod regressions {
         use super::*;
 
@@ -0,0 +1,329 @@
+//! Style checks for OPENQASM programs.
+//!
+//! Lints are not errors: a program with lints is still valid and can be
+//! simulated. They point out fragments of code that are likely to be
+//! mistakes or unnecessary.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::grammar::ast::{
+    Argument, BarrierPragma, OpenQasmProgram, QuantumOperation, Span, Statement, UnitaryOperation,
+};
+use crate::interpreter::expression_solver::ExpressionSolver;
+
+/// How important a [`Lint`] is.
+///
+/// [`Lint`]: ./struct.Lint.html
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+
+pub enum LintSeverity {
+    /// The code is likely a mistake.
+    Warning,
+    /// The code is correct but can be simplified.
+    Info,
+}
+
+/// A style issue found in a program.
+#[derive(Debug, Clone, PartialEq)]
+
+pub struct Lint {
+    /// How important the issue is.
+    pub severity: LintSeverity,
+    /// A human-readable description of the issue.
+    pub message: String,
+    /// The statement where the issue was found.
+    pub span: Span<Statement>,
+}
+
+/// Check `program` for style issues and return them in the order they appear
+/// in the source code.
+///
+/// The checks are:
+///
+/// - Quantum and classical registers declared but never used (warning).
+/// - `U(0, 0, 0)` gates, which amount to the identity (info).
+/// - Measurements of qubits on which no operation has been applied yet
+///   (warning).
+///
+/// # Examples
+///
+/// ```
+/// # use qasmsim::QasmSimError;
+/// use qasmsim::grammar::{lint::{lint, LintSeverity}, parse_program};
+///
+/// let program = parse_program(r"
+/// OPENQASM 2.0;
+/// qreg q[1];
+/// qreg unused[1];
+/// U(pi, 0, pi) q[0];
+/// ")?;
+///
+/// let lints = lint(&program);
+/// assert_eq!(lints.len(), 1);
+/// assert_eq!(lints[0].severity, LintSeverity::Warning);
+/// assert_eq!(lints[0].message, "quantum register `unused` is never used");
+/// # Ok::<(), QasmSimError>(())
+/// ```
+pub fn lint(program: &OpenQasmProgram) -> Vec<Lint> {
+    let mut linter = Linter::default();
+    for span in &program.program {
+        linter.check_statement(span);
+    }
+    linter.check_unused_registers();
+    linter.lints.sort_by_key(|lint| lint.span.boundaries.0);
+    linter.lints
+}
+
+#[derive(Debug, Default)]
+struct Linter<'program> {
+    lints: Vec<Lint>,
+    declarations: Vec<(&'program Span<Statement>, &'program str, bool)>,
+    used: HashSet<&'program str>,
+    operated_registers: HashSet<&'program str>,
+    operated_qubits: HashMap<&'program str, HashSet<usize>>,
+}
+
+impl<'program> Linter<'program> {
+    fn check_statement(&mut self, span: &'program Span<Statement>) {
+        match &*span.node {
+            Statement::QRegDecl(name, _) => self.declarations.push((span, name, true)),
+            Statement::CRegDecl(name, _) => self.declarations.push((span, name, false)),
+            Statement::Barrier(BarrierPragma(arguments)) => self.use_arguments(arguments),
+            Statement::QuantumOperation(operation) => self.check_operation(span, operation),
+            Statement::Conditional(register, _, operation) => {
+                self.use_arguments(std::slice::from_ref(register));
+                self.check_operation(span, operation);
+            }
+            Statement::ConditionalBlock(register, _, operations) => {
+                self.use_arguments(std::slice::from_ref(register));
+                for operation in operations {
+                    self.check_operation(span, operation);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn check_operation(
+        &mut self,
+        span: &'program Span<Statement>,
+        operation: &'program QuantumOperation,
+    ) {
+        match operation {
+            QuantumOperation::Unitary(unitary) => {
+                if is_trivial_identity(unitary) {
+                    self.push(
+                        span,
+                        LintSeverity::Info,
+                        "`U(0, 0, 0)` is the identity and can be removed".to_string(),
+                    );
+                }
+                self.use_arguments(&unitary.2);
+                self.operate_on(&unitary.2);
+            }
+            QuantumOperation::Reset(target) => {
+                let target = std::slice::from_ref(target);
+                self.use_arguments(target);
+                self.operate_on(target);
+            }
+            QuantumOperation::Measure(source, target) => {
+                if !self.was_operated(source) {
+                    self.push(
+                        span,
+                        LintSeverity::Warning,
+                        format!(
+                            "`{}` is measured before applying any operation on it",
+                            argument_repr(source)
+                        ),
+                    );
+                }
+                self.use_arguments(std::slice::from_ref(source));
+                self.use_arguments(std::slice::from_ref(target));
+            }
+        }
+    }
+
+    fn check_unused_registers(&mut self) {
+        let unused: Vec<_> = self
+            .declarations
+            .iter()
+            .filter(|(_, name, _)| !self.used.contains(name))
+            .map(|(span, name, is_quantum)| {
+                let kind = if *is_quantum { "quantum" } else { "classical" };
+                (*span, format!("{} register `{}` is never used", kind, name))
+            })
+            .collect();
+        for (span, message) in unused {
+            self.push(span, LintSeverity::Warning, message);
+        }
+    }
+
+    fn use_arguments(&mut self, arguments: &'program [Argument]) {
+        for argument in arguments {
+            self.used.insert(register_name(argument));
+        }
+    }
+
+    fn operate_on(&mut self, arguments: &'program [Argument]) {
+        for argument in arguments {
+            match argument {
+                Argument::Id(name) => {
+                    self.operated_registers.insert(name);
+                }
+                Argument::Item(name, index) => {
+                    self.operated_qubits.entry(name).or_default().insert(*index);
+                }
+            }
+        }
+    }
+
+    fn was_operated(&self, argument: &Argument) -> bool {
+        let name = register_name(argument);
+        if self.operated_registers.contains(name) {
+            return true;
+        }
+        match (argument, self.operated_qubits.get(name)) {
+            (_, None) => false,
+            (Argument::Id(_), Some(indices)) => !indices.is_empty(),
+            (Argument::Item(_, index), Some(indices)) => indices.contains(index),
+        }
+    }
+
+    fn push(&mut self, span: &Span<Statement>, severity: LintSeverity, message: String) {
+        self.lints.push(Lint {
+            severity,
+            message,
+            span: span.clone(),
+        });
+    }
+}
+
+fn is_trivial_identity(unitary: &UnitaryOperation) -> bool {
+    if unitary.0 != "U" {
+        return false;
+    }
+    let bindings = HashMap::new();
+    let solver = ExpressionSolver::new(&bindings);
+    unitary
+        .1
+        .iter()
+        .all(|expression| matches!(solver.solve(expression), Ok(value) if value == 0.0))
+}
+
+fn register_name(argument: &Argument) -> &str {
+    match argument {
+        Argument::Id(name) => name,
+        Argument::Item(name, _) => name,
+    }
+}
+
+fn argument_repr(argument: &Argument) -> String {
+    match argument {
+        Argument::Id(name) => name.to_string(),
+        Argument::Item(name, index) => format!("{}[{}]", name, index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use crate::grammar::parse_program;
+
+    use super::*;
+
+    fn messages(source: &str) -> Vec<(LintSeverity, String)> {
+        let program = parse_program(source).unwrap();
+        lint(&program)
+            .into_iter()
+            .map(|lint| (lint.severity, lint.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_program_has_no_lints() {
+        let source = indoc!(
+            "
+        OPENQASM 2.0;
+        qreg q[2];
+        creg c[2];
+        U(pi/2, 0, pi) q[0];
+        CX q[0], q[1];
+        measure q -> c;
+        "
+        );
+        assert_eq!(messages(source), vec![]);
+    }
+
+    #[test]
+    fn test_unused_registers() {
+        let source = indoc!(
+            "
+        OPENQASM 2.0;
+        qreg q[1];
+        creg c[1];
+        "
+        );
+        assert_eq!(
+            messages(source),
+            vec![
+                (
+                    LintSeverity::Warning,
+                    "quantum register `q` is never used".to_string()
+                ),
+                (
+                    LintSeverity::Warning,
+                    "classical register `c` is never used".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trivial_identity() {
+        let source = indoc!(
+            "
+        OPENQASM 2.0;
+        qreg q[1];
+        U(0, 0, 0) q[0];
+        U(0, 0, pi - pi) q[0];
+        U(0, 0, pi) q[0];
+        "
+        );
+        assert_eq!(
+            messages(source),
+            vec![
+                (
+                    LintSeverity::Info,
+                    "`U(0, 0, 0)` is the identity and can be removed".to_string()
+                ),
+                (
+                    LintSeverity::Info,
+                    "`U(0, 0, 0)` is the identity and can be removed".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_measurement_without_operations() {
+        let source = indoc!(
+            "
+        OPENQASM 2.0;
+        qreg q[2];
+        creg c[2];
+        U(pi, 0, pi) q[0];
+        measure q[0] -> c[0];
+        measure q[1] -> c[1];
+        "
+        );
+        assert_eq!(
+            messages(source),
+            vec![(
+                LintSeverity::Warning,
+                "`q[1]` is measured before applying any operation on it".to_string()
+            )]
+        );
+    }
+}
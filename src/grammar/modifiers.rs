@@ -0,0 +1,334 @@
+use crate::error::{extract_line, QasmSimError};
+
+/// Gate modifiers of OPENQASM 3.
+const MODIFIERS: [&str; 4] = ["ctrl", "inv", "negctrl", "pow"];
+
+/// Gates from `qelib1.inc` which are their own inverses.
+const SELF_INVERSE_GATES: [&str; 14] = [
+    "id", "x", "y", "z", "h", "CX", "cx", "cy", "cz", "ch", "swap", "ccx", "cswap", "u0",
+];
+
+/// Pairs of gates from `qelib1.inc` which are the inverse of each other.
+const INVERSE_GATE_PAIRS: [(&str, &str); 2] = [("s", "sdg"), ("t", "tdg")];
+
+/// Gates from `qelib1.inc` whose inverse is the same gate with all the
+/// parameters negated.
+const NEGATED_PARAMETER_GATES: [&str; 9] =
+    ["u1", "rx", "ry", "rz", "crx", "cry", "crz", "cu1", "rzz"];
+
+/// Gates from `qelib1.inc` with a controlled version in `qelib1.inc`.
+const CONTROLLED_GATES: [(&str, &str); 11] = [
+    ("x", "cx"),
+    ("y", "cy"),
+    ("z", "cz"),
+    ("h", "ch"),
+    ("rx", "crx"),
+    ("ry", "cry"),
+    ("rz", "crz"),
+    ("u1", "cu1"),
+    ("u3", "cu3"),
+    ("cx", "ccx"),
+    ("swap", "cswap"),
+];
+
+/// Rewrite the OPENQASM 3 `ctrl @` and `inv @` gate modifiers in `source`
+/// into applications of the equivalent gates from `qelib1.inc`, so the
+/// program can be parsed as OPENQASM 2.0.
+///
+/// Modifiers apply from right to left: `ctrl @ inv @ rz(pi) a, b;` becomes
+/// `crz(-(pi)) a, b;`. The inverse of a gate is the gate itself, its dagger
+/// version such as `tdg` for `t`, or the gate with its parameters negated.
+/// A control turns a gate such as `x` into its controlled version `cx`, with
+/// the control as the first argument. Comments are left untouched.
+///
+/// # Errors
+///
+/// The function fails with [`QasmSimError::UnsupportedFeature`] if the
+/// modified gate has no equivalent in `qelib1.inc`, or if other modifiers,
+/// such as `negctrl @`, `pow(k) @` or `ctrl(n) @`, are used.
+///
+/// # Examples
+///
+/// ```
+/// # use qasmsim::{QasmSimError, grammar::expand_modifiers};
+/// let source = expand_modifiers("inv @ t q[0];\nctrl @ x q[0], q[1];\n")?;
+/// assert_eq!(source, "tdg q[0];\ncx q[0], q[1];\n");
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// [`QasmSimError::UnsupportedFeature`]: ../error/enum.QasmSimError.html#variant.UnsupportedFeature
+pub fn expand_modifiers(source: &str) -> Result<String, QasmSimError<'_>> {
+    let mut output = String::with_capacity(source.len());
+    let mut cursor = 0;
+    let mut scanner = Scanner {
+        source,
+        position: 0,
+    };
+    while let Some((start, word)) = scanner.next_word() {
+        let unsupported = |feature: String, end: usize| {
+            let (line, lineno, startpos, endpos) = extract_line(start, Some(end), source);
+            QasmSimError::UnsupportedFeature {
+                source: line,
+                lineno,
+                startpos,
+                endpos,
+                feature,
+            }
+        };
+
+        let mut modifiers = Vec::new();
+        let mut word = word;
+        while let Some(modifier) = scanner.modifier(word) {
+            if modifier != "ctrl" && modifier != "inv" {
+                return Err(unsupported(format!("{} @", modifier), scanner.position));
+            }
+            modifiers.push(modifier);
+            match scanner.next_word() {
+                Some((_, next)) => word = next,
+                None => return Err(unsupported(format!("{} @", modifier), scanner.position)),
+            }
+        }
+        if modifiers.is_empty() {
+            continue;
+        }
+
+        let gate = word;
+        let parameters = scanner.parameters();
+        let (gate, parameters) = modifiers
+            .iter()
+            .rev()
+            .try_fold(
+                (gate.to_string(), parameters),
+                |(gate, parameters), modifier| apply_modifier(modifier, &gate, &parameters),
+            )
+            .ok_or_else(|| {
+                unsupported(
+                    format!("{} @ {}", modifiers.join(" @ "), gate),
+                    scanner.position,
+                )
+            })?;
+
+        output.push_str(&source[cursor..start]);
+        output.push_str(&gate);
+        if !parameters.is_empty() {
+            output.push_str(&format!("({})", parameters.join(", ")));
+        }
+        cursor = scanner.position;
+    }
+    output.push_str(&source[cursor..]);
+    Ok(output)
+}
+
+/// Return the gate, and its parameters, resulting of applying `modifier` to
+/// `gate` with `parameters`, if there is such a gate in `qelib1.inc`.
+fn apply_modifier(
+    modifier: &str,
+    gate: &str,
+    parameters: &[String],
+) -> Option<(String, Vec<String>)> {
+    let negate = |parameter: &String| format!("-({})", parameter.trim());
+    if modifier == "ctrl" {
+        return CONTROLLED_GATES
+            .iter()
+            .find(|(name, _)| *name == gate)
+            .map(|(_, controlled)| (controlled.to_string(), parameters.to_vec()));
+    }
+    if SELF_INVERSE_GATES.contains(&gate) {
+        return Some((gate.to_string(), parameters.to_vec()));
+    }
+    for (a, b) in INVERSE_GATE_PAIRS {
+        if gate == a || gate == b {
+            let inverse = if gate == a { b } else { a };
+            return Some((inverse.to_string(), parameters.to_vec()));
+        }
+    }
+    if NEGATED_PARAMETER_GATES.contains(&gate) {
+        return Some((gate.to_string(), parameters.iter().map(negate).collect()));
+    }
+    match (gate, parameters) {
+        ("U" | "u3" | "cu3", [theta, phi, lambda]) => Some((
+            gate.to_string(),
+            vec![negate(theta), negate(lambda), negate(phi)],
+        )),
+        ("u2", [phi, lambda]) => Some((
+            String::from("u3"),
+            vec![String::from("-pi/2"), negate(lambda), negate(phi)],
+        )),
+        _ => None,
+    }
+}
+
+/// Walk over the words of an OPENQASM source, skipping comments and
+/// strings.
+struct Scanner<'src> {
+    source: &'src str,
+    position: usize,
+}
+
+impl<'src> Scanner<'src> {
+    /// Return the offset and the text of the next identifier or keyword.
+    fn next_word(&mut self) -> Option<(usize, &'src str)> {
+        loop {
+            let rest = &self.source[self.position..];
+            let character = rest.chars().next()?;
+            if rest.starts_with("//") {
+                self.position += rest.find('\n').unwrap_or(rest.len());
+            } else if rest.starts_with("/*") {
+                self.position += rest.find("*/").map_or(rest.len(), |end| end + 2);
+            } else if character == '"' {
+                self.position += rest[1..].find('"').map_or(rest.len(), |end| end + 2);
+            } else if character.is_ascii_alphabetic() || character == '_' {
+                let start = self.position;
+                let length = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                self.position += length;
+                return Some((start, &self.source[start..self.position]));
+            } else if character.is_ascii_digit() {
+                self.position += rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '.')
+                    .unwrap_or(rest.len());
+            } else {
+                self.position += character.len_utf8();
+            }
+        }
+    }
+
+    /// Return the next character which is not whitespace, skipping the
+    /// whitespace before it.
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.source[self.position..];
+        let trimmed = rest.trim_start();
+        self.position += rest.len() - trimmed.len();
+        trimmed.chars().next()
+    }
+
+    /// If `word`, the word just scanned, is a modifier, that is, if it is
+    /// followed by `@`, maybe after a parenthesized argument, skip up to the
+    /// `@` and return the modifier with its argument.
+    fn modifier(&mut self, word: &'src str) -> Option<&'src str> {
+        if !MODIFIERS.contains(&word) {
+            return None;
+        }
+        let start = self.position - word.len();
+        let saved = self.position;
+        self.parameters();
+        let end = self.position;
+        if self.peek() == Some('@') {
+            self.position += 1;
+            return Some(&self.source[start..end]);
+        }
+        self.position = saved;
+        None
+    }
+
+    /// Return the parameters between parentheses following the current
+    /// position, skipping them, or none if there are no parentheses.
+    fn parameters(&mut self) -> Vec<String> {
+        let saved = self.position;
+        if self.peek() != Some('(') {
+            self.position = saved;
+            return Vec::new();
+        }
+        let start = self.position + 1;
+        let mut depth = 0;
+        let mut parameters = Vec::new();
+        let mut parameter_start = start;
+        for (offset, character) in self.source[self.position..].char_indices() {
+            let index = self.position + offset;
+            match character {
+                '(' => depth += 1,
+                ')' if depth == 1 => {
+                    parameters.push(self.source[parameter_start..index].trim().to_string());
+                    self.position = index + 1;
+                    return parameters;
+                }
+                ')' => depth -= 1,
+                ',' if depth == 1 => {
+                    parameters.push(self.source[parameter_start..index].trim().to_string());
+                    parameter_start = index + 1;
+                }
+                _ => (),
+            }
+        }
+        self.position = self.source.len();
+        parameters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use indoc::indoc;
+
+    #[test]
+    fn test_modifiers_are_rewritten_into_qelib1_gates() {
+        let source = indoc!(
+            "
+            inv @ t q[0];
+            inv @ sdg q[0];
+            inv @ u3(pi/2, 0, pi) q[0];
+            inv @ u2(0, pi) q[0];
+            ctrl @ x q[0], q[1];
+            ctrl @ inv @ rz(pi/4) q[0], q[1];
+            ctrl @ ctrl @ x q[0], q[1], q[2];
+            "
+        );
+        assert_eq!(
+            expand_modifiers(source).unwrap(),
+            indoc!(
+                "
+                tdg q[0];
+                s q[0];
+                u3(-(pi/2), -(pi), -(0)) q[0];
+                u3(-pi/2, -(pi), -(0)) q[0];
+                cx q[0], q[1];
+                crz(-(pi/4)) q[0], q[1];
+                ccx q[0], q[1], q[2];
+                "
+            )
+        );
+    }
+
+    #[test]
+    fn test_comments_and_unmodified_gates_are_untouched() {
+        let source = indoc!(
+            "
+            // inv @ t q[0];
+            h q[0]; // ctrl @ x q[0], q[1];
+            ctrl_gate q[0];
+            "
+        );
+        assert_eq!(expand_modifiers(source).unwrap(), source);
+    }
+
+    #[test]
+    fn test_unsupported_modifiers_are_errors() {
+        let source = "h q[0];\nnegctrl @ x q[0], q[1];\n";
+        assert_eq!(
+            expand_modifiers(source),
+            Err(QasmSimError::UnsupportedFeature {
+                source: "negctrl @ x q[0], q[1];\n",
+                lineno: 2,
+                startpos: 0,
+                endpos: Some(9),
+                feature: String::from("negctrl @"),
+            })
+        );
+        let source = "ctrl(2) @ x q[0], q[1], q[2];\n";
+        assert!(matches!(
+            expand_modifiers(source),
+            Err(QasmSimError::UnsupportedFeature { feature, .. }) if feature == "ctrl(2) @"
+        ));
+    }
+
+    #[test]
+    fn test_modifiers_without_qelib1_equivalent_are_errors() {
+        let source = "ctrl @ s q[0], q[1];\n";
+        assert!(matches!(
+            expand_modifiers(source),
+            Err(QasmSimError::UnsupportedFeature { feature, .. }) if feature == "ctrl @ s"
+        ));
+    }
+}
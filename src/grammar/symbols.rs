@@ -0,0 +1,283 @@
+//! Index the declarations in OPENQASM programs and the places where they are
+//! used, as needed by editor features such as go-to-definition.
+//!
+//! Spans are those of the statements containing the declarations and
+//! references. Declarations embedded from a library share the span of the
+//! `include` statement.
+
+use std::collections::HashMap;
+
+use crate::grammar::ast::{
+    Argument, BarrierPragma, GateOperation, OpenQasmProgram, QuantumOperation, Statement,
+    UnitaryOperation,
+};
+use crate::grammar::lexer::Location;
+
+/// What a declared symbol is.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+
+pub enum SymbolKind {
+    /// A quantum register.
+    QuantumRegister,
+    /// A classical register.
+    ClassicalRegister,
+    /// A gate with a definition.
+    Gate,
+    /// An opaque gate.
+    OpaqueGate,
+}
+
+/// The declaration of a symbol.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+
+pub struct Declaration {
+    /// Name of the symbol.
+    pub name: String,
+    /// What the symbol is.
+    pub kind: SymbolKind,
+    /// Boundaries of the declaring statement.
+    pub span: (Location, Location),
+}
+
+/// A use of a symbol.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+
+pub struct Reference {
+    /// Name of the symbol.
+    pub name: String,
+    /// Boundaries of the statement using the symbol.
+    pub span: (Location, Location),
+    /// Index in [`SymbolTable::declarations`] of the declaration the symbol
+    /// resolves to, or `None` if the symbol is not declared.
+    ///
+    /// [`SymbolTable::declarations`]: ./struct.SymbolTable.html#structfield.declarations
+    pub declaration: Option<usize>,
+}
+
+/// The declarations and references in a program, in source order.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+
+pub struct SymbolTable {
+    /// Declarations of registers and gates.
+    pub declarations: Vec<Declaration>,
+    /// Uses of registers and gates. The built-in gates `U` and `CX` are
+    /// key-words, not symbols, so their uses are not included.
+    pub references: Vec<Reference>,
+}
+
+/// Return the symbol table of `program`.
+///
+/// The program does not need to be valid for simulation: references to
+/// undeclared symbols are reported as unresolved.
+///
+/// # Examples
+///
+/// ```
+/// # use qasmsim::QasmSimError;
+/// use qasmsim::grammar::{parse_program, symbols::{symbols, SymbolKind}};
+///
+/// let table = symbols(&parse_program(r"
+/// OPENQASM 2.0;
+/// qreg q[1];
+/// U(pi, 0, pi) r[0];
+/// ")?);
+/// assert_eq!(table.declarations[0].kind, SymbolKind::QuantumRegister);
+/// assert_eq!(table.references[0].name, "r");
+/// assert_eq!(table.references[0].declaration, None);
+/// # Ok::<(), QasmSimError>(())
+/// ```
+pub fn symbols(program: &OpenQasmProgram) -> SymbolTable {
+    let mut table = SymbolTable::default();
+    let mut uses = Vec::new();
+    for span in &program.program {
+        let boundaries = span.boundaries;
+        match &*span.node {
+            Statement::QRegDecl(name, _) => {
+                table.declare(name, SymbolKind::QuantumRegister, boundaries)
+            }
+            Statement::CRegDecl(name, _) => {
+                table.declare(name, SymbolKind::ClassicalRegister, boundaries)
+            }
+            Statement::GateDecl { signature, .. } => {
+                table.declare(&signature.0, SymbolKind::Gate, boundaries);
+                for operation in &signature.3 {
+                    if let GateOperation::Unitary(unitary) = &operation.node {
+                        use_gate(&mut uses, unitary, boundaries);
+                    }
+                }
+            }
+            Statement::OpaqueGateDecl { signature, .. } => {
+                table.declare(&signature.0, SymbolKind::OpaqueGate, boundaries)
+            }
+            Statement::Barrier(BarrierPragma(arguments)) => {
+                use_arguments(&mut uses, arguments, boundaries)
+            }
+            Statement::QuantumOperation(operation) => {
+                use_operation(&mut uses, operation, boundaries)
+            }
+            Statement::Conditional(register, _, operation) => {
+                use_arguments(&mut uses, std::slice::from_ref(register), boundaries);
+                use_operation(&mut uses, operation, boundaries);
+            }
+            Statement::ConditionalBlock(register, _, operations) => {
+                use_arguments(&mut uses, std::slice::from_ref(register), boundaries);
+                for operation in operations {
+                    use_operation(&mut uses, operation, boundaries);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for (position, declaration) in table.declarations.iter().enumerate() {
+        index.entry(declaration.name.as_str()).or_insert(position);
+    }
+    let references = uses
+        .into_iter()
+        .map(|(name, span)| Reference {
+            declaration: index.get(name.as_str()).copied(),
+            name,
+            span,
+        })
+        .collect();
+    table.references = references;
+    table
+}
+
+impl SymbolTable {
+    fn declare(&mut self, name: &str, kind: SymbolKind, span: (Location, Location)) {
+        self.declarations.push(Declaration {
+            name: name.to_owned(),
+            kind,
+            span,
+        });
+    }
+}
+
+type Uses = Vec<(String, (Location, Location))>;
+
+fn use_operation(uses: &mut Uses, operation: &QuantumOperation, span: (Location, Location)) {
+    match operation {
+        QuantumOperation::Unitary(unitary) => {
+            use_gate(uses, unitary, span);
+            use_arguments(uses, &unitary.2, span);
+        }
+        QuantumOperation::Measure(source, target) => {
+            use_arguments(uses, &[source.clone(), target.clone()], span);
+        }
+        QuantumOperation::Reset(target) => {
+            use_arguments(uses, std::slice::from_ref(target), span);
+        }
+    }
+}
+
+fn use_gate(uses: &mut Uses, unitary: &UnitaryOperation, span: (Location, Location)) {
+    if unitary.0 != "U" && unitary.0 != "CX" {
+        uses.push((unitary.0.to_string(), span));
+    }
+}
+
+fn use_arguments(uses: &mut Uses, arguments: &[Argument], span: (Location, Location)) {
+    for argument in arguments {
+        let name = match argument {
+            Argument::Id(name) => name,
+            Argument::Item(name, _) => name,
+        };
+        uses.push((name.to_string(), span));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use crate::grammar::parse_program;
+
+    use super::*;
+
+    #[test]
+    fn test_declarations_and_references() {
+        let source = indoc!(
+            "
+        OPENQASM 2.0;
+        qreg q[2];
+        gate flip a {
+          U(pi, 0, pi) a;
+        }
+        flip q[0];
+        flip r[1];
+        "
+        );
+        let program = parse_program(source).unwrap();
+        let table = symbols(&program);
+        let span = |statement: &str| {
+            let start = source.find(statement).unwrap();
+            (Location(start), Location(start + statement.len()))
+        };
+        let gate_span = (
+            Location(source.find("gate").unwrap()),
+            Location(source.find("}").unwrap() + 1),
+        );
+        assert_eq!(
+            table.declarations,
+            vec![
+                Declaration {
+                    name: "q".into(),
+                    kind: SymbolKind::QuantumRegister,
+                    span: span("qreg q[2];"),
+                },
+                Declaration {
+                    name: "flip".into(),
+                    kind: SymbolKind::Gate,
+                    span: gate_span,
+                },
+            ]
+        );
+        assert_eq!(
+            table.references,
+            vec![
+                Reference {
+                    name: "flip".into(),
+                    span: span("flip q[0];"),
+                    declaration: Some(1),
+                },
+                Reference {
+                    name: "q".into(),
+                    span: span("flip q[0];"),
+                    declaration: Some(0),
+                },
+                Reference {
+                    name: "flip".into(),
+                    span: span("flip r[1];"),
+                    declaration: Some(1),
+                },
+                Reference {
+                    name: "r".into(),
+                    span: span("flip r[1];"),
+                    declaration: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_references_inside_gate_definitions() {
+        let source = indoc!(
+            "
+        OPENQASM 2.0;
+        gate inner a { U(0, 0, 0) a; }
+        gate outer a { inner a; missing a; }
+        "
+        );
+        let program = parse_program(source).unwrap();
+        let table = symbols(&program);
+        let names: Vec<(&str, Option<usize>)> = table
+            .references
+            .iter()
+            .map(|reference| (reference.name.as_str(), reference.declaration))
+            .collect();
+        assert_eq!(names, vec![("inner", Some(0)), ("missing", None)]);
+    }
+}
@@ -1,6 +1,8 @@
-mod argument_solver;
+pub(crate) mod argument_solver;
 mod computation;
-mod expression_solver;
+pub(crate) mod expression_solver;
+pub(crate) mod flattener;
 pub mod runtime;
 
-pub use self::computation::{Computation, Histogram};
+pub(crate) use self::computation::{write_summary, FinalState};
+pub use self::computation::{Computation, Histogram, ShotRecord, WeightedHistogram};
@@ -12,8 +12,8 @@ impl<'bindings> ArgumentSolver<'bindings> {
 
     pub fn solve(&self, arg: &ast::Argument) -> Result<ast::Argument, String> {
         match arg {
-            ast::Argument::Id(name) => match self.0.get(name) {
-                None => Err(name.clone()),
+            ast::Argument::Id(name) => match self.0.get(name.as_str()) {
+                None => Err(name.to_string()),
                 Some(argument) => Ok(argument.clone()),
             },
             _ => unreachable!("while solving, only valid argument style is Argument::Id"),
@@ -27,20 +27,20 @@ mod test {
 
     #[test]
     fn test_replace_formal_parameter_with_actual_parameter() {
-        let actual_argument = ast::Argument::Item("actual".to_owned(), 0);
+        let actual_argument = ast::Argument::Item("actual".into(), 0);
         let bindings = HashMap::from_iter(vec![("formal".to_owned(), actual_argument.clone())]);
         let solver = ArgumentSolver::new(&bindings);
-        let formal_argument = ast::Argument::Id("formal".to_owned());
+        let formal_argument = ast::Argument::Id("formal".into());
         let argument = solver.solve(&formal_argument).expect("get actual argument");
         assert_eq!(argument, actual_argument);
     }
 
     #[test]
     fn test_actual_parameter_not_found() {
-        let actual_argument = ast::Argument::Item("actual".to_owned(), 0);
+        let actual_argument = ast::Argument::Item("actual".into(), 0);
         let bindings = HashMap::from_iter(vec![("formal".to_owned(), actual_argument)]);
         let solver = ArgumentSolver::new(&bindings);
-        let formal_argument = ast::Argument::Id("fmal".to_owned());
+        let formal_argument = ast::Argument::Id("fmal".into());
         let error = solver
             .solve(&formal_argument)
             .expect_err("actual argument not found");
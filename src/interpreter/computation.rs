@@ -1,10 +1,26 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
 
-use crate::statevector::StateVector;
+use crate::statevector::{Complex, QuantumState, State, StateVector};
 
 /// Map classical registers with values and number of outcomes.
 /// register name -> (Vector of (value, count), register size)
-pub type Histogram = HashMap<String, (Vec<(u64, usize)>, usize)>;
+pub type Histogram = HashMap<String, (Vec<(u128, usize)>, usize)>;
+
+/// Map classical registers with their values at the end of one shot.
+/// register name -> value
+pub type ShotRecord = HashMap<String, u128>;
+
+/// Map classical registers with values and the sum of the weights of the
+/// shots producing them.
+/// register name -> (Vector of (value, accumulated weight), register size)
+///
+/// Unlike [`Histogram`], the accumulated weights can be fractional or
+/// negative.
+///
+/// [`Histogram`]: ./type.Histogram.html
+pub type WeightedHistogram = HashMap<String, (Vec<(u128, f64)>, usize)>;
 
 /// Represent the result of a simulation.
 ///
@@ -18,51 +34,129 @@ pub type Histogram = HashMap<String, (Vec<(u64, usize)>, usize)>;
 ///
 /// [`simulate()`]: ./fn.simulate.html
 /// [`simulate_with_shots()`]: ./fn.simulate_with_shots.html
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 
 pub struct Computation {
-    statevector: StateVector,
-    memory: HashMap<String, (u64, usize, usize)>,
-    probabilities: Vec<f64>,
+    state: FinalState,
+    memory: HashMap<String, (u128, usize, usize)>,
     histogram: Option<Histogram>,
-    sequences: Option<Vec<String>>,
+    sequences: Option<Vec<ShotRecord>>,
     stats: Option<HashMap<String, usize>>,
+    written_bits: HashMap<String, u128>,
+    max_entanglement: Option<f64>,
+    quantum_registers: HashMap<String, (usize, usize)>,
+    gate_count: HashMap<String, usize>,
+    peak_memory_bytes: usize,
+    density_matrix: Option<Vec<Vec<Complex>>>,
 }
 
 impl Computation {
-    /// Create a new computation.
+    /// Create a new computation with the final state of the quantum system,
+    /// either a [`StateVector`] or any other representation of [`State`].
+    ///
+    /// Probabilities are computed from the state the first time they are
+    /// requested.
     ///
-    /// Probabilities are computed from the state-vector.
+    /// [`StateVector`]: ./statevector/struct.StateVector.html
+    /// [`State`]: ./statevector/enum.State.html
     pub fn new(
-        memory: HashMap<String, (u64, usize, usize)>,
-        statevector: StateVector,
+        memory: HashMap<String, (u128, usize, usize)>,
+        statevector: impl Into<State>,
         histogram: Option<Histogram>,
-        sequences: Option<Vec<String>>,
+        sequences: Option<Vec<ShotRecord>>,
         stats: Option<HashMap<String, usize>>,
     ) -> Self {
         Computation {
-            probabilities: statevector.probabilities(),
-            statevector,
+            state: FinalState::new(statevector.into()),
             memory,
             histogram,
             sequences,
             stats,
+            written_bits: HashMap::new(),
+            max_entanglement: None,
+            quantum_registers: HashMap::new(),
+            gate_count: HashMap::new(),
+            peak_memory_bytes: 0,
+            density_matrix: None,
         }
     }
 
+    pub(crate) fn with_written_bits(mut self, written_bits: HashMap<String, u128>) -> Self {
+        self.written_bits = written_bits;
+        self
+    }
+
+    pub(crate) fn with_max_entanglement(mut self, max_entanglement: Option<f64>) -> Self {
+        self.max_entanglement = max_entanglement;
+        self
+    }
+
+    pub(crate) fn with_quantum_registers(
+        mut self,
+        quantum_registers: HashMap<String, (usize, usize)>,
+    ) -> Self {
+        self.quantum_registers = quantum_registers;
+        self
+    }
+
+    pub(crate) fn with_peak_memory_bytes(mut self, peak_memory_bytes: usize) -> Self {
+        self.peak_memory_bytes = peak_memory_bytes;
+        self
+    }
+
+    pub(crate) fn with_density_matrix(mut self, density_matrix: Option<Vec<Vec<Complex>>>) -> Self {
+        self.density_matrix = density_matrix;
+        self
+    }
+
+    pub(crate) fn with_gate_count(mut self, gate_count: HashMap<String, usize>) -> Self {
+        self.gate_count = gate_count;
+        self
+    }
+
+    /// Return the state of the quantum system in the representation used
+    /// for simulating.
+    ///
+    /// Unlike [`statevector()`], this does not allocate. Use it, and the
+    /// methods of [`QuantumState`], for the results of large simulations
+    /// with the sparse backend.
+    ///
+    /// [`statevector()`]: #method.statevector
+    /// [`QuantumState`]: ./statevector/trait.QuantumState.html
+    pub fn state(&self) -> &State {
+        self.state.state()
+    }
+
     /// Return the statevector of the quantum system.
+    ///
+    /// If the simulation did not use the dense backend in double precision,
+    /// the state-vector is built from the [`state()`] on the first call,
+    /// taking 16 bytes per basis state.
+    ///
+    /// [`state()`]: #method.state
     pub fn statevector(&self) -> &StateVector {
-        &self.statevector
+        self.state.statevector()
     }
 
     /// Return an associative map with classical names and the classical outcomes.
-    pub fn memory(&self) -> &HashMap<String, (u64, usize, usize)> {
+    pub fn memory(&self) -> &HashMap<String, (u128, usize, usize)> {
         &self.memory
     }
 
     /// Return the probabilities associated with the state-vector.
+    ///
+    /// Probabilities are computed on the first call, so simulations that
+    /// never ask for them do not store them. There is one per basis state,
+    /// even for the sparse backend; see [`QuantumState::marginal_outcomes()`]
+    /// for the probabilities of some qubits only.
+    ///
+    /// [`QuantumState::marginal_outcomes()`]: ./statevector/trait.QuantumState.html#method.marginal_outcomes
     pub fn probabilities(&self) -> &[f64] {
-        &self.probabilities
+        self.state.probabilities()
+    }
+
+    pub(crate) fn into_final_state(self) -> FinalState {
+        self.state
     }
 
     /// Return the histogram when simulating with several shots.
@@ -70,8 +164,10 @@ impl Computation {
         &self.histogram
     }
 
-    /// Return the sequences when simulating with several shots.
-    pub fn sequences(&self) -> &Option<Vec<String>> {
+    /// Return the values of the classical registers at the end of each
+    /// shot, in order, when simulating with several shots in the sequence
+    /// mode.
+    pub fn sequences(&self) -> &Option<Vec<ShotRecord>> {
         &self.sequences
     }
 
@@ -79,13 +175,239 @@ impl Computation {
     pub fn stats(&self) -> &Option<HashMap<String, usize>> {
         &self.stats
     }
+
+    /// Return an associative map with classical names and a mask of the bits
+    /// written by some measurement. A register with a mask of 0 was never
+    /// measured into, so its value in [`memory()`] is the initial 0. When
+    /// simulating with shots, a bit counts as written if it was written in
+    /// any of them.
+    ///
+    /// [`memory()`]: #method.memory
+    pub fn written_bits(&self) -> &HashMap<String, u128> {
+        &self.written_bits
+    }
+
+    /// Return the maximum entanglement entropy, in bits, reached during the
+    /// simulation if it was tracked.
+    pub fn max_entanglement(&self) -> Option<f64> {
+        self.max_entanglement
+    }
+
+    /// Return an associative map with quantum register names and the index
+    /// of their first qubit in the state-vector along with their size.
+    pub fn quantum_registers(&self) -> &HashMap<String, (usize, usize)> {
+        &self.quantum_registers
+    }
+
+    /// Return an associative map with gate names and the number of times
+    /// they were applied, over all the shots.
+    ///
+    /// Each application to one set of qubits counts, so broadcasting a gate
+    /// over a register of size `n` counts `n` applications. Applying a gate
+    /// definition counts for that gate and for every gate in its body, down
+    /// to `U` and `CX`. Gates inside unsatisfied conditionals do not count.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::{parse_and_link, simulate};
+    ///
+    /// let program = parse_and_link(r#"
+    ///     OPENQASM 2.0;
+    ///     include "qelib1.inc";
+    ///     qreg q[2];
+    ///     h q;
+    ///     cx q[0], q[1];
+    /// "#)?;
+    /// let computation = simulate(&program)?;
+    /// let count = computation.gate_application_count();
+    /// assert_eq!(count["h"], 2);
+    /// assert_eq!(count["cx"], 1);
+    /// assert_eq!(count["CX"], 1);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn gate_application_count(&self) -> &HashMap<String, usize> {
+        &self.gate_count
+    }
+
+    /// Return an estimate of the peak number of bytes taken by the
+    /// simulation: the largest size reached by the amplitudes of the state
+    /// plus the tables the dense state-vector caches for the gates applied.
+    ///
+    /// With the dense backend, the amplitudes take 16 bytes per basis state.
+    pub fn peak_memory_bytes(&self) -> usize {
+        self.peak_memory_bytes
+    }
+
+    /// Return the reduced density matrix of the qubits requested with
+    /// [`SimulationOptions::density_qubits`], averaged over the shots, if it
+    /// was requested. See [`StateVector::density_matrix()`] for how rows and
+    /// columns are indexed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::{parse_and_link, simulate_with_options};
+    /// use qasmsim::options::SimulationOptions;
+    ///
+    /// let program = parse_and_link(r#"
+    ///     OPENQASM 2.0;
+    ///     include "qelib1.inc";
+    ///     qreg q[1];
+    ///     creg c[1];
+    ///     h q[0];
+    ///     measure q[0] -> c[0];
+    /// "#)?;
+    /// let options = SimulationOptions {
+    ///     density_qubits: Some(vec![0]),
+    ///     ..Default::default()
+    /// };
+    /// let computation = simulate_with_options(&program, Some(1000), &options)?;
+    /// let density = computation.density_matrix().unwrap();
+    /// // Measuring turns the superposition into a mixed state.
+    /// assert!((density[0][0].re - 0.5).abs() < 0.1);
+    /// assert!(density[0][1].norm() < 1e-10);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    ///
+    /// [`SimulationOptions::density_qubits`]: ./options/struct.SimulationOptions.html#structfield.density_qubits
+    /// [`StateVector::density_matrix()`]: ./statevector/struct.StateVector.html#method.density_matrix
+    pub fn density_matrix(&self) -> Option<&Vec<Vec<Complex>>> {
+        self.density_matrix.as_ref()
+    }
+}
+
+/// Probabilities are left out of the comparison: they follow from the
+/// state-vector and may not have been computed yet.
+impl PartialEq for Computation {
+    fn eq(&self, other: &Self) -> bool {
+        self.state == other.state
+            && self.memory == other.memory
+            && self.histogram == other.histogram
+            && self.sequences == other.sequences
+            && self.stats == other.stats
+            && self.written_bits == other.written_bits
+            && self.max_entanglement == other.max_entanglement
+            && self.quantum_registers == other.quantum_registers
+            && self.gate_count == other.gate_count
+            && self.peak_memory_bytes == other.peak_memory_bytes
+            && self.density_matrix == other.density_matrix
+    }
+}
+
+impl fmt::Display for Computation {
+    /// Write a short summary of the computation with the number of qubits,
+    /// the most probable basis states, the values of the classical registers
+    /// and the number of shots, if any. The state-vector is not included.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_summary(f, self.state(), &self.memory, &self.stats)
+    }
+}
+
+/// The state at the end of a simulation along with the dense state-vector
+/// and the probabilities, computed from it the first time they are
+/// requested.
+#[derive(Debug, Clone)]
+pub(crate) struct FinalState {
+    state: State,
+    statevector: OnceLock<StateVector>,
+    probabilities: OnceLock<Vec<f64>>,
+}
+
+impl FinalState {
+    pub fn new(state: State) -> Self {
+        FinalState {
+            state,
+            statevector: OnceLock::new(),
+            probabilities: OnceLock::new(),
+        }
+    }
+
+    pub fn with_probabilities(self, probabilities: Vec<f64>) -> Self {
+        FinalState {
+            probabilities: OnceLock::from(probabilities),
+            ..self
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn statevector(&self) -> &StateVector {
+        match &self.state {
+            State::Dense(statevector) => statevector,
+            state => self.statevector.get_or_init(|| state.to_dense()),
+        }
+    }
+
+    pub fn probabilities(&self) -> &Vec<f64> {
+        self.probabilities
+            .get_or_init(|| self.state.probabilities())
+    }
+}
+
+/// The dense state-vector and the probabilities follow from the state.
+impl PartialEq for FinalState {
+    fn eq(&self, other: &Self) -> bool {
+        self.state == other.state
+    }
+}
+
+/// Number of basis states listed in the summaries.
+const SUMMARY_STATES: usize = 5;
+
+/// Write the summary of a simulation described in the `Display`
+/// implementation of [`Computation`].
+///
+/// [`Computation`]: ./struct.Computation.html
+pub(crate) fn write_summary(
+    f: &mut fmt::Formatter<'_>,
+    state: &State,
+    memory: &HashMap<String, (u128, usize, usize)>,
+    stats: &Option<HashMap<String, usize>>,
+) -> fmt::Result {
+    let width = state.qubit_width();
+    writeln!(f, "qubits: {}", width)?;
+    writeln!(f, "most probable states:")?;
+    for (index, probability) in state.top_k_outcomes(SUMMARY_STATES) {
+        if probability > f64::EPSILON {
+            writeln!(
+                f,
+                "  |{:0width$b}⟩ {:.6}",
+                index,
+                probability,
+                width = width
+            )?;
+        }
+    }
+    if !memory.is_empty() {
+        let mut registers: Vec<_> = memory.iter().collect();
+        registers.sort_by_key(|(_, (_, _, position))| *position);
+        writeln!(f, "memory:")?;
+        for (name, (value, size, _)) in registers {
+            writeln!(f, "  {}[{}] = {}", name, size, value)?;
+        }
+    }
+    if let Some(stats) = stats {
+        writeln!(
+            f,
+            "shots: {} ({} distinct outcomes)",
+            stats.values().sum::<usize>(),
+            stats.len()
+        )?;
+    }
+    Ok(())
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct HistogramBuilder {
     pub histogram: Histogram,
-    pub sequences: Vec<String>,
+    pub sequences: Vec<ShotRecord>,
     pub stats: HashMap<String, usize>,
+    /// Mask of the bits of each register written in some shot.
+    pub written: HashMap<String, u128>,
+    record_sequences: bool,
 }
 
 impl HistogramBuilder {
@@ -93,44 +415,66 @@ impl HistogramBuilder {
         Default::default()
     }
 
-    pub fn update(&mut self, memory: &HashMap<String, (u64, usize, usize)>) {
+    /// Create a builder that also records the outcome of each shot, in
+    /// order, as required by the sequence mode.
+    pub fn with_sequences() -> Self {
+        HistogramBuilder {
+            record_sequences: true,
+            ..Default::default()
+        }
+    }
+
+    /// Count the values of the classical registers in `memory` at the end of
+    /// a shot, where `written` holds the mask of the bits each measurement
+    /// of the shot wrote.
+    ///
+    /// The histogram only aggregates the registers some measurement wrote
+    /// into during the shot, while the statistics and the sequences take the
+    /// values of all of them, unwritten ones being 0. The masks are
+    /// accumulated over the shots in [`written`](#structfield.written).
+    pub fn update(
+        &mut self,
+        memory: &HashMap<String, (u128, usize, usize)>,
+        written: &HashMap<String, u128>,
+    ) {
+        for (key, mask) in written {
+            *self.written.entry(key.clone()).or_insert(0) |= mask;
+        }
         for (key, current_value) in memory {
-            if !self.histogram.contains_key(key) {
-                self.histogram
-                    .insert(key.clone(), (Vec::new(), current_value.1));
+            if written.get(key).copied().unwrap_or(0) == 0 {
+                continue;
             }
-            let values = &mut self.histogram.get_mut(key).expect("get values for key").0;
+            let values = &mut self
+                .histogram
+                .entry(key.clone())
+                .or_insert_with(|| (Vec::new(), current_value.1))
+                .0;
             match values.binary_search_by_key(&current_value.0, |(v, _)| *v) {
                 Err(idx) => values.insert(idx, (current_value.0, 1)),
                 Ok(found) => values[found].1 += 1,
             }
         }
 
-        let mut memory_vec = memory.into_iter().collect::<Vec<_>>();
-        memory_vec.sort_by(|x, y| y.1 .2.cmp(&x.1 .2));
-        let mut binary = String::new();
-        for (_, current_value) in memory_vec {
-            binary.push_str(&format!(
-                "{:0width$b}",
-                current_value.0,
-                width = current_value.1
-            ));
+        *self.stats.entry(outcome_as_binary(memory)).or_insert(0) += 1;
+        if self.record_sequences {
+            self.sequences.push(
+                memory
+                    .iter()
+                    .map(|(name, (value, _, _))| (name.clone(), *value))
+                    .collect(),
+            );
         }
-        *self.stats.entry(binary).or_insert(0) += 1;
-    }
-
-    pub fn update_sequences(&mut self, memory: &HashMap<String, (u64, usize, usize)>) {
-        let mut memory_vec = memory.into_iter().collect::<Vec<_>>();
-        memory_vec.sort_by(|x, y| y.1 .2.cmp(&x.1 .2));
-        let mut binary = String::new();
-        for (_, current_value) in memory_vec {
-            binary.push_str(&format!(
-                "{:0width$b}",
-                current_value.0,
-                width = current_value.1
-            ));
-        }
-        self.sequences.push(binary);
+    }
+
+    /// Return the outcomes with their counts, sorted by outcome.
+    pub fn stats_as_vec(&self) -> Vec<(String, usize)> {
+        let mut stats: Vec<_> = self
+            .stats
+            .iter()
+            .map(|(outcome, count)| (outcome.clone(), *count))
+            .collect();
+        stats.sort();
+        stats
     }
 
     pub fn histogram(self) -> Histogram {
@@ -141,16 +485,60 @@ impl HistogramBuilder {
         self.stats
     }
 
-    pub fn sequences(self) -> Vec<String> {
+    pub fn sequences(self) -> Vec<ShotRecord> {
         self.sequences
     }
 }
 
+/// Add `weight` to the values of each classical register in `memory`,
+/// keeping the values sorted.
+pub fn update_weighted_histogram(
+    histogram: &mut WeightedHistogram,
+    memory: &HashMap<String, (u128, usize, usize)>,
+    weight: f64,
+) {
+    for (key, (value, size, _)) in memory {
+        let values = &mut histogram
+            .entry(key.clone())
+            .or_insert_with(|| (Vec::new(), *size))
+            .0;
+        match values.binary_search_by_key(value, |(v, _)| *v) {
+            Err(idx) => values.insert(idx, (*value, weight)),
+            Ok(found) => values[found].1 += weight,
+        }
+    }
+}
+
+/// Concatenate the binary representation of all the registers in `memory`,
+/// from the last declared to the first.
+fn outcome_as_binary(memory: &HashMap<String, (u128, usize, usize)>) -> String {
+    let mut memory_vec = memory.iter().collect::<Vec<_>>();
+    memory_vec.sort_by_key(|entry| std::cmp::Reverse(entry.1 .2));
+    let mut binary = String::new();
+    for (_, current_value) in memory_vec {
+        binary.push_str(&format!(
+            "{:0width$b}",
+            current_value.0,
+            width = current_value.1
+        ));
+    }
+    binary
+}
+
 #[cfg(test)]
 mod test {
 
     use super::*;
 
+    /// Update `builder` with `memory` as if all its bits were written.
+    fn update(builder: &mut HistogramBuilder, memory: &HashMap<String, (u128, usize, usize)>) {
+        let written = memory
+            .keys()
+            .map(|name| (name.clone(), u128::MAX))
+            .collect();
+        builder.update(memory, &written);
+    }
+
     #[test]
     fn test_histogram_builder_empty_histogram() {
         let builder = HistogramBuilder::new();
@@ -161,7 +549,10 @@ mod test {
     #[test]
     fn test_histogram_builder_one_update() {
         let mut builder = HistogramBuilder::new();
-        builder.update(&HashMap::from_iter(vec![("a".into(), (1, 1, 1))]));
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (1, 1, 1))]),
+        );
         let histogram = builder.histogram();
         assert_eq!(
             histogram,
@@ -172,8 +563,14 @@ mod test {
     #[test]
     fn test_histogram_builder_couple_of_updates() {
         let mut builder = HistogramBuilder::new();
-        builder.update(&HashMap::from_iter(vec![("a".into(), (1, 1, 1))]));
-        builder.update(&HashMap::from_iter(vec![("a".into(), (1, 1, 1))]));
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (1, 1, 1))]),
+        );
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (1, 1, 1))]),
+        );
         let histogram = builder.histogram();
         assert_eq!(
             histogram,
@@ -184,8 +581,14 @@ mod test {
     #[test]
     fn test_histogram_builder_couple_of_registers() {
         let mut builder = HistogramBuilder::new();
-        builder.update(&HashMap::from_iter(vec![("a".into(), (1, 1, 1))]));
-        builder.update(&HashMap::from_iter(vec![("b".into(), (1, 1, 2))]));
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (1, 1, 1))]),
+        );
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("b".into(), (1, 1, 2))]),
+        );
         let histogram = builder.histogram();
         assert_eq!(
             histogram,
@@ -199,10 +602,22 @@ mod test {
     #[test]
     fn test_histogram_builder_different_values() {
         let mut builder = HistogramBuilder::new();
-        builder.update(&HashMap::from_iter(vec![("a".into(), (5, 3, 1))]));
-        builder.update(&HashMap::from_iter(vec![("b".into(), (4, 3, 2))]));
-        builder.update(&HashMap::from_iter(vec![("a".into(), (3, 3, 1))]));
-        builder.update(&HashMap::from_iter(vec![("b".into(), (2, 3, 2))]));
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (5, 3, 1))]),
+        );
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("b".into(), (4, 3, 2))]),
+        );
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (3, 3, 1))]),
+        );
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("b".into(), (2, 3, 2))]),
+        );
         let histogram = builder.histogram();
         assert_eq!(
             histogram,
@@ -216,10 +631,22 @@ mod test {
     #[test]
     fn test_histogram_builder_different_repeated_values() {
         let mut builder = HistogramBuilder::new();
-        builder.update(&HashMap::from_iter(vec![("a".into(), (5, 3, 1))]));
-        builder.update(&HashMap::from_iter(vec![("b".into(), (4, 3, 2))]));
-        builder.update(&HashMap::from_iter(vec![("a".into(), (5, 3, 1))]));
-        builder.update(&HashMap::from_iter(vec![("b".into(), (2, 3, 2))]));
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (5, 3, 1))]),
+        );
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("b".into(), (4, 3, 2))]),
+        );
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (5, 3, 1))]),
+        );
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("b".into(), (2, 3, 2))]),
+        );
         let histogram = builder.histogram();
         assert_eq!(
             histogram,
@@ -233,10 +660,22 @@ mod test {
     #[test]
     fn test_histogram_builder_stats_different_repeated_values() {
         let mut builder = HistogramBuilder::new();
-        builder.update(&HashMap::from_iter(vec![("a".into(), (5, 3, 1))]));
-        builder.update(&HashMap::from_iter(vec![("b".into(), (4, 3, 2))]));
-        builder.update(&HashMap::from_iter(vec![("a".into(), (5, 3, 1))]));
-        builder.update(&HashMap::from_iter(vec![("b".into(), (2, 3, 2))]));
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (5, 3, 1))]),
+        );
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("b".into(), (4, 3, 2))]),
+        );
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (5, 3, 1))]),
+        );
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("b".into(), (2, 3, 2))]),
+        );
         let stats = builder.stats();
         assert_eq!(
             stats,
@@ -247,4 +686,91 @@ mod test {
             ])
         );
     }
+
+    #[test]
+    fn test_histogram_builder_skips_unwritten_registers() {
+        let mut builder = HistogramBuilder::new();
+        let memory = HashMap::from([("a".into(), (0, 2, 1)), ("b".into(), (1, 1, 2))]);
+        builder.update(&memory, &HashMap::from([("a".into(), 0), ("b".into(), 1)]));
+        builder.update(
+            &memory,
+            &HashMap::from([("a".into(), 0b10), ("b".into(), 1)]),
+        );
+        assert_eq!(
+            builder.histogram,
+            HashMap::from([
+                ("a".into(), (vec![(0, 1)], 2)),
+                ("b".into(), (vec![(1, 2)], 1))
+            ])
+        );
+        assert_eq!(
+            builder.written,
+            HashMap::from([("a".into(), 0b10), ("b".into(), 1)])
+        );
+        assert_eq!(builder.stats_as_vec(), vec![("100".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_histogram_builder_records_sequences_only_if_asked() {
+        let mut builder = HistogramBuilder::new();
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (1, 1, 1))]),
+        );
+        assert_eq!(builder.sequences(), Vec::<ShotRecord>::new());
+
+        let mut builder = HistogramBuilder::with_sequences();
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (1, 2, 1))]),
+        );
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (2, 2, 1))]),
+        );
+        update(
+            &mut builder,
+            &HashMap::from_iter(vec![("a".into(), (1, 2, 1))]),
+        );
+        assert_eq!(
+            builder.stats_as_vec(),
+            vec![("01".to_string(), 2), ("10".to_string(), 1)]
+        );
+        assert_eq!(
+            builder.sequences(),
+            vec![
+                HashMap::from([("a".to_string(), 1)]),
+                HashMap::from([("a".to_string(), 2)]),
+                HashMap::from([("a".to_string(), 1)])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_display_summary_of_bell_state() {
+        use std::f64::consts::FRAC_1_SQRT_2;
+
+        use crate::statevector::Complex;
+
+        let bell = StateVector::from_complex_bases_unchecked(vec![
+            Complex::from(FRAC_1_SQRT_2),
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(FRAC_1_SQRT_2),
+        ]);
+        let memory = HashMap::from_iter(vec![("c".into(), (3, 2, 0)), ("d".into(), (0, 1, 1))]);
+        let stats = HashMap::from_iter(vec![("011".into(), 6), ("000".into(), 4)]);
+        let computation = Computation::new(memory, bell, None, None, Some(stats));
+        assert_eq!(
+            format!("{}", computation),
+            "qubits: 2\n\
+             most probable states:\n\
+             \x20 |00⟩ 0.500000\n\
+             \x20 |11⟩ 0.500000\n\
+             memory:\n\
+             \x20 c[2] = 3\n\
+             \x20 d[1] = 0\n\
+             shots: 10 (2 distinct outcomes)\n"
+        );
+    }
 }
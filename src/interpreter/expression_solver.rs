@@ -1,6 +1,27 @@
 use std::collections::HashMap;
 
-use crate::grammar::ast;
+use crate::grammar::{ast, lexer::Location};
+use crate::interpreter::runtime::RuntimeError;
+use crate::semantics::{QasmType, SemanticError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SolverError {
+    SymbolNotFound(String),
+    DivisionByZero,
+}
+
+impl SolverError {
+    pub fn into_runtime_error(self, location: Location) -> RuntimeError {
+        match self {
+            SolverError::SymbolNotFound(symbol_name) => RuntimeError::SymbolNotFound {
+                location,
+                symbol_name,
+                expected: QasmType::RealValue,
+            },
+            SolverError::DivisionByZero => SemanticError::DivisionByZero { location }.into(),
+        }
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ExpressionSolver<'bindings>(&'bindings HashMap<String, f64>);
@@ -10,7 +31,7 @@ impl<'bindings> ExpressionSolver<'bindings> {
         ExpressionSolver::<'bindings>(symbol_table)
     }
 
-    pub fn solve(&self, expression: &ast::Expression) -> Result<f64, String> {
+    pub fn solve(&self, expression: &ast::Expression) -> Result<f64, SolverError> {
         Ok(match expression {
             ast::Expression::Pi => std::f64::consts::PI,
             ast::Expression::Int(value) => *value as f64,
@@ -20,7 +41,14 @@ impl<'bindings> ExpressionSolver<'bindings> {
                 ast::OpCode::Add => self.solve(left)? + self.solve(right)?,
                 ast::OpCode::Sub => self.solve(left)? - self.solve(right)?,
                 ast::OpCode::Mul => self.solve(left)? * self.solve(right)?,
-                ast::OpCode::Div => self.solve(left)? / self.solve(right)?,
+                ast::OpCode::Div => {
+                    let dividend = self.solve(left)?;
+                    let divisor = self.solve(right)?;
+                    if divisor == 0.0 {
+                        return Err(SolverError::DivisionByZero);
+                    }
+                    dividend / divisor
+                }
                 ast::OpCode::Pow => self.solve(left)?.powf(self.solve(right)?),
             },
             ast::Expression::Function(func_code, expr) => match func_code {
@@ -32,11 +60,37 @@ impl<'bindings> ExpressionSolver<'bindings> {
                 ast::FuncCode::Sqrt => self.solve(expr)?.sqrt(),
             },
             ast::Expression::Id(name) => match self.0.get(name) {
-                None => return Err(name.into()),
+                None => return Err(SolverError::SymbolNotFound(name.into())),
                 Some(value) => *value,
             },
         })
     }
+
+    /// Replace the subexpressions of `expression` that can be solved with the
+    /// current bindings by their values, keeping the rest untouched. The
+    /// replacements keep the boundaries of the subexpressions they replace.
+    pub fn fold(
+        &self,
+        expression: &ast::Spanned<ast::Expression>,
+    ) -> ast::Spanned<ast::Expression> {
+        let node = match (self.solve(expression), &expression.node) {
+            (Ok(value), _) => ast::Expression::Real(value),
+            (_, ast::Expression::Minus(expr)) => ast::Expression::Minus(Box::new(self.fold(expr))),
+            (_, ast::Expression::Op(op_code, left, right)) => ast::Expression::Op(
+                *op_code,
+                Box::new(self.fold(left)),
+                Box::new(self.fold(right)),
+            ),
+            (_, ast::Expression::Function(func_code, expr)) => {
+                ast::Expression::Function(*func_code, Box::new(self.fold(expr)))
+            }
+            (_, other) => other.clone(),
+        };
+        ast::Spanned {
+            boundaries: expression.boundaries,
+            node,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -45,30 +99,43 @@ mod test {
 
     use super::*;
     use crate::grammar::ast::*;
+    use crate::grammar::parse_expression;
 
     #[test]
     #[allow(clippy::float_cmp)]
     fn test_expression_solver() {
         let expression = Expression::Op(
             OpCode::Add,
-            Box::new(Expression::Minus(Box::new(Expression::Pi))),
-            Box::new(Expression::Op(
-                OpCode::Div,
-                Box::new(Expression::Op(
-                    OpCode::Mul,
-                    Box::new(Expression::Op(
-                        OpCode::Sub,
-                        Box::new(Expression::Real(1.0)),
-                        Box::new(Expression::Op(
-                            OpCode::Pow,
-                            Box::new(Expression::Real(2.0)),
-                            Box::new(Expression::Real(3.0)),
-                        )),
-                    )),
-                    Box::new(Expression::Real(4.0)),
-                )),
-                Box::new(Expression::Real(5.0)),
-            )),
+            Box::new(Expression::Minus(Box::new(Expression::Pi.into())).into()),
+            Box::new(
+                Expression::Op(
+                    OpCode::Div,
+                    Box::new(
+                        Expression::Op(
+                            OpCode::Mul,
+                            Box::new(
+                                Expression::Op(
+                                    OpCode::Sub,
+                                    Box::new(Expression::Real(1.0).into()),
+                                    Box::new(
+                                        Expression::Op(
+                                            OpCode::Pow,
+                                            Box::new(Expression::Real(2.0).into()),
+                                            Box::new(Expression::Real(3.0).into()),
+                                        )
+                                        .into(),
+                                    ),
+                                )
+                                .into(),
+                            ),
+                            Box::new(Expression::Real(4.0).into()),
+                        )
+                        .into(),
+                    ),
+                    Box::new(Expression::Real(5.0).into()),
+                )
+                .into(),
+            ),
         );
         let empty = HashMap::new();
         let solver = ExpressionSolver::new(&empty);
@@ -81,22 +148,37 @@ mod test {
     fn test_expression_solver_with_functions() {
         let expression = Expression::Function(
             FuncCode::Sqrt,
-            Box::new(Expression::Function(
-                FuncCode::Ln,
-                Box::new(Expression::Function(
-                    FuncCode::Exp,
-                    Box::new(Expression::Function(
-                        FuncCode::Tan,
-                        Box::new(Expression::Function(
-                            FuncCode::Cos,
-                            Box::new(Expression::Function(
-                                FuncCode::Sin,
-                                Box::new(Expression::Real(1.0)),
-                            )),
-                        )),
-                    )),
-                )),
-            )),
+            Box::new(
+                Expression::Function(
+                    FuncCode::Ln,
+                    Box::new(
+                        Expression::Function(
+                            FuncCode::Exp,
+                            Box::new(
+                                Expression::Function(
+                                    FuncCode::Tan,
+                                    Box::new(
+                                        Expression::Function(
+                                            FuncCode::Cos,
+                                            Box::new(
+                                                Expression::Function(
+                                                    FuncCode::Sin,
+                                                    Box::new(Expression::Real(1.0).into()),
+                                                )
+                                                .into(),
+                                            ),
+                                        )
+                                        .into(),
+                                    ),
+                                )
+                                .into(),
+                            ),
+                        )
+                        .into(),
+                    ),
+                )
+                .into(),
+            ),
         );
         let empty = HashMap::new();
         let solver = ExpressionSolver::new(&empty);
@@ -109,20 +191,29 @@ mod test {
     fn test_expression_solver_with_symbol_substitution() {
         let expression = Expression::Op(
             OpCode::Add,
-            Box::new(Expression::Id("some_name".into())),
-            Box::new(Expression::Op(
-                OpCode::Div,
-                Box::new(Expression::Op(
-                    OpCode::Mul,
-                    Box::new(Expression::Op(
-                        OpCode::Sub,
-                        Box::new(Expression::Real(1.0)),
-                        Box::new(Expression::Real(2.0)),
-                    )),
-                    Box::new(Expression::Real(3.0)),
-                )),
-                Box::new(Expression::Real(4.0)),
-            )),
+            Box::new(Expression::Id("some_name".into()).into()),
+            Box::new(
+                Expression::Op(
+                    OpCode::Div,
+                    Box::new(
+                        Expression::Op(
+                            OpCode::Mul,
+                            Box::new(
+                                Expression::Op(
+                                    OpCode::Sub,
+                                    Box::new(Expression::Real(1.0).into()),
+                                    Box::new(Expression::Real(2.0).into()),
+                                )
+                                .into(),
+                            ),
+                            Box::new(Expression::Real(3.0).into()),
+                        )
+                        .into(),
+                    ),
+                    Box::new(Expression::Real(4.0).into()),
+                )
+                .into(),
+            ),
         );
         let bindings = HashMap::from_iter(vec![("some_name".into(), 1.0)]);
         let solver = ExpressionSolver::new(&bindings);
@@ -134,14 +225,84 @@ mod test {
     fn test_expression_solver_fails_at_symbol_substitution() {
         let expression = Expression::Op(
             OpCode::Add,
-            Box::new(Expression::Id("some_name".into())),
-            Box::new(Expression::Real(1.0)),
+            Box::new(Expression::Id("some_name".into()).into()),
+            Box::new(Expression::Real(1.0).into()),
         );
         let empty_bindings = HashMap::new();
         let solver = ExpressionSolver::new(&empty_bindings);
         let error = solver
             .solve(&expression)
             .expect_err("fails at replacing `some_name`");
-        assert_eq!(error, String::from("some_name"));
+        assert_eq!(
+            error,
+            SolverError::SymbolNotFound(String::from("some_name"))
+        );
+    }
+
+    #[test]
+    fn test_expression_solver_fails_at_division_by_zero() {
+        let expression = Expression::Op(
+            OpCode::Div,
+            Box::new(Expression::Real(1.0).into()),
+            Box::new(
+                Expression::Op(
+                    OpCode::Sub,
+                    Box::new(Expression::Real(2.0).into()),
+                    Box::new(Expression::Real(2.0).into()),
+                )
+                .into(),
+            ),
+        );
+        let empty = HashMap::new();
+        let solver = ExpressionSolver::new(&empty);
+        let error = solver.solve(&expression).expect_err("fails at dividing");
+        assert_eq!(error, SolverError::DivisionByZero);
+    }
+
+    #[test]
+    fn test_fold_replaces_closed_subexpressions() {
+        let expression = parse_expression("theta + pi/4 - 1/0").unwrap();
+        let empty = HashMap::new();
+        let solver = ExpressionSolver::new(&empty);
+        assert_eq!(
+            solver.fold(&expression),
+            Expression::Op(
+                OpCode::Sub,
+                Box::new(
+                    Expression::Op(
+                        OpCode::Add,
+                        Box::new(Expression::Id("theta".into()).into()),
+                        Box::new(Expression::Real(PI / 4.0).into()),
+                    )
+                    .into()
+                ),
+                Box::new(
+                    Expression::Op(
+                        OpCode::Div,
+                        Box::new(Expression::Real(1.0).into()),
+                        Box::new(Expression::Real(0.0).into()),
+                    )
+                    .into()
+                ),
+            )
+            .into()
+        );
+    }
+
+    #[test]
+    fn test_parse_and_solve_functions_and_powers() {
+        let empty = HashMap::new();
+        let solver = ExpressionSolver::new(&empty);
+        let subtests = [
+            ("exp(1)", std::f64::consts::E),
+            ("sqrt(2)", std::f64::consts::SQRT_2),
+            ("2^3", 8.0),
+            ("-pi/2 + ln(exp(2)) * tan(0)", -PI / 2.0),
+        ];
+        for (source, expected) in subtests {
+            let expression = parse_expression(source).expect("parse expression");
+            let result = solver.solve(&expression).expect("get value of expression");
+            assert!((result - expected).abs() < 1e-12, "{}", source);
+        }
     }
 }
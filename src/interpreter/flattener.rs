@@ -0,0 +1,639 @@
+//! Expand the statements of a program into the primitive operations the
+//! interpreter applies, for inspecting the compiled circuit and for the
+//! translators into other languages.
+//!
+//! Custom gates are inlined, register broadcasting is expanded, parameter
+//! expressions are evaluated and registers are resolved into the indices of
+//! the qubits in the unified quantum memory.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::RuntimeError;
+use crate::grammar::{ast, lexer::Location};
+use crate::interpreter::argument_solver::ArgumentSolver;
+use crate::interpreter::expression_solver::ExpressionSolver;
+use crate::semantics::{extract_semantics, QasmType, RegisterType, Semantics};
+
+type BindingMappings = (HashMap<String, f64>, HashMap<String, ast::Argument>);
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+/// A primitive operation on concrete qubits and bits, as the interpreter
+/// applies them once custom gates are inlined and register broadcasting is
+/// expanded. Qubits are numbered in order of declaration of their registers.
+///
+/// The [`Display`] implementation prints one operation per line, with
+/// conditionals enclosing their operations in braces.
+///
+/// [`Display`]: https://doc.rust-lang.org/std/fmt/trait.Display.html
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq)]
+
+pub enum PrimitiveOp {
+    /// The `U` gate.
+    U {
+        /// Evaluated `theta` parameter.
+        theta: f64,
+        /// Evaluated `phi` parameter.
+        phi: f64,
+        /// Evaluated `lambda` parameter.
+        lambda: f64,
+        /// Qubit the gate applies to.
+        target: usize,
+    },
+    /// The `CX` gate.
+    Cx {
+        /// Control qubit.
+        control: usize,
+        /// Target qubit.
+        target: usize,
+    },
+    /// Measurement of a qubit into a bit of a classical register.
+    Measure {
+        /// Measured qubit.
+        qubit: usize,
+        /// Name of the classical register.
+        register: String,
+        /// Index of the bit in the register.
+        bit: usize,
+    },
+    /// Reset of a qubit to the zero state.
+    Reset {
+        /// Reset qubit.
+        qubit: usize,
+    },
+    /// A barrier on some qubits.
+    Barrier {
+        /// Qubits of the barrier.
+        qubits: Vec<usize>,
+    },
+    /// An opaque gate, which has no body to expand.
+    Opaque {
+        /// Name of the gate.
+        name: String,
+        /// Evaluated parameters.
+        params: Vec<f64>,
+        /// Qubits the gate applies to.
+        qubits: Vec<usize>,
+    },
+    /// Operations applied only if the classical register, or one of its bits
+    /// if `bit` is set, holds `value`.
+    Conditional {
+        /// Name of the classical register.
+        register: String,
+        /// Index of the tested bit, if testing only one bit.
+        bit: Option<usize>,
+        /// Expected value.
+        value: u128,
+        /// Conditional operations.
+        body: Vec<PrimitiveOp>,
+    },
+}
+
+impl fmt::Display for PrimitiveOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrimitiveOp::U {
+                theta,
+                phi,
+                lambda,
+                target,
+            } => write!(f, "U({}, {}, {}) {}", theta, phi, lambda, target),
+            PrimitiveOp::Cx { control, target } => write!(f, "CX {}, {}", control, target),
+            PrimitiveOp::Measure {
+                qubit,
+                register,
+                bit,
+            } => write!(f, "measure {} -> {}[{}]", qubit, register, bit),
+            PrimitiveOp::Reset { qubit } => write!(f, "reset {}", qubit),
+            PrimitiveOp::Barrier { qubits } => write!(f, "barrier {}", join(qubits)),
+            PrimitiveOp::Opaque {
+                name,
+                params,
+                qubits,
+            } => write!(f, "{}({}) {}", name, join(params), join(qubits)),
+            PrimitiveOp::Conditional {
+                register,
+                bit,
+                value,
+                body,
+            } => {
+                match bit {
+                    None => writeln!(f, "if ({}=={}) {{", register, value)?,
+                    Some(bit) => writeln!(f, "if ({}[{}]=={}) {{", register, bit, value)?,
+                }
+                for operation in body {
+                    writeln!(f, "  {}", operation)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn join<T: fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Return the primitive operations the interpreter applies when simulating
+/// `program`, in order of execution. Conditionals are not evaluated but kept
+/// as [`PrimitiveOp::Conditional`] operations enclosing the operations they
+/// guard.
+///
+/// This is the circuit the simulation runs, useful for comparing it with
+/// the circuits other simulators generate.
+///
+/// # Errors
+///
+/// The function fails with [`RuntimeError`] in the same cases [`simulate()`]
+/// fails before measuring anything: undefined gates and registers, wrong
+/// number of arguments, out-of-bounds indices, and the like.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::{compile, parse_and_link, PrimitiveOp};
+///
+/// let program = parse_and_link(r#"
+///     OPENQASM 2.0;
+///     qreg q[2];
+///     CX q[0], q[1];
+/// "#)?;
+/// let circuit = compile(&program).unwrap();
+/// assert_eq!(circuit, vec![PrimitiveOp::Cx { control: 0, target: 1 }]);
+/// assert_eq!(circuit[0].to_string(), "CX 0, 1");
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// [`PrimitiveOp::Conditional`]: ./enum.PrimitiveOp.html#variant.Conditional
+/// [`RuntimeError`]: ./error/enum.RuntimeError.html
+/// [`simulate()`]: ./fn.simulate.html
+pub fn compile(program: &ast::OpenQasmProgram) -> Result<Vec<PrimitiveOp>> {
+    let semantics = extract_semantics(program)?;
+    let mut flattener = Flattener::new(&semantics);
+    let mut operations = Vec::new();
+    for span in &program.program {
+        flattener.location = span.boundaries.0;
+        operations.extend(flattener.flatten_statement(&span.node)?);
+    }
+    Ok(operations)
+}
+
+/// Return an OPENQASM 2.0 program equivalent to `program` that only uses
+/// the `U` and `CX` gates, plus `measure`, `reset` and `barrier`
+/// instructions, for tools that do not resolve includes or custom gates.
+///
+/// The operations are the ones [`compile()`] returns, written on the
+/// original registers. Register declarations are kept as they are, and so
+/// are opaque gate declarations and applications, since there is nothing to
+/// inline them with. Parameter expressions are written as numeric literals
+/// with 17 significant digits, which is enough to recover the same `f64`.
+/// Conditionals are repeated for each operation they guard.
+///
+/// # Errors
+///
+/// The function fails in the same cases [`compile()`] does.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::{decompose_to_qasm, parse_and_link};
+///
+/// let program = parse_and_link(r#"
+///     OPENQASM 2.0;
+///     include "qelib1.inc";
+///     qreg q[2];
+///     cx q[0], q[1];
+/// "#)?;
+/// assert_eq!(
+///     decompose_to_qasm(&program).unwrap(),
+///     "OPENQASM 2.0;\nqreg q[2];\nCX q[0], q[1];\n"
+/// );
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// [`compile()`]: ./fn.compile.html
+pub fn decompose_to_qasm(program: &ast::OpenQasmProgram) -> Result<String> {
+    let semantics = extract_semantics(program)?;
+    let qubits: Vec<String> = program
+        .program
+        .iter()
+        .filter_map(|span| match &*span.node {
+            ast::Statement::QRegDecl(name, size) => Some((name, *size)),
+            _ => None,
+        })
+        .flat_map(|(name, size)| (0..size).map(move |index| format!("{}[{}]", name, index)))
+        .collect();
+    let mut flattener = Flattener::new(&semantics);
+    let mut source = format!("OPENQASM {};\n", program.version);
+    for span in &program.program {
+        match &*span.node {
+            ast::Statement::QRegDecl(..)
+            | ast::Statement::CRegDecl(..)
+            | ast::Statement::OpaqueGateDecl { .. } => {
+                ast::write_statement(&mut source, &span.node);
+            }
+            statement => {
+                flattener.location = span.boundaries.0;
+                for operation in flattener.flatten_statement(statement)? {
+                    write_operation(&mut source, &operation, &qubits, "");
+                }
+            }
+        }
+    }
+    Ok(source)
+}
+
+/// Write `operation` as an OPENQASM statement after `prefix`, naming qubits
+/// as in `qubits`.
+fn write_operation(source: &mut String, operation: &PrimitiveOp, qubits: &[String], prefix: &str) {
+    let names = |indices: &[usize]| -> String {
+        indices
+            .iter()
+            .map(|index| qubits[*index].as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let statement = match operation {
+        PrimitiveOp::U {
+            theta,
+            phi,
+            lambda,
+            target,
+        } => format!(
+            "U({:.17e}, {:.17e}, {:.17e}) {}",
+            theta, phi, lambda, qubits[*target]
+        ),
+        PrimitiveOp::Cx { control, target } => {
+            format!("CX {}, {}", qubits[*control], qubits[*target])
+        }
+        PrimitiveOp::Measure {
+            qubit,
+            register,
+            bit,
+        } => format!("measure {} -> {}[{}]", qubits[*qubit], register, bit),
+        PrimitiveOp::Reset { qubit } => format!("reset {}", qubits[*qubit]),
+        PrimitiveOp::Barrier { qubits: targets } => format!("barrier {}", names(targets)),
+        PrimitiveOp::Opaque {
+            name,
+            params,
+            qubits: targets,
+        } => {
+            let params: Vec<String> = params
+                .iter()
+                .map(|param| format!("{:.17e}", param))
+                .collect();
+            format!("{}({}) {}", name, params.join(", "), names(targets))
+        }
+        PrimitiveOp::Conditional {
+            register,
+            bit,
+            value,
+            body,
+        } => {
+            let condition = match bit {
+                None => format!("{}if ({}=={}) ", prefix, register, value),
+                Some(bit) => format!("{}if ({}[{}]=={}) ", prefix, register, bit, value),
+            };
+            for operation in body {
+                write_operation(source, operation, qubits, &condition);
+            }
+            return;
+        }
+    };
+    source.push_str(prefix);
+    source.push_str(&statement);
+    source.push_str(";\n");
+}
+
+pub(crate) struct Flattener<'semantics> {
+    semantics: &'semantics Semantics,
+    /// Location of the statement being flattened, for the errors.
+    pub location: Location,
+}
+
+impl<'semantics> Flattener<'semantics> {
+    pub fn new(semantics: &'semantics Semantics) -> Self {
+        Flattener {
+            semantics,
+            location: Location::new(),
+        }
+    }
+
+    /// Return the instructions `statement` expands into. Declarations expand
+    /// into no instructions.
+    pub fn flatten_statement(&self, statement: &ast::Statement) -> Result<Vec<PrimitiveOp>> {
+        let mut instructions = Vec::new();
+        match statement {
+            ast::Statement::Barrier(ast::BarrierPragma(args)) => {
+                let mut qubits = Vec::new();
+                for argument in args {
+                    self.assert_register_type(register_name(argument), RegisterType::Q)?;
+                    for expansion in
+                        self.expand_arguments("barrier", std::slice::from_ref(argument))?
+                    {
+                        qubits.push(self.bit_mapping(&expansion[0])?);
+                    }
+                }
+                instructions.push(PrimitiveOp::Barrier { qubits });
+            }
+            ast::Statement::QuantumOperation(operation) => {
+                self.flatten_quantum_operation(operation, &mut instructions)?;
+            }
+            ast::Statement::Conditional(register, test, operation) => {
+                self.flatten_conditional(
+                    register,
+                    *test,
+                    std::iter::once(operation),
+                    &mut instructions,
+                )?;
+            }
+            ast::Statement::ConditionalBlock(register, test, operations) => {
+                self.flatten_conditional(
+                    register,
+                    *test,
+                    operations.iter().map(|operation| &operation.node),
+                    &mut instructions,
+                )?;
+            }
+            _ => (),
+        }
+        Ok(instructions)
+    }
+
+    fn flatten_conditional<'a>(
+        &self,
+        register: &ast::Argument,
+        test: u128,
+        operations: impl Iterator<Item = &'a ast::QuantumOperation>,
+        instructions: &mut Vec<PrimitiveOp>,
+    ) -> Result<()> {
+        let register_name = register_name(register);
+        self.assert_register_type(register_name, RegisterType::C)?;
+        let bit = match register {
+            ast::Argument::Item(..) => Some(self.bit_mapping(register)?),
+            ast::Argument::Id(_) => None,
+        };
+        let mut body = Vec::new();
+        for operation in operations {
+            self.flatten_quantum_operation(operation, &mut body)?;
+        }
+        instructions.push(PrimitiveOp::Conditional {
+            register: register_name.into(),
+            bit,
+            value: test,
+            body,
+        });
+        Ok(())
+    }
+
+    fn flatten_quantum_operation(
+        &self,
+        operation: &ast::QuantumOperation,
+        instructions: &mut Vec<PrimitiveOp>,
+    ) -> Result<()> {
+        match operation {
+            ast::QuantumOperation::Unitary(unitary) => {
+                self.flatten_unitary(unitary, None, instructions)
+            }
+            ast::QuantumOperation::Measure(source, target) => {
+                self.assert_register_type(register_name(source), RegisterType::Q)?;
+                self.assert_register_type(register_name(target), RegisterType::C)?;
+                let args = vec![source.clone(), target.clone()];
+                for expansion in self.expand_arguments("measure", &args)? {
+                    let qubit = self.bit_mapping(&expansion[0])?;
+                    let (register, bit) = match &expansion[1] {
+                        ast::Argument::Item(name, index) => (name.to_string(), *index),
+                        _ => unreachable!("after `expand_arguments()`, argument is an item"),
+                    };
+                    self.bit_mapping(&expansion[1])?;
+                    instructions.push(PrimitiveOp::Measure {
+                        qubit,
+                        register,
+                        bit,
+                    });
+                }
+                Ok(())
+            }
+            ast::QuantumOperation::Reset(target) => {
+                self.assert_register_type(register_name(target), RegisterType::Q)?;
+                for expansion in self.expand_arguments("reset", std::slice::from_ref(target))? {
+                    let qubit = self.bit_mapping(&expansion[0])?;
+                    instructions.push(PrimitiveOp::Reset { qubit });
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn flatten_unitary(
+        &self,
+        unitary: &ast::UnitaryOperation,
+        bindings: Option<&BindingMappings>,
+        instructions: &mut Vec<PrimitiveOp>,
+    ) -> Result<()> {
+        let ast::UnitaryOperation(name, real_args, args) = unitary;
+
+        let empty = HashMap::new();
+        let real_bindings = bindings.map_or(&empty, |bindings| &bindings.0);
+        let expression_solver = ExpressionSolver::new(real_bindings);
+        let mut solved_real_args = Vec::new();
+        for expression in real_args {
+            let value = expression_solver
+                .solve(expression)
+                .map_err(|error| error.into_runtime_error(self.location))?;
+            solved_real_args.push(value);
+        }
+
+        let actual_args = match bindings {
+            None => args.clone(),
+            Some((_, arg_bindings)) => {
+                let argument_solver = ArgumentSolver::new(arg_bindings);
+                let mut actual_args = Vec::new();
+                for argument in args {
+                    let actual = argument_solver.solve(argument).map_err(|symbol_name| {
+                        RuntimeError::SymbolNotFound {
+                            location: self.location,
+                            symbol_name,
+                            expected: QasmType::QuantumRegister,
+                        }
+                    })?;
+                    actual_args.push(actual);
+                }
+                actual_args
+            }
+        };
+        for argument in &actual_args {
+            self.assert_register_type(register_name(argument), RegisterType::Q)?;
+        }
+
+        for expansion in self.expand_arguments(name, &actual_args)? {
+            self.flatten_one_gate(name, &solved_real_args, &expansion, instructions)?;
+        }
+        Ok(())
+    }
+
+    fn flatten_one_gate(
+        &self,
+        name: &str,
+        real_args: &[f64],
+        args: &[ast::Argument],
+        instructions: &mut Vec<PrimitiveOp>,
+    ) -> Result<()> {
+        match name {
+            "U" => {
+                let target = self.bit_mapping(&args[0])?;
+                instructions.push(PrimitiveOp::U {
+                    theta: real_args[0],
+                    phi: real_args[1],
+                    lambda: real_args[2],
+                    target,
+                });
+            }
+            "CX" => {
+                let control = self.bit_mapping(&args[0])?;
+                let target = self.bit_mapping(&args[1])?;
+                instructions.push(PrimitiveOp::Cx { control, target });
+            }
+            macro_name => {
+                let opaque_definition = self.semantics.opaque_definitions.get(macro_name);
+                let definition = match opaque_definition
+                    .or_else(|| self.semantics.macro_definitions.get(macro_name))
+                {
+                    None => {
+                        return Err(RuntimeError::UndefinedGate {
+                            location: self.location,
+                            symbol_name: macro_name.into(),
+                            suggestion: self.semantics.suggest_gate(macro_name),
+                        })
+                    }
+                    Some(definition) => definition,
+                };
+                for (are_registers, expected, given) in [
+                    (false, definition.1.len(), real_args.len()),
+                    (true, definition.2.len(), args.len()),
+                ] {
+                    if expected != given {
+                        return Err(RuntimeError::WrongNumberOfParameters {
+                            are_registers,
+                            location: self.location,
+                            symbol_name: macro_name.into(),
+                            expected,
+                            given,
+                        });
+                    }
+                }
+                if opaque_definition.is_some() {
+                    let mut qubits = Vec::new();
+                    for argument in args {
+                        qubits.push(self.bit_mapping(argument)?);
+                    }
+                    instructions.push(PrimitiveOp::Opaque {
+                        name: macro_name.into(),
+                        params: real_args.to_vec(),
+                        qubits,
+                    });
+                    return Ok(());
+                }
+                let bindings = (
+                    HashMap::from_iter(definition.1.iter().cloned().zip(real_args.iter().cloned())),
+                    HashMap::from_iter(definition.2.iter().cloned().zip(args.iter().cloned())),
+                );
+                for operation in &definition.3 {
+                    if let ast::GateOperation::Unitary(unitary) = &operation.node {
+                        self.flatten_unitary(unitary, Some(&bindings), instructions)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn assert_register_type(&self, name: &str, rtype: RegisterType) -> Result<()> {
+        let expected = match rtype {
+            RegisterType::Q => QasmType::QuantumRegister,
+            RegisterType::C => QasmType::ClassicalRegister,
+        };
+        match self.semantics.register_table.get(name) {
+            None => Err(RuntimeError::SymbolNotFound {
+                location: self.location,
+                symbol_name: name.into(),
+                expected,
+            }),
+            Some(entry) if entry.1 != rtype => Err(RuntimeError::TypeMismatch {
+                location: self.location,
+                symbol_name: name.into(),
+                expected,
+            }),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn expand_arguments(
+        &self,
+        symbol_name: &str,
+        args: &[ast::Argument],
+    ) -> Result<Vec<Vec<ast::Argument>>> {
+        let sizes: Vec<usize> = args
+            .iter()
+            .filter_map(|argument| match argument {
+                ast::Argument::Id(name) => Some(self.semantics.register_table[name.as_str()].2),
+                ast::Argument::Item(..) => None,
+            })
+            .collect();
+        let size = match sizes.first() {
+            None => 1,
+            Some(size) if sizes.iter().all(|other| other == size) => *size,
+            Some(_) => {
+                return Err(RuntimeError::RegisterSizeMismatch {
+                    location: self.location,
+                    symbol_name: symbol_name.into(),
+                    sizes,
+                })
+            }
+        };
+        Ok((0..size)
+            .map(|index| {
+                args.iter()
+                    .map(|argument| match argument {
+                        ast::Argument::Id(name) => ast::Argument::Item(name.clone(), index),
+                        item => item.clone(),
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    fn bit_mapping(&self, argument: &ast::Argument) -> Result<usize> {
+        match argument {
+            ast::Argument::Item(name, index) => {
+                let mapping = &self.semantics.memory_map[name.as_str()];
+                let size = mapping.2 - mapping.1 + 1;
+                if *index >= size {
+                    return Err(RuntimeError::IndexOutOfBounds {
+                        location: self.location,
+                        symbol_name: name.to_string(),
+                        index: *index,
+                        size,
+                    });
+                }
+                Ok(mapping.1 + *index)
+            }
+            _ => unreachable!("after `expand_arguments()`, argument should be Argument::Item"),
+        }
+    }
+}
+
+pub(crate) fn register_name(argument: &ast::Argument) -> &str {
+    match argument {
+        ast::Argument::Id(name) => name,
+        ast::Argument::Item(name, _) => name,
+    }
+}
@@ -1,14 +1,22 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error;
 use std::fmt;
 use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::grammar::{ast, lexer::Location};
 use crate::interpreter::argument_solver::ArgumentSolver;
-use crate::interpreter::computation::{Computation, HistogramBuilder};
-use crate::interpreter::expression_solver::ExpressionSolver;
+use crate::interpreter::computation::{
+    update_weighted_histogram, Computation, HistogramBuilder, WeightedHistogram,
+};
+use crate::interpreter::expression_solver::{ExpressionSolver, SolverError};
+use crate::options::{Backend, Precision, SimulationMode, SimulationOptions};
+use crate::random::{check_draws, RandomSource, RecordingRandom, ThreadRandom, Trace};
 use crate::semantics::{extract_semantics, QasmType, RegisterType, SemanticError, Semantics};
-use crate::statevector::StateVector;
+use crate::statevector::{
+    cached_tables_bytes, check_density_qubits, check_entanglement_cut, Complex, QuantumState,
+    State, StateVector,
+};
 
 type BindingMappings = (HashMap<String, f64>, HashMap<String, ast::Argument>);
 
@@ -61,6 +69,8 @@ pub enum RuntimeError {
         location: Location,
         /// Name of the unknown gate.
         symbol_name: String,
+        /// A hint about how to fix the error, if any.
+        suggestion: Option<String>,
     },
     /// Found an unexpected type of value.
     TypeMismatch {
@@ -71,6 +81,21 @@ pub enum RuntimeError {
         /// Expected type.
         expected: QasmType,
     },
+    /// Application of an opaque gate, which has no definition to simulate.
+    OpaqueGateNotSimulatable {
+        /// Abstract location in the code.
+        location: Location,
+        /// Name of the opaque gate.
+        symbol_name: String,
+    },
+    /// Application of a controlled gate whose control and target are the
+    /// same qubit.
+    RepeatedQubit {
+        /// Abstract location in the code.
+        location: Location,
+        /// Name of the gate.
+        symbol_name: String,
+    },
     /// Use of an unknown/undeclared symbol.
     RegisterSizeMismatch {
         /// Abstract location in the code.
@@ -80,18 +105,114 @@ pub enum RuntimeError {
         /// Sizes of the different registers involved.
         sizes: Vec<usize>,
     },
+    /// The program declares more qubits than allowed.
+    TooManyQubits {
+        /// Abstract location in the code.
+        location: Location,
+        /// Number of qubits declared in the program.
+        qubits: usize,
+        /// Maximum number of qubits allowed.
+        max_qubits: usize,
+    },
+    /// Use of a classical register in a parameter expression without enabling
+    /// the extensions.
+    ClassicalRegisterInExpression {
+        /// Abstract location in the code.
+        location: Location,
+        /// Name of the classical register.
+        symbol_name: String,
+    },
+    /// The simulation did not consume exactly the random numbers of the trace
+    /// being replayed, so the trace was recorded for another program.
+    TraceMismatch {
+        /// Number of random numbers in the trace.
+        recorded: usize,
+        /// Number of random numbers the simulation tried to draw.
+        consumed: usize,
+    },
+    /// The source of random numbers ran out of numbers before the end of the
+    /// simulation.
+    RandomSourceExhausted,
+    /// The simulation was cancelled before finishing.
+    Cancelled,
+    /// The trace being replayed contains numbers which are not valid random
+    /// numbers.
+    InvalidTrace {
+        /// Description of the problem.
+        reason: String,
+    },
+    /// The qubits whose density matrix was requested are not valid qubits of
+    /// the program.
+    InvalidDensityQubits {
+        /// Description of the problem.
+        reason: String,
+    },
+    /// The cut requested for tracking the entanglement entropy splits the
+    /// system in two sides too large for computing it.
+    InvalidEntanglementCut {
+        /// Description of the problem.
+        reason: String,
+    },
+    /// The width requested for padding the quantum memory is smaller than
+    /// the qubits of the program.
+    PaddingTooNarrow {
+        /// Number of qubits declared in the program.
+        qubits: usize,
+        /// Number of qubits requested.
+        pad_qubits: usize,
+    },
+    /// The number of shots is not at least 1.
+    InvalidShots {
+        /// Number of shots requested.
+        shots: usize,
+    },
+    /// A post-selection constraint refers to a qubit that does not exist or
+    /// to an outcome that cannot happen.
+    InvalidPostselection {
+        /// Description of the problem.
+        reason: String,
+    },
 }
 
 impl fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let message = match self {
             RuntimeError::Other => "unknown error".to_string(),
+            RuntimeError::TraceMismatch { recorded, consumed } => format!(
+                "the trace has {} random numbers but the simulation drew {}",
+                recorded, consumed
+            ),
+            RuntimeError::RandomSourceExhausted => {
+                "the source of random numbers ran out of numbers".to_string()
+            }
+            RuntimeError::Cancelled => "the simulation was cancelled".to_string(),
+            RuntimeError::InvalidTrace { reason } => format!("invalid trace: {}", reason),
+            RuntimeError::InvalidDensityQubits { reason } => {
+                format!("invalid qubits for the density matrix: {}", reason)
+            }
+            RuntimeError::InvalidEntanglementCut { reason } => {
+                format!("invalid cut for tracking the entanglement: {}", reason)
+            }
+            RuntimeError::PaddingTooNarrow { qubits, pad_qubits } => format!(
+                "cannot pad the {} qubits of the program to {} qubits",
+                qubits, pad_qubits
+            ),
+            RuntimeError::InvalidPostselection { reason } => {
+                format!("invalid post-selection: {}", reason)
+            }
+            RuntimeError::InvalidShots { shots } => {
+                format!("cannot simulate {} shots, expected at least 1", shots)
+            }
             RuntimeError::SemanticError(semantic_error) => format!("{}", semantic_error),
             _ => match lazy_humanize! {
                 self,
+                RuntimeError::ClassicalRegisterInExpression,
                 RuntimeError::IndexOutOfBounds,
+                RuntimeError::OpaqueGateNotSimulatable,
                 RuntimeError::RegisterSizeMismatch,
+                RuntimeError::RepeatedQubit,
                 RuntimeError::SymbolNotFound,
+                RuntimeError::TooManyQubits,
                 RuntimeError::TypeMismatch,
                 RuntimeError::UndefinedGate,
                 RuntimeError::WrongNumberOfParameters
@@ -104,7 +225,14 @@ impl fmt::Display for RuntimeError {
     }
 }
 
-impl error::Error for RuntimeError {}
+impl error::Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            RuntimeError::SemanticError(semantic_error) => Some(semantic_error),
+            _ => None,
+        }
+    }
+}
 
 pub(crate) type Result<T> = std::result::Result<T, RuntimeError>;
 
@@ -114,26 +242,101 @@ impl From<SemanticError> for RuntimeError {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Track the memory used during the simulation: the largest size of the
+/// amplitudes and the keys of the tables cached for applying gates on the
+/// dense state-vector.
+#[derive(Debug, Default)]
+struct MemoryUsage {
+    peak_amplitude_bytes: usize,
+    target_rows: HashSet<usize>,
+    exchangeable_rows: HashSet<(usize, usize)>,
+    // `U` parameters, as the bits of theta, phi and lambda
+    matrices: HashSet<(u64, u64, u64)>,
+}
+
+impl MemoryUsage {
+    fn peak_bytes(&self, qubit_width: usize) -> usize {
+        self.peak_amplitude_bytes
+            + cached_tables_bytes(
+                qubit_width,
+                self.target_rows.len(),
+                self.exchangeable_rows.len(),
+                self.matrices.len(),
+            )
+    }
+}
+
+/// Operations between two checks of whether a dense state of the auto
+/// backend should become sparse. Each check counts the non-zero amplitudes of
+/// the whole state-vector.
+const DENSE_REBALANCE_PERIOD: usize = 32;
+
 struct Runtime<'program> {
     macro_stack: VecDeque<BindingMappings>,
     semantics: Semantics,
-    statevector: StateVector,
+    statevector: State,
+    backend: Backend,
+    // operations applied to a dense state since its last rebalance check
+    dense_operations: usize,
+    precision: Precision,
     // rigister name, (int value, size of the register, location of creg decl)
-    memory: HashMap<String, (u64, usize, usize)>,
+    memory: HashMap<String, (u128, usize, usize)>,
+    // register name, mask of the bits written by some measurement
+    written: HashMap<String, u128>,
+    entanglement_cut: Option<usize>,
+    max_entanglement: f64,
+    treat_opaque_as_identity: bool,
     location: Option<&'program Location>,
+    // gate name, applications over all the shots
+    gate_count: HashMap<String, usize>,
+    rng: &'program mut dyn RandomSource,
+    classical_parameters: bool,
+    // gates of qelib1.inc applied natively instead of expanding their body
+    native_gates: HashSet<&'static str>,
+    memory_usage: MemoryUsage,
+    density_qubits: Option<Vec<usize>>,
+    // sum of the density matrices at the end of the shots, and their number
+    density_sum: Vec<Vec<Complex>>,
+    density_samples: usize,
+    // set from another thread for stopping the simulation
+    cancelled: Option<&'program AtomicBool>,
 }
 
-impl<'src, 'program> Runtime<'program> {
-    pub fn new(semantics: Semantics) -> Self {
+impl<'program> Runtime<'program> {
+    pub fn new(
+        semantics: Semantics,
+        backend: Backend,
+        precision: Precision,
+        rng: &'program mut dyn RandomSource,
+    ) -> Self {
         let memory_size = semantics.quantum_memory_size;
+        let native_gates = ["y", "z"]
+            .into_iter()
+            .filter(|gate| semantics.is_qelib1_gate(gate))
+            .collect();
 
         let mut runtime = Runtime {
             macro_stack: VecDeque::new(),
             semantics,
-            statevector: StateVector::new(memory_size),
+            statevector: State::new(memory_size, backend, precision),
+            backend,
+            dense_operations: 0,
+            precision,
             memory: HashMap::new(),
+            written: HashMap::new(),
+            entanglement_cut: None,
+            max_entanglement: 0.0,
+            treat_opaque_as_identity: false,
             location: None,
+            gate_count: HashMap::new(),
+            rng,
+            classical_parameters: false,
+            native_gates,
+            memory_usage: MemoryUsage::default(),
+            density_qubits: None,
+            density_sum: Vec::new(),
+            density_samples: 0,
+            cancelled: None,
         };
 
         runtime.reset();
@@ -142,40 +345,61 @@ impl<'src, 'program> Runtime<'program> {
 
     pub fn reset(&mut self) {
         self.macro_stack.clear();
-        self.statevector.reset();
+        if self.backend == Backend::Auto {
+            self.statevector = State::new(
+                self.semantics.quantum_memory_size,
+                self.backend,
+                self.precision,
+            );
+            self.dense_operations = 0;
+        } else {
+            self.statevector.reset();
+        }
+        self.track_memory();
         self.clear_memory();
     }
 
     fn clear_memory(&mut self) {
         self.memory.clear();
+        self.written.clear();
         for register in self.semantics.register_table.values() {
             if register.1 == RegisterType::C {
                 self.memory
-                    .insert(register.0.clone(), (0_u64, register.2, register.3 .0));
+                    .insert(register.0.clone(), (0_u128, register.2, register.3 .0));
+                self.written.insert(register.0.clone(), 0);
             }
         }
     }
 
+    fn quantum_registers(&self) -> HashMap<String, (usize, usize)> {
+        self.semantics
+            .register_table
+            .values()
+            .filter(|register| register.1 == RegisterType::Q)
+            .map(|register| {
+                let offset = self.semantics.memory_map[&register.0].1;
+                (register.0.clone(), (offset, register.2))
+            })
+            .collect()
+    }
+
     fn apply_gates(&mut self, statements: &'program [ast::Span<ast::Statement>]) -> Result<()> {
         for span in statements {
+            self.check_cancelled()?;
             self.location = Some(&span.boundaries.0);
             match &*span.node {
                 ast::Statement::QuantumOperation(operation) => {
                     self.apply_quantum_operation(operation)?;
                 }
-                ast::Statement::Conditional(register, test, operation) => {
-                    let actual_register = (register).clone();
-                    let register_name = self.register_name(&actual_register);
-                    self.assert_is_classical_register(register_name)?;
-
-                    let value = match actual_register {
-                        ast::Argument::Id(register_name) => self
-                            .memory
-                            .get(&register_name)
-                            .expect("after `assert_is_classical_register()`, must exist"),
-                        _ => unreachable!("cannot index a register inside the condition"),
-                    };
-                    if &value.0 == test {
+                ast::Statement::Conditional(register, test, operation)
+                    if self.condition_holds(register, *test)? =>
+                {
+                    self.apply_quantum_operation(operation)?;
+                }
+                ast::Statement::ConditionalBlock(register, test, operations)
+                    if self.condition_holds(register, *test)? =>
+                {
+                    for operation in operations {
                         self.apply_quantum_operation(operation)?;
                     }
                 }
@@ -185,6 +409,30 @@ impl<'src, 'program> Runtime<'program> {
         Ok(())
     }
 
+    fn condition_holds(&self, register: &ast::Argument, test: u128) -> Result<bool> {
+        let register_name = self.register_name(register);
+        self.assert_is_classical_register(register_name)?;
+
+        let value = match register {
+            ast::Argument::Id(register_name) => {
+                self.memory
+                    .get(register_name.as_str())
+                    .expect("after `assert_is_classical_register()`, must exist")
+                    .0
+            }
+            ast::Argument::Item(register_name, _) => {
+                let index = self.bit_mapping(register)?;
+                let register_value = self
+                    .memory
+                    .get(register_name.as_str())
+                    .expect("after `assert_is_classical_register()`, must exist")
+                    .0;
+                (register_value >> index) & 1
+            }
+        };
+        Ok(value == test)
+    }
+
     fn apply_quantum_operation(&mut self, operation: &ast::QuantumOperation) -> Result<()> {
         match operation {
             ast::QuantumOperation::Unitary(unitary) => self.apply_unitary(unitary),
@@ -210,7 +458,7 @@ impl<'src, 'program> Runtime<'program> {
                 location: *self
                     .location
                     .expect("after `apply_gates()`, the location of the statement"),
-                symbol_name: name.clone(),
+                symbol_name: name.to_string(),
                 sizes,
             }
         })?;
@@ -230,7 +478,7 @@ impl<'src, 'program> Runtime<'program> {
         } else {
             let stack_entry = self
                 .macro_stack
-                .get(0)
+                .front()
                 .expect("if `is_running_macro()`, get first entry of the stack");
             let arg_bindings = &stack_entry.1;
             let argument_solver = ArgumentSolver::new(arg_bindings);
@@ -251,25 +499,44 @@ impl<'src, 'program> Runtime<'program> {
         Ok(actual)
     }
 
-    fn resolve_real_expressions(&self, exprs: &[ast::Expression]) -> Result<Vec<f64>> {
-        let mut real_bindings = &HashMap::new();
-        if self.is_running_macro() {
+    fn resolve_real_expressions(
+        &self,
+        exprs: &[ast::Spanned<ast::Expression>],
+    ) -> Result<Vec<f64>> {
+        let register_values;
+        let real_bindings = if self.is_running_macro() {
             let stack_entry = self
                 .macro_stack
-                .get(0)
+                .front()
                 .expect("if `is_running_macro()`, get first stack entry");
-            real_bindings = &stack_entry.0;
+            &stack_entry.0
+        } else if self.classical_parameters {
+            register_values = self
+                .memory
+                .iter()
+                .map(|(name, (value, _, _))| (name.clone(), *value as f64))
+                .collect();
+            &register_values
+        } else {
+            &HashMap::new()
         };
         let expression_solver = ExpressionSolver::new(real_bindings);
         let mut solved = Vec::new();
         for expression in exprs {
-            let value = expression_solver.solve(expression).map_err(|symbol_name| {
-                RuntimeError::SymbolNotFound {
-                    location: *self
-                        .location
-                        .expect("after `apply_gates()`, the location of the statement"),
-                    symbol_name,
-                    expected: QasmType::RealValue,
+            let value = expression_solver.solve(expression).map_err(|error| {
+                let location = *self
+                    .location
+                    .expect("after `apply_gates()`, the location of the statement");
+                match error {
+                    SolverError::SymbolNotFound(symbol_name)
+                        if !self.is_running_macro() && self.memory.contains_key(&symbol_name) =>
+                    {
+                        RuntimeError::ClassicalRegisterInExpression {
+                            location,
+                            symbol_name,
+                        }
+                    }
+                    error => error.into_runtime_error(location),
                 }
             })?;
             solved.push(value);
@@ -282,42 +549,157 @@ impl<'src, 'program> Runtime<'program> {
     }
 
     fn apply_measurement(&mut self, args: Vec<ast::Argument>) -> Result<()> {
-        self.assert_is_quantum_register(self.register_name(&args[0]))?;
-        self.assert_is_classical_register(self.register_name(&args[1]))?;
-
-        let expanded_arguments =
-            self.expand_arguments(&args)
-                .map_err(|sizes| RuntimeError::RegisterSizeMismatch {
-                    location: *self
-                        .location
-                        .expect("after `apply_gates()`, the location of the statement"),
-                    symbol_name: "measure".into(),
-                    sizes,
-                })?;
-
-        for argument_expansion in expanded_arguments {
+        for argument_expansion in self.expand_measurement(&args)? {
             self.apply_one_measurement(argument_expansion)?;
         }
-
         Ok(())
     }
 
+    fn expand_measurement(&self, args: &[ast::Argument]) -> Result<Vec<Vec<ast::Argument>>> {
+        self.assert_is_quantum_register(self.register_name(&args[0]))?;
+        self.assert_is_classical_register(self.register_name(&args[1]))?;
+
+        self.expand_arguments(args)
+            .map_err(|sizes| RuntimeError::RegisterSizeMismatch {
+                location: *self
+                    .location
+                    .expect("after `apply_gates()`, the location of the statement"),
+                symbol_name: "measure".into(),
+                sizes,
+            })
+    }
+
     fn apply_one_measurement(&mut self, args: Vec<ast::Argument>) -> Result<()> {
-        let classical_register_name = self.register_name(&args[1]);
         let source = self.bit_mapping(&args[0])?;
-        let measurement = self.statevector.measure(source) as u64;
+        let measurement = self.statevector.measure_with(source, self.rng);
+        self.check_rng()?;
+        self.rebalance();
 
         let target = self.bit_mapping(&args[1])?;
-        let value = measurement * (1 << target);
+        self.write_bit(self.register_name(&args[1]), target, measurement);
+        Ok(())
+    }
+
+    fn check_rng(&self) -> Result<()> {
+        if self.rng.ran_out() {
+            return Err(RuntimeError::RandomSourceExhausted);
+        }
+        Ok(())
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        match self.cancelled {
+            Some(cancelled) if cancelled.load(Ordering::Relaxed) => Err(RuntimeError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    fn write_bit(&mut self, classical_register_name: &str, target: usize, measurement: bool) {
+        let mask = 1 << target;
         let prev_value = *(self
             .memory
             .get(classical_register_name)
             .expect("after `apply_measurement()`, get the entry"));
+        // Overwrite the bit so measuring it again replaces the previous
+        // outcome instead of carrying into the next bits.
+        let value = if measurement {
+            prev_value.0 | mask
+        } else {
+            prev_value.0 & !mask
+        };
         self.memory.insert(
             classical_register_name.into(),
-            (prev_value.0 + value, prev_value.1, prev_value.2),
+            (value, prev_value.1, prev_value.2),
         );
+        *self
+            .written
+            .get_mut(classical_register_name)
+            .expect("after `apply_measurement()`, get the written mask") |= mask;
+    }
+
+    /// Simulate `shots` times a program whose measurements start at
+    /// `measurements_start` and are the last operations, as detected by
+    /// [`terminal_measurements_start()`].
+    ///
+    /// The gates are applied only once. For all the shots but the last, the
+    /// outcomes are sampled from the probabilities of the measured qubits
+    /// without collapsing the state. The last shot measures the state so it
+    /// ends collapsed. Outcomes are decided with the same random numbers
+    /// measuring every shot would draw, so results do not change.
+    fn sample_terminal_measurements(
+        &mut self,
+        statements: &'program [ast::Span<ast::Statement>],
+        measurements_start: usize,
+        shots: usize,
+        histogram_builder: &mut HistogramBuilder,
+    ) -> Result<()> {
+        let (gates, measurements) = statements.split_at(measurements_start);
+        self.reset();
+        self.apply_gates(gates)?;
 
+        // source qubit, classical register name, target bit
+        let mut targets = Vec::new();
+        for span in measurements {
+            self.location = Some(&span.boundaries.0);
+            if let ast::Statement::QuantumOperation(ast::QuantumOperation::Measure(
+                source,
+                target,
+            )) = &*span.node
+            {
+                for args in self.expand_measurement(&[source.clone(), target.clone()])? {
+                    let name = self.register_name(&args[1]).to_owned();
+                    targets.push((
+                        self.bit_mapping(&args[0])?,
+                        name,
+                        self.bit_mapping(&args[1])?,
+                    ));
+                }
+            }
+        }
+
+        let mut measured_qubits: Vec<usize> = targets.iter().map(|target| target.0).collect();
+        measured_qubits.sort_unstable();
+        measured_qubits.dedup();
+        let marginals = self.statevector.marginal_outcomes(&measured_qubits);
+        let positions: Vec<usize> = targets
+            .iter()
+            .map(|target| {
+                measured_qubits
+                    .binary_search(&target.0)
+                    .expect("the qubit is measured")
+            })
+            .collect();
+
+        for _ in 1..shots {
+            self.check_cancelled()?;
+            self.clear_memory();
+            let (mut fixed_mask, mut fixed_value) = (0, 0);
+            for ((_, name, target), position) in targets.iter().zip(&positions) {
+                let (mut chance_universe_0, mut total) = (0.0, 0.0);
+                for (index, probability) in &marginals {
+                    if index & fixed_mask == fixed_value {
+                        total += probability;
+                        if index >> position & 1 == 0 {
+                            chance_universe_0 += probability;
+                        }
+                    }
+                }
+                let measurement = self.rng.next_f64() >= chance_universe_0 / total;
+                self.check_rng()?;
+                fixed_mask |= 1 << position;
+                fixed_value |= (measurement as usize) << position;
+                self.write_bit(name, *target, measurement);
+            }
+            histogram_builder.update(&self.memory, &self.written);
+        }
+
+        self.clear_memory();
+        self.apply_gates(measurements)?;
+        histogram_builder.update(&self.memory, &self.written);
+
+        for count in self.gate_count.values_mut() {
+            *count *= shots;
+        }
         Ok(())
     }
 
@@ -327,27 +709,150 @@ impl<'src, 'program> Runtime<'program> {
         real_args: &[f64],
         args: &[ast::Argument],
     ) -> Result<()> {
+        *self.gate_count.entry(name.to_owned()).or_insert(0) += 1;
         match name {
             "U" => {
                 let theta = real_args[0];
                 let phi = real_args[1];
                 let lambda = real_args[2];
                 let target = self.bit_mapping(&args[0])?;
+                if let State::Dense(_) = self.statevector {
+                    self.memory_usage.target_rows.insert(target);
+                    self.memory_usage.matrices.insert((
+                        theta.to_bits(),
+                        phi.to_bits(),
+                        lambda.to_bits(),
+                    ));
+                }
                 self.statevector.u(theta, phi, lambda, target);
+                self.rebalance();
+                self.track_entanglement();
             }
             "CX" => {
                 let control = self.bit_mapping(&args[0])?;
                 let target = self.bit_mapping(&args[1])?;
+                if control == target {
+                    return Err(RuntimeError::RepeatedQubit {
+                        location: *self
+                            .location
+                            .expect("after `apply_gates()`, the location of the statement"),
+                        symbol_name: name.into(),
+                    });
+                }
+                if let State::Dense(_) = self.statevector {
+                    self.memory_usage
+                        .exchangeable_rows
+                        .insert((control, target));
+                }
                 self.statevector.cnot(control, target);
+                self.track_entanglement();
             }
             macro_name => {
                 let binding_mappings = self.bind(macro_name.to_owned(), real_args, args)?;
-                self.call(macro_name.to_owned(), binding_mappings)?;
+                if self.semantics.opaque_definitions.contains_key(macro_name) {
+                    if !self.treat_opaque_as_identity {
+                        return Err(RuntimeError::OpaqueGateNotSimulatable {
+                            location: *self
+                                .location
+                                .expect("after `apply_gates()`, the location of the statement"),
+                            symbol_name: macro_name.into(),
+                        });
+                    }
+                } else if self.native_gates.contains(macro_name) {
+                    let target = self.bit_mapping(&args[0])?;
+                    match macro_name {
+                        "y" => self.statevector.y(target),
+                        _ => self.statevector.z(target),
+                    }
+                    self.count_body_gates(macro_name);
+                    self.rebalance();
+                    self.track_entanglement();
+                } else {
+                    self.call(macro_name.to_owned(), binding_mappings)?;
+                }
             }
         };
+        self.track_memory();
         Ok(())
     }
 
+    /// Count the applications of the gates in the body of `macro_name`, down
+    /// to `U` and `CX`, as if the gate had been expanded.
+    fn count_body_gates(&mut self, macro_name: &str) {
+        let body_gates: Vec<String> = self.semantics.macro_definitions[macro_name]
+            .3
+            .iter()
+            .filter_map(|operation| match &operation.node {
+                ast::GateOperation::Unitary(ast::UnitaryOperation(gate, _, _)) => {
+                    Some(gate.to_string())
+                }
+                _ => None,
+            })
+            .collect();
+        for gate in body_gates {
+            *self.gate_count.entry(gate.clone()).or_insert(0) += 1;
+            if gate != "U" && gate != "CX" {
+                self.count_body_gates(&gate);
+            }
+        }
+    }
+
+    fn rebalance(&mut self) {
+        if self.backend != Backend::Auto {
+            return;
+        }
+        if let State::Dense(_) = self.statevector {
+            self.dense_operations += 1;
+            if self.dense_operations < DENSE_REBALANCE_PERIOD {
+                return;
+            }
+        }
+        self.dense_operations = 0;
+        self.statevector.rebalance();
+    }
+
+    /// Add the density matrix of the density qubits, if any, to the sum of
+    /// the matrices of the shots.
+    fn accumulate_density(&mut self) {
+        let qubits = match &self.density_qubits {
+            None => return,
+            Some(qubits) => qubits,
+        };
+        let matrix = self.statevector.density_matrix(qubits);
+        if self.density_sum.is_empty() {
+            self.density_sum = matrix;
+        } else {
+            for (sum_row, row) in self.density_sum.iter_mut().zip(matrix) {
+                for (sum, element) in sum_row.iter_mut().zip(row) {
+                    *sum += element;
+                }
+            }
+        }
+        self.density_samples += 1;
+    }
+
+    /// Return the average of the density matrices of the shots.
+    fn average_density(&self) -> Vec<Vec<Complex>> {
+        let samples = self.density_samples.max(1) as f64;
+        self.density_sum
+            .iter()
+            .map(|row| row.iter().map(|element| element / samples).collect())
+            .collect()
+    }
+
+    fn track_memory(&mut self) {
+        let amplitude_bytes = self.statevector.amplitude_bytes();
+        let peak = &mut self.memory_usage.peak_amplitude_bytes;
+        *peak = (*peak).max(amplitude_bytes);
+    }
+
+    fn track_entanglement(&mut self) {
+        if let Some(cut) = self.entanglement_cut {
+            let entropy = self.statevector.entanglement_entropy_bits(cut);
+            self.max_entanglement = self.max_entanglement.max(entropy);
+        }
+    }
+
     fn check_all_are_quantum_registers(&self, args: &[ast::Argument]) -> Result<()> {
         for argument in args {
             let register_name = self.register_name(argument);
@@ -407,9 +912,12 @@ impl<'src, 'program> Runtime<'program> {
         }
     }
 
-    fn apply_gate_operations(&mut self, operations: &[ast::GateOperation]) -> Result<()> {
+    fn apply_gate_operations(
+        &mut self,
+        operations: &[ast::Spanned<ast::GateOperation>],
+    ) -> Result<()> {
         for one_operation in operations {
-            if let ast::GateOperation::Unitary(unitary) = one_operation {
+            if let ast::GateOperation::Unitary(unitary) = &one_operation.node {
                 self.apply_unitary(unitary)?;
             }
         }
@@ -426,29 +934,31 @@ impl<'src, 'program> Runtime<'program> {
 
     fn bit_mapping(&self, argument: &ast::Argument) -> Result<usize> {
         match argument {
-            ast::Argument::Item(name, index) => match self.semantics.memory_map.get(name) {
-                None => Err(RuntimeError::SymbolNotFound {
-                    location: *self
-                        .location
-                        .expect("after `apply_gates()`, location of the statement"),
-                    symbol_name: name.into(),
-                    expected: QasmType::Register,
-                }),
-                Some(mapping) => {
-                    let size = mapping.2 - mapping.1 + 1;
-                    if *index >= size {
-                        return Err(RuntimeError::IndexOutOfBounds {
-                            location: *self
-                                .location
-                                .expect("after `apply_gates()`, location of the statement"),
-                            symbol_name: name.into(),
-                            index: *index,
-                            size,
-                        });
+            ast::Argument::Item(name, index) => {
+                match self.semantics.memory_map.get(name.as_str()) {
+                    None => Err(RuntimeError::SymbolNotFound {
+                        location: *self
+                            .location
+                            .expect("after `apply_gates()`, location of the statement"),
+                        symbol_name: name.to_string(),
+                        expected: QasmType::Register,
+                    }),
+                    Some(mapping) => {
+                        let size = mapping.2 - mapping.1 + 1;
+                        if *index >= size {
+                            return Err(RuntimeError::IndexOutOfBounds {
+                                location: *self
+                                    .location
+                                    .expect("after `apply_gates()`, location of the statement"),
+                                symbol_name: name.to_string(),
+                                index: *index,
+                                size,
+                            });
+                        }
+                        Ok(mapping.1 + *index)
                     }
-                    Ok(mapping.1 + *index)
                 }
-            },
+            }
             _ => unreachable!("after `expand_arguments()`, argument should be Argument::Item"),
         }
     }
@@ -509,12 +1019,18 @@ impl<'src, 'program> Runtime<'program> {
         real_args: &[f64],
         args: &[ast::Argument],
     ) -> Result<BindingMappings> {
-        let definition = match self.semantics.macro_definitions.get(&macro_name) {
+        let definition = match self
+            .semantics
+            .macro_definitions
+            .get(&macro_name)
+            .or_else(|| self.semantics.opaque_definitions.get(&macro_name))
+        {
             None => {
                 return Err(RuntimeError::UndefinedGate {
                     location: *self
                         .location
                         .expect("after `apply_gates()`, the location of the statement"),
+                    suggestion: self.semantics.suggest_gate(&macro_name),
                     symbol_name: macro_name,
                 });
             }
@@ -614,16 +1130,7 @@ impl<'src, 'program> Runtime<'program> {
 ///
 /// [`parse_and_link()`]: ./fn.parse_and_link.html
 pub fn simulate(program: &ast::OpenQasmProgram) -> Result<Computation> {
-    let semantics = extract_semantics(program)?;
-    let mut runtime = Runtime::new(semantics);
-    runtime.apply_gates(&program.program)?;
-    Ok(Computation::new(
-        runtime.memory,
-        runtime.statevector,
-        None,
-        None,
-        None,
-    ))
+    simulate_with_options(program, None, &SimulationOptions::default())
 }
 
 /// Perform `shots` number of simulations of the parsed proram `program`.
@@ -668,60 +1175,584 @@ pub fn simulate(program: &ast::OpenQasmProgram) -> Result<Computation> {
 ///
 /// [`parse_and_link()`]: ./fn.parse_and_link.html
 pub fn simulate_with_shots(program: &ast::OpenQasmProgram, shots: usize) -> Result<Computation> {
-    let semantics = extract_semantics(program)?;
-    let mut runtime = Runtime::new(semantics);
-    let mut histogram_builder = HistogramBuilder::new();
-    for _ in 0..shots {
-        runtime.reset();
-        runtime.apply_gates(&program.program)?;
-        histogram_builder.update(&runtime.memory);
+    simulate_with_options(program, Some(shots), &SimulationOptions::default())
+}
+
+/// Perform a simulation of the parsed `program` with optional `shots`,
+/// configured by `options`.
+///
+/// # Errors
+///
+/// Simulate can fail during runtime returning an `Err` variant with a value
+/// of the [`RuntimeError`] type.
+///
+/// [`RuntimeError`]: ./error/enum.RuntimeError.html
+///
+/// # Examples
+///
+/// ```
+/// # use qasmsim::parse_and_link;
+/// use qasmsim::options::SimulationOptions;
+/// use qasmsim::simulate_with_options;
+///
+/// let program = parse_and_link(r#"
+///     OPENQASM 2.0;
+///     include "qelib1.inc";
+///     qreg q[2];
+///     h q[0];
+///     cx q[0], q[1];
+/// "#)?;
+/// let options = SimulationOptions {
+///     track_entanglement: Some(1),
+///     ..Default::default()
+/// };
+/// let computation = simulate_with_options(&program, None, &options)?;
+/// assert!((computation.max_entanglement().unwrap() - 1.0).abs() < 1e-9);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn simulate_with_options(
+    program: &ast::OpenQasmProgram,
+    shots: Option<usize>,
+    options: &SimulationOptions,
+) -> Result<Computation> {
+    simulate_in_mode(program, shots, None, options, &mut ThreadRandom, None)
+}
+
+/// Same as [`simulate_with_options()`] but drawing the random numbers
+/// deciding the outcomes of the measurements from `rng`.
+///
+/// # Errors
+///
+/// The function fails in the same cases [`simulate()`] does.
+///
+/// # Examples
+///
+/// ```
+/// # use qasmsim::parse_and_link;
+/// use qasmsim::options::SimulationOptions;
+/// use qasmsim::random::SeededRandom;
+/// use qasmsim::simulate_with_rng;
+///
+/// let program = parse_and_link(r#"
+///     OPENQASM 2.0;
+///     include "qelib1.inc";
+///     qreg q[2];
+///     creg c[2];
+///     h q;
+///     measure q -> c;
+/// "#)?;
+/// let options = SimulationOptions::default();
+/// let first = simulate_with_rng(&program, Some(100), &options, &mut SeededRandom::new(7))?;
+/// let second = simulate_with_rng(&program, Some(100), &options, &mut SeededRandom::new(7))?;
+/// assert_eq!(first.histogram(), second.histogram());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// [`simulate()`]: ./fn.simulate.html
+/// [`simulate_with_options()`]: ./fn.simulate_with_options.html
+pub fn simulate_with_rng(
+    program: &ast::OpenQasmProgram,
+    shots: Option<usize>,
+    options: &SimulationOptions,
+    rng: &mut dyn RandomSource,
+) -> Result<Computation> {
+    simulate_in_mode(program, shots, None, options, rng, None)
+}
+
+/// Perform a simulation of the parsed `program` and return, along with the
+/// computation, the trace of the random numbers drawn for deciding the
+/// outcomes of the measurements. Replay the trace with [`simulate_replay()`]
+/// to reproduce the same computation.
+///
+/// # Errors
+///
+/// The function fails in the same cases [`simulate()`] does.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::{parse_and_link, simulate_replay, simulate_traced};
+///
+/// let program = parse_and_link(r#"
+///     OPENQASM 2.0;
+///     include "qelib1.inc";
+///     qreg q[2];
+///     creg c[2];
+///     h q;
+///     measure q -> c;
+/// "#)?;
+/// let (computation, trace) = simulate_traced(&program)?;
+/// assert_eq!(trace.draws.len(), 2);
+/// assert_eq!(simulate_replay(&program, &trace)?, computation);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// [`simulate()`]: ./fn.simulate.html
+/// [`simulate_replay()`]: ./fn.simulate_replay.html
+pub fn simulate_traced(program: &ast::OpenQasmProgram) -> Result<(Computation, Trace)> {
+    let mut rng = RecordingRandom::new(ThreadRandom);
+    let computation = simulate_with_rng(program, None, &SimulationOptions::default(), &mut rng)?;
+    Ok((computation, Trace::new(rng.into_recorded())))
+}
+
+/// Perform a simulation of the parsed `program` deciding the outcomes of the
+/// measurements with the random numbers in `trace`, as recorded by
+/// [`simulate_traced()`].
+///
+/// # Errors
+///
+/// The function fails in the same cases [`simulate()`] does, with
+/// [`RuntimeError::InvalidTrace`] if any number in the trace is not in
+/// [0.0, 1.0), and with [`RuntimeError::TraceMismatch`] if the simulation
+/// does not draw exactly the random numbers in the trace.
+///
+/// [`simulate()`]: ./fn.simulate.html
+/// [`simulate_traced()`]: ./fn.simulate_traced.html
+/// [`RuntimeError::InvalidTrace`]: ./error/enum.RuntimeError.html#variant.InvalidTrace
+/// [`RuntimeError::TraceMismatch`]: ./error/enum.RuntimeError.html#variant.TraceMismatch
+pub fn simulate_replay(program: &ast::OpenQasmProgram, trace: &Trace) -> Result<Computation> {
+    check_draws(&trace.draws).map_err(|error| RuntimeError::InvalidTrace {
+        reason: error.to_string(),
+    })?;
+    let mut rng = TraceReplay {
+        draws: &trace.draws,
+        consumed: 0,
+    };
+    let computation = simulate_with_rng(program, None, &SimulationOptions::default(), &mut rng)?;
+    if rng.consumed != trace.draws.len() {
+        return Err(RuntimeError::TraceMismatch {
+            recorded: trace.draws.len(),
+            consumed: rng.consumed,
+        });
+    }
+    Ok(computation)
+}
+
+/// Source replaying a trace which, instead of failing when running out of
+/// numbers, keeps counting the numbers drawn.
+struct TraceReplay<'a> {
+    draws: &'a [f64],
+    consumed: usize,
+}
+
+impl RandomSource for TraceReplay<'_> {
+    fn next_f64(&mut self) -> f64 {
+        let draw = self.draws.get(self.consumed).copied().unwrap_or(0.0);
+        self.consumed += 1;
+        draw
     }
+}
 
+/// Perform a simulation of the parsed `program` with optional `shots`,
+/// configured by `options`, and keep the shot results required by `mode`.
+/// Measurement outcomes are decided by numbers drawn from `rng`.
+///
+/// The mode is ignored when simulating without shots. With no `mode`, the
+/// sequences of outcomes are not recorded but an empty list of sequences is
+/// returned when simulating with shots.
+///
+/// Setting `cancelled` stops the simulation before the next statement or
+/// shot with [`RuntimeError::Cancelled`].
+pub(crate) fn simulate_in_mode(
+    program: &ast::OpenQasmProgram,
+    shots: Option<usize>,
+    mode: Option<SimulationMode>,
+    options: &SimulationOptions,
+    rng: &mut dyn RandomSource,
+    cancelled: Option<&AtomicBool>,
+) -> Result<Computation> {
+    if let Some(max_qubits) = options.max_qubits {
+        check_qubit_limit(program, max_qubits)?;
+    }
+    if shots == Some(0) {
+        return Err(RuntimeError::InvalidShots { shots: 0 });
+    }
+    let record_sequences = mode == Some(SimulationMode::Sequence);
+    let mut semantics = extract_semantics(program)?;
+    if let Some(pad_qubits) = options.pad_qubits {
+        if pad_qubits < semantics.quantum_memory_size {
+            return Err(RuntimeError::PaddingTooNarrow {
+                qubits: semantics.quantum_memory_size,
+                pad_qubits,
+            });
+        }
+        semantics.quantum_memory_size = pad_qubits;
+    }
+    if let Some(qubits) = &options.density_qubits {
+        check_density_qubits(qubits, semantics.quantum_memory_size)
+            .map_err(|reason| RuntimeError::InvalidDensityQubits { reason })?;
+    }
+    if let Some(cut) = options.track_entanglement {
+        check_entanglement_cut(cut, semantics.quantum_memory_size)
+            .map_err(|reason| RuntimeError::InvalidEntanglementCut { reason })?;
+    }
+    let mut runtime = Runtime::new(semantics, options.backend, options.precision, rng);
+    runtime.entanglement_cut = options.track_entanglement;
+    runtime.density_qubits = options.density_qubits.clone();
+    runtime.treat_opaque_as_identity = options.treat_opaque_as_identity;
+    runtime.classical_parameters = options.extensions;
+    runtime.cancelled = cancelled;
+    let (histogram, sequences, stats) = match shots {
+        None => {
+            runtime.apply_gates(&program.program)?;
+            runtime.accumulate_density();
+            (None, None, None)
+        }
+        Some(shots) => {
+            let mut histogram_builder = if record_sequences {
+                HistogramBuilder::with_sequences()
+            } else {
+                HistogramBuilder::new()
+            };
+            // Sampling the terminal measurements skips the states of the
+            // shots after measuring, which the density matrix averages.
+            match terminal_measurements_start(&program.program) {
+                Some(start) if options.density_qubits.is_none() => {
+                    runtime.sample_terminal_measurements(
+                        &program.program,
+                        start,
+                        shots,
+                        &mut histogram_builder,
+                    )?;
+                }
+                _ => {
+                    for _ in 0..shots {
+                        runtime.check_cancelled()?;
+                        runtime.reset();
+                        runtime.apply_gates(&program.program)?;
+                        runtime.accumulate_density();
+                        histogram_builder.update(&runtime.memory, &runtime.written);
+                    }
+                }
+            }
+            let sequences = if mode.is_none() || record_sequences {
+                Some(histogram_builder.sequences)
+            } else {
+                None
+            };
+            // Report the bits written in any shot, not only in the last one.
+            runtime.written = histogram_builder.written;
+            (
+                Some(histogram_builder.histogram),
+                sequences,
+                Some(histogram_builder.stats),
+            )
+        }
+    };
+
+    let max_entanglement = options.track_entanglement.map(|_| runtime.max_entanglement);
+    let density_matrix = options
+        .density_qubits
+        .as_ref()
+        .map(|_| runtime.average_density());
+    let quantum_registers = runtime.quantum_registers();
+    let peak_memory_bytes = runtime
+        .memory_usage
+        .peak_bytes(runtime.semantics.quantum_memory_size);
     Ok(Computation::new(
         runtime.memory,
         runtime.statevector,
-        Some(histogram_builder.histogram),
-        Some(histogram_builder.sequences),
-        Some(histogram_builder.stats),
-    ))
+        histogram,
+        sequences,
+        stats,
+    )
+    .with_written_bits(runtime.written)
+    .with_max_entanglement(max_entanglement)
+    .with_quantum_registers(quantum_registers)
+    .with_gate_count(runtime.gate_count)
+    .with_peak_memory_bytes(peak_memory_bytes)
+    .with_density_matrix(density_matrix))
 }
 
-pub fn simulate_with_mode(
+/// Simulate the statements of `program` from `start` on, resuming from
+/// `statevector` and the values of the classical registers in `memory` and
+/// `written`, which the earlier statements led to.
+///
+/// Qubits declared from `start` on are added to `statevector` in the zero
+/// state, and classical registers declared from `start` on start at 0.
+pub(crate) fn resume(
     program: &ast::OpenQasmProgram,
-    shots: usize,
-    mode: String,
+    start: usize,
+    statevector: &StateVector,
+    memory: &HashMap<String, (u128, usize, usize)>,
+    written: &HashMap<String, u128>,
+    rng: &mut dyn RandomSource,
 ) -> Result<Computation> {
     let semantics = extract_semantics(program)?;
-    let mut runtime = Runtime::new(semantics);
-    let mut histogram_builder = HistogramBuilder::new();
+    let width = semantics.quantum_memory_size;
+    let mut runtime = Runtime::new(semantics, Backend::Dense, Precision::Double, rng);
+    let mut amplitudes: Vec<Complex> = statevector.amplitudes().copied().collect();
+    amplitudes.resize(1 << width, Complex::from(0.0));
+    runtime.statevector = State::Dense(StateVector::from_complex_bases_unchecked(amplitudes));
+    for (name, (value, _, _)) in memory {
+        if let Some(entry) = runtime.memory.get_mut(name) {
+            entry.0 = *value;
+        }
+    }
+    for (name, bits) in written {
+        if let Some(entry) = runtime.written.get_mut(name) {
+            *entry = *bits;
+        }
+    }
+    runtime.apply_gates(&program.program[start..])?;
+    let quantum_registers = runtime.quantum_registers();
+    Ok(
+        Computation::new(runtime.memory, runtime.statevector, None, None, None)
+            .with_written_bits(runtime.written)
+            .with_quantum_registers(quantum_registers)
+            .with_gate_count(runtime.gate_count),
+    )
+}
 
-    if mode == "sequence" {
-        for _ in 0..shots {
-            runtime.reset();
-            runtime.apply_gates(&program.program)?;
-            histogram_builder.update_sequences(&runtime.memory);
-        }
-        Ok(Computation::new(
-            runtime.memory,
-            runtime.statevector,
-            Some(histogram_builder.histogram),
-            Some(histogram_builder.sequences),
-            Some(histogram_builder.stats),
-        ))
-    } else if mode == "aggregation" || mode == "max" || mode == "min" {
-        for _ in 0..shots {
-            runtime.reset();
-            runtime.apply_gates(&program.program)?;
-            histogram_builder.update(&runtime.memory);
-        }
-        Ok(Computation::new(
-            runtime.memory,
-            runtime.statevector,
-            Some(histogram_builder.histogram),
-            None,
-            Some(histogram_builder.stats),
-        ))
-    } else {
-        Err(RuntimeError::Other)
+/// Return the index of the first measurement in `statements` if no gate is
+/// applied after it and there are no conditionals nor resets, or the number of
+/// statements if there are no measurements. Otherwise, return `None`.
+///
+/// The state before measuring is the same for all the shots of such programs
+/// so it can be computed once and sampled afterwards.
+fn terminal_measurements_start(statements: &[ast::Span<ast::Statement>]) -> Option<usize> {
+    let mut start = None;
+    for (index, span) in statements.iter().enumerate() {
+        match &*span.node {
+            ast::Statement::QuantumOperation(ast::QuantumOperation::Measure(_, _)) => {
+                start.get_or_insert(index);
+            }
+            ast::Statement::QuantumOperation(ast::QuantumOperation::Unitary(_))
+                if start.is_some() =>
+            {
+                return None;
+            }
+            ast::Statement::QuantumOperation(ast::QuantumOperation::Reset(_))
+            | ast::Statement::Conditional(..)
+            | ast::Statement::ConditionalBlock(..) => return None,
+            _ => (),
+        }
+    }
+    Some(start.unwrap_or(statements.len()))
+}
+
+/// Fail at the first quantum register declaration making the number of
+/// qubits in `program` to exceed `max_qubits`.
+fn check_qubit_limit(program: &ast::OpenQasmProgram, max_qubits: usize) -> Result<()> {
+    let mut qubits = 0;
+    for span in &program.program {
+        if let ast::Statement::QRegDecl(_, size) = &*span.node {
+            qubits += size;
+            if qubits > max_qubits {
+                return Err(RuntimeError::TooManyQubits {
+                    location: span.boundaries.0,
+                    qubits: program.qubit_width(),
+                    max_qubits,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Simulate the parsed `program` `shots` times and return the values of the
+/// classical registers at the end of each simulation.
+///
+/// Only the classical memory is kept between shots so memory consumption
+/// does not grow with the size of the statevector.
+///
+/// # Errors
+///
+/// The function fails in the same cases [`simulate()`] does, and with
+/// [`RuntimeError::InvalidShots`] if `shots` is 0.
+///
+/// [`simulate()`]: ./fn.simulate.html
+/// [`RuntimeError::InvalidShots`]: ./error/enum.RuntimeError.html#variant.InvalidShots
+pub fn sample(program: &ast::OpenQasmProgram, shots: usize) -> Result<Vec<HashMap<String, u128>>> {
+    if shots == 0 {
+        return Err(RuntimeError::InvalidShots { shots });
+    }
+    let semantics = extract_semantics(program)?;
+    let mut rng = ThreadRandom;
+    let mut runtime = Runtime::new(semantics, Backend::Dense, Precision::Double, &mut rng);
+    let mut samples = Vec::with_capacity(shots);
+    for _ in 0..shots {
+        runtime.reset();
+        runtime.apply_gates(&program.program)?;
+        samples.push(
+            runtime
+                .memory
+                .iter()
+                .map(|(name, (value, _, _))| (name.clone(), *value))
+                .collect(),
+        );
+    }
+    Ok(samples)
+}
+
+/// Simulate a program once per item and yield the values of the classical
+/// registers at the end of each simulation, as [`sample()`] does but without
+/// keeping the samples. The iterator ends after the first failing shot.
+pub(crate) struct Shots {
+    program: ast::OpenQasmProgram,
+    semantics: Option<Semantics>,
+    rng: Box<dyn RandomSource>,
+}
+
+impl Shots {
+    pub fn new(program: ast::OpenQasmProgram, rng: Box<dyn RandomSource>) -> Result<Self> {
+        let semantics = extract_semantics(&program)?;
+        Ok(Shots {
+            program,
+            semantics: Some(semantics),
+            rng,
+        })
+    }
+}
+
+impl Iterator for Shots {
+    type Item = Result<HashMap<String, u128>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let semantics = self.semantics.take()?;
+        let mut runtime =
+            Runtime::new(semantics, Backend::Dense, Precision::Double, &mut *self.rng);
+        if let Err(error) = runtime.apply_gates(&self.program.program) {
+            return Some(Err(error));
+        }
+        let shot = runtime
+            .memory
+            .iter()
+            .map(|(name, (value, _, _))| (name.clone(), *value))
+            .collect();
+        self.semantics = Some(runtime.semantics);
+        Some(Ok(shot))
+    }
+}
+
+/// Simulate the parsed `program` and return its final state post-selected
+/// on the outcomes in `constraints`.
+///
+/// Each constraint is the name of a quantum register, the index of a qubit
+/// in the register and the outcome that qubit must have. The final state is
+/// projected on every outcome, in order, and renormalized, as if measuring
+/// the qubits and keeping only the runs with the expected results. The
+/// measurements in the program, if any, are simulated as usual before.
+///
+/// # Errors
+///
+/// The function fails in the same cases [`simulate()`] does, and with
+/// [`RuntimeError::InvalidPostselection`] if a constraint refers to a qubit
+/// that does not exist or to an outcome with probability 0.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::{parse_and_link, simulate_postselected};
+///
+/// let program = parse_and_link(r#"
+/// OPENQASM 2.0;
+/// include "qelib1.inc";
+/// qreg q[2];
+/// h q[0];
+/// cx q[0], q[1];
+/// "#)?;
+/// let statevector = simulate_postselected(&program, &[("q".to_string(), 0, true)]).unwrap();
+/// assert!((statevector.probabilities()[0b11] - 1.0).abs() < 1e-12);
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// [`simulate()`]: ./fn.simulate.html
+/// [`RuntimeError::InvalidPostselection`]: ./error/enum.RuntimeError.html#variant.InvalidPostselection
+pub fn simulate_postselected(
+    program: &ast::OpenQasmProgram,
+    constraints: &[(String, usize, bool)],
+) -> Result<StateVector> {
+    let computation = simulate(program)?;
+    let mut statevector = computation.statevector().clone();
+    for (register, index, outcome) in constraints {
+        let qubit = match computation.quantum_registers().get(register) {
+            Some(&(start, size)) if *index < size => start + index,
+            Some(&(_, size)) => {
+                return Err(RuntimeError::InvalidPostselection {
+                    reason: format!(
+                        "qubit {} is out of the {} qubits of register `{}`",
+                        index, size, register
+                    ),
+                })
+            }
+            None => {
+                return Err(RuntimeError::InvalidPostselection {
+                    reason: format!("there is no quantum register `{}`", register),
+                })
+            }
+        };
+        if statevector.postselect(qubit, *outcome) <= f64::EPSILON {
+            return Err(RuntimeError::InvalidPostselection {
+                reason: format!(
+                    "outcome {} of `{}[{}]` has probability 0",
+                    *outcome as u8, register, index
+                ),
+            });
+        }
+    }
+    Ok(statevector)
+}
+
+/// Simulate the parsed `program` `shots` times and accumulate, for each value
+/// of the classical registers, the weights `weight` assigns to the shots
+/// producing them.
+///
+/// `weight` receives the values of the classical registers at the end of
+/// each shot. Giving a weight of `1.0` to every shot reproduces the counts
+/// of [`simulate_with_shots()`].
+///
+/// # Errors
+///
+/// The function fails in the same cases [`simulate()`] does, and with
+/// [`RuntimeError::InvalidShots`] if `shots` is 0.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::{parse_and_link, simulate_weighted};
+///
+/// let program = parse_and_link(r#"
+///     OPENQASM 2.0;
+///     include "qelib1.inc";
+///     qreg q[1];
+///     creg c[1];
+///     x q[0];
+///     measure q[0] -> c[0];
+/// "#)?;
+/// let histogram = simulate_weighted(&program, 10, |_| -0.5)?;
+/// assert_eq!(histogram["c"].0, vec![(1, -5.0)]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// [`simulate()`]: ./fn.simulate.html
+/// [`simulate_with_shots()`]: ./fn.simulate_with_shots.html
+/// [`RuntimeError::InvalidShots`]: ./error/enum.RuntimeError.html#variant.InvalidShots
+pub fn simulate_weighted<F>(
+    program: &ast::OpenQasmProgram,
+    shots: usize,
+    mut weight: F,
+) -> Result<WeightedHistogram>
+where
+    F: FnMut(&HashMap<String, u128>) -> f64,
+{
+    if shots == 0 {
+        return Err(RuntimeError::InvalidShots { shots });
+    }
+    let semantics = extract_semantics(program)?;
+    let mut rng = ThreadRandom;
+    let mut runtime = Runtime::new(semantics, Backend::Dense, Precision::Double, &mut rng);
+    let mut histogram = WeightedHistogram::new();
+    for _ in 0..shots {
+        runtime.reset();
+        runtime.apply_gates(&program.program)?;
+        let values = runtime
+            .memory
+            .iter()
+            .map(|(name, (value, _, _))| (name.clone(), *value))
+            .collect();
+        update_weighted_histogram(&mut histogram, &runtime.memory, weight(&values));
     }
+    Ok(histogram)
 }
@@ -61,18 +61,32 @@
 //! ```
 #[macro_use]
 pub mod error;
+
+#[cfg(feature = "memory-tracking")]
+pub mod alloc_tracking;
+pub mod circuit;
+pub mod diagram;
 pub mod grammar;
 pub mod options;
+pub mod output;
+pub mod random;
 pub mod statevector;
 
 pub use crate::{
     arch::native::{
-        get_gate_info, parse_and_link, run, run_mode, simulate, simulate_with_shots, Execution,
-        ExecutionTimes,
+        compile, decompose_to_qasm, get_builtin_library_version, get_gate_info, optimize_circuit,
+        parse_and_link, parse_and_link_with_extensions, program_registers, register_library, run,
+        run_and_sample, run_mode, run_with_options, run_with_timeout, simulate, simulate_body,
+        simulate_postselected, simulate_replay, simulate_traced, simulate_weighted,
+        simulate_with_options, simulate_with_rng, simulate_with_shots, streaming_shots,
+        substitute_gate, symbols, Execution, ExecutionTimes, GateInfo, GateLibrary, LinkedProgram,
+        PrimitiveOp, QasmSim, Session,
     },
+    circuit::{amplitude_encode, Circuit},
+    complex::ComplexMargin,
     error::QasmSimError,
-    interpreter::{Computation, Histogram},
-    output::output::{print_info, print_result},
+    interpreter::{Computation, Histogram, ShotRecord, WeightedHistogram},
+    output::output::{print_info, print_result, write_result, Tee},
     semantics::QasmType,
 };
 
@@ -81,7 +95,5 @@ mod arch;
 mod complex;
 mod interpreter;
 mod linker;
-mod output;
 mod qe;
-mod random;
 mod semantics;
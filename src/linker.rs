@@ -1,10 +1,13 @@
 //! Contain utilities for combining multiple AST spread into several locations.
 //! The module is **unstable**.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
 
+use crate::error::QasmSimError;
 use crate::grammar::ast;
-use crate::grammar::lexer::Location;
+use crate::grammar::lexer::{Lexer, Location, Tok};
 use crate::grammar::parse_library;
 
 /// Represent a filure during linkage.
@@ -40,6 +43,158 @@ pub enum LinkerError {
         /// Library path passed to the `include` directive.
         libpath: String,
     },
+    /// The library included at `location` declares a gate already declared,
+    /// differently, by a library included before.
+    GateRedefinition {
+        /// Location of the `include` directive of the later library.
+        location: Location,
+        /// Name of the gate.
+        symbol_name: String,
+        /// Path of the later library.
+        libpath: String,
+        /// Path of the library declaring the gate first.
+        previous_libpath: String,
+    },
+    /// A registered library declares a gate the program already declares
+    /// differently at `location`.
+    RegisteredGateRedefinition {
+        /// Location of the declaration in the program.
+        location: Location,
+        /// Name of the gate.
+        symbol_name: String,
+    },
+    /// The library included at `location` is not valid OPENQASM.
+    InvalidLibrary {
+        /// Location of the `include` directive.
+        location: Location,
+        /// Library path passed to the `include` directive.
+        libpath: String,
+        /// Line of the library where parsing fails.
+        library_lineno: usize,
+        /// Column of the library where parsing fails.
+        library_column: usize,
+        /// Description of the parsing error.
+        reason: String,
+    },
+}
+
+impl fmt::Display for LinkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match lazy_humanize! {
+            self,
+            LinkerError::LibraryNotFound,
+            LinkerError::GateRedefinition,
+            LinkerError::RegisteredGateRedefinition,
+            LinkerError::InvalidLibrary
+        } {
+            Some(message) => message,
+            None => unreachable!(),
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl error::Error for LinkerError {}
+
+/// A set of gate declarations to add to a program with
+/// [`register_library()`], without writing the library to disk and
+/// including it.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::{parse_and_link, register_library, GateLibrary};
+///
+/// let library = GateLibrary::from_qasm_string(r#"
+///     gate bell a, b { U(pi/2, 0, pi) a; CX a, b; }
+/// "#)?;
+/// let mut program = parse_and_link(r#"
+///     OPENQASM 2.0;
+///     qreg q[2];
+///     bell q[0], q[1];
+/// "#)?;
+/// register_library(&mut program, library).unwrap();
+/// assert!(program.gate_names().contains(&"bell"));
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// [`register_library()`]: ./fn.register_library.html
+#[derive(Debug, Clone, Default, PartialEq)]
+
+pub struct GateLibrary {
+    gates: Vec<ast::Statement>,
+}
+
+impl GateLibrary {
+    /// Parse `source`, a fragment of OPENQASM containing only gate
+    /// declarations, as the ones in `.inc` files.
+    ///
+    /// # Errors
+    ///
+    /// The function fails with [`QasmSimError`] if `source` is not a valid
+    /// library.
+    ///
+    /// [`QasmSimError`]: ./error/enum.QasmSimError.html
+    pub fn from_qasm_string(source: &str) -> std::result::Result<Self, QasmSimError<'_>> {
+        let library = parse_library(source)?;
+        Ok(GateLibrary {
+            gates: library
+                .definitions
+                .into_iter()
+                .map(|span| *span.node)
+                .collect(),
+        })
+    }
+
+    /// Return the names of the gates in the library, in order of
+    /// declaration.
+    pub fn gate_names(&self) -> Vec<&str> {
+        self.gates.iter().filter_map(declared_gate_name).collect()
+    }
+}
+
+/// Add the gates declared in `library` to the beginning of `program`, so
+/// the program can use them as if it included the library.
+///
+/// As when linking libraries, gates the program already declares
+/// identically, save for the docstrings, are skipped, so registering the
+/// same library twice has no effect.
+///
+/// # Errors
+///
+/// The function fails with [`LinkerError::RegisteredGateRedefinition`] if
+/// the library declares a gate the program declares differently.
+///
+/// [`LinkerError::RegisteredGateRedefinition`]: ./error/enum.LinkerError.html#variant.RegisteredGateRedefinition
+pub fn register_library(program: &mut ast::OpenQasmProgram, library: GateLibrary) -> Result<()> {
+    let mut declarations = HashMap::new();
+    for span in &program.program {
+        if let Some(name) = declared_gate_name(&span.node) {
+            declarations.insert(name.to_owned(), span);
+        }
+    }
+    let mut new_spans = Vec::new();
+    for definition in library.gates {
+        if let Some(name) = declared_gate_name(&definition) {
+            if let Some(previous) = declarations.get(name) {
+                if !same_declaration(&previous.node, &definition) {
+                    return Err(LinkerError::RegisteredGateRedefinition {
+                        location: previous.boundaries.0,
+                        symbol_name: name.into(),
+                    });
+                }
+                continue;
+            }
+        }
+        new_spans.push(ast::Span {
+            boundaries: (Location::new(), Location::new()),
+            node: Box::new(definition),
+            origin: None,
+        });
+    }
+    program.program.splice(0..0, new_spans);
+    Ok(())
 }
 
 #[derive(Debug, Clone, Default)]
@@ -57,32 +212,100 @@ impl Linker {
 
     /// Look into `tree` for `include` statements, parse the referred libraries,
     /// and integrate their ASTs into `tree`, effectively modifying `tree`.
-    pub fn link(&self, mut tree: ast::OpenQasmProgram) -> Result<ast::OpenQasmProgram> {
+    ///
+    /// The statements of a library keep the boundaries of the `include`
+    /// statement, and their [`origin`] relates them with the library path
+    /// and their boundaries in the library source.
+    ///
+    /// Libraries are included once: repeating the `include` of a library has
+    /// no effect. Several libraries can declare the same gate as long as the
+    /// declarations are identical, save for the docstrings, in which case
+    /// only the first one is kept. Declaring the same gate differently fails
+    /// with [`LinkerError::GateRedefinition`] instead of letting one
+    /// definition silently win, since which one the program expects cannot
+    /// be known.
+    ///
+    /// [`LinkerError::GateRedefinition`]: ./enum.LinkerError.html#variant.GateRedefinition
+    /// [`origin`]: ../grammar/ast/struct.Span.html#structfield.origin
+    pub fn link(&self, tree: ast::OpenQasmProgram) -> Result<ast::OpenQasmProgram> {
+        Ok(self.link_with_origins(tree)?.0)
+    }
+
+    /// Same as [`link()`] but also return a map relating the names of the
+    /// gates declared in the included libraries with the path of the library.
+    ///
+    /// [`link()`]: #method.link
+    pub fn link_with_origins(
+        &self,
+        mut tree: ast::OpenQasmProgram,
+    ) -> Result<(ast::OpenQasmProgram, HashMap<String, String>)> {
+        let mut origins: HashMap<String, String> = HashMap::new();
+        let mut included = HashSet::new();
+        let mut declarations: HashMap<String, ast::Statement> = HashMap::new();
         let mut to_embed = vec![];
         for (index, span) in tree.program.iter().enumerate() {
             if let ast::Statement::Include(libpath) = &*span.node {
+                let libpath = libpath.as_str();
+                if !included.insert(libpath) {
+                    to_embed.push((index, Vec::new()));
+                    continue;
+                }
                 let source = self
                     .sources(libpath)
                     .map_err(|_| LinkerError::LibraryNotFound {
                         location: span.boundaries.0,
                         libpath: libpath.into(),
                     })?;
-                let library_tree = parse_library(&source).unwrap();
-                to_embed.push((index, span.boundaries, library_tree.definitions));
+                let library_tree = parse_library(&source).map_err(|error| {
+                    let diagnostic = error.to_diagnostic();
+                    LinkerError::InvalidLibrary {
+                        location: span.boundaries.0,
+                        libpath: libpath.into(),
+                        library_lineno: diagnostic.line,
+                        library_column: diagnostic.column,
+                        reason: diagnostic.message,
+                    }
+                })?;
+                let mut definitions = Vec::new();
+                for definition in library_tree.definitions {
+                    if let Some(name) = declared_gate_name(&definition.node) {
+                        if let Some(previous) = declarations.get(name) {
+                            if !same_declaration(previous, &definition.node) {
+                                return Err(LinkerError::GateRedefinition {
+                                    location: span.boundaries.0,
+                                    symbol_name: name.into(),
+                                    libpath: libpath.into(),
+                                    previous_libpath: origins[name].clone(),
+                                });
+                            }
+                            continue;
+                        }
+                        origins.insert(name.to_owned(), libpath.to_owned());
+                        declarations.insert(name.to_owned(), (*definition.node).clone());
+                    }
+                    definitions.push(ast::Span {
+                        boundaries: span.boundaries,
+                        node: definition.node,
+                        origin: Some(ast::Origin {
+                            libpath: libpath.to_owned(),
+                            boundaries: definition.boundaries,
+                        }),
+                    });
+                }
+                to_embed.push((index, definitions));
             }
         }
         to_embed.reverse();
-        for (index, boundaries, statements) in to_embed {
-            let mut inner_spans = vec![];
-            for one_statement in statements {
-                inner_spans.push(ast::Span {
-                    boundaries,
-                    node: Box::new(one_statement),
-                })
-            }
+        for (index, inner_spans) in to_embed {
             tree.program.splice(index..=index, inner_spans);
         }
-        Ok(tree)
+        Ok((tree, origins))
+    }
+
+    /// Return the line (1-based) of the declaration of the gate `gate_name`
+    /// in the library `libpath`, if the library declares it.
+    pub(crate) fn declaration_line(&self, libpath: &str, gate_name: &str) -> Option<usize> {
+        declaration_line(&self.sources(libpath).ok()?, gate_name)
     }
 
     fn sources(&self, libpath: &str) -> std::result::Result<String, ()> {
@@ -93,6 +316,52 @@ impl Linker {
     }
 }
 
+/// Return the line (1-based) of the `gate` or `opaque` keyword declaring the
+/// gate `gate_name` in `source`, if `source` declares it. Unlike the span of
+/// the declaration, the line does not include the docstring.
+pub(crate) fn declaration_line(source: &str, gate_name: &str) -> Option<usize> {
+    let mut keyword = None;
+    for token in Lexer::new(source) {
+        let (start, token, _) = token.ok()?;
+        match token {
+            Tok::Gate | Tok::Opaque => {
+                keyword.get_or_insert(start);
+            }
+            Tok::Id { repr } if repr == gate_name => {
+                if let Some(keyword) = keyword {
+                    return Some(keyword.line_column(source).0);
+                }
+            }
+            _ => keyword = None,
+        }
+    }
+    None
+}
+
+pub(crate) fn declared_gate_name(statement: &ast::Statement) -> Option<&str> {
+    match statement {
+        ast::Statement::GateDecl { signature, .. } => Some(signature.0.as_str()),
+        ast::Statement::OpaqueGateDecl { signature, .. } => Some(signature.0.as_str()),
+        _ => None,
+    }
+}
+
+/// Return whether two gate declarations are equal, ignoring their
+/// docstrings.
+pub(crate) fn same_declaration(a: &ast::Statement, b: &ast::Statement) -> bool {
+    match (a, b) {
+        (
+            ast::Statement::GateDecl { signature: a, .. },
+            ast::Statement::GateDecl { signature: b, .. },
+        ) => a == b,
+        (
+            ast::Statement::OpaqueGateDecl { signature: a, .. },
+            ast::Statement::OpaqueGateDecl { signature: b, .. },
+        ) => a == b,
+        _ => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -102,15 +371,6 @@ mod tests {
 
     use super::*;
 
-    macro_rules! span {
-        ($left:expr, $node:expr, $right:expr) => {
-            Span {
-                boundaries: (Location($left), Location($right)),
-                node: Box::new($node),
-            }
-        };
-    }
-
     #[test]
     fn test_linker_loads_embedded_libraries() {
         let source = indoc!(
@@ -129,15 +389,251 @@ mod tests {
             linked_tree,
             ast::OpenQasmProgram {
                 version: "2.0".to_owned(),
-                program: vec![span!(
-                    14,
-                    ast::Statement::GateDecl {
-                        signature: ("test".to_owned(), vec![], vec!["q".to_string()], vec![]),
+                program: vec![Span {
+                    boundaries: (Location(14), Location(33)),
+                    node: Box::new(ast::Statement::GateDecl {
+                        signature: ("test".into(), vec![], vec!["q".into()], vec![]),
                         docstring: None
-                    },
-                    33
-                )]
+                    }),
+                    origin: Some(ast::Origin {
+                        libpath: "test.inc".into(),
+                        boundaries: (Location(0), Location(17)),
+                    }),
+                }]
             }
         )
     }
+
+    #[test]
+    fn test_linker_reports_the_origin_of_library_gates() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    include \"test.inc\";
+    gate other q {}
+    "
+        );
+        let linker = Linker::with_embedded(HashMap::from_iter(vec![(
+            "test.inc".to_owned(),
+            "gate test () q {}\nopaque gate test2 q;".to_owned(),
+        )]));
+        let tree = parse_program(source).unwrap();
+        let (_, origins) = linker.link_with_origins(tree).unwrap();
+        assert_eq!(
+            origins,
+            HashMap::from_iter(vec![
+                ("test".to_owned(), "test.inc".to_owned()),
+                ("test2".to_owned(), "test.inc".to_owned()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_library_statements_keep_the_span_of_the_include() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[1];
+    "
+        );
+        let linker = Linker::with_embedded(HashMap::from_iter(vec![(
+            "qelib1.inc".to_owned(),
+            crate::qe::QELIB1.to_owned(),
+        )]));
+        let tree = parse_program(source).unwrap();
+        let (linked_tree, origins) = linker.link_with_origins(tree).unwrap();
+        let include_offset = source.find("include").unwrap();
+        let included = linked_tree
+            .statement_at(include_offset)
+            .expect("some included statement");
+        let name = declared_gate_name(included).expect("a gate declaration");
+        assert_eq!(origins[name], "qelib1.inc");
+        assert_eq!(
+            linked_tree.statement_at(source.find("qreg").unwrap()),
+            Some(&ast::Statement::QRegDecl("q".into(), 1))
+        );
+    }
+
+    #[test]
+    fn test_library_statements_keep_their_spans_in_the_library() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    "
+        );
+        let library = crate::qe::QELIB1;
+        let text = |boundaries: (Location, Location)| &library[boundaries.0 .0..boundaries.1 .0];
+        let linker = Linker::with_embedded(HashMap::from_iter(vec![(
+            "qelib1.inc".to_owned(),
+            library.to_owned(),
+        )]));
+        let linked_tree = linker.link(parse_program(source).unwrap()).unwrap();
+        let hadamard = linked_tree
+            .program
+            .iter()
+            .find(|span| declared_gate_name(&span.node) == Some("h"))
+            .expect("the h gate");
+        let origin = hadamard.origin.as_ref().expect("a library origin");
+        assert_eq!(origin.libpath, "qelib1.inc");
+        assert_eq!(
+            text(origin.boundaries),
+            "// Clifford gate: Hadamard\ngate h a { u2(0,pi) a; }"
+        );
+        match &*hadamard.node {
+            ast::Statement::GateDecl {
+                signature: (name, _, qubits, body),
+                ..
+            } => {
+                assert_eq!(text(name.boundaries), "h");
+                assert_eq!(text(qubits[0].boundaries), "a");
+                assert_eq!(text(body[0].boundaries), "u2(0,pi) a;");
+                match &body[0].node {
+                    ast::GateOperation::Unitary(ast::UnitaryOperation(name, parameters, _)) => {
+                        assert_eq!(text(name.boundaries), "u2");
+                        assert_eq!(text(parameters[1].boundaries), "pi");
+                    }
+                    other => panic!("unexpected operation {:?}", other),
+                }
+            }
+            other => panic!("unexpected statement {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_linker_includes_each_library_once() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    include \"test.inc\";
+    include \"test.inc\";
+    "
+        );
+        let linker = Linker::with_embedded(HashMap::from_iter(vec![(
+            "test.inc".to_owned(),
+            "gate test q {}".to_owned(),
+        )]));
+        let tree = parse_program(source).unwrap();
+        let linked_tree = linker.link(tree).unwrap();
+        assert_eq!(linked_tree.program.len(), 1);
+    }
+
+    #[test]
+    fn test_linker_keeps_the_first_of_identical_declarations() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    include \"a.inc\";
+    include \"b.inc\";
+    "
+        );
+        let linker = Linker::with_embedded(HashMap::from_iter(vec![
+            (
+                "a.inc".to_owned(),
+                "gate test q { U(0, 0, 0) q; }".to_owned(),
+            ),
+            (
+                "b.inc".to_owned(),
+                "// Same gate.\ngate test q { U(0, 0, 0) q; }\ngate other q {}".to_owned(),
+            ),
+        ]));
+        let tree = parse_program(source).unwrap();
+        let (linked_tree, origins) = linker.link_with_origins(tree).unwrap();
+        assert_eq!(linked_tree.gate_names(), vec!["test", "other"]);
+        assert_eq!(origins["test"], "a.inc");
+        assert_eq!(origins["other"], "b.inc");
+    }
+
+    #[test]
+    fn test_linker_rejects_different_declarations() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    include \"a.inc\";
+    include \"b.inc\";
+    "
+        );
+        let linker = Linker::with_embedded(HashMap::from_iter(vec![
+            (
+                "a.inc".to_owned(),
+                "gate test q { U(0, 0, 0) q; }".to_owned(),
+            ),
+            ("b.inc".to_owned(), "opaque gate test q;".to_owned()),
+        ]));
+        let tree = parse_program(source).unwrap();
+        assert_eq!(
+            linker.link(tree).unwrap_err(),
+            LinkerError::GateRedefinition {
+                location: Location(31),
+                symbol_name: "test".into(),
+                libpath: "b.inc".into(),
+                previous_libpath: "a.inc".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_register_library_prepends_the_gates() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    qreg q[1];
+    test q[0];
+    "
+        );
+        let library = GateLibrary::from_qasm_string("gate test q { U(0, 0, 0) q; }").unwrap();
+        assert_eq!(library.gate_names(), vec!["test"]);
+        let mut tree = parse_program(source).unwrap();
+        register_library(&mut tree, library.clone()).unwrap();
+        assert_eq!(tree.program.len(), 3);
+        assert_eq!(tree.gate_names(), vec!["test"]);
+
+        register_library(&mut tree, library).unwrap();
+        assert_eq!(tree.program.len(), 3);
+    }
+
+    #[test]
+    fn test_register_library_rejects_different_declarations() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    gate test q {}
+    "
+        );
+        let library = GateLibrary::from_qasm_string("opaque gate test q;").unwrap();
+        let mut tree = parse_program(source).unwrap();
+        assert_eq!(
+            register_library(&mut tree, library).unwrap_err(),
+            LinkerError::RegisteredGateRedefinition {
+                location: Location(14),
+                symbol_name: "test".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_linker_rejects_invalid_libraries() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    include \"a.inc\";
+    "
+        );
+        let linker = Linker::with_embedded(HashMap::from_iter(vec![(
+            "a.inc".to_owned(),
+            "gate broken q {\n  U(0, 0, 0) q".to_owned(),
+        )]));
+        let tree = parse_program(source).unwrap();
+        assert_eq!(
+            linker.link(tree).unwrap_err(),
+            LinkerError::InvalidLibrary {
+                location: Location(14),
+                libpath: "a.inc".into(),
+                library_lineno: 2,
+                library_column: 15,
+                reason: "expected \";\", or \"[\", found EOF".into(),
+            }
+        );
+    }
 }
@@ -1,4 +1,10 @@
-//! This module contains the definition of the command line options.
+//! This module contains the definition of the command line and simulation
+//! options.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::QasmSimError;
 
 /// Output format.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -10,6 +16,63 @@ pub enum Format {
     Json,
 }
 
+/// What to keep from the shots of a simulation.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+
+pub enum SimulationMode {
+    /// Aggregate the outcomes of all the shots in a histogram.
+    #[default]
+    Aggregation,
+
+    /// Keep the outcomes of each shot, in order.
+    Sequence,
+
+    /// Keep only the least frequent outcome. Ties are broken in favor of
+    /// the lexicographically smallest outcome, and reported.
+    Min,
+
+    /// Keep only the most frequent outcome. Ties are broken in favor of the
+    /// lexicographically smallest outcome, and reported.
+    Max,
+}
+
+impl fmt::Display for SimulationMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SimulationMode::Aggregation => "aggregation",
+            SimulationMode::Sequence => "sequence",
+            SimulationMode::Min => "min",
+            SimulationMode::Max => "max",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Parse the names `"aggregation"`, `"sequence"`, `"min"` and `"max"`.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::options::SimulationMode;
+///
+/// assert_eq!("sequence".parse(), Ok(SimulationMode::Sequence));
+/// assert!("average".parse::<SimulationMode>().is_err());
+/// ```
+impl FromStr for SimulationMode {
+    type Err = QasmSimError<'static>;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "aggregation" => Ok(SimulationMode::Aggregation),
+            "sequence" => Ok(SimulationMode::Sequence),
+            "min" => Ok(SimulationMode::Min),
+            "max" => Ok(SimulationMode::Max),
+            _ => Err(QasmSimError::InvalidMode(mode.to_string())),
+        }
+    }
+}
+
 /// Output options.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Options {
@@ -34,11 +97,39 @@ pub struct Options {
     /// Prints times measured for parsing and simulating.
     pub times: bool,
 
+    /// Prints the value of each classical bit alongside the register value.
+    pub show_bits: bool,
+
+    /// Prints registers never written by a measurement as `-` (or `null` in
+    /// JSON) instead of 0.
+    pub mark_unwritten: bool,
+
+    /// Prints the marginal probabilities of each quantum register instead of
+    /// the probabilities of the whole state. Only honored by the JSON format.
+    pub split_registers: bool,
+
+    /// Prints the Bloch vector of each qubit. Ignored if shots is set.
+    pub bloch: bool,
+
+    /// Prints only the `k` most probable basis states of the state vector and
+    /// the probabilities vector, from the most to the least probable. Prints
+    /// all of them, in order, if `None`.
+    pub max_states: Option<usize>,
+
+    /// Prints only the classical registers with these names, in the memory,
+    /// the histogram and the outcomes of the shots. Prints all of them if
+    /// `None`. The simulation still records every register.
+    pub register_filter: Option<Vec<String>>,
+
     /// Specify the number of simulations.
     pub shots: Option<usize>,
 
+    /// Prints the frequency of each outcome of the shots, and its Wilson
+    /// score 95% confidence interval, alongside the count.
+    pub frequencies: bool,
+
     /// Specify the mode of return value
-    pub mode: String,
+    pub mode: SimulationMode,
 }
 
 impl Default for Options {
@@ -51,8 +142,330 @@ impl Default for Options {
             statevector: true,
             probabilities: true,
             times: false,
+            show_bits: false,
+            mark_unwritten: false,
+            split_registers: false,
+            bloch: false,
+            max_states: None,
+            register_filter: None,
             shots: None,
-            mode: "aggregation".to_string(),
+            frequencies: false,
+            mode: SimulationMode::Aggregation,
         }
     }
 }
+
+impl Options {
+    /// Return a builder for configuring `Options`, starting from the default
+    /// values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::options::{Format, Options, SimulationMode};
+    ///
+    /// let options = Options::builder()
+    ///     .format(Format::Json)
+    ///     .shots(1024)
+    ///     .mode(SimulationMode::Sequence)
+    ///     .build();
+    ///
+    /// assert_eq!(options.format, Format::Json);
+    /// assert_eq!(options.shots, Some(1024));
+    /// ```
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::default()
+    }
+}
+
+/// Builder for [`Options`].
+///
+/// [`Options`]: ./struct.Options.html
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+impl OptionsBuilder {
+    /// Set the output format.
+    pub fn format(mut self, format: Format) -> Self {
+        self.options.format = format;
+        self
+    }
+
+    /// Set whether to print the binary representation of the values.
+    pub fn binary(mut self, binary: bool) -> Self {
+        self.options.binary = binary;
+        self
+    }
+
+    /// Set whether to print the hexadecimal representation of the values.
+    pub fn hexadecimal(mut self, hexadecimal: bool) -> Self {
+        self.options.hexadecimal = hexadecimal;
+        self
+    }
+
+    /// Set whether to print the integer representation of the values.
+    pub fn integer(mut self, integer: bool) -> Self {
+        self.options.integer = integer;
+        self
+    }
+
+    /// Set whether to print the state vector of the simulation.
+    pub fn statevector(mut self, statevector: bool) -> Self {
+        self.options.statevector = statevector;
+        self
+    }
+
+    /// Set whether to print the probabilities vector of the simulation.
+    pub fn probabilities(mut self, probabilities: bool) -> Self {
+        self.options.probabilities = probabilities;
+        self
+    }
+
+    /// Set whether to print the times measured for parsing and simulating.
+    pub fn times(mut self, times: bool) -> Self {
+        self.options.times = times;
+        self
+    }
+
+    /// Set whether to print the value of each classical bit.
+    pub fn show_bits(mut self, show_bits: bool) -> Self {
+        self.options.show_bits = show_bits;
+        self
+    }
+
+    /// Set whether to mark the registers never written by a measurement.
+    pub fn mark_unwritten(mut self, mark_unwritten: bool) -> Self {
+        self.options.mark_unwritten = mark_unwritten;
+        self
+    }
+
+    /// Set whether to print the marginal probabilities of each quantum
+    /// register.
+    pub fn split_registers(mut self, split_registers: bool) -> Self {
+        self.options.split_registers = split_registers;
+        self
+    }
+
+    /// Set whether to print the Bloch vector of each qubit.
+    pub fn bloch(mut self, bloch: bool) -> Self {
+        self.options.bloch = bloch;
+        self
+    }
+
+    /// Set the number of most probable basis states to print.
+    pub fn max_states(mut self, max_states: usize) -> Self {
+        self.options.max_states = Some(max_states);
+        self
+    }
+
+    /// Add `name` to the classical registers to print. Unless calling this
+    /// method, all the registers are printed.
+    pub fn register(mut self, name: impl Into<String>) -> Self {
+        self.options
+            .register_filter
+            .get_or_insert_with(Vec::new)
+            .push(name.into());
+        self
+    }
+
+    /// Set the number of simulations.
+    pub fn shots(mut self, shots: usize) -> Self {
+        self.options.shots = Some(shots);
+        self
+    }
+
+    /// Set whether to print the frequencies of the outcomes of the shots.
+    pub fn frequencies(mut self, frequencies: bool) -> Self {
+        self.options.frequencies = frequencies;
+        self
+    }
+
+    /// Set the mode of the return value.
+    pub fn mode(mut self, mode: SimulationMode) -> Self {
+        self.options.mode = mode;
+        self
+    }
+
+    /// Return the configured `Options`.
+    pub fn build(self) -> Options {
+        self.options
+    }
+}
+
+/// Representation of the state of the quantum system during the simulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Backend {
+    /// Store every amplitude in a [`StateVector`].
+    ///
+    /// [`StateVector`]: ../statevector/struct.StateVector.html
+    #[default]
+    Dense,
+
+    /// Store only the non-zero amplitudes in a [`SparseStateVector`].
+    ///
+    /// [`SparseStateVector`]: ../statevector/struct.SparseStateVector.html
+    Sparse,
+
+    /// Use the sparse representation while fewer than 10% of the amplitudes
+    /// are non-zero, and the dense one otherwise. A dense state is only
+    /// checked every few operations, since checking it takes a pass over all
+    /// of its amplitudes.
+    Auto,
+}
+
+/// Precision of the amplitudes of the state during the simulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Precision {
+    /// Store the amplitudes as pairs of `f64`.
+    #[default]
+    Double,
+
+    /// Store the amplitudes as pairs of `f32` in a
+    /// [`SinglePrecisionStateVector`], taking half the memory.
+    ///
+    /// [`SinglePrecisionStateVector`]: ../statevector/struct.SinglePrecisionStateVector.html
+    Single,
+}
+
+/// Simulation options.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct SimulationOptions {
+    /// Track the entanglement entropy between the `n` least significant
+    /// qubits and the rest of the system after each gate, and report the
+    /// maximum. Disabled if `None`. The smallest side of the bipartition can
+    /// have up to [`MAX_SCHMIDT_QUBITS`] qubits.
+    ///
+    /// [`MAX_SCHMIDT_QUBITS`]: ../statevector/constant.MAX_SCHMIDT_QUBITS.html
+    pub track_entanglement: Option<usize>,
+
+    /// Skip the applications of opaque gates instead of failing with
+    /// [`RuntimeError::OpaqueGateNotSimulatable`].
+    ///
+    /// [`RuntimeError::OpaqueGateNotSimulatable`]: ../error/enum.RuntimeError.html#variant.OpaqueGateNotSimulatable
+    pub treat_opaque_as_identity: bool,
+
+    /// Accept the syntax extensions to OPENQASM 2.0 when parsing the program.
+    /// See [`parse_program_with_extensions()`] for the list of extensions.
+    ///
+    /// The extensions also allow classical registers in the parameters of
+    /// the gates applied outside gate definitions, such as `rx(c) q[0];`.
+    /// The register stands for its integer value at the time of applying the
+    /// gate. Otherwise, the simulation fails with
+    /// [`RuntimeError::ClassicalRegisterInExpression`].
+    ///
+    /// [`RuntimeError::ClassicalRegisterInExpression`]: ../error/enum.RuntimeError.html#variant.ClassicalRegisterInExpression
+    /// [`parse_program_with_extensions()`]: ../grammar/fn.parse_program_with_extensions.html
+    pub extensions: bool,
+
+    /// Reject nonstandard syntax instead of accepting it with a warning. See
+    /// [`parse_program_lenient()`] for the list of nonstandard syntax
+    /// accepted when not strict.
+    ///
+    /// [`parse_program_lenient()`]: ../grammar/fn.parse_program_lenient.html
+    pub strict: bool,
+
+    /// Fail with [`RuntimeError::TooManyQubits`] instead of simulating if the
+    /// program declares more qubits than this. Unlimited if `None`.
+    ///
+    /// [`RuntimeError::TooManyQubits`]: ../error/enum.RuntimeError.html#variant.TooManyQubits
+    pub max_qubits: Option<usize>,
+
+    /// Simulate on this many qubits, even if the program declares fewer, so
+    /// the state vector always has `2^pad_qubits` amplitudes. The qubits not
+    /// declared by the program follow the declared ones and stay in |0⟩.
+    /// Simulating fails with [`RuntimeError::PaddingTooNarrow`] if the
+    /// program declares more qubits. Disabled if `None`.
+    ///
+    /// [`RuntimeError::PaddingTooNarrow`]: ../error/enum.RuntimeError.html#variant.PaddingTooNarrow
+    pub pad_qubits: Option<usize>,
+
+    /// Representation of the state during the simulation. The final state
+    /// is kept in the same representation by [`Computation::state()`]; the
+    /// dense [`StateVector`] is only built if asked for with
+    /// [`Computation::statevector()`].
+    ///
+    /// [`Computation::state()`]: ../struct.Computation.html#method.state
+    /// [`Computation::statevector()`]: ../struct.Computation.html#method.statevector
+    /// [`StateVector`]: ../statevector/struct.StateVector.html
+    pub backend: Backend,
+
+    /// Compute the reduced density matrix of these qubits, indexed in the
+    /// unified quantum memory, at the end of the simulation. With shots, the
+    /// matrices at the end of every shot are averaged, so mid-circuit
+    /// measurements show as mixed states. Disabled if `None`.
+    ///
+    /// Up to [`MAX_DENSITY_QUBITS`] qubits are supported.
+    ///
+    /// [`MAX_DENSITY_QUBITS`]: ../statevector/constant.MAX_DENSITY_QUBITS.html
+    pub density_qubits: Option<Vec<usize>>,
+
+    /// Precision of the amplitudes during the simulation. In single
+    /// precision, every amplitude is stored and the backend is ignored. The
+    /// final state is kept in single precision by [`Computation::state()`];
+    /// the probabilities and [`Computation::statevector()`] are converted to
+    /// double precision when asked for.
+    ///
+    /// [`Computation::state()`]: ../struct.Computation.html#method.state
+    /// [`Computation::statevector()`]: ../struct.Computation.html#method.statevector
+    pub precision: Precision,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_defaults_to_default_options() {
+        assert_eq!(Options::builder().build(), Options::default());
+    }
+
+    #[test]
+    fn test_simulation_mode_names_round_trip() {
+        for mode in [
+            SimulationMode::Aggregation,
+            SimulationMode::Sequence,
+            SimulationMode::Min,
+            SimulationMode::Max,
+        ] {
+            assert_eq!(mode.to_string().parse(), Ok(mode));
+        }
+        let error = "Sequence".parse::<SimulationMode>().unwrap_err();
+        assert_eq!(
+            format!("{}", error),
+            "error: invalid mode `Sequence`, expected `aggregation`, `sequence`, `min` or `max`\n"
+        );
+    }
+
+    #[test]
+    fn test_builder_is_equivalent_to_struct_literal() {
+        let built = Options::builder()
+            .format(Format::Json)
+            .binary(false)
+            .hexadecimal(false)
+            .statevector(false)
+            .times(true)
+            .show_bits(true)
+            .max_states(4)
+            .register("c")
+            .register("d")
+            .shots(100)
+            .mode(SimulationMode::Sequence)
+            .build();
+        let literal = Options {
+            format: Format::Json,
+            binary: false,
+            hexadecimal: false,
+            statevector: false,
+            times: true,
+            show_bits: true,
+            max_states: Some(4),
+            register_filter: Some(vec!["c".to_string(), "d".to_string()]),
+            shots: Some(100),
+            mode: SimulationMode::Sequence,
+            ..Default::default()
+        };
+        assert_eq!(built, literal);
+    }
+}
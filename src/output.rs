@@ -1,9 +1,118 @@
 //! Output module.
 
+use std::collections::{HashMap, HashSet};
+
+use crate::error::QasmSimError;
+use crate::options::{Options, SimulationMode};
+use crate::statevector::StateVector;
+
 /// output msg in tabular format.
 mod tabular;
 
 mod json;
 
+/// compute the frequencies of the outcomes of the shots.
+mod stats_utils;
+
+/// translate programs into Quil.
+pub mod quil;
+
+/// translate programs into Qiskit Qobj JSON.
+pub mod qiskit_json;
+
+/// draw programs as LaTeX circuit diagrams.
+pub mod latex;
+
 /// print gate info and result.
+#[allow(clippy::module_inception)]
 pub mod output;
+
+/// Format the bits of a register value as `name[n-1]=b ... name[0]=b`, using
+/// the register `width` to include the bits that were never written.
+fn format_bits(name: &str, value: u128, width: usize) -> String {
+    (0..width)
+        .rev()
+        .map(|index| format!("{}[{}]={}", name, index, (value >> index) & 1))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Return the content of `value`, or a [`QasmSimError::MalformedResult`]
+/// telling the `name` of the missing part of the result if there is none.
+fn required<'a, T>(value: &'a Option<T>, name: &str) -> Result<&'a T, QasmSimError<'static>> {
+    value.as_ref().ok_or_else(|| QasmSimError::MalformedResult {
+        reason: format!("there are no {}, was the simulation run with shots?", name),
+    })
+}
+
+/// Return the names of the registers no measurement wrote into.
+fn unwritten_registers(written_bits: &HashMap<String, u128>) -> HashSet<String> {
+    written_bits
+        .iter()
+        .filter(|(_, mask)| **mask == 0)
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Return the indices of the basis states to print: the `max_states` most
+/// probable ones if limited, or all of them otherwise.
+fn printed_bases(statevector: &StateVector, options: &Options) -> Vec<usize> {
+    match options.max_states {
+        Some(k) => statevector
+            .top_k_outcomes(k)
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect(),
+        None => (0..statevector.len()).collect(),
+    }
+}
+
+/// Return the outcomes of `stats` sharing the least count in `Min` mode, or
+/// the greatest count otherwise, in lexicographic order, and that count.
+///
+/// The first outcome is the one reported: ties are broken in favor of the
+/// lexicographically smallest bitstring so repeated runs agree.
+fn minmax_outcomes(stats: &HashMap<String, usize>, mode: SimulationMode) -> (Vec<&str>, usize) {
+    let count = if mode == SimulationMode::Min {
+        stats.values().min()
+    } else {
+        stats.values().max()
+    };
+    let Some(&count) = count else {
+        return (Vec::new(), 0);
+    };
+    let mut outcomes: Vec<&str> = stats
+        .iter()
+        .filter(|(_, &value)| value == count)
+        .map(|(key, _)| key.as_str())
+        .collect();
+    outcomes.sort_unstable();
+    (outcomes, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minmax_outcomes_break_ties_lexicographically() {
+        let stats = HashMap::from([
+            (String::from("11"), 3),
+            (String::from("01"), 3),
+            (String::from("10"), 3),
+            (String::from("00"), 1),
+        ]);
+        assert_eq!(
+            minmax_outcomes(&stats, SimulationMode::Max),
+            (vec!["01", "10", "11"], 3)
+        );
+        assert_eq!(
+            minmax_outcomes(&stats, SimulationMode::Min),
+            (vec!["00"], 1)
+        );
+        assert_eq!(
+            minmax_outcomes(&HashMap::new(), SimulationMode::Max),
+            (vec![], 0)
+        );
+    }
+}
@@ -55,7 +55,7 @@ pub fn print(path: &mut PathBuf, result: &Execution, options: &Options) {
 
 fn print_memory<W>(
     writer: &mut csv::Writer<W>,
-    memory: &HashMap<String, u64>,
+    memory: &HashMap<String, u128>,
     options: &Options,
 ) -> io::Result<()>
 where
@@ -159,8 +159,7 @@ where
     writer.write_record(&titles)?;
 
     let amplitudes_and_probabilities = statevector
-        .as_complex_bases()
-        .iter()
+        .amplitudes()
         .zip(probabilities)
         .enumerate();
     for (idx, (amplitude, probability)) in amplitudes_and_probabilities {
@@ -1,59 +1,69 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Write};
 
 use serde_json::{json, Value};
 
-use crate::statevector::StateVector;
+use crate::error::QasmSimError;
+use crate::statevector::{Complex, StateVector};
 use crate::{Execution, ExecutionTimes, Histogram};
 
-use crate::options::Options;
+use super::stats_utils::wilson_interval;
+use super::{format_bits, minmax_outcomes, printed_bases, required, unwritten_registers};
+use crate::options::{Options, SimulationMode};
 
 /// Writes the `msg` in the `buffer`
-pub fn print<W>(buffer: &mut W, result: &Execution, options: &Options)
-where
-    W: Write,
-{
-    do_print(buffer, result, options).expect("writes in stdout");
-}
-
-/// Writes the `msg` in the `buffer`
-fn do_print<W>(buffer: &mut W, result: &Execution, options: &Options) -> fmt::Result
+pub fn print<W>(
+    buffer: &mut W,
+    result: &Execution,
+    options: &Options,
+) -> Result<(), QasmSimError<'static>>
 where
     W: Write,
 {
     let mut output = json!({});
     if options.shots.is_some() {
-        let stats = result.stats().as_ref().expect("there is some histogram");
-        println!("{:?}", stats.is_empty());
-        if !stats.is_empty() {
-            if options.mode == "aggregation" {
-                print_stats(&mut output, stats)?;
-            } else if options.mode == "min" || options.mode == "max" {
-                print_minmax(&mut output, stats, options)?;
-            } else {
-                panic!("Invalid mode");
+        if options.mode == SimulationMode::Sequence {
+            let sequences = required(result.sequences(), "sequences")?;
+            if !sequences.is_empty() {
+                let sequences = sequences
+                    .iter()
+                    .map(|shot| result.format_shot(shot))
+                    .collect::<Option<Vec<String>>>()
+                    .ok_or_else(|| QasmSimError::MalformedResult {
+                        reason: String::from("some shot lacks the value of a register"),
+                    })?;
+                print_sequence(&mut output, &sequences)?;
             }
-        } else if !result
-            .sequences()
-            .as_ref()
-            .expect("there is some sequences")
-            .is_empty()
-        {
-            if options.mode == "sequence" {
-                print_sequence(
-                    &mut output,
-                    result
-                        .sequences()
-                        .as_ref()
-                        .expect("there is some sequences"),
-                )?;
-            } else {
-                panic!("Invalid mode");
+        } else {
+            let stats = required(result.stats(), "statistics")?;
+            if !stats.is_empty() {
+                match options.mode {
+                    SimulationMode::Min | SimulationMode::Max => {
+                        print_minmax(&mut output, stats, options)?
+                    }
+                    _ => print_stats(&mut output, stats, options)?,
+                }
             }
         }
     }
 
-    if (options.statevector || options.probabilities) && options.shots.is_none() {
+    if (options.show_bits || options.mark_unwritten)
+        && options.shots.is_none()
+        && !result.memory().is_empty()
+    {
+        print_memory(&mut output, result.memory(), result.written_bits(), options)?;
+    }
+
+    if (options.statevector || options.probabilities)
+        && options.shots.is_none()
+        && options.split_registers
+    {
+        print_split_state(
+            &mut output,
+            result.statevector(),
+            result.quantum_registers(),
+        )?;
+    } else if (options.statevector || options.probabilities) && options.shots.is_none() {
         print_state(
             &mut output,
             result.statevector(),
@@ -62,17 +72,27 @@ where
         )?;
     }
 
+    if options.bloch && options.shots.is_none() {
+        print_bloch(&mut output, result.statevector())?;
+    }
+
+    if let Some(density_matrix) = result.density_matrix() {
+        print_density_matrix(&mut output, density_matrix)?;
+    }
+
     if options.times {
-        print_times(&mut output, result.times())?;
+        print_times(&mut output, result.times(), result.peak_memory_bytes())?;
     }
 
-    let output_str = serde_json::to_string_pretty(&output).expect("json pretty print");
-    write!(buffer, "{}", output_str)
+    let output_str = serde_json::to_string_pretty(&output).map_err(|_| fmt::Error)?;
+    write!(buffer, "{}", output_str)?;
+    Ok(())
 }
 
 fn print_memory(
     value: &mut Value,
-    memory: &HashMap<String, (u64, usize, usize)>,
+    memory: &HashMap<String, (u128, usize, usize)>,
+    written_bits: &HashMap<String, u128>,
     options: &Options,
 ) -> fmt::Result {
     let histogram = HashMap::from_iter(
@@ -80,16 +100,22 @@ fn print_memory(
             .iter()
             .map(|(key, value)| (key.clone(), (vec![(value.0, 1)], value.1))),
     );
-    print_memory_summary(value, &histogram, options, true)
+    let unwritten = if options.mark_unwritten {
+        unwritten_registers(written_bits)
+    } else {
+        HashSet::new()
+    };
+    print_memory_summary(value, &histogram, &unwritten, options, true)
 }
 
 fn print_histogram(value: &mut Value, histogram: &Histogram, options: &Options) -> fmt::Result {
-    print_memory_summary(value, histogram, options, false)
+    print_memory_summary(value, histogram, &HashSet::new(), options, false)
 }
 
 fn print_memory_summary(
     value: &mut Value,
     histogram: &Histogram,
+    unwritten: &HashSet<String>,
     options: &Options,
     omit_count: bool,
 ) -> fmt::Result {
@@ -108,8 +134,25 @@ fn print_memory_summary(
         for (idx, (value, count)) in hist.0.iter().enumerate() {
             json[key][format!("{}", idx)] = json!({});
             json[key][format!("{}", idx)]["Register length"] = json!(hist.1);
+            if unwritten.contains(key) {
+                for (shown, column) in [
+                    (integer, "Int value"),
+                    (hexadecimal, "Hex value"),
+                    (binary, "Bin value"),
+                    (options.show_bits, "Bits"),
+                ] {
+                    if shown {
+                        json[key][format!("{}", idx)][column] = Value::Null;
+                    }
+                }
+                continue;
+            }
             if integer {
-                json[key][format!("{}", idx)]["Int value"] = json!(value);
+                // JSON numbers cannot hold values wider than 64 bits.
+                json[key][format!("{}", idx)]["Int value"] = match u64::try_from(*value) {
+                    Ok(value) => json!(value),
+                    Err(_) => json!(value.to_string()),
+                };
             }
             if hexadecimal {
                 json[key][format!("{}", idx)]["Hex value"] = json!(format!("0x{:x}", value));
@@ -118,6 +161,9 @@ fn print_memory_summary(
                 json[key][format!("{}", idx)]["Bin value"] =
                     json!(format!("0b{:0width$b}", value, width = hist.1));
             }
+            if options.show_bits {
+                json[key][format!("{}", idx)]["Bits"] = json!(format_bits(key, *value, hist.1));
+            }
             if !omit_count {
                 json[key][format!("{}", idx)]["Count"] = json!(count);
             }
@@ -129,15 +175,33 @@ fn print_memory_summary(
     Ok(())
 }
 
-fn print_stats(value: &mut Value, stats: &HashMap<String, usize>) -> fmt::Result {
+fn print_stats(
+    value: &mut Value,
+    stats: &HashMap<String, usize>,
+    options: &Options,
+) -> fmt::Result {
     let json = json!(stats);
 
     value["Memory"] = json;
 
+    if options.frequencies {
+        let shots = stats.values().sum();
+        let mut json = json!({});
+        for (key, count) in stats {
+            let (frequency, lower, upper) = wilson_interval(*count, shots);
+            json[key] = json!({
+                "Frequency": frequency,
+                "Lower": lower,
+                "Upper": upper,
+            });
+        }
+        value["Frequencies"] = json;
+    }
+
     Ok(())
 }
 
-fn print_sequence(value: &mut Value, sequences: &Vec<String>) -> fmt::Result {
+fn print_sequence(value: &mut Value, sequences: &[String]) -> fmt::Result {
     let json = json!(sequences);
 
     value["Sequences"] = json;
@@ -150,12 +214,13 @@ fn print_minmax(
     stats: &HashMap<String, usize>,
     options: &Options,
 ) -> fmt::Result {
-    if options.mode == "max" {
-        let max_state = stats.iter().max_by_key(|(_, &v)| v).unwrap();
-        value["Memory"] = json!({max_state.0: max_state.1});
-    } else {
-        let min_state = stats.iter().min_by_key(|(_, &v)| v).unwrap();
-        value["Memory"] = json!({min_state.0: min_state.1});
+    let (outcomes, count) = minmax_outcomes(stats, options.mode);
+    value["Memory"] = match outcomes.first() {
+        Some(key) => json!({ *key: count }),
+        None => json!({}),
+    };
+    if outcomes.len() > 1 {
+        value["Tied"] = json!(outcomes);
     }
     Ok(())
 }
@@ -173,12 +238,11 @@ fn print_state(
 
     let mut json = json!({});
 
-    let amplitudes_and_probabilities = statevector
-        .as_complex_bases()
-        .iter()
-        .zip(probabilities)
-        .enumerate();
-    for (idx, (amplitude, probability)) in amplitudes_and_probabilities {
+    for idx in printed_bases(statevector, options) {
+        let amplitude = statevector
+            .amplitude_at(idx)
+            .expect("the basis is in the state-vector");
+        let probability = probabilities[idx];
         json[format!("{}", idx)] = json!({});
         if options.statevector {
             json[format!("{}", idx)]["Real"] = json!(format!("{:.6}", amplitude.re));
@@ -203,13 +267,126 @@ fn print_state(
     Ok(())
 }
 
-fn print_times(value: &mut Value, times: &ExecutionTimes) -> fmt::Result {
-    let json = json!({
+fn print_split_state(
+    value: &mut Value,
+    statevector: &StateVector,
+    quantum_registers: &HashMap<String, (usize, usize)>,
+) -> fmt::Result {
+    let mut json = json!({});
+
+    for (name, (offset, size)) in quantum_registers {
+        json[name] = json!({});
+        let marginals = statevector.marginal_probabilities(*offset, *size);
+        for (idx, probability) in marginals.iter().enumerate() {
+            let key = format!("{:0width$b}", idx, width = size);
+            json[name][&key] = json!({});
+            json[name][&key]["Probability"] = json!(format!("{:.6}", probability));
+        }
+    }
+
+    value["State"] = json;
+
+    Ok(())
+}
+
+fn print_bloch(value: &mut Value, statevector: &StateVector) -> fmt::Result {
+    let vectors: Vec<Value> = (0..statevector.qubit_width())
+        .map(|qubit| {
+            let (x, y, z) = statevector.bloch_vector(qubit);
+            json!([x, y, z])
+        })
+        .collect();
+    value["Bloch"] = Value::Array(vectors);
+
+    Ok(())
+}
+
+fn print_density_matrix(value: &mut Value, density_matrix: &[Vec<Complex>]) -> fmt::Result {
+    let rows: Vec<Value> = density_matrix
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|element| json!([element.re, element.im]))
+                .collect()
+        })
+        .collect();
+    value["DensityMatrix"] = Value::Array(rows);
+
+    Ok(())
+}
+
+fn print_times(value: &mut Value, times: &ExecutionTimes, peak_memory_bytes: usize) -> fmt::Result {
+    let mut json = json!({
         "Parsing": times.parsing_time(),
         "Simulation": times.simulation_time(),
+        "EstimatedPeakMemoryBytes": peak_memory_bytes,
     });
+    if let Some(measured_peak_memory_bytes) = times.peak_memory_bytes() {
+        json["MeasuredPeakMemoryBytes"] = json!(measured_peak_memory_bytes);
+    }
 
     value["Times"] = json;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_print_minmax_with_empty_stats() {
+        for mode in [SimulationMode::Min, SimulationMode::Max] {
+            let options = Options::builder().shots(1).mode(mode).build();
+            let mut output = json!({});
+            print_minmax(&mut output, &HashMap::new(), &options).unwrap();
+            assert_eq!(output, json!({ "Memory": {} }));
+        }
+    }
+    #[test]
+    fn test_print_times_names_each_peak_memory() {
+        let mut output = json!({});
+        print_times(&mut output, &ExecutionTimes::new(1, 2), 1_250_000).unwrap();
+        assert_eq!(
+            output,
+            json!({ "Times": {
+                "Parsing": 1,
+                "Simulation": 2,
+                "EstimatedPeakMemoryBytes": 1_250_000
+            } })
+        );
+
+        let mut output = json!({});
+        let times = ExecutionTimes::with_peak_memory(1, 2, 2_500_000);
+        print_times(&mut output, &times, 1_250_000).unwrap();
+        assert_eq!(output["Times"]["EstimatedPeakMemoryBytes"], 1_250_000);
+        assert_eq!(output["Times"]["MeasuredPeakMemoryBytes"], 2_500_000);
+    }
+    #[test]
+    fn test_print_minmax_lists_tied_outcomes() {
+        let stats = HashMap::from([
+            (String::from("11"), 3),
+            (String::from("01"), 3),
+            (String::from("10"), 3),
+            (String::from("00"), 1),
+        ]);
+        let options = Options::builder()
+            .shots(10)
+            .mode(SimulationMode::Max)
+            .build();
+        let mut output = json!({});
+        print_minmax(&mut output, &stats, &options).unwrap();
+        assert_eq!(
+            output,
+            json!({ "Memory": { "01": 3 }, "Tied": ["01", "10", "11"] })
+        );
+
+        let options = Options::builder()
+            .shots(10)
+            .mode(SimulationMode::Min)
+            .build();
+        let mut output = json!({});
+        print_minmax(&mut output, &stats, &options).unwrap();
+        assert_eq!(output, json!({ "Memory": { "00": 1 } }));
+    }
+}
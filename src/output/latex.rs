@@ -0,0 +1,407 @@
+//! Draw OPENQASM programs as circuit diagrams in LaTeX, with the [quantikz]
+//! TikZ package.
+//!
+//! The output is a standalone document which compiles with `pdflatex`. Each
+//! qubit is a wire and every operation is placed in the leftmost column
+//! where all its qubits, and the wires crossed by its vertical lines, are
+//! free, so the columns are the layers of the circuit.
+//!
+//! [quantikz]: https://ctan.org/pkg/quantikz
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::diagram::parameters;
+use crate::error::RuntimeError;
+use crate::grammar::{
+    ast::{Argument, OpenQasmProgram, QuantumOperation, Statement, UnitaryOperation},
+    lexer::Location,
+};
+use crate::semantics::{extract_semantics, QasmType, RegisterType, Semantics};
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+/// Return a LaTeX document drawing the circuit of `program`.
+///
+/// Gates are drawn as boxes labeled with their names and parameters, `CX`
+/// as a control dot joined to a target circle, and measurements as meters.
+/// Gates acting on several qubits are boxes joined by a vertical wire.
+/// Conditional operations are drawn as boxes labeled with their condition.
+/// Barriers are not drawn, but operations after a barrier are never placed
+/// before it. As with [`circuit_to_dot()`], gates are not expanded into
+/// their definitions.
+///
+/// # Errors
+///
+/// The function fails with [`RuntimeError`] if the program is not valid,
+/// for instance, if it uses undeclared registers.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::output::latex::circuit_to_latex;
+/// use qasmsim::parse_and_link;
+///
+/// let program = parse_and_link(r#"
+/// OPENQASM 2.0;
+/// include "qelib1.inc";
+/// qreg q[2];
+/// h q[0];
+/// cx q[0], q[1];
+/// "#)?;
+/// let latex = circuit_to_latex(&program).unwrap();
+/// assert!(latex.contains(r"\ctrl{1} & \qw \\"));
+/// assert!(latex.contains(r"\targ{} & \qw"));
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// [`circuit_to_dot()`]: ../../diagram/fn.circuit_to_dot.html
+/// [`RuntimeError`]: ../../error/enum.RuntimeError.html
+pub fn circuit_to_latex(program: &OpenQasmProgram) -> Result<String> {
+    let semantics = extract_semantics(program).map_err(RuntimeError::from)?;
+    let mut layout = Layout::new(&semantics);
+    for span in &program.program {
+        layout.location = span.boundaries.0;
+        layout.draw_statement(&span.node)?;
+    }
+    Ok(layout.finish())
+}
+
+struct Layout<'semantics> {
+    semantics: &'semantics Semantics,
+    location: Location,
+    /// The cells of each column, by wire. Wires with no cell are plain.
+    columns: Vec<HashMap<usize, String>>,
+    /// The first free column of each wire.
+    frontier: Vec<usize>,
+}
+
+impl<'semantics> Layout<'semantics> {
+    fn new(semantics: &'semantics Semantics) -> Self {
+        Layout {
+            semantics,
+            location: Location::new(),
+            columns: Vec::new(),
+            frontier: vec![0; semantics.quantum_memory_size],
+        }
+    }
+
+    fn draw_statement(&mut self, statement: &Statement) -> Result<()> {
+        match statement {
+            Statement::QuantumOperation(operation) => self.draw_operation(operation, None),
+            Statement::Conditional(register, test, operation) => {
+                let condition = condition(register, *test);
+                self.draw_operation(operation, Some(&condition))
+            }
+            Statement::ConditionalBlock(register, test, operations) => {
+                let condition = condition(register, *test);
+                for operation in operations {
+                    self.draw_operation(operation, Some(&condition))?;
+                }
+                Ok(())
+            }
+            Statement::Barrier(barrier) => {
+                let mut wires = Vec::new();
+                for argument in &barrier.0 {
+                    for qubits in self.broadcast(std::slice::from_ref(argument))? {
+                        wires.push(self.wire(&qubits[0]));
+                    }
+                }
+                let column = wires.iter().map(|&wire| self.frontier[wire]).max();
+                for &wire in &wires {
+                    self.frontier[wire] = column.expect("some wire");
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn draw_operation(
+        &mut self,
+        operation: &QuantumOperation,
+        condition: Option<&str>,
+    ) -> Result<()> {
+        match operation {
+            QuantumOperation::Unitary(unitary) => {
+                for qubits in self.broadcast(&unitary.2)? {
+                    let wires: Vec<usize> = qubits.iter().map(|qubit| self.wire(qubit)).collect();
+                    self.draw_unitary(unitary, &wires, condition);
+                }
+            }
+            QuantumOperation::Measure(source, target) => {
+                for pair in self.broadcast(&[source.clone(), target.clone()])? {
+                    let wire = self.wire(&pair[0]);
+                    let label = condition.unwrap_or_default();
+                    self.place(vec![(wire, format!("\\meter{{{}}}", label))]);
+                }
+            }
+            QuantumOperation::Reset(target) => {
+                for qubits in self.broadcast(std::slice::from_ref(target))? {
+                    let wire = self.wire(&qubits[0]);
+                    let label = with_condition(&text("reset"), condition);
+                    self.place(vec![(wire, format!("\\gate{{{}}}", label))]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_unitary(
+        &mut self,
+        unitary: &UnitaryOperation,
+        wires: &[usize],
+        condition: Option<&str>,
+    ) {
+        let is_cnot = (unitary.0 == "CX" || unitary.0 == "cx") && wires.len() == 2;
+        if is_cnot && condition.is_none() {
+            let (control, target) = (wires[0], wires[1]);
+            let distance = target as isize - control as isize;
+            self.place(vec![
+                (control, format!("\\ctrl{{{}}}", distance)),
+                (target, String::from("\\targ{}")),
+            ]);
+            return;
+        }
+        let label = with_condition(
+            &format!("{}{}", text(&unitary.0), parameters(&unitary.1)),
+            condition,
+        );
+        let mut wires = wires.to_vec();
+        wires.sort_unstable();
+        let mut cells: Vec<(usize, String)> = wires
+            .iter()
+            .map(|&wire| (wire, format!("\\gate{{{}}}", label)))
+            .collect();
+        for index in 1..cells.len() {
+            let distance = cells[index].0 - cells[index - 1].0;
+            let _ = write!(cells[index - 1].1, " \\vqw{{{}}}", distance);
+        }
+        self.place(cells);
+    }
+
+    /// Place `cells` in the leftmost column where their wires, and the wires
+    /// between them, are free.
+    fn place(&mut self, cells: Vec<(usize, String)>) {
+        let first = cells
+            .iter()
+            .map(|(wire, _)| *wire)
+            .min()
+            .expect("some cell");
+        let last = cells
+            .iter()
+            .map(|(wire, _)| *wire)
+            .max()
+            .expect("some cell");
+        let column = self.frontier[first..=last]
+            .iter()
+            .copied()
+            .max()
+            .expect("some wire");
+        if column == self.columns.len() {
+            self.columns.push(HashMap::new());
+        }
+        self.columns[column].extend(cells);
+        for free in &mut self.frontier[first..=last] {
+            *free = column + 1;
+        }
+    }
+
+    /// Return the wire of `qubit`, which must be valid after `broadcast()`.
+    fn wire(&self, qubit: &(String, usize)) -> usize {
+        let entry = &self.semantics.memory_map[&qubit.0];
+        entry.1 + qubit.1
+    }
+
+    /// Expand the register arguments in `arguments` into as many lists of
+    /// individual bits as the size of the registers, checking the registers
+    /// are declared and the indices are in bounds.
+    fn broadcast(&self, arguments: &[Argument]) -> Result<Vec<Vec<(String, usize)>>> {
+        let mut size = 1;
+        for argument in arguments {
+            let (name, index) = match argument {
+                Argument::Id(name) => (name, None),
+                Argument::Item(name, index) => (name, Some(*index)),
+            };
+            let register_size = match self.semantics.register_table.get(name.as_str()) {
+                Some(entry) => entry.2,
+                None => {
+                    return Err(RuntimeError::SymbolNotFound {
+                        location: self.location,
+                        symbol_name: name.to_string(),
+                        expected: QasmType::Register,
+                    })
+                }
+            };
+            match index {
+                Some(index) if index >= register_size => {
+                    return Err(RuntimeError::IndexOutOfBounds {
+                        location: self.location,
+                        symbol_name: name.to_string(),
+                        index,
+                        size: register_size,
+                    })
+                }
+                Some(_) => (),
+                None => size = register_size,
+            }
+        }
+        Ok((0..size)
+            .map(|index| {
+                arguments
+                    .iter()
+                    .map(|argument| match argument {
+                        Argument::Id(name) => (name.to_string(), index),
+                        Argument::Item(name, item) => (name.to_string(), *item),
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    fn finish(self) -> String {
+        let mut labels = vec![String::new(); self.semantics.quantum_memory_size];
+        for (name, entry) in &self.semantics.register_table {
+            if entry.1 == RegisterType::Q {
+                let start = self.semantics.memory_map[name].1;
+                for index in 0..entry.2 {
+                    labels[start + index] = format!("{}_{{{}}}", text(name), index);
+                }
+            }
+        }
+        let rows: Vec<String> = labels
+            .iter()
+            .enumerate()
+            .map(|(wire, label)| {
+                let mut row = format!("\\lstick{{{}}}", label);
+                for column in &self.columns {
+                    let cell = column.get(&wire).map_or("\\qw", String::as_str);
+                    let _ = write!(row, " & {}", cell);
+                }
+                row + " & \\qw"
+            })
+            .collect();
+        format!(
+            concat!(
+                "\\documentclass{{standalone}}\n",
+                "\\usepackage{{quantikz}}\n",
+                "\\begin{{document}}\n",
+                "\\begin{{quantikz}}\n",
+                "{}\n",
+                "\\end{{quantikz}}\n",
+                "\\end{{document}}\n"
+            ),
+            rows.join(" \\\\\n")
+        )
+    }
+}
+
+/// Return `name` as upright text in math mode.
+fn text(name: &str) -> String {
+    format!("\\mathrm{{{}}}", name.replace('_', "\\_"))
+}
+
+fn condition(register: &Argument, test: u128) -> String {
+    let register = match register {
+        Argument::Id(name) => text(name),
+        Argument::Item(name, index) => format!("{}[{}]", text(name), index),
+    };
+    format!("{} = {}", register, test)
+}
+
+fn with_condition(label: &str, condition: Option<&str>) -> String {
+    match condition {
+        None => label.to_string(),
+        Some(condition) => format!("{}\\ ({})", label, condition),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use crate::api::parse_and_link;
+
+    use super::*;
+
+    #[test]
+    fn test_circuit_to_latex() {
+        let source = indoc!(
+            "
+        OPENQASM 2.0;
+        include \"qelib1.inc\";
+        qreg q[2];
+        qreg r[1];
+        creg c[2];
+        h q[0];
+        x r[0];
+        cx q[0], q[1];
+        measure q -> c;
+        "
+        );
+        let program = parse_and_link(source).unwrap();
+        assert_eq!(
+            circuit_to_latex(&program).unwrap(),
+            indoc!(
+                r"
+            \documentclass{standalone}
+            \usepackage{quantikz}
+            \begin{document}
+            \begin{quantikz}
+            \lstick{\mathrm{q}_{0}} & \gate{\mathrm{h}} & \ctrl{1} & \meter{} & \qw \\
+            \lstick{\mathrm{q}_{1}} & \qw & \targ{} & \meter{} & \qw \\
+            \lstick{\mathrm{r}_{0}} & \gate{\mathrm{x}} & \qw & \qw & \qw
+            \end{quantikz}
+            \end{document}
+            "
+            )
+        );
+    }
+
+    #[test]
+    fn test_multiqubit_gates_barriers_and_conditionals() {
+        let source = indoc!(
+            "
+        OPENQASM 2.0;
+        include \"qelib1.inc\";
+        qreg q[3];
+        creg c[1];
+        ccx q[2], q[0], q[1];
+        rz(pi) q[0];
+        barrier q;
+        if (c==1) u1(0) q[2];
+        "
+        );
+        let program = parse_and_link(source).unwrap();
+        let latex = circuit_to_latex(&program).unwrap();
+        assert!(latex.contains(concat!(
+            r"\lstick{\mathrm{q}_{0}} & \gate{\mathrm{ccx}} \vqw{1} & ",
+            r"\gate{\mathrm{rz}(3.142)} & \qw & \qw \\"
+        )));
+        assert!(latex.contains(concat!(
+            r"\lstick{\mathrm{q}_{2}} & \gate{\mathrm{ccx}} & \qw & ",
+            r"\gate{\mathrm{u1}(0.000)\ (\mathrm{c} = 1)} & \qw"
+        )));
+    }
+
+    #[test]
+    fn test_out_of_bounds_qubits_are_errors() {
+        let source = indoc!(
+            "
+        OPENQASM 2.0;
+        qreg q[1];
+        U(0, 0, 0) q[1];
+        "
+        );
+        let program = parse_and_link(source).unwrap();
+        assert!(matches!(
+            circuit_to_latex(&program),
+            Err(RuntimeError::IndexOutOfBounds {
+                index: 1,
+                size: 1,
+                ..
+            })
+        ));
+    }
+}
@@ -1,6 +1,8 @@
+use crate::error::QasmSimError;
 use crate::Execution;
 use crate::{options, output};
 use std::fmt;
+use std::io;
 
 /// print gate info.
 pub fn print_info(
@@ -22,13 +24,103 @@ pub fn print_info(
     Ok(())
 }
 
-/// print result.
-pub fn print_result(result: &Execution, options: &options::Options) -> String {
+/// Return the result of the simulation as formatted by `options`.
+///
+/// # Errors
+///
+/// The function fails with [`QasmSimError::MalformedResult`] if `result`
+/// lacks the data `options` ask for, such as a histogram when `options` set
+/// some shots but the simulation ran without them.
+///
+/// [`QasmSimError::MalformedResult`]: ../../error/enum.QasmSimError.html#variant.MalformedResult
+pub fn print_result(
+    result: &Execution,
+    options: &options::Options,
+) -> Result<String, QasmSimError<'static>> {
+    let filtered;
+    let result = match &options.register_filter {
+        None => result,
+        Some(names) => {
+            filtered = result.retain_registers(names);
+            &filtered
+        }
+    };
     let mut output = String::new();
     match options.format {
-        options::Format::Tabular => output::tabular::print(&mut output, result, options),
-        options::Format::Json => output::json::print(&mut output, result, options),
+        options::Format::Tabular => output::tabular::print(&mut output, result, options)?,
+        options::Format::Json => output::json::print(&mut output, result, options)?,
     }
 
-    output
+    Ok(output)
+}
+
+/// Write the result of the simulation, as formatted by `options`, into
+/// `sink`.
+///
+/// Pass a [`Tee`] as `sink` for writing the result into two sinks at once,
+/// such as a file and the standard output.
+///
+/// # Errors
+///
+/// The function fails in the same cases [`print_result()`] does, and with
+/// [`QasmSimError::UnknownError`] if writing into `sink` fails.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::{run, write_result, Tee};
+///
+/// let result = run("OPENQASM 2.0; qreg q[1];", None)?;
+/// let mut sink = Tee::new(Vec::new(), std::io::sink());
+/// write_result(&mut sink, &result, &Default::default())?;
+/// let (log, _) = sink.into_inner();
+/// assert!(!log.is_empty());
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// [`Tee`]: ./struct.Tee.html
+/// [`print_result()`]: ./fn.print_result.html
+/// [`QasmSimError::UnknownError`]: ../../error/enum.QasmSimError.html#variant.UnknownError
+pub fn write_result<W: io::Write>(
+    sink: &mut W,
+    result: &Execution,
+    options: &options::Options,
+) -> Result<(), QasmSimError<'static>> {
+    let output = print_result(result, options)?;
+    sink.write_all(output.as_bytes())?;
+    sink.flush()?;
+    Ok(())
+}
+
+/// A writer that writes the same bytes into two writers, in order.
+#[derive(Debug)]
+pub struct Tee<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: io::Write, B: io::Write> Tee<A, B> {
+    /// Create a writer that writes into `first` and then into `second`.
+    pub fn new(first: A, second: B) -> Self {
+        Tee { first, second }
+    }
+
+    /// Return the two writers.
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: io::Write, B: io::Write> io::Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.first.write_all(buf)?;
+        self.second.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.first.flush()?;
+        self.second.flush()
+    }
 }
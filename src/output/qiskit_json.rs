@@ -0,0 +1,403 @@
+//! Translate OPENQASM programs into the JSON serialization of the [Qobj]
+//! format, the payload Qiskit submits to simulators and IBM Quantum devices.
+//!
+//! The program becomes a `QASM` Qobj with one experiment. As when
+//! translating into Quil, custom gates are inlined and register broadcasting
+//! is expanded so the experiment only uses the `u3` and `cx` gates, plus
+//! `measure`, `reset` and `barrier` instructions. Opaque gates are kept as
+//! instructions with the name of the gate, to be resolved by the backend.
+//! Parameter expressions are evaluated during the translation.
+//!
+//! Conditionals are translated as Qiskit does: a `bfunc` instruction
+//! compares the classical bits with the expected value and stores the result
+//! in a register slot after the memory slots, which the conditional
+//! instructions then refer to.
+//!
+//! [Qobj]: https://arxiv.org/abs/1809.03452
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::error::RuntimeError;
+use crate::grammar::ast;
+use crate::interpreter::flattener::{Flattener, PrimitiveOp};
+use crate::semantics::extract_semantics;
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+/// Version of the Qobj schema the output follows.
+pub const SCHEMA_VERSION: &str = "1.3.0";
+
+/// Translate `program` into a Qobj with one experiment, in the form of a
+/// JSON value.
+///
+/// Qubits and classical bits are numbered in order of declaration of their
+/// registers, and the experiment header lists the registers with their sizes
+/// so Qiskit can label the results.
+///
+/// # Errors
+///
+/// The function fails with [`RuntimeError`] if the program is not valid.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::parse_and_link;
+/// use qasmsim::output::qiskit_json::translate;
+///
+/// let program = parse_and_link(r#"
+/// OPENQASM 2.0;
+/// qreg q[2];
+/// creg c[2];
+/// CX q[0], q[1];
+/// measure q[1] -> c[0];
+/// "#)?;
+///
+/// let qobj = translate(&program).unwrap();
+/// let experiment = &qobj["experiments"][0];
+/// assert_eq!(experiment["config"]["n_qubits"], 2);
+/// assert_eq!(experiment["config"]["memory_slots"], 2);
+/// assert_eq!(experiment["instructions"][0]["name"], "cx");
+/// assert_eq!(experiment["instructions"][1]["qubits"][0], 1);
+/// assert_eq!(experiment["instructions"][1]["memory"][0], 0);
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// [`RuntimeError`]: ../../error/enum.RuntimeError.html
+pub fn translate(program: &ast::OpenQasmProgram) -> Result<Value> {
+    let semantics = extract_semantics(program)?;
+
+    let mut qreg_sizes = Vec::new();
+    let mut qubit_labels = Vec::new();
+    let mut creg_sizes = Vec::new();
+    let mut clbit_labels = Vec::new();
+    let mut classical_registers = HashMap::new();
+    for span in &program.program {
+        match &*span.node {
+            ast::Statement::QRegDecl(name, size) => {
+                qreg_sizes.push(json!([name.as_str(), size]));
+                qubit_labels.extend((0..*size).map(|index| json!([name.as_str(), index])));
+            }
+            ast::Statement::CRegDecl(name, size) => {
+                classical_registers.insert(name.as_str(), (clbit_labels.len(), *size));
+                creg_sizes.push(json!([name.as_str(), size]));
+                clbit_labels.extend((0..*size).map(|index| json!([name.as_str(), index])));
+            }
+            _ => (),
+        }
+    }
+
+    let mut flattener = Flattener::new(&semantics);
+    let mut translator = Translator {
+        classical_registers,
+        memory_slots: semantics.classical_memory_size,
+        conditional_count: 0,
+        instructions: Vec::new(),
+    };
+    for span in &program.program {
+        flattener.location = span.boundaries.0;
+        for instruction in flattener.flatten_statement(&span.node)? {
+            translator.translate_instruction(&instruction, None);
+        }
+    }
+    let is_conditional = translator.conditional_count > 0;
+    let instructions: Vec<Value> = translator
+        .instructions
+        .into_iter()
+        .map(|mut instruction| {
+            // Qiskit copies measurements into the register slots too when
+            // there are conditionals, so `bfunc` can compare them.
+            if is_conditional && instruction["name"] == "measure" {
+                instruction["register"] = instruction["memory"].clone();
+            }
+            instruction
+        })
+        .collect();
+
+    let n_qubits = semantics.quantum_memory_size;
+    let memory_slots = semantics.classical_memory_size;
+    Ok(json!({
+        "qobj_id": "qasmsim",
+        "type": "QASM",
+        "schema_version": SCHEMA_VERSION,
+        "header": {},
+        "config": {
+            "n_qubits": n_qubits,
+            "memory_slots": memory_slots,
+        },
+        "experiments": [{
+            "header": {
+                "name": "circuit",
+                "n_qubits": n_qubits,
+                "qreg_sizes": qreg_sizes,
+                "qubit_labels": qubit_labels,
+                "memory_slots": memory_slots,
+                "creg_sizes": creg_sizes,
+                "clbit_labels": clbit_labels,
+            },
+            "config": {
+                "n_qubits": n_qubits,
+                "memory_slots": memory_slots,
+            },
+            "instructions": instructions,
+        }],
+    }))
+}
+
+struct Translator<'program> {
+    // register name, (index of the first bit, size of the register)
+    classical_registers: HashMap<&'program str, (usize, usize)>,
+    memory_slots: usize,
+    conditional_count: usize,
+    instructions: Vec<Value>,
+}
+
+impl<'program> Translator<'program> {
+    fn translate_instruction(&mut self, instruction: &PrimitiveOp, conditional: Option<usize>) {
+        let mut translation = match instruction {
+            PrimitiveOp::U {
+                theta,
+                phi,
+                lambda,
+                target,
+            } => json!({
+                "name": "u3",
+                "qubits": [target],
+                "params": [theta, phi, lambda],
+            }),
+            PrimitiveOp::Cx { control, target } => json!({
+                "name": "cx",
+                "qubits": [control, target],
+            }),
+            PrimitiveOp::Measure {
+                qubit,
+                register,
+                bit,
+            } => json!({
+                "name": "measure",
+                "qubits": [qubit],
+                "memory": [self.classical_registers[register.as_str()].0 + bit],
+            }),
+            PrimitiveOp::Reset { qubit } => json!({
+                "name": "reset",
+                "qubits": [qubit],
+            }),
+            PrimitiveOp::Barrier { qubits } => json!({
+                "name": "barrier",
+                "qubits": qubits,
+            }),
+            PrimitiveOp::Opaque {
+                name,
+                params,
+                qubits,
+            } => json!({
+                "name": name,
+                "qubits": qubits,
+                "params": params,
+            }),
+            PrimitiveOp::Conditional {
+                register,
+                bit,
+                value,
+                body,
+            } => {
+                let register_slot = self.translate_bfunc(register, *bit, *value);
+                for instruction in body {
+                    self.translate_instruction(instruction, Some(register_slot));
+                }
+                return;
+            }
+        };
+        if let Some(register_slot) = conditional {
+            translation["conditional"] = json!(register_slot);
+        }
+        self.instructions.push(translation);
+    }
+
+    /// Emit the `bfunc` instruction testing if the classical `register`, or
+    /// its `bit` if set, equals `value`, and return the register slot the
+    /// result is stored in.
+    fn translate_bfunc(&mut self, register: &str, bit: Option<usize>, value: u128) -> usize {
+        let (offset, size) = self.classical_registers[register];
+        let mask_bits: Vec<usize> = match bit {
+            Some(bit) => vec![offset + bit],
+            None => (offset..offset + size).collect(),
+        };
+        let first_bit = mask_bits[0];
+        // The bits of the value beyond the tested bits fall outside the mask
+        // so, as in the simulation, the condition never holds.
+        let value_bits: Vec<usize> = (0..u128::BITS as usize)
+            .filter(|index| value >> index & 1 == 1)
+            .map(|index| first_bit + index)
+            .collect();
+
+        let register_slot = self.memory_slots + self.conditional_count;
+        self.conditional_count += 1;
+        self.instructions.push(json!({
+            "name": "bfunc",
+            "mask": hex(&mask_bits),
+            "relation": "==",
+            "val": hex(&value_bits),
+            "register": register_slot,
+        }));
+        register_slot
+    }
+}
+
+/// Format the number with the bits at `positions` set as an hexadecimal
+/// literal, as Qobj expects masks and values of any width.
+fn hex(positions: &[usize]) -> String {
+    let width = positions.iter().max().map_or(1, |last| last / 4 + 1);
+    let mut nibbles = vec![0_u8; width];
+    for position in positions {
+        nibbles[position / 4] |= 1 << (position % 4);
+    }
+    let digits: String = nibbles
+        .iter()
+        .rev()
+        .map(|nibble| format!("{:X}", nibble))
+        .collect();
+    format!("0x{}", digits)
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+    use serde_json::json;
+
+    use super::*;
+    use crate::api::parse_and_link;
+    use crate::grammar::lexer::Location;
+
+    #[test]
+    fn test_translate_registers_and_primitive_gates() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    qreg q[1];
+    qreg r[1];
+    creg c[1];
+    creg d[2];
+    U(1, 2, 3) r[0];
+    CX q[0], r[0];
+    barrier q, r;
+    reset q;
+    measure r[0] -> d[1];
+    "
+        );
+        let program = parse_and_link(source).unwrap();
+        let qobj = translate(&program).unwrap();
+        assert_eq!(qobj["type"], "QASM");
+        let experiment = &qobj["experiments"][0];
+        assert_eq!(
+            experiment["header"]["qubit_labels"],
+            json!([["q", 0], ["r", 0]])
+        );
+        assert_eq!(
+            experiment["header"]["creg_sizes"],
+            json!([["c", 1], ["d", 2]])
+        );
+        assert_eq!(experiment["config"]["memory_slots"], 3);
+        assert_eq!(
+            experiment["instructions"],
+            json!([
+                { "name": "u3", "qubits": [1], "params": [1.0, 2.0, 3.0] },
+                { "name": "cx", "qubits": [0, 1] },
+                { "name": "barrier", "qubits": [0, 1] },
+                { "name": "reset", "qubits": [0] },
+                { "name": "measure", "qubits": [1], "memory": [2] },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_translate_inlines_custom_gates() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    gate rot(a) t { U(a, 0, a/2) t; }
+    qreg q[2];
+    rot(2) q;
+    "
+        );
+        let program = parse_and_link(source).unwrap();
+        let qobj = translate(&program).unwrap();
+        assert_eq!(
+            qobj["experiments"][0]["instructions"],
+            json!([
+                { "name": "u3", "qubits": [0], "params": [2.0, 0.0, 1.0] },
+                { "name": "u3", "qubits": [1], "params": [2.0, 0.0, 1.0] },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_translate_conditionals_into_bfunc() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    qreg q[2];
+    creg c[1];
+    creg d[2];
+    measure q[0] -> d[1];
+    if (d==2) CX q[0], q[1];
+    "
+        );
+        let program = parse_and_link(source).unwrap();
+        let qobj = translate(&program).unwrap();
+        assert_eq!(
+            qobj["experiments"][0]["instructions"],
+            json!([
+                { "name": "measure", "qubits": [0], "memory": [2], "register": [2] },
+                {
+                    "name": "bfunc",
+                    "mask": "0x6",
+                    "relation": "==",
+                    "val": "0x4",
+                    "register": 3
+                },
+                { "name": "cx", "qubits": [0, 1], "conditional": 3 },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_translate_keeps_opaque_gates() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    opaque gate magic(a) q;
+    qreg q[1];
+    magic(1) q[0];
+    "
+        );
+        let program = parse_and_link(source).unwrap();
+        let qobj = translate(&program).unwrap();
+        assert_eq!(
+            qobj["experiments"][0]["instructions"],
+            json!([{ "name": "magic", "qubits": [0], "params": [1.0] }])
+        );
+    }
+
+    #[test]
+    fn test_translation_reports_invalid_programs() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    qreg q[2];
+    xxx q;
+    "
+        );
+        let program = parse_and_link(source).unwrap();
+        assert_eq!(
+            translate(&program).unwrap_err(),
+            RuntimeError::UndefinedGate {
+                location: Location(25),
+                symbol_name: "xxx".into(),
+                suggestion: None
+            }
+        );
+    }
+}
@@ -0,0 +1,412 @@
+//! Translate OPENQASM programs into [Quil], the instruction language used by
+//! Rigetti quantum computers.
+//!
+//! Custom gates are inlined and register broadcasting is expanded so the
+//! resulting program only uses the `RZ`, `RY` and `CNOT` gates, plus `MEASURE`
+//! and `RESET` instructions. Parameter expressions are evaluated during the
+//! translation.
+//!
+//! [Quil]: https://github.com/quil-lang/quil
+
+use std::error;
+use std::fmt::{self, Write};
+
+use crate::error::RuntimeError;
+use crate::grammar::{ast, lexer::Location};
+use crate::interpreter::flattener::{Flattener, PrimitiveOp};
+use crate::semantics::{extract_semantics, Semantics};
+
+/// Represent the constructs that cannot be translated into Quil.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+
+pub enum TranslationError {
+    /// Opaque gates have no body so there is nothing to translate them into.
+    OpaqueGate {
+        /// Location of the opaque gate declaration.
+        location: Location,
+        /// Name of the opaque gate.
+        symbol_name: String,
+    },
+    /// A conditional without Quil equivalent. Only conditionals on
+    /// single-bit registers or on single bits can be translated.
+    UnsupportedConditional {
+        /// Location of the conditional statement.
+        location: Location,
+        /// Name of the classical register in the condition.
+        symbol_name: String,
+    },
+    /// The program is not valid and would fail when simulated.
+    RuntimeError(RuntimeError),
+}
+
+impl fmt::Display for TranslationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            TranslationError::RuntimeError(runtime_error) => format!("{}", runtime_error),
+            _ => match lazy_humanize! {
+                self,
+                TranslationError::OpaqueGate,
+                TranslationError::UnsupportedConditional
+            } {
+                Some(message) => message,
+                None => unreachable!(),
+            },
+        };
+        write!(f, "{}", message)
+    }
+}
+
+impl error::Error for TranslationError {}
+
+impl From<RuntimeError> for TranslationError {
+    fn from(runtime_error: RuntimeError) -> Self {
+        TranslationError::RuntimeError(runtime_error)
+    }
+}
+
+type Result<T> = std::result::Result<T, TranslationError>;
+
+/// Translate `program` into equivalent Quil source code.
+///
+/// # Errors
+///
+/// The function fails with [`TranslationError`] if the program contains
+/// opaque gate declarations, conditionals on registers of more than one bit,
+/// or if the program is not valid.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::parse_and_link;
+/// use qasmsim::output::quil::translate;
+///
+/// let program = parse_and_link(r#"
+/// OPENQASM 2.0;
+/// qreg q[2];
+/// creg c[2];
+/// CX q[0], q[1];
+/// measure q -> c;
+/// "#)?;
+///
+/// assert_eq!(translate(&program).unwrap(), concat!(
+///     "DECLARE c BIT[2]\n",
+///     "CNOT 0 1\n",
+///     "MEASURE 0 c[0]\n",
+///     "MEASURE 1 c[1]\n"
+/// ));
+/// # use qasmsim::QasmSimError;
+/// # Ok::<(), QasmSimError>(())
+/// ```
+///
+/// [`TranslationError`]: ./enum.TranslationError.html
+pub fn translate(program: &ast::OpenQasmProgram) -> Result<String> {
+    let semantics = extract_semantics(program).map_err(RuntimeError::from)?;
+    let mut translator = Translator::new(&semantics);
+    for span in &program.program {
+        if let ast::Statement::CRegDecl(name, size) = &*span.node {
+            translator.emit(format!("DECLARE {} BIT[{}]", name, size));
+        }
+    }
+    let mut flattener = Flattener::new(&semantics);
+    for span in &program.program {
+        flattener.location = span.boundaries.0;
+        translator.location = span.boundaries.0;
+        match &*span.node {
+            ast::Statement::OpaqueGateDecl { signature, .. } => {
+                return Err(TranslationError::OpaqueGate {
+                    location: translator.location,
+                    symbol_name: signature.0.to_string(),
+                })
+            }
+            ast::Statement::Barrier(_) => (),
+            statement => {
+                for instruction in flattener.flatten_statement(statement)? {
+                    translator.translate_instruction(&instruction)?;
+                }
+            }
+        }
+    }
+    Ok(translator.output)
+}
+
+struct Translator<'semantics> {
+    semantics: &'semantics Semantics,
+    location: Location,
+    label_count: usize,
+    output: String,
+}
+
+impl<'semantics> Translator<'semantics> {
+    fn new(semantics: &'semantics Semantics) -> Self {
+        Translator {
+            semantics,
+            location: Location::new(),
+            label_count: 0,
+            output: String::new(),
+        }
+    }
+
+    fn emit(&mut self, instruction: String) {
+        writeln!(self.output, "{}", instruction).expect("write into a string");
+    }
+
+    fn translate_instruction(&mut self, instruction: &PrimitiveOp) -> Result<()> {
+        match instruction {
+            PrimitiveOp::U {
+                theta,
+                phi,
+                lambda,
+                target,
+            } => {
+                self.emit(format!("RZ({}) {}", lambda, target));
+                self.emit(format!("RY({}) {}", theta, target));
+                self.emit(format!("RZ({}) {}", phi, target));
+            }
+            PrimitiveOp::Cx { control, target } => {
+                self.emit(format!("CNOT {} {}", control, target));
+            }
+            PrimitiveOp::Measure {
+                qubit,
+                register,
+                bit,
+            } => self.emit(format!("MEASURE {} {}[{}]", qubit, register, bit)),
+            PrimitiveOp::Reset { qubit } => self.emit(format!("RESET {}", qubit)),
+            PrimitiveOp::Barrier { .. } => (),
+            PrimitiveOp::Opaque { name, .. } => {
+                return Err(TranslationError::OpaqueGate {
+                    location: self.location,
+                    symbol_name: name.clone(),
+                })
+            }
+            PrimitiveOp::Conditional {
+                register,
+                bit,
+                value,
+                body,
+            } => self.translate_conditional(register, *bit, *value, body)?,
+        }
+        Ok(())
+    }
+
+    fn translate_conditional(
+        &mut self,
+        register: &str,
+        bit: Option<usize>,
+        test: u128,
+        body: &[PrimitiveOp],
+    ) -> Result<()> {
+        let bit = match bit {
+            Some(bit) => bit,
+            None => {
+                if self.semantics.register_table[register].2 != 1 {
+                    return Err(TranslationError::UnsupportedConditional {
+                        location: self.location,
+                        symbol_name: register.into(),
+                    });
+                }
+                0
+            }
+        };
+
+        // A single bit can never hold a value greater than 1.
+        if test > 1 {
+            return Ok(());
+        }
+
+        let label = format!("@skip{}", self.label_count);
+        self.label_count += 1;
+        let jump = if test == 1 {
+            "JUMP-UNLESS"
+        } else {
+            "JUMP-WHEN"
+        };
+        self.emit(format!("{} {} {}[{}]", jump, label, register, bit));
+        for instruction in body {
+            self.translate_instruction(instruction)?;
+        }
+        self.emit(format!("LABEL {}", label));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use indoc::indoc;
+
+    use super::*;
+    use crate::api::parse_and_link;
+    use crate::grammar::parse_program_with_extensions;
+
+    #[test]
+    fn test_translate_primitive_gates() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    qreg q[2];
+    U(1, 2, 3) q[1];
+    CX q[1], q[0];
+    "
+        );
+        let program = parse_and_link(source).unwrap();
+        assert_eq!(
+            translate(&program).unwrap(),
+            indoc!(
+                "
+        RZ(3) 1
+        RY(1) 1
+        RZ(2) 1
+        CNOT 1 0
+        "
+            )
+        );
+    }
+
+    #[test]
+    fn test_translate_inlines_custom_gates_and_broadcasts() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    gate rot(a) t { U(a, 0, a/2) t; }
+    gate pair c, t { rot(2) c; CX c, t; }
+    qreg q[1];
+    qreg r[1];
+    creg c[1];
+    creg d[1];
+    pair q, r;
+    reset q;
+    measure r -> d;
+    "
+        );
+        let program = parse_and_link(source).unwrap();
+        assert_eq!(
+            translate(&program).unwrap(),
+            indoc!(
+                "
+        DECLARE c BIT[1]
+        DECLARE d BIT[1]
+        RZ(1) 0
+        RY(2) 0
+        RZ(0) 0
+        CNOT 0 1
+        RESET 0
+        MEASURE 1 d[0]
+        "
+            )
+        );
+    }
+
+    #[test]
+    fn test_translate_single_bit_conditionals() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    qreg q[2];
+    creg c[1];
+    measure q[0] -> c[0];
+    if (c==1) U(0, 0, 1) q[0];
+    if (c==0) CX q[0], q[1];
+    "
+        );
+        let program = parse_and_link(source).unwrap();
+        assert_eq!(
+            translate(&program).unwrap(),
+            indoc!(
+                "
+        DECLARE c BIT[1]
+        MEASURE 0 c[0]
+        JUMP-UNLESS @skip0 c[0]
+        RZ(1) 0
+        RY(0) 0
+        RZ(0) 0
+        LABEL @skip0
+        JUMP-WHEN @skip1 c[0]
+        CNOT 0 1
+        LABEL @skip1
+        "
+            )
+        );
+    }
+
+    #[test]
+    fn test_translate_bit_conditionals() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    qreg q[2];
+    creg c[2];
+    if (c[1]==1) CX q[0], q[1];
+    "
+        );
+        let program = parse_program_with_extensions(source).unwrap();
+        assert_eq!(
+            translate(&program).unwrap(),
+            indoc!(
+                "
+        DECLARE c BIT[2]
+        JUMP-UNLESS @skip0 c[1]
+        CNOT 0 1
+        LABEL @skip0
+        "
+            )
+        );
+    }
+
+    #[test]
+    fn test_cannot_translate_conditionals_on_wide_registers() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    qreg q[2];
+    creg c[2];
+    if (c==2) CX q[0], q[1];
+    "
+        );
+        let program = parse_and_link(source).unwrap();
+        assert_eq!(
+            translate(&program).unwrap_err(),
+            TranslationError::UnsupportedConditional {
+                location: Location(36),
+                symbol_name: "c".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_cannot_translate_opaque_gates() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    opaque gate magic q;
+    "
+        );
+        let program = parse_and_link(source).unwrap();
+        assert_eq!(
+            translate(&program).unwrap_err(),
+            TranslationError::OpaqueGate {
+                location: Location(14),
+                symbol_name: "magic".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_translation_reports_invalid_programs() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    qreg q[2];
+    xxx q;
+    "
+        );
+        let program = parse_and_link(source).unwrap();
+        assert_eq!(
+            translate(&program).unwrap_err(),
+            TranslationError::RuntimeError(RuntimeError::UndefinedGate {
+                location: Location(25),
+                symbol_name: "xxx".into(),
+                suggestion: None
+            })
+        );
+    }
+}
@@ -0,0 +1,57 @@
+/// Quantile of the standard normal distribution for a 95% confidence level.
+const Z_95: f64 = 1.959_963_984_540_054;
+
+/// Return the frequency of an outcome observed `count` times in `shots`
+/// shots, and the lower and upper bounds of its Wilson score 95% confidence
+/// interval.
+///
+/// The bounds are clamped to `[0, 1]` so that `count = 0` and
+/// `count = shots` yield exactly 0 and 1. With no shots at all, the
+/// frequency is 0 and the interval spans the whole range.
+pub fn wilson_interval(count: usize, shots: usize) -> (f64, f64, f64) {
+    if shots == 0 {
+        return (0.0, 0.0, 1.0);
+    }
+    let n = shots as f64;
+    let frequency = count as f64 / n;
+    let z2 = Z_95 * Z_95;
+    let denominator = 1.0 + z2 / n;
+    let center = (frequency + z2 / (2.0 * n)) / denominator;
+    let margin =
+        Z_95 / denominator * (frequency * (1.0 - frequency) / n + z2 / (4.0 * n * n)).sqrt();
+    let lower = if count == 0 { 0.0 } else { center - margin };
+    let upper = if count == shots { 1.0 } else { center + margin };
+    (frequency, lower.clamp(0.0, 1.0), upper.clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: (f64, f64, f64), expected: (f64, f64, f64)) {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-4;
+        assert!(
+            close(actual.0, expected.0)
+                && close(actual.1, expected.1)
+                && close(actual.2, expected.2),
+            "{:?} is not close to {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_wilson_interval_known_values() {
+        assert_close(wilson_interval(50, 100), (0.5, 0.4038, 0.5962));
+        assert_close(wilson_interval(81, 263), (0.3080, 0.2553, 0.3662));
+        assert_close(wilson_interval(0, 10), (0.0, 0.0, 0.2775));
+        assert_close(wilson_interval(10, 10), (1.0, 0.7225, 1.0));
+    }
+
+    #[test]
+    fn test_wilson_interval_edge_cases_are_not_nan() {
+        assert_eq!(wilson_interval(0, 1000).1, 0.0);
+        assert_eq!(wilson_interval(1000, 1000).2, 1.0);
+        assert_eq!(wilson_interval(0, 0), (0.0, 0.0, 1.0));
+    }
+}
@@ -1,12 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Write};
 
 use prettytable::{cell, format, row, Table};
 
+use crate::error::QasmSimError;
 use crate::statevector::StateVector;
 use crate::{Execution, ExecutionTimes, Histogram};
 
-use crate::options::Options;
+use super::stats_utils::wilson_interval;
+use super::{format_bits, minmax_outcomes, printed_bases, required, unwritten_registers};
+use crate::options::{Options, SimulationMode};
 
 /// Writes the `msg` in the `buffer` if `options.verbose` is greater than 0.
 macro_rules! vvprint {
@@ -27,33 +30,34 @@ macro_rules! vvprintln {
 }
 
 /// Writes the `msg` in the `buffer`
-pub fn print<W>(buffer: &mut W, result: &Execution, options: &Options)
-where
-    W: Write,
-{
-    do_print(buffer, result, options).expect("writes in stdout");
-}
-
-/// Writes the `msg` in the `buffer`
-fn do_print<W>(buffer: &mut W, result: &Execution, options: &Options) -> fmt::Result
+pub fn print<W>(
+    buffer: &mut W,
+    result: &Execution,
+    options: &Options,
+) -> Result<(), QasmSimError<'static>>
 where
     W: Write,
 {
-    if options.shots.is_some() {
-        let histogram = result
-            .histogram()
-            .as_ref()
-            .expect("there is some histogram");
+    if options.shots.is_some() && matches!(options.mode, SimulationMode::Min | SimulationMode::Max)
+    {
+        let stats = required(result.stats(), "statistics")?;
+        if !stats.is_empty() {
+            vvprintln!(options, buffer, "Memory:")?;
+            print_minmax(buffer, stats, options)?;
+            vvprintln!(options, buffer)?;
+        }
+    } else if let Some(shots) = options.shots {
+        let histogram = required(result.histogram(), "histogram")?;
         if !histogram.is_empty() {
             vvprintln!(options, buffer, "Memory histogram:")?;
-            print_histogram(buffer, histogram, options)?;
+            print_histogram(buffer, histogram, shots, options)?;
             vvprintln!(options, buffer)?;
         }
     } else {
         let memory = result.memory();
         if !memory.is_empty() {
             vvprintln!(options, buffer, "Memory:")?;
-            print_memory(buffer, memory, options)?;
+            print_memory(buffer, memory, result.written_bits(), options)?;
             vvprintln!(options, buffer)?;
         }
     }
@@ -69,9 +73,15 @@ where
         vvprintln!(options, buffer)?;
     }
 
+    if options.bloch && options.shots.is_none() {
+        vvprintln!(options, buffer, "Bloch vectors:")?;
+        print_bloch(buffer, result.statevector(), result.quantum_registers())?;
+        vvprintln!(options, buffer)?;
+    }
+
     if options.times {
         vvprintln!(options, buffer, "Times:")?;
-        print_times(buffer, result.times())?;
+        print_times(buffer, result.times(), result.peak_memory_bytes())?;
         vvprintln!(options, buffer)?;
     }
     Ok(())
@@ -79,7 +89,8 @@ where
 
 fn print_memory<W>(
     buffer: &mut W,
-    memory: &HashMap<String, (u64, usize, usize)>,
+    memory: &HashMap<String, (u128, usize, usize)>,
+    written_bits: &HashMap<String, u128>,
     options: &Options,
 ) -> fmt::Result
 where
@@ -90,19 +101,34 @@ where
             .iter()
             .map(|(key, value)| (key.clone(), (vec![(value.0, 1)], value.1))),
     );
-    print_memory_summary(buffer, &histogram, options, true)
+    let unwritten = if options.mark_unwritten {
+        unwritten_registers(written_bits)
+    } else {
+        HashSet::new()
+    };
+    print_memory_summary(buffer, &histogram, &unwritten, 1, options, true)
 }
 
-fn print_histogram<W>(buffer: &mut W, histogram: &Histogram, options: &Options) -> fmt::Result
+fn print_histogram<W>(
+    buffer: &mut W,
+    histogram: &Histogram,
+    shots: usize,
+    options: &Options,
+) -> fmt::Result
 where
     W: Write,
 {
-    print_memory_summary(buffer, histogram, options, false)
+    print_memory_summary(buffer, histogram, &HashSet::new(), shots, options, false)
 }
 
+/// Print the values of the registers in `histogram`, out of `shots` shots.
+/// The frequencies are relative to `shots`, since the shots not writing a
+/// register are not counted in its histogram.
 fn print_memory_summary<W>(
     buffer: &mut W,
     histogram: &Histogram,
+    unwritten: &HashSet<String>,
+    shots: usize,
     options: &Options,
     omit_count: bool,
 ) -> fmt::Result
@@ -131,15 +157,31 @@ where
     if binary {
         titles.add_cell(cell!(c -> "Bin value"));
     }
+    if options.show_bits {
+        titles.add_cell(cell!(c -> "Bits"));
+    }
     if !omit_count {
         titles.add_cell(cell!(c -> "Count"));
     }
+    let frequencies = options.frequencies && !omit_count;
+    if frequencies {
+        titles.add_cell(cell!(c -> "Frequency"));
+        titles.add_cell(cell!(c -> "95% CI"));
+    }
     table.set_titles(titles);
 
     for (key, hist) in histogram {
         for (idx, (value, count)) in hist.0.iter().enumerate() {
             let mut row = row![r -> if idx == 0 { key } else { "" }];
             row.add_cell(cell!(r -> hist.1));
+            if unwritten.contains(key) {
+                let columns = [integer, hexadecimal, binary, options.show_bits];
+                for _ in columns.iter().filter(|&&shown| shown) {
+                    row.add_cell(cell!(r -> "-"));
+                }
+                table.add_row(row);
+                continue;
+            }
             if integer {
                 row.add_cell(cell!(r -> value));
             }
@@ -149,9 +191,17 @@ where
             if binary {
                 row.add_cell(cell!(r -> format!("0b{:0width$b}", value, width = hist.1)));
             }
+            if options.show_bits {
+                row.add_cell(cell!(r -> format_bits(key, *value, hist.1)));
+            }
             if !omit_count {
                 row.add_cell(cell!(r -> count));
             }
+            if frequencies {
+                let (frequency, lower, upper) = wilson_interval(*count, shots);
+                row.add_cell(cell!(r -> format!("{:.4}", frequency)));
+                row.add_cell(cell!(r -> format!("[{:.4}, {:.4}]", lower, upper)));
+            }
             table.add_row(row);
         }
     }
@@ -159,6 +209,25 @@ where
     write!(buffer, "{}", table)
 }
 
+fn print_minmax<W>(buffer: &mut W, stats: &HashMap<String, usize>, options: &Options) -> fmt::Result
+where
+    W: Write,
+{
+    let (outcomes, count) = minmax_outcomes(stats, options.mode);
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+
+    table.set_titles(row![c => "Outcome", "Count"]);
+    if let Some(outcome) = outcomes.first() {
+        table.add_row(row![r => outcome, count]);
+    }
+    if outcomes.len() > 1 {
+        table.add_row(row![r => "Tied", outcomes.join(", ")]);
+    }
+
+    write!(buffer, "{}", table)
+}
+
 fn print_state<W>(
     buffer: &mut W,
     statevector: &StateVector,
@@ -186,12 +255,11 @@ where
     }
     table.set_titles(titles);
 
-    let amplitudes_and_probabilities = statevector
-        .as_complex_bases()
-        .iter()
-        .zip(probabilities)
-        .enumerate();
-    for (idx, (amplitude, probability)) in amplitudes_and_probabilities {
+    for idx in printed_bases(statevector, options) {
+        let amplitude = statevector
+            .amplitude_at(idx)
+            .expect("the basis is in the state-vector");
+        let probability = probabilities[idx];
         let mut row = row![idx];
         if options.statevector {
             row.add_cell(cell!(format!("{:.6}", amplitude.re)));
@@ -206,7 +274,39 @@ where
     write!(buffer, "{}", table)
 }
 
-fn print_times<W>(buffer: &mut W, times: &ExecutionTimes) -> fmt::Result
+fn print_bloch<W>(
+    buffer: &mut W,
+    statevector: &StateVector,
+    quantum_registers: &HashMap<String, (usize, usize)>,
+) -> fmt::Result
+where
+    W: Write,
+{
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+
+    table.set_titles(row![c => "Qubit", "X", "Y", "Z"]);
+    for qubit in 0..statevector.qubit_width() {
+        let label = quantum_registers
+            .iter()
+            .find(|(_, (first, size))| (*first..*first + *size).contains(&qubit))
+            .map_or_else(
+                || qubit.to_string(),
+                |(name, (first, _))| format!("{}[{}]", name, qubit - first),
+            );
+        let (x, y, z) = statevector.bloch_vector(qubit);
+        table.add_row(row![
+            r -> label,
+            format!("{:.6}", x),
+            format!("{:.6}", y),
+            format!("{:.6}", z)
+        ]);
+    }
+
+    write!(buffer, "{}", table)
+}
+
+fn print_times<W>(buffer: &mut W, times: &ExecutionTimes, peak_memory_bytes: usize) -> fmt::Result
 where
     W: Write,
 {
@@ -217,5 +317,81 @@ where
     table.add_row(row!["parsing", times.parsing_time()]);
     table.add_row(row!["simulation", times.simulation_time()]);
 
+    let megabytes = |bytes: usize| format!("{:.3}", bytes as f64 / 1_000_000.0);
+    table.add_row(row![
+        "Estimated Peak Memory (MB)",
+        megabytes(peak_memory_bytes)
+    ]);
+    if let Some(measured_peak_memory_bytes) = times.peak_memory_bytes() {
+        table.add_row(row![
+            "Measured Peak Memory (MB)",
+            megabytes(measured_peak_memory_bytes)
+        ]);
+    }
+
     write!(buffer, "{}", table)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_print_times_with_peak_memory() {
+        let mut output = String::new();
+        print_times(&mut output, &ExecutionTimes::new(1, 2), 1_250_000).unwrap();
+        assert!(output.contains("Estimated Peak Memory (MB)"));
+        assert!(output.contains("1.250"));
+        assert!(!output.contains("Measured Peak Memory"));
+
+        let mut output = String::new();
+        print_times(
+            &mut output,
+            &ExecutionTimes::with_peak_memory(1, 2, 2_500_000),
+            1_250_000,
+        )
+        .unwrap();
+        assert!(output.contains("Measured Peak Memory (MB)"));
+        assert!(output.contains("2.500"));
+    }
+
+    #[test]
+    fn test_frequencies_are_relative_to_all_the_shots() {
+        // The register was only written in 6 of the 10 shots.
+        let histogram = HashMap::from([(String::from("c"), (vec![(0, 2), (1, 4)], 1))]);
+        let options = Options::builder().shots(10).frequencies(true).build();
+        let mut output = String::new();
+        print_histogram(&mut output, &histogram, 10, &options).unwrap();
+        assert!(output.contains("0.2000"));
+        assert!(output.contains("0.4000"));
+        assert!(!output.contains("0.6667"));
+    }
+
+    #[test]
+    fn test_print_minmax_lists_tied_outcomes() {
+        let stats = HashMap::from([
+            (String::from("11"), 3),
+            (String::from("01"), 3),
+            (String::from("10"), 3),
+            (String::from("00"), 1),
+        ]);
+        let options = Options::builder()
+            .shots(10)
+            .mode(SimulationMode::Max)
+            .build();
+        let mut output = String::new();
+        print_minmax(&mut output, &stats, &options).unwrap();
+        let rows: Vec<&str> = output.lines().skip(3).collect();
+        assert!(rows[0].contains("01") && rows[0].contains('3'));
+        assert!(rows[1].contains("Tied") && rows[1].contains("01, 10, 11"));
+
+        let options = Options::builder()
+            .shots(10)
+            .mode(SimulationMode::Min)
+            .build();
+        let mut output = String::new();
+        print_minmax(&mut output, &stats, &options).unwrap();
+        assert!(output.contains("00"));
+        assert!(!output.contains("Tied"));
+    }
+}
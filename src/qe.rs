@@ -1,3 +1,7 @@
+/// Version of the OPENQASM specification whose standard header, `qelib1.inc`,
+/// is embedded in [`QELIB1`].
+pub const QELIB1_VERSION: &str = "2.0";
+
 pub const QELIB1: &str = r#"
 // Quantum Experience (QE) Standard Header
 // file: qelib1.inc
@@ -142,3 +146,89 @@ gate rzz(theta) a,b
   cx a,b;
 }
 "#;
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_4};
+
+    use super::*;
+    use crate::grammar::{ast::Statement, parse_library};
+    use crate::statevector::{
+        assert_approx_eq, assert_approx_eq_with, Complex, ComplexMargin, StateVector,
+    };
+
+    fn run(body: &str, qubits: usize) -> StateVector {
+        let source = format!(
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[{}];\n{}",
+            qubits, body
+        );
+        crate::run(&source, None)
+            .expect("the circuit runs")
+            .statevector()
+            .clone()
+    }
+
+    fn basis(qubits: usize, index: usize) -> StateVector {
+        let mut bases = vec![Complex::from(0.0); 1 << qubits];
+        bases[index] = Complex::from(1.0);
+        StateVector::from_complex_bases_unchecked(bases)
+    }
+
+    fn superposition(phase: Complex) -> StateVector {
+        StateVector::from_complex_bases_unchecked(vec![
+            Complex::from(FRAC_1_SQRT_2),
+            phase * FRAC_1_SQRT_2,
+        ])
+    }
+
+    #[test]
+    fn validate_qelib1_inc() {
+        let library = parse_library(QELIB1).expect("the embedded qelib1.inc parses");
+        let gates: Vec<&str> = library
+            .definitions
+            .iter()
+            .filter_map(|span| match &*span.node {
+                Statement::GateDecl { signature, .. } => Some(signature.0.as_str()),
+                _ => None,
+            })
+            .collect();
+        for gate in [
+            "h", "cx", "x", "y", "z", "s", "t", "sdg", "tdg", "rx", "ry", "rz", "u1", "u2", "u3",
+            "ccx", "swap",
+        ] {
+            assert!(gates.contains(&gate), "qelib1.inc lacks `{}`", gate);
+        }
+
+        let i = Complex::new(0.0, 1.0);
+        let plus = superposition(Complex::from(1.0));
+        assert_approx_eq(&run("h q[0];", 1), &plus);
+        assert_approx_eq(&run("u2(0, pi) q[0];", 1), &plus);
+        assert_approx_eq(&run("ry(pi/2) q[0];", 1), &plus);
+        assert_approx_eq(&run("x q[0];", 1), &basis(1, 1));
+        assert_approx_eq(&run("u3(pi, 0, pi) q[0];", 1), &basis(1, 1));
+        assert_approx_eq(
+            &run("y q[0];", 1),
+            &StateVector::from_complex_bases_unchecked(vec![Complex::from(0.0), i]),
+        );
+        assert_approx_eq(&run("rx(pi/2) q[0];", 1), &superposition(-i));
+
+        let minus = superposition(Complex::from(-1.0));
+        assert_approx_eq(&run("h q[0]; z q[0];", 1), &minus);
+        assert_approx_eq(&run("h q[0]; u1(pi) q[0];", 1), &minus);
+        assert_approx_eq(&run("h q[0]; s q[0];", 1), &superposition(i));
+        assert_approx_eq(&run("h q[0]; rz(pi/2) q[0];", 1), &superposition(i));
+        assert_approx_eq(&run("h q[0]; sdg q[0];", 1), &superposition(-i));
+        let eighth = Complex::from_polar(1.0, FRAC_PI_4);
+        assert_approx_eq(&run("h q[0]; t q[0];", 1), &superposition(eighth));
+        assert_approx_eq(&run("h q[0]; tdg q[0];", 1), &superposition(eighth.conj()));
+
+        assert_approx_eq(&run("x q[0]; cx q[0], q[1];", 2), &basis(2, 0b11));
+        assert_approx_eq(&run("x q[0]; swap q[0], q[1];", 2), &basis(2, 0b10));
+        // The Toffoli decomposition accumulates rounding errors.
+        assert_approx_eq_with(
+            &run("x q[0]; x q[1]; ccx q[0], q[1], q[2];", 3),
+            &basis(3, 0b111),
+            ComplexMargin::epsilon(1e-12),
+        );
+    }
+}
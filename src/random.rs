@@ -1,6 +1,10 @@
-//! This module exists to force using JavaScript `Math.random()` in replacement
-//! of Rust `rand::random()` when targeting wasm32-unknown-unknown
-//! architecture.
+//! Contain the sources of random numbers deciding the outcomes of
+//! measurements.
+//!
+//! Simulations draw their numbers from a [`RandomSource`]. Unless told
+//! otherwise, they use [`ThreadRandom`], which forces using JavaScript
+//! `Math.random()` in replacement of Rust `rand::random()` when targeting
+//! wasm32-unknown-unknown architecture.
 //!
 //! For some reason, not using it, even [after enabling WASM features],
 //! causes the module `crypto` not to be found in the browser. It seems the
@@ -9,13 +13,347 @@
 //! > can't access property "crypto", getObject(...) is undefined
 //!
 //! [after enabling WASM features]: https://rust-random.github.io/book/crates.html?highlight=wasm#wasm-support
+//!
+//! # Examples
+//!
+//! Record the numbers drawn during a simulation and replay them to reproduce
+//! the exact same outcomes:
+//!
+//! ```
+//! use qasmsim::options::SimulationOptions;
+//! use qasmsim::random::{RecordingRandom, ReplayRandom, ThreadRandom};
+//! use qasmsim::{parse_and_link, simulate_with_rng};
+//!
+//! let program = parse_and_link(r#"
+//!     OPENQASM 2.0;
+//!     include "qelib1.inc";
+//!     qreg q[2];
+//!     creg c[2];
+//!     h q;
+//!     measure q -> c;
+//! "#)?;
+//! let options = SimulationOptions::default();
+//!
+//! let mut recording = RecordingRandom::new(ThreadRandom);
+//! let recorded = simulate_with_rng(&program, Some(100), &options, &mut recording)?;
+//!
+//! let mut replay = ReplayRandom::new(recording.into_recorded());
+//! let replayed = simulate_with_rng(&program, Some(100), &options, &mut replay)?;
+//! assert_eq!(recorded.histogram(), replayed.histogram());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::error;
+use std::fmt;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// A source of random numbers uniformly distributed in [0.0, 1.0).
+pub trait RandomSource {
+    /// Return the next random number.
+    fn next_f64(&mut self) -> f64;
+
+    /// Return `true` if the source was asked for more numbers than it can
+    /// provide. Simulations fail with [`RuntimeError::RandomSourceExhausted`]
+    /// as soon as their source runs out. Sources with an unlimited supply of
+    /// numbers never run out.
+    ///
+    /// [`RuntimeError::RandomSourceExhausted`]: ../error/enum.RuntimeError.html#variant.RandomSourceExhausted
+    fn ran_out(&self) -> bool {
+        false
+    }
+}
+
+/// The default source, drawing numbers from the random generator of the
+/// current thread or from `Math.random()` in WASM.
+#[derive(Debug, Clone, Copy, Default)]
+
+pub struct ThreadRandom;
+
+impl RandomSource for ThreadRandom {
+    fn next_f64(&mut self) -> f64 {
+        random()
+    }
+}
+
+/// A source drawing numbers from a generator seeded with a fixed value, so
+/// the same seed always produces the same numbers.
+#[derive(Debug, Clone)]
+
+pub struct SeededRandom {
+    rng: StdRng,
+}
+
+impl SeededRandom {
+    /// Create a new source seeded with `seed`.
+    pub fn new(seed: u64) -> Self {
+        SeededRandom {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_f64(&mut self) -> f64 {
+        self.rng.gen()
+    }
+}
+
+/// A source returning, in order, a list of numbers given in advance.
+///
+/// Once all the numbers are returned, the source [runs out] and returns 0.0
+/// for the next numbers. Simulations drawing from it fail then.
+///
+/// [runs out]: ./trait.RandomSource.html#method.ran_out
+#[derive(Debug, Clone, PartialEq)]
+
+pub struct ReplayRandom {
+    values: Vec<f64>,
+    position: usize,
+}
+
+impl ReplayRandom {
+    /// Create a new source returning `values`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the `values` is not in [0.0, 1.0). Use [`try_new()`]
+    /// for handling the error instead.
+    ///
+    /// [`try_new()`]: #method.try_new
+    pub fn new(values: Vec<f64>) -> Self {
+        Self::try_new(values).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Create a new source returning `values`.
+    ///
+    /// # Errors
+    ///
+    /// Fail with the first of the `values` which is not in [0.0, 1.0).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::random::ReplayRandom;
+    ///
+    /// assert!(ReplayRandom::try_new(vec![0.25, 0.5]).is_ok());
+    ///
+    /// let error = ReplayRandom::try_new(vec![0.25, 1.5]).unwrap_err();
+    /// assert_eq!(error.index, 1);
+    /// ```
+    pub fn try_new(values: Vec<f64>) -> Result<Self, RandomNumberOutOfRange> {
+        check_draws(&values)?;
+        Ok(ReplayRandom {
+            values,
+            position: 0,
+        })
+    }
+
+    /// Return the number of values not returned yet.
+    pub fn remaining(&self) -> usize {
+        self.values.len().saturating_sub(self.position)
+    }
+}
+
+impl RandomSource for ReplayRandom {
+    fn next_f64(&mut self) -> f64 {
+        let value = self.values.get(self.position).copied().unwrap_or(0.0);
+        self.position += 1;
+        value
+    }
+
+    fn ran_out(&self) -> bool {
+        self.position > self.values.len()
+    }
+}
+
+/// A source drawing numbers from another `source` and recording them so they
+/// can be replayed with [`ReplayRandom`].
+#[derive(Debug, Clone, Default)]
+
+pub struct RecordingRandom<R> {
+    source: R,
+    recorded: Vec<f64>,
+}
+
+impl<R: RandomSource> RecordingRandom<R> {
+    /// Create a new source recording the numbers drawn from `source`.
+    pub fn new(source: R) -> Self {
+        RecordingRandom {
+            source,
+            recorded: Vec::new(),
+        }
+    }
+
+    /// Return the numbers drawn so far.
+    pub fn recorded(&self) -> &[f64] {
+        &self.recorded
+    }
+
+    /// Consume the source and return the numbers drawn.
+    pub fn into_recorded(self) -> Vec<f64> {
+        self.recorded
+    }
+}
+
+impl<R: RandomSource> RandomSource for RecordingRandom<R> {
+    fn next_f64(&mut self) -> f64 {
+        let value = self.source.next_f64();
+        self.recorded.push(value);
+        value
+    }
+
+    fn ran_out(&self) -> bool {
+        self.source.ran_out()
+    }
+}
+
+/// Version of the JSON format written by [`Trace::to_json()`].
+///
+/// [`Trace::to_json()`]: ./struct.Trace.html#method.to_json
+pub const TRACE_VERSION: u64 = 1;
+
+/// The random numbers drawn during a simulation, in order, as returned by
+/// [`simulate_traced()`] and consumed by [`simulate_replay()`].
+///
+/// [`simulate_traced()`]: ../fn.simulate_traced.html
+/// [`simulate_replay()`]: ../fn.simulate_replay.html
+#[derive(Debug, Clone, PartialEq, Default)]
+
+pub struct Trace {
+    /// The random numbers drawn.
+    pub draws: Vec<f64>,
+}
+
+impl Trace {
+    /// Create a new trace with the random numbers `draws`.
+    pub fn new(draws: Vec<f64>) -> Self {
+        Trace { draws }
+    }
+
+    /// Return the trace in JSON format, such as
+    /// `{"version": 1, "draws": [0.25, 0.5]}`. Numbers are written with
+    /// enough precision for [`from_json()`] to read them back bit by bit.
+    ///
+    /// [`from_json()`]: #method.from_json
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "version": TRACE_VERSION,
+            "draws": self.draws,
+        })
+        .to_string()
+    }
+
+    /// Read a trace in the JSON format written by [`to_json()`].
+    ///
+    /// # Errors
+    ///
+    /// Fail with [`TraceError::InvalidFormat`] if `json` is not a trace or
+    /// any of its numbers is not in [0.0, 1.0), and with
+    /// [`TraceError::UnsupportedVersion`] if it was written in a version of
+    /// the format other than [`TRACE_VERSION`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::random::Trace;
+    ///
+    /// let trace = Trace::new(vec![0.1, 0.75]);
+    /// assert_eq!(Trace::from_json(&trace.to_json()), Ok(trace));
+    /// ```
+    ///
+    /// [`to_json()`]: #method.to_json
+    /// [`TraceError::InvalidFormat`]: ./enum.TraceError.html#variant.InvalidFormat
+    /// [`TraceError::UnsupportedVersion`]: ./enum.TraceError.html#variant.UnsupportedVersion
+    /// [`TRACE_VERSION`]: ./constant.TRACE_VERSION.html
+    pub fn from_json(json: &str) -> Result<Self, TraceError> {
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|_| TraceError::InvalidFormat)?;
+        let version = value["version"].as_u64().ok_or(TraceError::InvalidFormat)?;
+        if version != TRACE_VERSION {
+            return Err(TraceError::UnsupportedVersion(version));
+        }
+        let draws = value["draws"]
+            .as_array()
+            .ok_or(TraceError::InvalidFormat)?
+            .iter()
+            .map(|draw| draw.as_f64().ok_or(TraceError::InvalidFormat))
+            .collect::<Result<Vec<f64>, TraceError>>()?;
+        check_draws(&draws).map_err(|_| TraceError::InvalidFormat)?;
+        Ok(Trace { draws })
+    }
+}
+
+/// Check all the numbers in `draws` are valid random numbers, in [0.0, 1.0),
+/// and return the first one which is not.
+pub(crate) fn check_draws(draws: &[f64]) -> Result<(), RandomNumberOutOfRange> {
+    match draws
+        .iter()
+        .enumerate()
+        .find(|(_, draw)| !(0.0..1.0).contains(*draw))
+    {
+        Some((index, draw)) => Err(RandomNumberOutOfRange {
+            index,
+            value: *draw,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// A number given as random which is not in [0.0, 1.0).
+#[derive(Debug, Clone, Copy, PartialEq)]
+
+pub struct RandomNumberOutOfRange {
+    /// Position of the number in the list of numbers.
+    pub index: usize,
+    /// The number.
+    pub value: f64,
+}
+
+impl fmt::Display for RandomNumberOutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the random number at {} is {}, outside [0.0, 1.0)",
+            self.index, self.value
+        )
+    }
+}
+
+impl error::Error for RandomNumberOutOfRange {}
+
+/// Represent the errors reading a [`Trace`].
+///
+/// [`Trace`]: ./struct.Trace.html
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+
+pub enum TraceError {
+    /// The input is not a trace.
+    InvalidFormat,
+    /// The trace was written in an unknown version of the format.
+    UnsupportedVersion(u64),
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::InvalidFormat => write!(f, "invalid trace format"),
+            TraceError::UnsupportedVersion(version) => {
+                write!(f, "unsupported trace version {}", version)
+            }
+        }
+    }
+}
+
+impl error::Error for TraceError {}
 
 #[cfg(not(target_arch = "wasm32"))]
-pub(crate) fn random() -> f64 {
+fn random() -> f64 {
     rand::random()
 }
 
 #[cfg(target_arch = "wasm32")]
-pub(crate) fn random() -> f64 {
+fn random() -> f64 {
     js_sys::Math::random()
 }
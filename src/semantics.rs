@@ -2,8 +2,65 @@ use std::collections::HashMap;
 use std::error;
 use std::fmt;
 
-use crate::grammar::ast;
+use lazy_static::lazy_static;
+
 use crate::grammar::lexer::Location;
+use crate::grammar::{ast, parse_library};
+use crate::interpreter::expression_solver::ExpressionSolver;
+use crate::qe;
+
+lazy_static! {
+    /// Names of the gates defined in `qelib1.inc`.
+    static ref QELIB1_GATES: Vec<String> = parse_library(qe::QELIB1)
+        .expect("qelib1.inc is a valid library")
+        .definitions
+        .iter()
+        .filter_map(|definition| match &*definition.node {
+            ast::Statement::GateDecl { signature, .. } => Some(signature.0.to_string()),
+            _ => None,
+        })
+        .collect();
+
+    /// Definitions of the gates in `qelib1.inc`, with their bodies folded.
+    static ref QELIB1_DEFINITIONS: HashMap<String, MacroDefinition> =
+        parse_library(qe::QELIB1)
+            .expect("qelib1.inc is a valid library")
+            .definitions
+            .into_iter()
+            .filter_map(|definition| match *definition.node {
+                ast::Statement::GateDecl {
+                    signature: (name, real_args, args, body),
+                    ..
+                } => Some((
+                    name.to_string(),
+                    MacroDefinition(
+                        name.into_node(),
+                        names(&real_args),
+                        names(&args),
+                        fold_constants(body),
+                        Location(0),
+                    ),
+                )),
+                _ => None,
+            })
+            .collect();
+}
+
+/// Common names of gates, paired with the name of the gate in `qelib1.inc`.
+const WELL_KNOWN_GATE_NAMES: &[(&str, &str)] = &[
+    ("hadamard", "h"),
+    ("not", "x"),
+    ("cnot", "cx"),
+    ("toffoli", "ccx"),
+    ("ccnot", "ccx"),
+    ("identity", "id"),
+    ("i", "id"),
+    ("phase", "u1"),
+    ("p", "u1"),
+    ("cphase", "cu1"),
+    ("sdag", "sdg"),
+    ("tdag", "tdg"),
+];
 
 /// The different types for OPENQASM values.
 #[non_exhaustive]
@@ -56,13 +113,55 @@ pub enum SemanticError {
         /// Location of the original definition.
         previous_location: Location,
     },
+    /// Attempt to divide by zero in a parameter expression.
+    DivisionByZero {
+        /// Location of the statement with the division.
+        location: Location,
+    },
+    /// Attempt to declare a classical register wider than supported.
+    RegisterTooWide {
+        /// Name of the register.
+        symbol_name: String,
+        /// Location of the declaration.
+        location: Location,
+        /// Declared size of the register.
+        size: usize,
+        /// Maximum size supported.
+        max_size: usize,
+    },
+    /// Attempt to declare a register with no bits or qubits.
+    EmptyRegister {
+        /// Name of the register.
+        symbol_name: String,
+        /// Location of the declaration.
+        location: Location,
+    },
+    /// Attempt to test a bit beyond the end of a classical register in a
+    /// conditional.
+    IndexOutOfBounds {
+        /// Name of the register.
+        symbol_name: String,
+        /// Location of the conditional.
+        location: Location,
+        /// Index of the bit.
+        index: usize,
+        /// Size of the register.
+        size: usize,
+    },
 }
 
+/// Maximum number of bits of a classical register.
+pub const MAX_CLASSICAL_REGISTER_SIZE: usize = 128;
+
 impl fmt::Display for SemanticError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let message = match lazy_humanize! {
             self,
-            SemanticError::RedefinitionError
+            SemanticError::RedefinitionError,
+            SemanticError::DivisionByZero,
+            SemanticError::RegisterTooWide,
+            SemanticError::EmptyRegister,
+            SemanticError::IndexOutOfBounds
         } {
             Some(message) => message,
             None => unreachable!(),
@@ -89,13 +188,15 @@ pub struct MacroDefinition(
     pub String,
     pub Vec<String>,
     pub Vec<String>,
-    pub Vec<ast::GateOperation>,
+    pub Vec<ast::Spanned<ast::GateOperation>>,
     pub Location,
 );
 
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct Semantics {
     pub macro_definitions: HashMap<String, MacroDefinition>,
+    /// Opaque gates are stored as macro definitions with no body.
+    pub opaque_definitions: HashMap<String, MacroDefinition>,
     pub symbol_docstrings: HashMap<String, String>,
     pub register_table: HashMap<String, RegisterEntry>,
     /// Map quantum registers to a unique unified register while classical
@@ -105,6 +206,92 @@ pub struct Semantics {
     pub classical_memory_size: usize,
 }
 
+impl Semantics {
+    /// Return whether the gate `name`, and every gate in its body down to
+    /// `U` and `CX`, is defined exactly as in `qelib1.inc`.
+    pub fn is_qelib1_gate(&self, name: &str) -> bool {
+        if name == "U" || name == "CX" {
+            return true;
+        }
+        let (definition, reference) = match (
+            self.macro_definitions.get(name),
+            QELIB1_DEFINITIONS.get(name),
+        ) {
+            (Some(definition), Some(reference)) => (definition, reference),
+            _ => return false,
+        };
+        definition.1 == reference.1
+            && definition.2 == reference.2
+            && definition.3 == reference.3
+            && definition.3.iter().all(|operation| match &operation.node {
+                ast::GateOperation::Unitary(ast::UnitaryOperation(gate, _, _)) => {
+                    self.is_qelib1_gate(gate)
+                }
+                _ => true,
+            })
+    }
+
+    /// Return a hint about how to fix the use of the undefined gate `name`,
+    /// if some defined gate, or some gate in `qelib1.inc`, resembles it.
+    pub fn suggest_gate(&self, name: &str) -> Option<String> {
+        let qelib1_hint = |gate: &str| {
+            format!(
+                "gate '{}' is defined in \"qelib1.inc\"; add `include \"qelib1.inc\";`",
+                gate
+            )
+        };
+        let is_defined =
+            |gate: &str| self.macro_definitions.contains_key(gate) || gate == "U" || gate == "CX";
+
+        if QELIB1_GATES.iter().any(|gate| gate == name) {
+            return Some(qelib1_hint(name));
+        }
+
+        let alias = WELL_KNOWN_GATE_NAMES
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+            .map(|(_, gate)| *gate);
+        let candidate = alias.map(String::from).or_else(|| {
+            let threshold = name.chars().count() / 4;
+            self.macro_definitions
+                .keys()
+                .chain(self.opaque_definitions.keys())
+                .map(String::as_str)
+                .chain(["U", "CX"])
+                .chain(QELIB1_GATES.iter().map(String::as_str))
+                .map(|gate| (edit_distance(name, gate), gate))
+                .filter(|(distance, _)| *distance <= threshold)
+                .min()
+                .map(|(_, gate)| String::from(gate))
+        })?;
+
+        if is_defined(&candidate) || self.opaque_definitions.contains_key(&candidate) {
+            Some(format!("did you mean `{}`?", candidate))
+        } else {
+            Some(format!(
+                "did you mean `{}`? {}",
+                candidate,
+                qelib1_hint(&candidate)
+            ))
+        }
+    }
+}
+
+/// Return the Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
 #[derive(Debug, Clone, PartialEq, Default)]
 struct SemanticsBuilder {
     semantics: Semantics,
@@ -136,6 +323,14 @@ impl SemanticsBuilder {
         size: usize,
         location: Location,
     ) -> Result<()> {
+        if size > MAX_CLASSICAL_REGISTER_SIZE {
+            return Err(SemanticError::RegisterTooWide {
+                symbol_name: name,
+                location,
+                size,
+                max_size: MAX_CLASSICAL_REGISTER_SIZE,
+            });
+        }
         self.new_register(name.clone(), RegisterType::C, size, location)?;
         self.map_register(name.clone(), RegisterType::C, size);
         self.semantics.classical_memory_size += size;
@@ -143,28 +338,77 @@ impl SemanticsBuilder {
         Ok(())
     }
 
+    /// Check the bit tested in a conditional is inside its classical register.
+    /// Unknown registers are left for the runtime to report.
+    pub fn check_condition_register(
+        &self,
+        register: &ast::Argument,
+        location: Location,
+    ) -> Result<()> {
+        if let ast::Argument::Item(name, index) = register {
+            if let Some(RegisterEntry(_, RegisterType::C, size, _)) =
+                self.semantics.register_table.get(name.as_str())
+            {
+                if index >= size {
+                    return Err(SemanticError::IndexOutOfBounds {
+                        symbol_name: name.to_string(),
+                        location,
+                        index: *index,
+                        size: *size,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn new_gate(
         &mut self,
         name: String,
         real_args: Vec<String>,
         args: Vec<String>,
-        body: Vec<ast::GateOperation>,
+        body: Vec<ast::Spanned<ast::GateOperation>>,
+        location: Location,
+    ) -> Result<()> {
+        self.assert_gate_is_not_defined(&name, location)?;
+        let body = fold_constants(body);
+        self.semantics.macro_definitions.insert(
+            name.clone(),
+            MacroDefinition(name, real_args, args, body, location),
+        );
+
+        Ok(())
+    }
+
+    pub fn new_opaque_gate(
+        &mut self,
+        name: String,
+        real_args: Vec<String>,
+        args: Vec<String>,
         location: Location,
     ) -> Result<()> {
-        let entry = self.semantics.macro_definitions.get(&name);
+        self.assert_gate_is_not_defined(&name, location)?;
+        self.semantics.opaque_definitions.insert(
+            name.clone(),
+            MacroDefinition(name, real_args, args, Vec::new(), location),
+        );
+
+        Ok(())
+    }
+
+    fn assert_gate_is_not_defined(&self, name: &str, location: Location) -> Result<()> {
+        let entry = self
+            .semantics
+            .macro_definitions
+            .get(name)
+            .or_else(|| self.semantics.opaque_definitions.get(name));
         if let Some(MacroDefinition(_, _, _, _, previous_location)) = entry {
             return Err(SemanticError::RedefinitionError {
-                symbol_name: name,
+                symbol_name: name.into(),
                 location,
                 previous_location: *previous_location,
             });
         }
-
-        self.semantics.macro_definitions.insert(
-            name.clone(),
-            MacroDefinition(name, real_args, args, body, location),
-        );
-
         Ok(())
     }
 
@@ -181,6 +425,13 @@ impl SemanticsBuilder {
         size: usize,
         location: Location,
     ) -> Result<()> {
+        if size == 0 {
+            return Err(SemanticError::EmptyRegister {
+                symbol_name: name,
+                location,
+            });
+        }
+
         let entry = self.semantics.register_table.get(&name);
         if let Some(RegisterEntry(_, _, _, previous_location)) = entry {
             return Err(SemanticError::RedefinitionError {
@@ -227,33 +478,80 @@ impl SemanticsBuilder {
     }
 }
 
+/// Evaluate the parameter expressions in `body` that do not depend on the
+/// formal parameters of the gate, so they are not evaluated on every call.
+fn fold_constants(
+    body: Vec<ast::Spanned<ast::GateOperation>>,
+) -> Vec<ast::Spanned<ast::GateOperation>> {
+    let empty = HashMap::new();
+    let solver = ExpressionSolver::new(&empty);
+    body.into_iter()
+        .map(|operation| match operation.node {
+            ast::GateOperation::Unitary(ast::UnitaryOperation(name, real_args, args)) => {
+                let real_args = real_args.iter().map(|expr| solver.fold(expr)).collect();
+                ast::Spanned {
+                    boundaries: operation.boundaries,
+                    node: ast::GateOperation::Unitary(ast::UnitaryOperation(name, real_args, args)),
+                }
+            }
+            _ => operation,
+        })
+        .collect()
+}
+
+/// Return the names of a list of identifiers.
+fn names(identifiers: &[ast::Spanned<String>]) -> Vec<String> {
+    identifiers
+        .iter()
+        .map(|identifier| identifier.to_string())
+        .collect()
+}
+
 pub fn extract_semantics(tree: &ast::OpenQasmProgram) -> Result<Semantics> {
     let mut builder = SemanticsBuilder::new();
     for span in &tree.program {
         let location = span.boundaries.0;
         match &*span.node {
             ast::Statement::QRegDecl(name, size) => {
-                builder.new_quantum_register(name.clone(), *size, location)?
+                builder.new_quantum_register(name.to_string(), *size, location)?
             }
             ast::Statement::CRegDecl(name, size) => {
-                builder.new_classical_register(name.clone(), *size, location)?
+                builder.new_classical_register(name.to_string(), *size, location)?
             }
             ast::Statement::GateDecl {
                 signature: (name, real_args, args, operations),
                 docstring,
             } => {
                 if let Some(docstring_content) = docstring {
-                    builder.update_docstring(name.clone(), docstring_content.clone());
+                    builder.update_docstring(name.to_string(), docstring_content.clone());
                 }
                 builder.new_gate(
-                    name.clone(),
-                    real_args.to_vec(),
-                    args.to_vec(),
+                    name.to_string(),
+                    names(real_args),
+                    names(args),
                     operations.to_vec(),
                     location,
                 )?
             }
-            // TODO: What to do with opaque gates?
+            ast::Statement::OpaqueGateDecl {
+                signature: (name, real_args, args),
+                ..
+            } => builder.new_opaque_gate(
+                name.to_string(),
+                names(real_args),
+                names(args),
+                location,
+            )?,
+            _ => (),
+        }
+    }
+    for span in &tree.program {
+        let location = span.boundaries.0;
+        match &*span.node {
+            ast::Statement::Conditional(register, ..)
+            | ast::Statement::ConditionalBlock(register, ..) => {
+                builder.check_condition_register(register, location)?
+            }
             _ => (),
         }
     }
@@ -342,7 +640,7 @@ mod test {
 
     #[test]
     fn test_cannot_redeclare_a_register() {
-        let sources = vec![
+        let sources = [
             indoc!(
                 "
       OPENQASM 2.0;
@@ -450,10 +748,11 @@ mod test {
                     vec![],
                     vec!["q".to_owned()],
                     vec![ast::GateOperation::Unitary(ast::UnitaryOperation(
-                        "h".to_owned(),
+                        "h".into(),
                         vec![],
-                        vec![ast::Argument::Id("q".to_owned())],
-                    ))],
+                        vec![ast::Argument::Id("q".into())],
+                    ))
+                    .into()],
                     Location(14),
                 ),
             ),
@@ -464,18 +763,20 @@ mod test {
                     vec!["a".to_owned(), "b".to_owned()],
                     vec!["q".to_owned(), "r".to_owned()],
                     vec![ast::GateOperation::Unitary(ast::UnitaryOperation(
-                        "U".to_owned(),
+                        "U".into(),
                         vec![
                             ast::Expression::Op(
                                 ast::OpCode::Div,
-                                Box::new(ast::Expression::Id("a".to_owned())),
-                                Box::new(ast::Expression::Id("b".to_owned())),
-                            ),
-                            ast::Expression::Real(0.0),
-                            ast::Expression::Real(0.0),
+                                Box::new(ast::Expression::Id("a".to_owned()).into()),
+                                Box::new(ast::Expression::Id("b".to_owned()).into()),
+                            )
+                            .into(),
+                            ast::Expression::Real(0.0).into(),
+                            ast::Expression::Real(0.0).into(),
                         ],
-                        vec![ast::Argument::Id("q".to_owned())],
-                    ))],
+                        vec![ast::Argument::Id("q".into())],
+                    ))
+                    .into()],
                     Location(69),
                 ),
             ),
@@ -484,4 +785,130 @@ mod test {
             assert_eq!(semantics.macro_definitions, expected_definitions);
         }
     }
+
+    #[test]
+    fn test_gate_definitions_fold_closed_expressions() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    gate t q {
+      U(0, 0, pi/4) q;
+    }
+    gate rz(phi) q {
+      U(0, 0, phi/2 + pi/2) q;
+    }
+    "
+        );
+        let tree = parse_program(source).unwrap();
+        let semantics = extract_semantics(&tree).unwrap();
+
+        let t_body = &semantics.macro_definitions["t"].3;
+        assert_eq!(
+            t_body,
+            &vec![ast::GateOperation::Unitary(ast::UnitaryOperation(
+                "U".into(),
+                vec![
+                    ast::Expression::Real(0.0).into(),
+                    ast::Expression::Real(0.0).into(),
+                    ast::Expression::Real(std::f64::consts::FRAC_PI_4).into(),
+                ],
+                vec![ast::Argument::Id("q".into())],
+            ))
+            .into()]
+        );
+
+        let rz_body = &semantics.macro_definitions["rz"].3;
+        assert_eq!(
+            rz_body,
+            &vec![ast::GateOperation::Unitary(ast::UnitaryOperation(
+                "U".into(),
+                vec![
+                    ast::Expression::Real(0.0).into(),
+                    ast::Expression::Real(0.0).into(),
+                    ast::Expression::Op(
+                        ast::OpCode::Add,
+                        Box::new(
+                            ast::Expression::Op(
+                                ast::OpCode::Div,
+                                Box::new(ast::Expression::Id("phi".to_owned()).into()),
+                                Box::new(ast::Expression::Real(2.0).into()),
+                            )
+                            .into()
+                        ),
+                        Box::new(ast::Expression::Real(std::f64::consts::FRAC_PI_2).into()),
+                    )
+                    .into(),
+                ],
+                vec![ast::Argument::Id("q".into())],
+            ))
+            .into()]
+        );
+    }
+
+    #[test]
+    fn test_suggest_gate() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    gate bell_pair a, b {
+      CX a, b;
+    }
+    "
+        );
+        let tree = parse_program(source).unwrap();
+        let semantics = extract_semantics(&tree).unwrap();
+        assert_eq!(
+            semantics.suggest_gate("cx"),
+            Some(String::from(
+                "gate 'cx' is defined in \"qelib1.inc\"; add `include \"qelib1.inc\";`"
+            ))
+        );
+        assert_eq!(
+            semantics.suggest_gate("bell_pir"),
+            Some(String::from("did you mean `bell_pair`?"))
+        );
+        assert_eq!(
+            semantics.suggest_gate("cnot"),
+            Some(String::from(
+                "did you mean `cx`? gate 'cx' is defined in \"qelib1.inc\"; add `include \"qelib1.inc\";`"
+            ))
+        );
+        assert_eq!(semantics.suggest_gate("xyzzy"), None);
+    }
+
+    #[test]
+    fn test_is_qelib1_gate() {
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    "
+        );
+        let semantics = extract_semantics(&crate::parse_and_link(source).unwrap()).unwrap();
+        assert!(semantics.is_qelib1_gate("y"));
+        assert!(semantics.is_qelib1_gate("z"));
+        assert!(semantics.is_qelib1_gate("CX"));
+
+        let source = indoc!(
+            "
+    OPENQASM 2.0;
+    gate u3(theta,phi,lambda) q { U(phi,theta,lambda) q; }
+    gate y a { u3(pi,pi/2,pi/2) a; }
+    gate u1(lambda) q { U(0,0,lambda) q; }
+    gate z a { u1(-pi) a; }
+    "
+        );
+        let semantics = extract_semantics(&parse_program(source).unwrap()).unwrap();
+        assert!(!semantics.is_qelib1_gate("y"));
+        assert!(semantics.is_qelib1_gate("u1"));
+        assert!(!semantics.is_qelib1_gate("z"));
+        assert!(!semantics.is_qelib1_gate("x"));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("h", "h"), 0);
+    }
 }
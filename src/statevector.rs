@@ -1,5 +1,9 @@
 //! Contain utilities for representing the internal state of a quantum system.
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
 use std::f64;
+use std::fmt;
+use std::mem;
 
 use float_cmp::ApproxEq;
 use num::complex::ComplexFloat;
@@ -7,29 +11,196 @@ use num::complex::ComplexFloat;
 use self::cached_fns::{build_u, find_exchangeable_rows, find_target_rows};
 use crate::complex;
 pub use crate::complex::{Complex, ComplexMargin};
-use crate::random;
+use crate::error::QasmSimError;
+use crate::options::{Backend, Precision};
+use crate::random::{RandomSource, ThreadRandom};
+
+mod single;
+mod sparse;
+mod storage;
+
+pub use self::single::{Complex32, SinglePrecisionStateVector};
+pub use self::sparse::SparseStateVector;
+use self::storage::{insert_zero_bit, Storage};
+
+/// The operations a representation of the state of a quantum system needs to
+/// support for simulating OPENQASM programs.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::statevector::{QuantumState, SparseStateVector, StateVector};
+///
+/// fn bell(state: &mut dyn QuantumState) {
+///     state.u(std::f64::consts::FRAC_PI_2, 0.0, std::f64::consts::PI, 0);
+///     state.cnot(0, 1);
+/// }
+///
+/// let mut dense = StateVector::new(2);
+/// let mut sparse = SparseStateVector::new(2);
+/// bell(&mut dense);
+/// bell(&mut sparse);
+/// assert_eq!(dense.probabilities(), sparse.probabilities());
+/// ```
+pub trait QuantumState {
+    /// Return the number of qubits in the system.
+    fn qubit_width(&self) -> usize;
+
+    /// Apply a controlled not operation on qubit `target`. `control` and
+    /// `target` must be different qubits.
+    fn cnot(&mut self, control: usize, target: usize);
+
+    /// Apply a general rotation on `target` qubit, specified as
+    /// RZ(`phi`)RY(`theta`)RZ(`lambda`).
+    fn u(&mut self, theta: f64, phi: f64, lambda: f64, target: usize);
+
+    /// Apply the Pauli Y gate on `target` qubit.
+    fn y(&mut self, target: usize) {
+        self.u(
+            f64::consts::PI,
+            f64::consts::FRAC_PI_2,
+            f64::consts::FRAC_PI_2,
+            target,
+        )
+    }
+
+    /// Apply the Pauli Z gate on `target` qubit.
+    fn z(&mut self, target: usize) {
+        self.u(0.0, 0.0, f64::consts::PI, target)
+    }
+
+    /// Perform a measurement on the Z-axis of the quantum state on `target`
+    /// qubit.
+    fn measure(&mut self, target: usize) -> bool {
+        self.measure_with(target, &mut ThreadRandom)
+    }
+
+    /// Same as [`measure()`] but deciding the outcome with a number drawn
+    /// from `rng`.
+    ///
+    /// [`measure()`]: #method.measure
+    fn measure_with(&mut self, target: usize, rng: &mut dyn RandomSource) -> bool;
+
+    /// Return the probabilities of all the bases of the system.
+    fn probabilities(&self) -> Vec<f64>;
+
+    /// Return the probabilities of the outcomes of measuring `qubits` which
+    /// are not zero, in increasing order of outcome. The `j`-th bit of an
+    /// outcome is the result of measuring `qubits[j]`.
+    fn marginal_outcomes(&self, qubits: &[usize]) -> Vec<(usize, f64)> {
+        marginals_of(self.probabilities().into_iter().enumerate(), qubits)
+    }
+
+    /// Return the `k` bases with the highest probabilities along with those
+    /// probabilities, sorted in descending order of probability. Ties
+    /// resolve in favor of the lowest index.
+    fn top_k_outcomes(&self, k: usize) -> Vec<(usize, f64)> {
+        top_k_of(self.probabilities().into_iter().enumerate(), k)
+    }
+
+    /// Reset the state to |0⟩.
+    fn reset(&mut self);
+}
 
 /// Represent the state vector of a quantum system simulation.
 #[derive(Debug, Clone, PartialEq)]
 
 pub struct StateVector {
-    bases: Vec<Complex>,
+    bases: Storage,
     qubit_width: usize,
 }
 
+/// A basis state ordered by probability and, for equal probabilities, with
+/// the lowest index being the greatest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    probability: f64,
+    index: usize,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.probability
+            .total_cmp(&other.probability)
+            .then(other.index.cmp(&self.index))
+    }
+}
+
 impl StateVector {
     /// Create a new state-vector with of length 2 to the `qubit_width` power
     /// and all the amplitude concentrated in the all-zeroes outcome.
     pub fn new(qubit_width: usize) -> Self {
-        let bases = vec![Complex::new(0.0, 0.0); exp2(qubit_width)];
+        let bases = Storage::Dense(vec![Complex::new(0.0, 0.0); exp2(qubit_width)]);
+        let mut statevector = StateVector { bases, qubit_width };
+        statevector.reset();
+        statevector
+    }
+
+    /// Same as [`new()`] but storing the amplitudes in chunks of fixed size
+    /// instead of in a single contiguous block of memory.
+    ///
+    /// Simulating more than 28 qubits requires allocating several GiB for the
+    /// amplitudes. Chunked state-vectors can use fragmented memory, at the
+    /// cost of being slightly slower when applying gates.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::statevector::StateVector;
+    ///
+    /// let mut dense = StateVector::new(3);
+    /// let mut chunked = StateVector::new_chunked(3);
+    /// dense.u(std::f64::consts::FRAC_PI_2, 0.0, std::f64::consts::PI, 0);
+    /// chunked.u(std::f64::consts::FRAC_PI_2, 0.0, std::f64::consts::PI, 0);
+    /// assert_eq!(dense, chunked);
+    /// ```
+    ///
+    /// [`new()`]: #method.new
+    pub fn new_chunked(qubit_width: usize) -> Self {
+        let bases = Storage::zeroed_chunked(exp2(qubit_width));
         let mut statevector = StateVector { bases, qubit_width };
         statevector.reset();
         statevector
     }
 
-    /// Return the amplitudes corresponding to the bases of the system.
-    pub fn as_complex_bases(&self) -> &[Complex] {
-        &self.bases
+    /// Return the amplitudes corresponding to the bases of the system, or
+    /// `None` if the state-vector was created with [`new_chunked()`] and its
+    /// amplitudes do not fit in a single chunk. Use [`amplitudes()`] or
+    /// [`amplitude_at()`] for reading the amplitudes of any state-vector.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::statevector::StateVector;
+    ///
+    /// assert_eq!(StateVector::new(2).as_complex_bases().unwrap().len(), 4);
+    /// ```
+    ///
+    /// [`new_chunked()`]: #method.new_chunked
+    /// [`amplitudes()`]: #method.amplitudes
+    /// [`amplitude_at()`]: #method.amplitude_at
+    pub fn as_complex_bases(&self) -> Option<&[Complex]> {
+        self.bases.as_slice()
+    }
+
+    /// Return an iterator over the amplitudes corresponding to the bases of
+    /// the system, in increasing order of basis, however they are stored.
+    pub fn amplitudes(&self) -> impl Iterator<Item = &Complex> + '_ {
+        self.bases.iter()
+    }
+
+    /// Return the amplitude of the basis `index`, or `None` if `index` is
+    /// out of the state-vector.
+    pub fn amplitude_at(&self, index: usize) -> Option<Complex> {
+        self.bases.get(index).copied()
     }
 
     /// Return the 2-base logarithm of the number of amplitudes representing the
@@ -41,9 +212,204 @@ impl StateVector {
     /// Create a new state-vector from a vector of complex numbers representing
     /// amplitudes. It does not check the length of the vector is a power of
     /// two, not the norm of the vector is 1.
+    #[deprecated(
+        note = "use `from_amplitudes_with_validation()` or `from_complex_bases_unchecked()` instead"
+    )]
     pub fn from_complex_bases(bases: Vec<Complex>) -> Self {
+        StateVector::from_complex_bases_unchecked(bases)
+    }
+
+    /// Create a new state-vector from a vector of complex numbers representing
+    /// amplitudes. It does not check the length of the vector is a power of
+    /// two, not the norm of the vector is 1. Prefer
+    /// [`from_amplitudes_with_validation()`] unless the amplitudes are known
+    /// to be valid.
+    ///
+    /// [`from_amplitudes_with_validation()`]: #method.from_amplitudes_with_validation
+    pub fn from_complex_bases_unchecked(bases: Vec<Complex>) -> Self {
         let qubit_width = (bases.len() as f64).log2() as usize;
-        StateVector { bases, qubit_width }
+        StateVector {
+            bases: Storage::Dense(bases),
+            qubit_width,
+        }
+    }
+
+    /// Create a new state-vector from a vector of complex numbers representing
+    /// amplitudes, checking they describe a valid quantum state.
+    ///
+    /// # Errors
+    ///
+    /// Fail with [`QasmSimError::InvalidStateVector`] if the length of the
+    /// vector is not a power of two, if some amplitude is not finite, or if
+    /// the norm of the vector differs from 1 by more than 1e-6.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::f64::consts::FRAC_1_SQRT_2;
+    /// use qasmsim::statevector::{Complex, StateVector};
+    ///
+    /// let plus = StateVector::from_amplitudes_with_validation(vec![
+    ///     Complex::from(FRAC_1_SQRT_2),
+    ///     Complex::from(FRAC_1_SQRT_2),
+    /// ])?;
+    /// assert_eq!(plus.qubit_width(), 1);
+    ///
+    /// let unnormalized = StateVector::from_amplitudes_with_validation(vec![
+    ///     Complex::from(1.0),
+    ///     Complex::from(1.0),
+    /// ]);
+    /// assert!(unnormalized.is_err());
+    /// # Ok::<(), qasmsim::QasmSimError>(())
+    /// ```
+    ///
+    /// [`QasmSimError::InvalidStateVector`]: ../error/enum.QasmSimError.html#variant.InvalidStateVector
+    pub fn from_amplitudes_with_validation(
+        amplitudes: Vec<Complex>,
+    ) -> Result<Self, QasmSimError<'static>> {
+        let invalid = |reason: String| Err(QasmSimError::InvalidStateVector { reason });
+        if !amplitudes.len().is_power_of_two() {
+            return invalid(format!(
+                "the number of amplitudes, {}, is not a power of two",
+                amplitudes.len()
+            ));
+        }
+        if let Some(index) = amplitudes
+            .iter()
+            .position(|amplitude| !amplitude.is_finite())
+        {
+            return invalid(format!("the amplitude at {} is not finite", index));
+        }
+        let norm = amplitudes
+            .iter()
+            .map(|amplitude| amplitude.norm_sqr())
+            .sum::<f64>()
+            .sqrt();
+        if (norm - 1.0).abs() > 1e-6 {
+            return invalid(format!("the norm of the amplitudes, {}, is not 1", norm));
+        }
+        Ok(StateVector::from_complex_bases_unchecked(amplitudes))
+    }
+
+    /// Create the Bell state of index `bell_index` on two qubits:
+    ///
+    /// - 0: `1/sqrt(2)(|00⟩ + |11⟩)`
+    /// - 1: `1/sqrt(2)(|00⟩ - |11⟩)`
+    /// - 2: `1/sqrt(2)(|01⟩ + |10⟩)`
+    /// - 3: `1/sqrt(2)(|01⟩ - |10⟩)`
+    ///
+    /// # Panics
+    ///
+    /// The function panics if `bell_index` is greater than 3.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::run;
+    /// use qasmsim::statevector::{assert_approx_eq, StateVector};
+    ///
+    /// let result = run(r#"
+    /// OPENQASM 2.0;
+    /// include "qelib1.inc";
+    /// qreg q[2];
+    /// h q[0];
+    /// cx q[0], q[1];
+    /// "#, None)?;
+    /// assert_approx_eq(result.statevector(), &StateVector::bell_state(0));
+    /// # use qasmsim::QasmSimError;
+    /// # Ok::<(), QasmSimError>(())
+    /// ```
+    pub fn bell_state(bell_index: u8) -> Self {
+        assert!(
+            bell_index < 4,
+            "there are 4 Bell states, got index {}",
+            bell_index
+        );
+        let sign = if bell_index.is_multiple_of(2) {
+            1.0
+        } else {
+            -1.0
+        };
+        let (first, second) = if bell_index < 2 {
+            (0b00, 0b11)
+        } else {
+            (0b01, 0b10)
+        };
+        let mut amplitudes = vec![Complex::from(0.0); 4];
+        amplitudes[first] = Complex::from(f64::consts::FRAC_1_SQRT_2);
+        amplitudes[second] = Complex::from(sign * f64::consts::FRAC_1_SQRT_2);
+        StateVector::from_complex_bases_unchecked(amplitudes)
+    }
+
+    /// Create the GHZ state of `n_qubits` qubits,
+    /// `1/sqrt(2)(|0...0⟩ + |1...1⟩)`.
+    ///
+    /// # Panics
+    ///
+    /// The function panics if `n_qubits` is 0.
+    pub fn ghz_state(n_qubits: usize) -> Self {
+        assert!(n_qubits > 0, "the GHZ state needs at least one qubit");
+        let mut amplitudes = vec![Complex::from(0.0); exp2(n_qubits)];
+        amplitudes[0] = Complex::from(f64::consts::FRAC_1_SQRT_2);
+        amplitudes[exp2(n_qubits) - 1] = Complex::from(f64::consts::FRAC_1_SQRT_2);
+        StateVector::from_complex_bases_unchecked(amplitudes)
+    }
+
+    /// Create the W state of `n_qubits` qubits, the uniform superposition of
+    /// the basis states with exactly one qubit set,
+    /// `1/sqrt(n)(|0...01⟩ + |0...10⟩ + ... + |1...0⟩)`.
+    ///
+    /// # Panics
+    ///
+    /// The function panics if `n_qubits` is 0.
+    pub fn w_state(n_qubits: usize) -> Self {
+        assert!(n_qubits > 0, "the W state needs at least one qubit");
+        let amplitude = Complex::from(1.0 / (n_qubits as f64).sqrt());
+        let mut amplitudes = vec![Complex::from(0.0); exp2(n_qubits)];
+        for qubit in 0..n_qubits {
+            amplitudes[exp2(qubit)] = amplitude;
+        }
+        StateVector::from_complex_bases_unchecked(amplitudes)
+    }
+
+    /// Return the amplitude of the basis state written as the ket `label`,
+    /// such as `"011"` for |011⟩, or `None` if `label` is not made of exactly
+    /// as many `0` and `1` characters as qubits.
+    ///
+    /// As in [`as_ket_string()`], the rightmost character is the value of
+    /// qubit 0 and the leftmost one, the value of the last qubit, so the label
+    /// is the binary representation of the index of the amplitude.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::statevector::{Complex, StateVector};
+    ///
+    /// let state = StateVector::from_complex_bases_unchecked(vec![
+    ///     Complex::from(0.0),
+    ///     Complex::from(1.0),
+    ///     Complex::from(0.0),
+    ///     Complex::from(0.0),
+    /// ]);
+    /// assert_eq!(state.amplitude("01"), Some(Complex::from(1.0)));
+    /// assert_eq!(state.amplitude("10"), Some(Complex::from(0.0)));
+    /// assert_eq!(state.amplitude("1"), None);
+    /// ```
+    ///
+    /// [`as_ket_string()`]: #method.as_ket_string
+    pub fn amplitude(&self, label: &str) -> Option<Complex> {
+        if label.len() != self.qubit_width {
+            return None;
+        }
+        let mut index = 0;
+        for bit in label.chars() {
+            index = match bit {
+                '0' => index << 1,
+                '1' => (index << 1) | 1,
+                _ => return None,
+            };
+        }
+        self.bases.get(index).copied()
     }
 
     /// Get the length of the state-vector.
@@ -56,30 +422,472 @@ impl StateVector {
         self.bases.is_empty()
     }
 
+    /// Return whether both state-vectors have the same number of amplitudes
+    /// and each of them is within `margin` of the corresponding amplitude of
+    /// `other`. The [`ApproxEq`] implementation compares the same way.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::statevector::{Complex, ComplexMargin, StateVector};
+    ///
+    /// let v1 = StateVector::from_complex_bases_unchecked(vec![
+    ///     Complex::from(1.0),
+    ///     Complex::from(0.0),
+    /// ]);
+    /// let v2 = StateVector::from_complex_bases_unchecked(vec![
+    ///     Complex::from(1.0 - 1e-7),
+    ///     Complex::from(1e-7),
+    /// ]);
+    /// assert!(!v1.approx_eq_with_margin(&v2, ComplexMargin::default()));
+    /// assert!(v1.approx_eq_with_margin(&v2, ComplexMargin::epsilon(1e-6)));
+    /// ```
+    ///
+    /// [`ApproxEq`]: https://docs.rs/float-cmp/latest/float_cmp/trait.ApproxEq.html
+    pub fn approx_eq_with_margin(&self, other: &StateVector, margin: ComplexMargin) -> bool {
+        self.bases.len() == other.bases.len()
+            && self
+                .bases
+                .iter()
+                .zip(other.bases.iter())
+                .all(|(c1, c2)| margin.approx_eq(*c1, *c2))
+    }
+
+    /// Multiply the state-vector by the phase which makes its first
+    /// non-negligible amplitude real and positive. Amplitudes with a modulus
+    /// not greater than [`GLOBAL_PHASE_EPSILON`] are negligible. The
+    /// state-vector is left untouched if all the amplitudes are negligible.
+    ///
+    /// States differing only by a global phase are physically the same, so
+    /// normalizing the phase gives the same amplitudes for all of them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::f64::consts::FRAC_1_SQRT_2;
+    /// use qasmsim::statevector::{Complex, StateVector};
+    ///
+    /// let mut state = StateVector::from_complex_bases_unchecked(vec![
+    ///     Complex::new(0.0, -FRAC_1_SQRT_2),
+    ///     Complex::from(FRAC_1_SQRT_2),
+    /// ]);
+    /// state.normalize_global_phase();
+    /// assert!((state.amplitude_at(0).unwrap() - FRAC_1_SQRT_2).norm() < 1e-12);
+    /// assert!((state.amplitude_at(1).unwrap() - Complex::new(0.0, FRAC_1_SQRT_2)).norm() < 1e-12);
+    /// ```
+    ///
+    /// [`GLOBAL_PHASE_EPSILON`]: ./constant.GLOBAL_PHASE_EPSILON.html
+    pub fn normalize_global_phase(&mut self) {
+        let reference = self
+            .bases
+            .iter()
+            .find(|amplitude| amplitude.norm() > GLOBAL_PHASE_EPSILON)
+            .copied();
+        if let Some(reference) = reference {
+            let phase = reference.conj() / reference.norm();
+            for amplitude in self.bases.iter_mut() {
+                *amplitude *= phase;
+            }
+        }
+    }
+
+    /// Return whether both state-vectors have the same number of amplitudes
+    /// and are within `margin` of each other, as in
+    /// [`approx_eq_with_margin()`], once `other` is rotated by the global
+    /// phase that aligns it best with the state-vector.
+    ///
+    /// The phase is the one of the inner product of both state-vectors, so
+    /// the comparison does not depend on which amplitudes are negligible.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::f64::consts::{FRAC_1_SQRT_2, PI};
+    /// use qasmsim::statevector::{Complex, ComplexMargin, StateVector};
+    ///
+    /// let psi = StateVector::from_complex_bases_unchecked(vec![
+    ///     Complex::from(FRAC_1_SQRT_2),
+    ///     Complex::new(0.0, FRAC_1_SQRT_2),
+    /// ]);
+    /// let phase = Complex::from_polar(1.0, PI / 3.0);
+    /// let rotated: StateVector = psi.amplitudes().map(|a| a * phase).collect();
+    /// let margin = ComplexMargin::epsilon(1e-12);
+    /// assert!(!psi.approx_eq_with_margin(&rotated, margin));
+    /// assert!(psi.approx_eq_up_to_global_phase(&rotated, margin));
+    /// ```
+    ///
+    /// [`approx_eq_with_margin()`]: #method.approx_eq_with_margin
+    pub fn approx_eq_up_to_global_phase(&self, other: &StateVector, margin: ComplexMargin) -> bool {
+        if self.bases.len() != other.bases.len() {
+            return false;
+        }
+        let overlap: Complex = self
+            .bases
+            .iter()
+            .zip(other.bases.iter())
+            .map(|(c1, c2)| c2.conj() * c1)
+            .sum();
+        let phase = if overlap.norm() > GLOBAL_PHASE_EPSILON {
+            overlap / overlap.norm()
+        } else {
+            Complex::from(1.0)
+        };
+        self.bases
+            .iter()
+            .zip(other.bases.iter())
+            .all(|(c1, c2)| margin.approx_eq(*c1, c2 * phase))
+    }
+
     /// Apply a controlled not operation on qubit `target`.
     pub fn cnot(&mut self, control: usize, target: usize) {
-        let exchangable_rows = find_exchangeable_rows(self.qubit_width, control, target);
-        for (index_a, index_b) in exchangable_rows {
-            self.bases.swap(index_a, index_b);
+        if let Storage::Dense(bases) = &mut self.bases {
+            let exchangable_rows = find_exchangeable_rows(self.qubit_width, control, target);
+            for (index_a, index_b) in exchangable_rows {
+                bases.swap(index_a, index_b);
+            }
+            return;
+        }
+
+        // Chunked state-vectors are meant for widths whose cached rows would
+        // not fit in memory either, so the rows are computed on the fly.
+        let (low, high) = (control.min(target), control.max(target));
+        for n in 0..exp2(self.qubit_width - 2) {
+            let index_a = insert_zero_bit(insert_zero_bit(n, low), high) | exp2(control);
+            let index_b = index_a | exp2(target);
+            let amplitude_a = self.bases[index_a];
+            self.bases[index_a] = self.bases[index_b];
+            self.bases[index_b] = amplitude_a;
         }
     }
 
     /// Apply a general rotation on `target` qubit, specified as
     /// RZ(`phi`)RY(`theta`)RZ(`lambda`).
     pub fn u(&mut self, theta: f64, phi: f64, lambda: f64, target: usize) {
-        let target_rows = find_target_rows(self.qubit_width, target);
-        let u_matrix = build_u(theta, phi, lambda);
-        for (index_0, index_1) in target_rows {
+        self.apply_matrix(build_u(theta, phi, lambda), target);
+    }
+
+    /// Apply the general rotations `gates`, each of them specified as
+    /// `(theta, phi, lambda)` as in [`u()`], in order, on `target` qubit.
+    ///
+    /// The matrices of the gates are composed first so the amplitudes are
+    /// updated in a single pass instead of once per gate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::f64::consts::{FRAC_PI_2, PI};
+    /// use qasmsim::statevector::{assert_approx_eq, StateVector};
+    ///
+    /// let hadamard = (FRAC_PI_2, 0.0, PI);
+    /// let mut state = StateVector::new(1);
+    /// state.apply_gate_sequence(&[hadamard, hadamard], 0);
+    /// assert_approx_eq(&state, &StateVector::new(1));
+    /// ```
+    ///
+    /// [`u()`]: #method.u
+    pub fn apply_gate_sequence(&mut self, gates: &[(f64, f64, f64)], target: usize) {
+        if let Some(matrix) = compose_u(gates.iter().copied()) {
+            self.apply_matrix(matrix, target);
+        }
+    }
+
+    /// Apply the general rotations `ops`, each of them specified as
+    /// `(theta, phi, lambda, target)` as in [`u()`], in order.
+    ///
+    /// The matrices of consecutive rotations on the same target are composed
+    /// first, so the amplitudes are updated once per change of target
+    /// instead of once per rotation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::f64::consts::{FRAC_PI_2, PI};
+    /// use qasmsim::statevector::{assert_approx_eq, StateVector};
+    ///
+    /// let mut state = StateVector::new(2);
+    /// state.apply_sequence_1q(&[
+    ///     (PI, 0.0, PI, 0),
+    ///     (FRAC_PI_2, 0.0, PI, 1),
+    ///     (FRAC_PI_2, 0.0, PI, 1),
+    /// ]);
+    /// let mut expected = StateVector::new(2);
+    /// expected.u(PI, 0.0, PI, 0);
+    /// assert_approx_eq(&state, &expected);
+    /// ```
+    ///
+    /// [`u()`]: #method.u
+    pub fn apply_sequence_1q(&mut self, ops: &[(f64, f64, f64, usize)]) {
+        for group in ops.chunk_by(|a, b| a.3 == b.3) {
+            let gates = group
+                .iter()
+                .map(|&(theta, phi, lambda, _)| (theta, phi, lambda));
+            if let Some(matrix) = compose_u(gates) {
+                self.apply_matrix(matrix, group[0].3);
+            }
+        }
+    }
+
+    fn apply_matrix(&mut self, u_matrix: UMatrix, target: usize) {
+        if let Storage::Dense(bases) = &mut self.bases {
+            let target_rows = find_target_rows(self.qubit_width, target);
+            for (index_0, index_1) in target_rows {
+                let selected = (bases[index_0], bases[index_1]);
+                bases[index_0] = u_matrix.0 * selected.0 + u_matrix.1 * selected.1;
+                bases[index_1] = u_matrix.2 * selected.0 + u_matrix.3 * selected.1;
+            }
+            return;
+        }
+
+        for n in 0..exp2(self.qubit_width - 1) {
+            let index_0 = insert_zero_bit(n, target);
+            let index_1 = index_0 | exp2(target);
             let selected = (self.bases[index_0], self.bases[index_1]);
             self.bases[index_0] = u_matrix.0 * selected.0 + u_matrix.1 * selected.1;
             self.bases[index_1] = u_matrix.2 * selected.0 + u_matrix.3 * selected.1;
         }
     }
 
+    /// Apply the Pauli Y gate on `target` qubit, exchanging the amplitudes of
+    /// each pair of bases differing in `target` and multiplying them by `-i`
+    /// and `i`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::statevector::{Complex, StateVector};
+    ///
+    /// let mut state = StateVector::new(1);
+    /// state.y(0);
+    /// assert_eq!(state.amplitude("1"), Some(Complex::new(0.0, 1.0)));
+    /// ```
+    pub fn y(&mut self, target: usize) {
+        for n in 0..exp2(self.qubit_width - 1) {
+            let index_0 = insert_zero_bit(n, target);
+            let index_1 = index_0 | exp2(target);
+            let (amplitude_0, amplitude_1) = (self.bases[index_0], self.bases[index_1]);
+            self.bases[index_0] = Complex::new(amplitude_1.im, -amplitude_1.re);
+            self.bases[index_1] = Complex::new(-amplitude_0.im, amplitude_0.re);
+        }
+    }
+
+    /// Apply the Pauli Z gate on `target` qubit, negating the amplitudes of
+    /// the bases where `target` is 1.
+    pub fn z(&mut self, target: usize) {
+        for (index, amplitude) in self.bases.iter_mut().enumerate() {
+            if check_bit(index, target) == 1 {
+                *amplitude = -*amplitude;
+            }
+        }
+    }
+
+    /// Apply the quantum Fourier transform over `qubits`, with `qubits[0]`
+    /// being the least significant. The transform includes the final swaps
+    /// reversing the order of the qubits.
+    pub fn qft(&mut self, qubits: &[usize]) {
+        for (position, &target) in qubits.iter().enumerate().rev() {
+            self.u(f64::consts::FRAC_PI_2, 0.0, f64::consts::PI, target);
+            for (distance, &control) in qubits[..position].iter().rev().enumerate() {
+                let lambda = f64::consts::PI / exp2(distance + 1) as f64;
+                self.controlled_phase(lambda, control, target);
+            }
+        }
+        let half = qubits.len() / 2;
+        let mirrored: Vec<usize> = qubits.iter().rev().take(half).copied().collect();
+        self.swap_registers(&qubits[..half], &mirrored)
+            .expect("halves of the same length");
+    }
+
+    /// Swap the qubits in `register_a` with the qubits in `register_b`, so
+    /// that `register_a[i]` and `register_b[i]` exchange their values.
+    ///
+    /// The swaps are composed into a single permutation of the qubits and
+    /// the amplitudes are moved once, instead of applying one `swap` gate per
+    /// pair of qubits. The swaps are composed in order, so qubits shared by
+    /// both registers behave as if swapped one pair after another.
+    ///
+    /// Amplitudes are moved in place, following the cycles of the
+    /// permutation, so the only extra memory is one bit per amplitude for
+    /// marking the amplitudes already moved.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`QasmSimError::InvalidQubits`] if the registers have
+    /// different lengths or if any qubit is not a qubit of the state-vector.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::statevector::StateVector;
+    ///
+    /// let mut state = StateVector::new(4);
+    /// state.u(std::f64::consts::PI, 0.0, std::f64::consts::PI, 0);
+    /// state.u(std::f64::consts::PI, 0.0, std::f64::consts::PI, 1);
+    /// state.swap_registers(&[0, 1], &[2, 3])?;
+    /// assert_eq!(state.probabilities()[0b1100], 1.0);
+    /// # Ok::<(), qasmsim::QasmSimError>(())
+    /// ```
+    ///
+    /// [`QasmSimError::InvalidQubits`]: ../error/enum.QasmSimError.html#variant.InvalidQubits
+    pub fn swap_registers(
+        &mut self,
+        register_a: &[usize],
+        register_b: &[usize],
+    ) -> Result<(), QasmSimError<'static>> {
+        if register_a.len() != register_b.len() {
+            return Err(QasmSimError::InvalidQubits {
+                reason: format!(
+                    "cannot swap a register of {} qubits with a register of {} qubits",
+                    register_a.len(),
+                    register_b.len()
+                ),
+            });
+        }
+        if let Some(&qubit) = register_a
+            .iter()
+            .chain(register_b)
+            .find(|&&qubit| qubit >= self.qubit_width)
+        {
+            return Err(QasmSimError::InvalidQubits {
+                reason: format!(
+                    "qubit {} is out of the state-vector of {} qubits",
+                    qubit, self.qubit_width
+                ),
+            });
+        }
+        // The qubit whose value ends up at each position.
+        let mut origins: Vec<usize> = (0..self.qubit_width).collect();
+        for (&qubit_a, &qubit_b) in register_a.iter().zip(register_b) {
+            origins.swap(qubit_a, qubit_b);
+        }
+        let moved: Vec<(usize, usize)> = origins
+            .into_iter()
+            .enumerate()
+            .filter(|(position, origin)| position != origin)
+            .collect();
+        if moved.is_empty() {
+            return Ok(());
+        }
+        let moved_mask = moved
+            .iter()
+            .fold(0, |mask, &(position, _)| mask | exp2(position));
+        let permute = |index: usize| {
+            moved
+                .iter()
+                .fold(index & !moved_mask, |permuted, &(position, origin)| {
+                    permuted | (check_bit(index, position) << origin)
+                })
+        };
+        // Moving the bits is linear, so permuting the low and the high halves
+        // of an index separately, with precomputed tables, is enough.
+        let low_width = self.qubit_width / 2;
+        let low_mask = exp2(low_width) - 1;
+        let low_table: Vec<usize> = (0..exp2(low_width)).map(permute).collect();
+        let high_table: Vec<usize> = (0..exp2(self.qubit_width - low_width))
+            .map(|high| permute(high << low_width))
+            .collect();
+        let origin = |index: usize| high_table[index >> low_width] | low_table[index & low_mask];
+
+        // Each amplitude takes the value of the amplitude at its origin, so
+        // walking every cycle from its start moves all of its amplitudes
+        // with a single temporary.
+        let mut visited = vec![0_u64; self.len().div_ceil(64)];
+        for start in 0..self.len() {
+            if visited[start / 64] >> (start % 64) & 1 == 1 {
+                continue;
+            }
+            let first = self.bases[start];
+            let mut index = start;
+            loop {
+                visited[index / 64] |= 1 << (index % 64);
+                let next = origin(index);
+                if next == start {
+                    self.bases[index] = first;
+                    break;
+                }
+                self.bases[index] = self.bases[next];
+                index = next;
+            }
+        }
+        Ok(())
+    }
+
+    fn controlled_phase(&mut self, lambda: f64, control: usize, target: usize) {
+        let phase = e_power_to(lambda);
+        for (index, amplitude) in self.bases.iter_mut().enumerate() {
+            if check_bit(index, control) == 1 && check_bit(index, target) == 1 {
+                *amplitude *= phase;
+            }
+        }
+    }
+
+    /// Project the state on the `outcome` of the `target` qubit and
+    /// renormalize it. Return the probability of the outcome before the
+    /// projection, leaving the state untouched if it is not greater than
+    /// `f64::EPSILON`.
+    pub(crate) fn postselect(&mut self, target: usize, outcome: bool) -> f64 {
+        let value = outcome as usize;
+        let probability: f64 = self
+            .bases
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| check_bit(*index, target) == value)
+            .map(|(_, amplitude)| amplitude.norm_sqr())
+            .sum();
+        if probability > f64::EPSILON {
+            let normalization_factor = probability.sqrt();
+            for (index, amplitude) in self.bases.iter_mut().enumerate() {
+                if check_bit(index, target) == value {
+                    *amplitude /= normalization_factor;
+                } else {
+                    *amplitude = Complex::from(0.0);
+                }
+            }
+        }
+        probability
+    }
+
     /// Perform a measurement on the Z-axis of the quantum state on `target` qubit.
     pub fn measure(&mut self, target: usize) -> bool {
+        self.measure_with(target, &mut ThreadRandom)
+    }
+
+    /// Same as [`measure()`] but deciding the outcome with a number drawn
+    /// from `rng`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::random::ReplayRandom;
+    /// use qasmsim::statevector::StateVector;
+    ///
+    /// let mut state = StateVector::new(1);
+    /// state.u(std::f64::consts::FRAC_PI_2, 0.0, std::f64::consts::PI, 0);
+    /// assert!(state.measure_with(0, &mut ReplayRandom::new(vec![0.75])));
+    /// ```
+    ///
+    /// [`measure()`]: #method.measure
+    pub fn measure_with(&mut self, target: usize, rng: &mut dyn RandomSource) -> bool {
         let mut measurement = Measurement::new(&mut self.bases, target);
-        measurement.collapse(random::random())
+        measurement.collapse(rng.next_f64())
+    }
+
+    /// Measure every qubit, in order, collapsing the state-vector and return
+    /// the outcomes indexed by qubit.
+    pub fn measure_all(&mut self) -> Vec<bool> {
+        (0..self.qubit_width)
+            .map(|qubit| self.measure(qubit))
+            .collect()
+    }
+
+    /// Measure the `qubits`, in order, collapsing the state-vector and return
+    /// the outcomes as an integer whose `i`-th bit corresponds to the outcome
+    /// of measuring `qubits[i]`.
+    pub fn measure_register(&mut self, qubits: &[usize]) -> u64 {
+        qubits
+            .iter()
+            .enumerate()
+            .map(|(index, qubit)| (self.measure(*qubit) as u64) << index)
+            .sum()
     }
 
     /// Return the probabilities associated to the amplitudes in the
@@ -88,6 +896,54 @@ impl StateVector {
         self.bases.iter().map(|c| c.norm_sqr()).collect()
     }
 
+    /// Same as [`top_k_outcomes()`].
+    ///
+    /// [`top_k_outcomes()`]: #method.top_k_outcomes
+    pub fn top_k(&self, k: usize) -> Vec<(usize, f64)> {
+        self.top_k_outcomes(k)
+    }
+
+    /// Return the `k` basis states with the highest probabilities along with
+    /// those probabilities, sorted in descending order of probability. Ties
+    /// resolve in favor of the lowest index.
+    ///
+    /// Only the `k` best candidates are kept in a heap while scanning the
+    /// amplitudes so the cost is O(2^n log k) and the memory used does not
+    /// grow with the size of the state-vector.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qasmsim::statevector::StateVector;
+    ///
+    /// let mut state = StateVector::new(2);
+    /// state.u(std::f64::consts::FRAC_PI_2, 0.0, std::f64::consts::PI, 1);
+    /// let top = state.top_k_outcomes(3);
+    /// let indices: Vec<usize> = top.iter().map(|(index, _)| *index).collect();
+    /// assert_eq!(indices, vec![0b00, 0b10, 0b01]);
+    /// ```
+    pub fn top_k_outcomes(&self, k: usize) -> Vec<(usize, f64)> {
+        top_k_of(
+            self.bases
+                .iter()
+                .map(|amplitude| amplitude.norm_sqr())
+                .enumerate(),
+            k.min(self.len()),
+        )
+    }
+
+    /// Return the marginal probabilities of the `size` qubits starting at
+    /// `offset`. The `i`-th probability corresponds to the outcome whose
+    /// `j`-th bit is the outcome of measuring qubit `offset + j`.
+    pub fn marginal_probabilities(&self, offset: usize, size: usize) -> Vec<f64> {
+        let mask = (1 << size) - 1;
+        let mut marginals = vec![0.0; 1 << size];
+        for (index, amplitude) in self.bases.iter().enumerate() {
+            marginals[(index >> offset) & mask] += amplitude.norm_sqr();
+        }
+        marginals
+    }
+
     /// Perform a expectation value measurement on the Z-axis of the quantum state
     pub fn expectation_values(&self) -> Vec<f64> {
         let probabilities = self.probabilities();
@@ -103,7 +959,7 @@ impl StateVector {
                 }
             }
             // deal with floating point errors, for zero and one
-            sum = f64::max(0.0, f64::min(1.0, sum));
+            sum = sum.clamp(0.0, 1.0);
             expectation_values.push(sum);
         }
         expectation_values
@@ -131,6 +987,309 @@ impl StateVector {
         expectation.re()
     }
 
+    /// Return the entanglement entropy, in bits, of the bipartition between
+    /// the `cut` least significant qubits and the rest of the system.
+    ///
+    /// The entropy is computed from the reduced density matrix of the
+    /// smallest side of the bipartition.
+    ///
+    /// # Panics
+    ///
+    /// Panics if both sides of the bipartition have more than
+    /// [`MAX_SCHMIDT_QUBITS`] qubits.
+    ///
+    /// [`MAX_SCHMIDT_QUBITS`]: ./constant.MAX_SCHMIDT_QUBITS.html
+    pub fn entanglement_entropy_bits(&self, cut: usize) -> f64 {
+        if cut == 0 || cut >= self.qubit_width {
+            return 0.0;
+        }
+        if let Err(reason) = check_entanglement_cut(cut, self.qubit_width) {
+            panic!("{}", reason);
+        }
+        let (kept, traced) = smallest_side(cut, self.qubit_width);
+        entropy_in_bits(&self.reduced_density_eigenvalues(&kept, &traced))
+    }
+
+    /// Return the Schmidt coefficients of the bipartition between the
+    /// `subsystem` qubits and the rest of the system, in descending order.
+    ///
+    /// There are as many coefficients as amplitudes in the smallest side of
+    /// the bipartition, and the sum of their squares is 1. A product state
+    /// has a single non-zero coefficient.
+    ///
+    /// Computing them takes the eigenvalues of a 2^k×2^k matrix, with k the
+    /// number of qubits of the smallest side, built from the 2^k×2^(n−k)
+    /// matrix of amplitudes, so the cost grows quickly with k.
+    ///
+    /// # Panics
+    ///
+    /// Panics if some qubit in `subsystem` is repeated or out of the
+    /// state-vector, or if both sides of the bipartition have more than
+    /// [`MAX_SCHMIDT_QUBITS`] qubits.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_2, PI};
+    /// use qasmsim::statevector::StateVector;
+    ///
+    /// let mut bell = StateVector::new(2);
+    /// bell.u(FRAC_PI_2, 0.0, PI, 0);
+    /// bell.cnot(0, 1);
+    /// let coefficients = bell.schmidt_coefficients(&[0]);
+    /// assert!((coefficients[0] - FRAC_1_SQRT_2).abs() < 1e-10);
+    /// assert!((coefficients[1] - FRAC_1_SQRT_2).abs() < 1e-10);
+    /// ```
+    ///
+    /// [`MAX_SCHMIDT_QUBITS`]: ./constant.MAX_SCHMIDT_QUBITS.html
+    pub fn schmidt_coefficients(&self, subsystem: &[usize]) -> Vec<f64> {
+        if let Err(reason) = check_schmidt_subsystem(subsystem, self.qubit_width) {
+            panic!("{}", reason);
+        }
+        let rest: Vec<usize> = (0..self.qubit_width)
+            .filter(|qubit| !subsystem.contains(qubit))
+            .collect();
+        let (kept, traced) = if subsystem.len() <= rest.len() {
+            (subsystem, rest.as_slice())
+        } else {
+            (rest.as_slice(), subsystem)
+        };
+        self.reduced_density_eigenvalues(kept, traced)
+            .into_iter()
+            .map(|eigenvalue| eigenvalue.max(0.0).sqrt())
+            .collect()
+    }
+
+    /// Return the entanglement entropy, in nats, of the bipartition between
+    /// the `subsystem` qubits and the rest of the system, computed as
+    /// −Σ λ² ln λ² over the [Schmidt coefficients] λ.
+    ///
+    /// Unlike [`entanglement_entropy_bits()`], which is in bits and only
+    /// splits the system at a cut, the subsystem can be any set of qubits. It
+    /// is 0 for product states and ln 2 for a Bell pair. Divide by ln 2 to
+    /// convert to bits.
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases [`schmidt_coefficients()`] does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::f64::consts::{FRAC_PI_2, LN_2, PI};
+    /// use qasmsim::statevector::StateVector;
+    ///
+    /// let mut bell = StateVector::new(2);
+    /// bell.u(FRAC_PI_2, 0.0, PI, 0);
+    /// bell.cnot(0, 1);
+    /// assert!((bell.entanglement_entropy(&[1]) - LN_2).abs() < 1e-10);
+    /// ```
+    ///
+    /// [Schmidt coefficients]: #method.schmidt_coefficients
+    /// [`schmidt_coefficients()`]: #method.schmidt_coefficients
+    /// [`entanglement_entropy_bits()`]: #method.entanglement_entropy_bits
+    pub fn entanglement_entropy(&self, subsystem: &[usize]) -> f64 {
+        self.schmidt_coefficients(subsystem)
+            .iter()
+            .map(|coefficient| coefficient * coefficient)
+            .filter(|&probability| probability > f64::EPSILON)
+            .map(|probability| -probability * probability.ln())
+            .sum()
+    }
+
+    /// Return the reduced density matrix of the `qubits`, tracing out the
+    /// rest of the system.
+    ///
+    /// Rows and columns are indexed by the values of the `qubits`, the first
+    /// of them being the least significant bit, so the matrix of `&[1, 0]` is
+    /// that of `&[0, 1]` with the qubits swapped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if some qubit in `qubits` is repeated or out of the
+    /// state-vector, or if there are more than [`MAX_DENSITY_QUBITS`] of
+    /// them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::f64::consts::{FRAC_PI_2, PI};
+    /// use qasmsim::statevector::StateVector;
+    ///
+    /// let mut bell = StateVector::new(2);
+    /// bell.u(FRAC_PI_2, 0.0, PI, 0);
+    /// bell.cnot(0, 1);
+    /// let density = bell.density_matrix(&[0]);
+    /// assert!((density[0][0].re - 0.5).abs() < 1e-10);
+    /// assert!(density[0][1].norm() < 1e-10);
+    /// ```
+    ///
+    /// [`MAX_DENSITY_QUBITS`]: ./constant.MAX_DENSITY_QUBITS.html
+    pub fn density_matrix(&self, qubits: &[usize]) -> Vec<Vec<Complex>> {
+        if let Err(reason) = check_density_qubits(qubits, self.qubit_width) {
+            panic!("{}", reason);
+        }
+        let traced: Vec<usize> = (0..self.qubit_width)
+            .filter(|qubit| !qubits.contains(qubit))
+            .collect();
+        let matrix = self.reduced_density_matrix(qubits, &traced);
+        debug_assert!(
+            (matrix
+                .iter()
+                .enumerate()
+                .map(|(index, row)| row[index].re)
+                .sum::<f64>()
+                - 1.0)
+                .abs()
+                < 1e-6,
+            "the trace of a density matrix is 1"
+        );
+        debug_assert!(
+            matrix.iter().enumerate().all(|(row, elements)| elements
+                .iter()
+                .enumerate()
+                .all(|(column, element)| (element - matrix[column][row].conj()).norm() < 1e-9)),
+            "a density matrix is hermitian"
+        );
+        matrix
+    }
+
+    /// Return the Bloch vector `(x, y, z)` of `qubit`, the expectation values
+    /// of the Pauli X, Y and Z observables on its reduced density matrix.
+    ///
+    /// |0⟩ is `(0, 0, 1)`, |1⟩ is `(0, 0, -1)`, |+⟩ is `(1, 0, 0)` and
+    /// (|0⟩ + i|1⟩)/√2 is `(0, 1, 0)`. The vector has norm 1 only if the qubit
+    /// is not entangled with the rest of the system: the norm decreases with
+    /// the entanglement, down to `(0, 0, 0)` for a qubit of a Bell pair.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `qubit` is out of the state-vector.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::f64::consts::{FRAC_PI_2, PI};
+    /// use qasmsim::statevector::StateVector;
+    ///
+    /// let mut plus = StateVector::new(1);
+    /// plus.u(FRAC_PI_2, 0.0, PI, 0);
+    /// let (x, y, z) = plus.bloch_vector(0);
+    /// assert!((x - 1.0).abs() < 1e-10);
+    /// assert!(y.abs() < 1e-10 && z.abs() < 1e-10);
+    /// ```
+    pub fn bloch_vector(&self, qubit: usize) -> (f64, f64, f64) {
+        let density = self.density_matrix(&[qubit]);
+        (
+            2.0 * density[0][1].re,
+            -2.0 * density[0][1].im,
+            density[0][0].re - density[1][1].re,
+        )
+    }
+
+    /// Return the eigenvalues, in descending order, of the reduced density
+    /// matrix of the `kept` qubits after tracing out the `traced` ones.
+    fn reduced_density_eigenvalues(&self, kept: &[usize], traced: &[usize]) -> Vec<f64> {
+        hermitian_eigenvalues(&self.reduced_density_matrix(kept, traced))
+    }
+
+    /// Return the reduced density matrix of the `kept` qubits after tracing
+    /// out the `traced` ones.
+    fn reduced_density_matrix(&self, kept: &[usize], traced: &[usize]) -> Vec<Vec<Complex>> {
+        reduced_density_matrix(kept, traced, |index| self.bases[index])
+    }
+
+    /// Return an iterator over the bases whose probability is above `1e-10`
+    /// along with their amplitudes. See [`nonzero_above()`].
+    ///
+    /// [`nonzero_above()`]: #method.nonzero_above
+    pub fn nonzero(&self) -> impl Iterator<Item = (usize, Complex)> + '_ {
+        self.nonzero_above(1e-10)
+    }
+
+    /// Return an iterator over the bases whose probability is above `eps`
+    /// along with their amplitudes, in increasing order of basis.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::f64::consts::FRAC_1_SQRT_2;
+    /// use qasmsim::statevector::{Complex, StateVector};
+    ///
+    /// let bell = StateVector::from_complex_bases_unchecked(vec![
+    ///     Complex::from(FRAC_1_SQRT_2),
+    ///     Complex::from(0.0),
+    ///     Complex::from(0.0),
+    ///     Complex::from(FRAC_1_SQRT_2),
+    /// ]);
+    /// let bases: Vec<usize> = bell.nonzero_above(1e-10).map(|(index, _)| index).collect();
+    /// assert_eq!(bases, vec![0, 3]);
+    /// ```
+    pub fn nonzero_above(&self, eps: f64) -> impl Iterator<Item = (usize, Complex)> + '_ {
+        self.bases
+            .iter()
+            .enumerate()
+            .filter(move |(_, amplitude)| amplitude.norm_sqr() > eps)
+            .map(|(index, amplitude)| (index, *amplitude))
+    }
+
+    /// Return the state as a sum of kets such as `0.707|00⟩ + 0.707|11⟩`,
+    /// omitting the bases whose probability is below `threshold`. Complex
+    /// amplitudes are written as `(0.500+0.500i)|01⟩` and a state with only
+    /// one base is written as the ket alone, `|01⟩`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::f64::consts::FRAC_1_SQRT_2;
+    /// use qasmsim::statevector::{Complex, StateVector};
+    ///
+    /// let bell = StateVector::from_complex_bases_unchecked(vec![
+    ///     Complex::from(FRAC_1_SQRT_2),
+    ///     Complex::from(0.0),
+    ///     Complex::from(0.0),
+    ///     Complex::from(FRAC_1_SQRT_2),
+    /// ]);
+    /// assert_eq!(bell.as_ket_string(1e-10), "0.707|00⟩ + 0.707|11⟩");
+    /// ```
+    pub fn as_ket_string(&self, threshold: f64) -> String {
+        let terms: Vec<(usize, &Complex)> = self
+            .bases
+            .iter()
+            .enumerate()
+            .filter(|(_, amplitude)| amplitude.norm_sqr() >= threshold)
+            .collect();
+
+        let ket = |index: usize| format!("|{:0width$b}⟩", index, width = self.qubit_width);
+        match terms.as_slice() {
+            [] => String::from("0"),
+            [(index, _)] => ket(*index),
+            _ => {
+                let mut output = String::new();
+                for (position, (index, amplitude)) in terms.iter().enumerate() {
+                    let is_real = amplitude.im.abs() < 1e-10;
+                    let coefficient = if !is_real {
+                        format!("({:.3}{:+.3}i)", amplitude.re, amplitude.im)
+                    } else if position == 0 {
+                        format!("{:.3}", amplitude.re)
+                    } else {
+                        format!("{:.3}", amplitude.re.abs())
+                    };
+                    if position > 0 {
+                        output.push_str(if is_real && amplitude.re < 0.0 {
+                            " - "
+                        } else {
+                            " + "
+                        });
+                    }
+                    output.push_str(&coefficient);
+                    output.push_str(&ket(*index));
+                }
+                output
+            }
+        }
+    }
+
     /// Reset the state-vector to the state |0⟩.
     pub fn reset(&mut self) {
         for amplitude in self.bases.iter_mut() {
@@ -141,36 +1300,297 @@ impl StateVector {
     }
 }
 
-impl<'a> ApproxEq for &'a StateVector {
-    type Margin = ComplexMargin;
+impl QuantumState for StateVector {
+    fn qubit_width(&self) -> usize {
+        StateVector::qubit_width(self)
+    }
 
-    fn approx_eq<T: Into<Self::Margin>>(self, other: Self, margin: T) -> bool {
-        let margin = margin.into();
-        for (c1, c2) in self.bases.iter().zip(&other.bases) {
-            if c1.re.approx_ne(c2.re, margin) || c1.im.approx_ne(c2.im, margin) {
-                return false;
+    fn cnot(&mut self, control: usize, target: usize) {
+        StateVector::cnot(self, control, target)
+    }
+
+    fn u(&mut self, theta: f64, phi: f64, lambda: f64, target: usize) {
+        StateVector::u(self, theta, phi, lambda, target)
+    }
+
+    fn y(&mut self, target: usize) {
+        StateVector::y(self, target)
+    }
+
+    fn z(&mut self, target: usize) {
+        StateVector::z(self, target)
+    }
+
+    fn measure_with(&mut self, target: usize, rng: &mut dyn RandomSource) -> bool {
+        StateVector::measure_with(self, target, rng)
+    }
+
+    fn probabilities(&self) -> Vec<f64> {
+        StateVector::probabilities(self)
+    }
+
+    fn marginal_outcomes(&self, qubits: &[usize]) -> Vec<(usize, f64)> {
+        marginals_of(
+            self.bases
+                .iter()
+                .map(|amplitude| amplitude.norm_sqr())
+                .enumerate(),
+            qubits,
+        )
+    }
+
+    fn top_k_outcomes(&self, k: usize) -> Vec<(usize, f64)> {
+        StateVector::top_k_outcomes(self, k)
+    }
+
+    fn reset(&mut self) {
+        StateVector::reset(self)
+    }
+}
+
+/// The state of a quantum system in the representation chosen by the
+/// [`Backend`] and the [`Precision`] of a simulation.
+///
+/// Simulations keep the state in this representation, so the results of a
+/// simulation with the sparse backend take memory proportional to the
+/// number of non-zero amplitudes.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::options::{Backend, SimulationOptions};
+/// use qasmsim::statevector::{QuantumState, State};
+/// use qasmsim::{parse_and_link, simulate_with_options};
+///
+/// let program = parse_and_link(r#"
+///     OPENQASM 2.0;
+///     include "qelib1.inc";
+///     qreg q[40];
+///     h q[0];
+///     cx q[0], q[39];
+/// "#)?;
+/// let options = SimulationOptions {
+///     backend: Backend::Sparse,
+///     ..Default::default()
+/// };
+/// let computation = simulate_with_options(&program, None, &options)?;
+/// match computation.state() {
+///     State::Sparse(state) => assert_eq!(state.nonzero_len(), 2),
+///     _ => unreachable!("the state is sparse"),
+/// }
+/// assert_eq!(computation.state().marginal_outcomes(&[39]).len(), 2);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// [`Backend`]: ../options/enum.Backend.html
+/// [`Precision`]: ../options/enum.Precision.html
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq)]
+pub enum State {
+    /// Every amplitude, in double precision.
+    Dense(StateVector),
+    /// The non-zero amplitudes, in double precision.
+    Sparse(SparseStateVector),
+    /// Every amplitude, in single precision.
+    Single(SinglePrecisionStateVector),
+}
+
+impl State {
+    /// Create the state of `qubit_width` qubits in |0⟩ for simulating with
+    /// `backend` and `precision`. The [`Backend::Auto`] backend starts
+    /// sparse.
+    ///
+    /// [`Backend::Auto`]: ../options/enum.Backend.html#variant.Auto
+    pub(crate) fn new(qubit_width: usize, backend: Backend, precision: Precision) -> Self {
+        match (precision, backend) {
+            (Precision::Single, _) => State::Single(SinglePrecisionStateVector::new(qubit_width)),
+            (_, Backend::Dense) => State::Dense(StateVector::new(qubit_width)),
+            (_, Backend::Sparse | Backend::Auto) => {
+                State::Sparse(SparseStateVector::new(qubit_width))
+            }
+        }
+    }
+
+    /// Return the equivalent dense state-vector in double precision, taking
+    /// 16 bytes per basis state.
+    pub fn to_dense(&self) -> StateVector {
+        match self {
+            State::Dense(statevector) => statevector.clone(),
+            State::Sparse(statevector) => statevector.to_dense(),
+            State::Single(statevector) => statevector.to_dense(),
+        }
+    }
+
+    /// Return the reduced density matrix of the `qubits`. See
+    /// [`StateVector::density_matrix()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases [`StateVector::density_matrix()`] does.
+    ///
+    /// [`StateVector::density_matrix()`]: ./struct.StateVector.html#method.density_matrix
+    pub fn density_matrix(&self, qubits: &[usize]) -> Vec<Vec<Complex>> {
+        match self {
+            State::Dense(statevector) => statevector.density_matrix(qubits),
+            State::Sparse(statevector) => statevector.density_matrix(qubits),
+            State::Single(statevector) => statevector.density_matrix(qubits),
+        }
+    }
+
+    /// Return the entanglement entropy, in bits, of the bipartition at
+    /// `cut`. See [`StateVector::entanglement_entropy_bits()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases [`StateVector::entanglement_entropy_bits()`]
+    /// does.
+    ///
+    /// [`StateVector::entanglement_entropy_bits()`]: ./struct.StateVector.html#method.entanglement_entropy_bits
+    pub fn entanglement_entropy_bits(&self, cut: usize) -> f64 {
+        match self {
+            State::Dense(statevector) => statevector.entanglement_entropy_bits(cut),
+            State::Sparse(statevector) => statevector.entanglement_entropy_bits(cut),
+            State::Single(statevector) => statevector.entanglement_entropy_bits(cut),
+        }
+    }
+
+    /// Switch to the sparse representation if fewer than 10% of the
+    /// amplitudes are non-zero, or to the dense one otherwise.
+    pub(crate) fn rebalance(&mut self) {
+        match self {
+            State::Dense(statevector) => {
+                if statevector.nonzero_above(0.0).count() * 10 < statevector.len() {
+                    *self = State::Sparse(SparseStateVector::from_dense(statevector));
+                }
+            }
+            State::Sparse(statevector) => {
+                if statevector.nonzero_len() * 10 >= exp2(statevector.qubit_width()) {
+                    *self = State::Dense(statevector.to_dense());
+                }
+            }
+            State::Single(_) => (),
+        }
+    }
+
+    /// Return the bytes taken by the amplitudes. The sparse representation
+    /// is estimated as one index and one amplitude per non-zero amplitude.
+    pub(crate) fn amplitude_bytes(&self) -> usize {
+        match self {
+            State::Dense(statevector) => statevector.len() * mem::size_of::<Complex>(),
+            State::Sparse(statevector) => {
+                statevector.nonzero_len() * mem::size_of::<(usize, Complex)>()
             }
+            State::Single(statevector) => statevector.capacity() * mem::size_of::<Complex32>(),
+        }
+    }
+
+    fn as_quantum_state(&self) -> &dyn QuantumState {
+        match self {
+            State::Dense(statevector) => statevector,
+            State::Sparse(statevector) => statevector,
+            State::Single(statevector) => statevector,
+        }
+    }
+
+    fn as_quantum_state_mut(&mut self) -> &mut dyn QuantumState {
+        match self {
+            State::Dense(statevector) => statevector,
+            State::Sparse(statevector) => statevector,
+            State::Single(statevector) => statevector,
         }
-        true
+    }
+}
+
+impl QuantumState for State {
+    fn qubit_width(&self) -> usize {
+        self.as_quantum_state().qubit_width()
+    }
+
+    fn cnot(&mut self, control: usize, target: usize) {
+        self.as_quantum_state_mut().cnot(control, target)
+    }
+
+    fn u(&mut self, theta: f64, phi: f64, lambda: f64, target: usize) {
+        self.as_quantum_state_mut().u(theta, phi, lambda, target)
+    }
+
+    fn y(&mut self, target: usize) {
+        self.as_quantum_state_mut().y(target)
+    }
+
+    fn z(&mut self, target: usize) {
+        self.as_quantum_state_mut().z(target)
+    }
+
+    fn measure_with(&mut self, target: usize, rng: &mut dyn RandomSource) -> bool {
+        self.as_quantum_state_mut().measure_with(target, rng)
+    }
+
+    fn probabilities(&self) -> Vec<f64> {
+        self.as_quantum_state().probabilities()
+    }
+
+    fn marginal_outcomes(&self, qubits: &[usize]) -> Vec<(usize, f64)> {
+        self.as_quantum_state().marginal_outcomes(qubits)
+    }
+
+    fn top_k_outcomes(&self, k: usize) -> Vec<(usize, f64)> {
+        self.as_quantum_state().top_k_outcomes(k)
+    }
+
+    fn reset(&mut self) {
+        self.as_quantum_state_mut().reset()
+    }
+}
+
+impl From<StateVector> for State {
+    fn from(statevector: StateVector) -> Self {
+        State::Dense(statevector)
+    }
+}
+
+impl From<SparseStateVector> for State {
+    fn from(statevector: SparseStateVector) -> Self {
+        State::Sparse(statevector)
+    }
+}
+
+impl From<SinglePrecisionStateVector> for State {
+    fn from(statevector: SinglePrecisionStateVector) -> Self {
+        State::Single(statevector)
+    }
+}
+
+impl ApproxEq for &StateVector {
+    type Margin = ComplexMargin;
+
+    fn approx_eq<T: Into<Self::Margin>>(self, other: Self, margin: T) -> bool {
+        self.approx_eq_with_margin(other, margin.into())
+    }
+}
+
+impl fmt::Display for StateVector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_ket_string(1e-10))
     }
 }
 
 impl FromIterator<Complex> for StateVector {
     fn from_iter<I: IntoIterator<Item = Complex>>(iter: I) -> Self {
         let bases: Vec<Complex> = iter.into_iter().collect();
-        StateVector::from_complex_bases(bases)
+        StateVector::from_complex_bases_unchecked(bases)
     }
 }
 
 #[derive(Debug, PartialEq)]
 struct Measurement<'a> {
-    bases: &'a mut Vec<Complex>,
+    bases: &'a mut Storage,
     chances: [f64; 2],
     target: usize,
 }
 
 impl<'a> Measurement<'a> {
-    pub fn new(bases: &'a mut Vec<Complex>, target: usize) -> Self {
+    pub fn new(bases: &'a mut Storage, target: usize) -> Self {
         let mut chance_universe_0 = 0.0;
         for (index, amplitude) in bases.iter().enumerate() {
             if check_bit(index, target) == 0 {
@@ -192,11 +1612,11 @@ impl<'a> Measurement<'a> {
         );
         let value = (fate >= self.chances[0]) as usize;
         let normalization_factor = self.chances[value].sqrt();
-        for index in 0..self.bases.len() {
+        for (index, amplitude) in self.bases.iter_mut().enumerate() {
             if check_bit(index, self.target) == value {
-                self.bases[index] /= normalization_factor;
+                *amplitude /= normalization_factor;
             } else {
-                self.bases[index] = Complex::from(0.0);
+                *amplitude = Complex::from(0.0);
             }
         }
         value != 0
@@ -206,14 +1626,338 @@ impl<'a> Measurement<'a> {
 /// Assert two state-vector are approximately equal by an error no higher than
 /// the f64 margin for each of the complex components.
 pub fn assert_approx_eq(v1: &StateVector, v2: &StateVector) {
-    if !v1.approx_eq(v2, complex::ComplexMargin::default()) {
-        panic!(
+    assert_approx_eq_with(v1, v2, complex::ComplexMargin::default())
+}
+
+/// Assert two state-vector are approximately equal by an error no higher than
+/// `margin` for each of the complex components.
+///
+/// # Examples
+///
+/// Relax the tolerance for comparing the results of deep circuits:
+///
+/// ```
+/// use qasmsim::statevector::{assert_approx_eq_with, Complex, ComplexMargin, StateVector};
+///
+/// let v1 = StateVector::from_amplitudes_with_validation(vec![
+///     Complex::from(1.0),
+///     Complex::from(0.0),
+/// ])?;
+/// let v2 = StateVector::from_amplitudes_with_validation(vec![
+///     Complex::from(1.0 - 1e-9),
+///     Complex::from(0.0),
+/// ])?;
+/// assert_approx_eq_with(&v1, &v2, ComplexMargin::epsilon(1e-6));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn assert_approx_eq_with(v1: &StateVector, v2: &StateVector, margin: complex::ComplexMargin) {
+    if !v1.approx_eq_with_margin(v2, margin) {
+        panic!(
             "assertion failed `(left ~= right)`\n  left: `{:?}`\n right: `{:?}`",
             v1, v2
         );
     }
 }
 
+/// Assert two state-vectors are approximately equal, as in
+/// [`assert_approx_eq()`], up to a global phase.
+///
+/// # Examples
+///
+/// Gates equal up to a global phase yield equivalent states:
+///
+/// ```
+/// use std::f64::consts::PI;
+/// use qasmsim::statevector::{assert_approx_eq_phase, StateVector};
+///
+/// let (mut y, mut ry) = (StateVector::new(1), StateVector::new(1));
+/// y.y(0);
+/// ry.u(PI, 0.0, 0.0, 0);
+/// assert_approx_eq_phase(&y, &ry);
+/// ```
+///
+/// [`assert_approx_eq()`]: ./fn.assert_approx_eq.html
+pub fn assert_approx_eq_phase(v1: &StateVector, v2: &StateVector) {
+    if !v1.approx_eq_up_to_global_phase(v2, complex::ComplexMargin::default()) {
+        panic!(
+            "assertion failed `(left ~= right)` up to a global phase\n  left: `{:?}`\n right: `{:?}`",
+            v1, v2
+        );
+    }
+}
+
+/// Return why the entanglement entropy of the bipartition between the `cut`
+/// least significant qubits and the rest of a system of `qubit_width` qubits
+/// cannot be computed, if it cannot.
+pub(crate) fn check_entanglement_cut(cut: usize, qubit_width: usize) -> Result<(), String> {
+    let smallest = cut.min(qubit_width.saturating_sub(cut));
+    if smallest > MAX_SCHMIDT_QUBITS {
+        return Err(format!(
+            "the smallest side of the bipartition has {} qubits, more than {}",
+            smallest, MAX_SCHMIDT_QUBITS
+        ));
+    }
+    Ok(())
+}
+
+/// Return why the Schmidt coefficients of the bipartition between the
+/// `subsystem` qubits and the rest of a system of `qubit_width` qubits cannot
+/// be computed, if they cannot.
+fn check_schmidt_subsystem(subsystem: &[usize], qubit_width: usize) -> Result<(), String> {
+    for (position, &qubit) in subsystem.iter().enumerate() {
+        if qubit >= qubit_width {
+            return Err(format!(
+                "qubit {} is out of the state-vector of {} qubits",
+                qubit, qubit_width
+            ));
+        }
+        if subsystem[..position].contains(&qubit) {
+            return Err(format!("qubit {} is repeated in the subsystem", qubit));
+        }
+    }
+    let smallest = subsystem.len().min(qubit_width - subsystem.len());
+    if smallest > MAX_SCHMIDT_QUBITS {
+        return Err(format!(
+            "the smallest side of the bipartition has {} qubits, more than {}",
+            smallest, MAX_SCHMIDT_QUBITS
+        ));
+    }
+    Ok(())
+}
+
+/// Return why `qubits` cannot be the qubits of a reduced density matrix of a
+/// system of `qubit_width` qubits, if they cannot.
+pub(crate) fn check_density_qubits(qubits: &[usize], qubit_width: usize) -> Result<(), String> {
+    if qubits.len() > MAX_DENSITY_QUBITS {
+        return Err(format!(
+            "the density matrix of {} qubits is more than the limit of {}",
+            qubits.len(),
+            MAX_DENSITY_QUBITS
+        ));
+    }
+    for (position, &qubit) in qubits.iter().enumerate() {
+        if qubit >= qubit_width {
+            return Err(format!(
+                "qubit {} is out of the state-vector of {} qubits",
+                qubit, qubit_width
+            ));
+        }
+        if qubits[..position].contains(&qubit) {
+            return Err(format!("qubit {} is repeated", qubit));
+        }
+    }
+    Ok(())
+}
+
+/// Return the qubits of the smallest side of the bipartition between the
+/// `cut` least significant qubits and the rest of a system of `qubit_width`
+/// qubits, along with the qubits of the other side.
+fn smallest_side(cut: usize, qubit_width: usize) -> (Vec<usize>, Vec<usize>) {
+    let (low, high): (Vec<usize>, Vec<usize>) = (0..qubit_width).partition(|&q| q < cut);
+    if low.len() <= high.len() {
+        (low, high)
+    } else {
+        (high, low)
+    }
+}
+
+/// Return the reduced density matrix of the `kept` qubits after tracing out
+/// the `traced` ones, of the state whose amplitudes are given by `amplitude`.
+fn reduced_density_matrix(
+    kept: &[usize],
+    traced: &[usize],
+    amplitude: impl Fn(usize) -> Complex,
+) -> Vec<Vec<Complex>> {
+    let scatter = |qubits: &[usize]| -> Vec<usize> {
+        (0..exp2(qubits.len()))
+            .map(|value| {
+                qubits
+                    .iter()
+                    .enumerate()
+                    .map(|(bit, qubit)| check_bit(value, bit) << qubit)
+                    .sum()
+            })
+            .collect()
+    };
+    let (kept_offsets, traced_offsets) = (scatter(kept), scatter(traced));
+    kept_offsets
+        .iter()
+        .map(|row_offset| {
+            kept_offsets
+                .iter()
+                .map(|column_offset| {
+                    traced_offsets
+                        .iter()
+                        .map(|t| amplitude(row_offset | t) * amplitude(column_offset | t).conj())
+                        .sum()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Return the entropy, in bits, of a density matrix with `eigenvalues`.
+fn entropy_in_bits(eigenvalues: &[f64]) -> f64 {
+    eigenvalues
+        .iter()
+        .filter(|&&eigenvalue| eigenvalue > f64::EPSILON)
+        .map(|eigenvalue| -eigenvalue * eigenvalue.log2())
+        .sum()
+}
+
+/// Return the `k` bases with the highest probabilities in `probabilities`,
+/// sorted as [`StateVector::top_k_outcomes()`] does.
+///
+/// [`StateVector::top_k_outcomes()`]: ./struct.StateVector.html#method.top_k_outcomes
+fn top_k_of(probabilities: impl Iterator<Item = (usize, f64)>, k: usize) -> Vec<(usize, f64)> {
+    if k == 0 {
+        return Vec::new();
+    }
+    let mut best = BinaryHeap::with_capacity(k + 1);
+    for (index, probability) in probabilities {
+        best.push(Reverse(Candidate { probability, index }));
+        if best.len() > k {
+            best.pop();
+        }
+    }
+    best.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(candidate)| (candidate.index, candidate.probability))
+        .collect()
+}
+
+/// Return the sum of the `probabilities` of the bases for each value of
+/// `qubits`, leaving out the values whose sum is zero. See
+/// [`QuantumState::marginal_outcomes()`].
+///
+/// [`QuantumState::marginal_outcomes()`]: ./trait.QuantumState.html#method.marginal_outcomes
+fn marginals_of(
+    probabilities: impl Iterator<Item = (usize, f64)>,
+    qubits: &[usize],
+) -> Vec<(usize, f64)> {
+    let mut marginals = vec![0.0; exp2(qubits.len())];
+    for (index, probability) in probabilities {
+        marginals[gather_bits(index, qubits)] += probability;
+    }
+    marginals
+        .into_iter()
+        .enumerate()
+        .filter(|(_, probability)| *probability > 0.0)
+        .collect()
+}
+
+/// Return the value of `qubits` in the basis `index`, the first qubit being
+/// the least significant bit.
+fn gather_bits(index: usize, qubits: &[usize]) -> usize {
+    qubits
+        .iter()
+        .enumerate()
+        .map(|(bit, qubit)| check_bit(index, *qubit) << bit)
+        .sum()
+}
+
+/// Return the eigenvalues, in descending order, of the hermitian `matrix`.
+fn hermitian_eigenvalues(matrix: &[Vec<Complex>]) -> Vec<f64> {
+    let size = matrix.len();
+
+    // The eigenvalues of an hermitian matrix H = R + iI are those of the
+    // real symmetric matrix [[R, -I], [I, R]], each of them twice.
+    let mut embedding = vec![vec![0.0; 2 * size]; 2 * size];
+    for (row, elements) in matrix.iter().enumerate() {
+        for (column, element) in elements.iter().enumerate() {
+            embedding[row][column] = element.re;
+            embedding[row + size][column + size] = element.re;
+            embedding[row][column + size] = -element.im;
+            embedding[row + size][column] = element.im;
+        }
+    }
+
+    let mut eigenvalues = symmetric_eigenvalues(embedding);
+    eigenvalues.sort_by(|a, b| b.total_cmp(a));
+    eigenvalues.into_iter().step_by(2).collect()
+}
+
+/// Compute the eigenvalues of a real symmetric matrix with the cyclic Jacobi
+/// method.
+fn symmetric_eigenvalues(mut matrix: Vec<Vec<f64>>) -> Vec<f64> {
+    let size = matrix.len();
+    for _ in 0..100 {
+        let off_diagonal: f64 = (0..size)
+            .flat_map(|row| (0..size).map(move |column| (row, column)))
+            .filter(|(row, column)| row != column)
+            .map(|(row, column)| matrix[row][column].powi(2))
+            .sum();
+        if off_diagonal < 1e-24 {
+            break;
+        }
+
+        for p in 0..size {
+            for q in p + 1..size {
+                if matrix[p][q].abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (matrix[q][q] - matrix[p][p]) / (2.0 * matrix[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let cos = 1.0 / (t * t + 1.0).sqrt();
+                let sin = t * cos;
+                for row in matrix.iter_mut() {
+                    let (kp, kq) = (row[p], row[q]);
+                    row[p] = cos * kp - sin * kq;
+                    row[q] = sin * kp + cos * kq;
+                }
+                let (head, tail) = matrix.split_at_mut(q);
+                for (pk, qk) in head[p].iter_mut().zip(tail[0].iter_mut()) {
+                    let (old_pk, old_qk) = (*pk, *qk);
+                    *pk = cos * old_pk - sin * old_qk;
+                    *qk = sin * old_pk + cos * old_qk;
+                }
+            }
+        }
+    }
+    (0..size).map(|index| matrix[index][index]).collect()
+}
+
+type UMatrix = (Complex, Complex, Complex, Complex);
+
+/// Return the matrix of applying the general rotations `gates` in order, or
+/// `None` if there are no gates.
+fn compose_u(mut gates: impl Iterator<Item = (f64, f64, f64)>) -> Option<UMatrix> {
+    let (theta, phi, lambda) = gates.next()?;
+    Some(gates.fold(
+        build_u(theta, phi, lambda),
+        |composed, (theta, phi, lambda)| multiply(build_u(theta, phi, lambda), composed),
+    ))
+}
+
+/// Maximum number of qubits in the smallest side of a bipartition for
+/// computing its Schmidt coefficients or its entanglement entropy, which
+/// takes the eigenvalues of a 512×512 real matrix.
+pub const MAX_SCHMIDT_QUBITS: usize = 8;
+
+/// Maximum number of qubits of the reduced density matrices returned by
+/// [`StateVector::density_matrix()`], 1024×1024 matrices of 16 MiB.
+///
+/// [`StateVector::density_matrix()`]: ./struct.StateVector.html#method.density_matrix
+pub const MAX_DENSITY_QUBITS: usize = 10;
+
+/// Modulus under which [`StateVector::normalize_global_phase()`] considers
+/// an amplitude negligible, and skips it when looking for the amplitude to
+/// make real and positive. It is well above the rounding errors of
+/// simulating deep circuits and well below the amplitudes of interest.
+///
+/// [`StateVector::normalize_global_phase()`]: ./struct.StateVector.html#method.normalize_global_phase
+pub const GLOBAL_PHASE_EPSILON: f64 = 1e-10;
+
+/// Return the product `a·b` of two 2×2 matrices in row-major order.
+fn multiply(a: UMatrix, b: UMatrix) -> UMatrix {
+    (
+        a.0 * b.0 + a.1 * b.2,
+        a.0 * b.1 + a.1 * b.3,
+        a.2 * b.0 + a.3 * b.2,
+        a.2 * b.1 + a.3 * b.3,
+    )
+}
+
 #[inline]
 fn check_bit(value: usize, index: usize) -> usize {
     (value & (1 << index)) >> index
@@ -229,12 +1973,98 @@ fn e_power_to(x: f64) -> Complex {
     Complex::new(0.0, x).exp()
 }
 
+/// Number of matrices of `U` gates the cache of [`build_u()`] keeps by
+/// default.
+pub const DEFAULT_U_CACHE_CAPACITY: usize = 20;
+
+/// Hit and miss counters of the cache of `U` matrices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+
+pub struct UCacheStats {
+    /// Number of `U` gates whose matrix was found in the cache.
+    pub hits: u64,
+    /// Number of `U` gates whose matrix had to be computed.
+    pub misses: u64,
+}
+
+impl UCacheStats {
+    /// Return the fraction of lookups served by the cache, or 0 if there
+    /// were no lookups.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// Set how many matrices of `U` gates the simulator caches, dropping the
+/// cached matrices and resetting the counters of [`u_cache_stats()`].
+///
+/// The cache is shared by all the simulations in the process and keeps the
+/// matrices of the most recently used parameters. Each entry takes 112
+/// bytes plus the bookkeeping of the cache, so the default of
+/// [`DEFAULT_U_CACHE_CAPACITY`] entries is negligible, but programs sweeping
+/// more distinct angles than the capacity recompute every matrix. Enlarge
+/// it to the number of distinct parameters of the program to trade a few
+/// kilobytes for the trigonometric functions of every gate.
+///
+/// # Panics
+///
+/// The function panics if `capacity` is 0.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::statevector::{set_u_cache_capacity, u_cache_capacity};
+///
+/// set_u_cache_capacity(256);
+/// assert_eq!(u_cache_capacity(), 256);
+/// ```
+pub fn set_u_cache_capacity(capacity: usize) {
+    assert!(capacity > 0, "the capacity of the U cache must be positive");
+    cached_fns::set_build_u_capacity(capacity);
+}
+
+/// Return how many matrices of `U` gates the simulator caches.
+pub fn u_cache_capacity() -> usize {
+    cached_fns::build_u_capacity()
+}
+
+/// Return the hits and misses of the cache of `U` matrices since it was
+/// created or last resized with [`set_u_cache_capacity()`].
+pub fn u_cache_stats() -> UCacheStats {
+    cached_fns::build_u_stats()
+}
+
+/// Return the bytes taken by the tables of rows the dense state-vector of
+/// `qubit_width` qubits caches after applying `U` gates on `target_count`
+/// distinct targets and `CX` gates on `pair_count` distinct control-target
+/// pairs, plus the `U` matrices cached for `matrix_count` distinct
+/// parameters.
+pub(crate) fn cached_tables_bytes(
+    qubit_width: usize,
+    target_count: usize,
+    pair_count: usize,
+    matrix_count: usize,
+) -> usize {
+    let row_size = mem::size_of::<(usize, usize)>();
+    let target_rows = exp2(qubit_width.saturating_sub(1)) * row_size;
+    let exchangeable_rows = exp2(qubit_width.saturating_sub(2)) * row_size;
+    let matrix_size = mem::size_of::<(cached_fns::BuildUKey, UMatrix)>();
+    target_count * target_rows
+        + pair_count * exchangeable_rows
+        + matrix_count.min(u_cache_capacity()) * matrix_size
+}
+
 // This module intentionally disable documentation of the cached functions.
 mod cached_fns {
     #![allow(missing_docs)]
 
-    use super::{e_power_to, exp2, Complex};
-    use cached::{cached, cached_key, SizedCache};
+    use super::{e_power_to, exp2, Complex, UCacheStats, UMatrix, DEFAULT_U_CACHE_CAPACITY};
+    use cached::{cached, cached_key, Cached, SizedCache};
     use num::Float;
 
     cached! {
@@ -292,11 +2122,10 @@ mod cached_fns {
     }
 
     type DecodedFloat = (u64, i16, i8);
-    type BuildUKey = (DecodedFloat, DecodedFloat, DecodedFloat);
-    type UMatrix = (Complex, Complex, Complex, Complex);
+    pub type BuildUKey = (DecodedFloat, DecodedFloat, DecodedFloat);
 
     cached_key! {
-        BUILD_U: SizedCache<BuildUKey, UMatrix> = SizedCache::with_size(20);
+        BUILD_U: SizedCache<BuildUKey, UMatrix> = SizedCache::with_size(DEFAULT_U_CACHE_CAPACITY);
         Key = {(
             Float::integer_decode(theta),
             Float::integer_decode(phi),
@@ -311,6 +2140,23 @@ mod cached_fns {
             )
         }
     }
+
+    pub fn set_build_u_capacity(capacity: usize) {
+        *BUILD_U.lock().unwrap() = SizedCache::with_size(capacity);
+    }
+
+    pub fn build_u_capacity() -> usize {
+        let cache = BUILD_U.lock().unwrap();
+        cache.cache_capacity().unwrap_or(DEFAULT_U_CACHE_CAPACITY)
+    }
+
+    pub fn build_u_stats() -> UCacheStats {
+        let cache = BUILD_U.lock().unwrap();
+        UCacheStats {
+            hits: cache.cache_hits().unwrap_or(0),
+            misses: cache.cache_misses().unwrap_or(0),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -320,14 +2166,83 @@ mod tests {
 
     use float_cmp::approx_eq;
 
+    #[test]
+    fn test_amplitude_by_label() {
+        let mut bell = StateVector::new(2);
+        bell.u(PI / 2.0, 0.0, PI, 0);
+        bell.cnot(0, 1);
+        assert!((bell.amplitude("00").unwrap().re - FRAC_1_SQRT_2).abs() < 1e-10);
+        assert!((bell.amplitude("11").unwrap().re - FRAC_1_SQRT_2).abs() < 1e-10);
+        assert_eq!(bell.amplitude("01").unwrap().norm_sqr(), 0.0);
+        assert_eq!(bell.amplitude("0"), None);
+        assert_eq!(bell.amplitude("011"), None);
+        assert_eq!(bell.amplitude("0x"), None);
+        assert_eq!(bell.amplitude("+1"), None);
+
+        let mut v = StateVector::new(3);
+        v.u(PI, 0.0, PI, 0);
+        assert_eq!(v.amplitude("001"), v.amplitude_at(1));
+    }
+
+    #[test]
+    fn test_validated_constructor_rejects_invalid_amplitudes() {
+        let reason = |amplitudes: Vec<Complex>| match StateVector::from_amplitudes_with_validation(
+            amplitudes,
+        ) {
+            Err(QasmSimError::InvalidStateVector { reason }) => reason,
+            other => panic!("expected an invalid state-vector, got {:?}", other),
+        };
+        assert_eq!(
+            reason(vec![
+                Complex::from(1.0),
+                Complex::from(0.0),
+                Complex::from(0.0)
+            ]),
+            "the number of amplitudes, 3, is not a power of two"
+        );
+        assert_eq!(
+            reason(vec![]),
+            "the number of amplitudes, 0, is not a power of two"
+        );
+        assert_eq!(
+            reason(vec![Complex::from(1.0), Complex::new(0.0, f64::NAN)]),
+            "the amplitude at 1 is not finite"
+        );
+        assert_eq!(
+            reason(vec![Complex::from(0.5), Complex::from(0.5)]),
+            format!("the norm of the amplitudes, {}, is not 1", 0.5_f64.sqrt())
+        );
+        assert!(StateVector::from_amplitudes_with_validation(vec![
+            Complex::from(FRAC_1_SQRT_2),
+            Complex::new(0.0, FRAC_1_SQRT_2 + 1e-9)
+        ])
+        .is_ok());
+    }
+
+    #[test]
+    fn test_state_vectors_are_compared_within_a_custom_margin() {
+        let v1 =
+            StateVector::from_complex_bases_unchecked(vec![Complex::from(1.0), Complex::from(0.0)]);
+        let v2 = StateVector::from_complex_bases_unchecked(vec![
+            Complex::from(1.0 - 1e-9),
+            Complex::from(1e-9),
+        ]);
+        assert!(!(&v1).approx_eq(&v2, ComplexMargin::default()));
+        assert!((&v1).approx_eq(&v2, ComplexMargin::epsilon(1e-8)));
+        assert!(!(&v1).approx_eq(&v2, ComplexMargin::ulps(4)));
+    }
+
     #[test]
     fn test_cnot_c0t1() {
         let p = Default::default();
         let a = Complex::new(1.0, 0.0);
         let b = Complex::new(0.0, 1.0);
-        let mut v = StateVector::from_complex_bases(vec![p, a, p, b]);
+        let mut v = StateVector::from_complex_bases_unchecked(vec![p, a, p, b]);
         v.cnot(0, 1);
-        assert_eq!(v, StateVector::from_complex_bases(vec!(p, b, p, a)));
+        assert_eq!(
+            v,
+            StateVector::from_complex_bases_unchecked(vec!(p, b, p, a))
+        );
     }
 
     #[test]
@@ -335,9 +2250,12 @@ mod tests {
         let p = Default::default();
         let a = Complex::new(1.0, 0.0);
         let b = Complex::new(0.0, 1.0);
-        let mut v = StateVector::from_complex_bases(vec![p, p, a, b]);
+        let mut v = StateVector::from_complex_bases_unchecked(vec![p, p, a, b]);
         v.cnot(1, 0);
-        assert_eq!(v, StateVector::from_complex_bases(vec!(p, p, b, a)));
+        assert_eq!(
+            v,
+            StateVector::from_complex_bases_unchecked(vec!(p, p, b, a))
+        );
     }
 
     #[test]
@@ -345,11 +2263,11 @@ mod tests {
         let p = Default::default();
         let a = Complex::new(1.0, 0.0);
         let b = Complex::new(0.0, 1.0);
-        let mut v = StateVector::from_complex_bases(vec![p, p, p, p, a, b, a, b]);
+        let mut v = StateVector::from_complex_bases_unchecked(vec![p, p, p, p, a, b, a, b]);
         v.cnot(2, 0);
         assert_eq!(
             v,
-            StateVector::from_complex_bases(vec!(p, p, p, p, b, a, b, a))
+            StateVector::from_complex_bases_unchecked(vec!(p, p, p, p, b, a, b, a))
         );
     }
 
@@ -358,11 +2276,11 @@ mod tests {
         let p = Default::default();
         let a = Complex::new(1.0, 0.0);
         let b = Complex::new(0.0, 1.0);
-        let mut v = StateVector::from_complex_bases(vec![p, a, p, a, p, b, p, b]);
+        let mut v = StateVector::from_complex_bases_unchecked(vec![p, a, p, a, p, b, p, b]);
         v.cnot(0, 2);
         assert_eq!(
             v,
-            StateVector::from_complex_bases(vec!(p, b, p, b, p, a, p, a))
+            StateVector::from_complex_bases_unchecked(vec!(p, b, p, b, p, a, p, a))
         );
     }
 
@@ -371,10 +2289,271 @@ mod tests {
         let p = Default::default();
         let a = Complex::new(1.0, 0.0);
         let b = Complex::new(0.0, 1.0);
-        let mut v = StateVector::from_complex_bases(vec![p, a, p, b]);
+        let mut v = StateVector::from_complex_bases_unchecked(vec![p, a, p, b]);
+        v.cnot(0, 1);
+        v.cnot(0, 1);
+        assert_eq!(
+            v,
+            StateVector::from_complex_bases_unchecked(vec!(p, a, p, b))
+        );
+    }
+
+    #[test]
+    fn test_qft_matches_dft() {
+        let bases: Vec<Complex> = (0..8)
+            .map(|index| Complex::new(index as f64 + 1.0, 0.5 - index as f64))
+            .collect();
+        let norm = bases.iter().map(|c| c.norm_sqr()).sum::<f64>().sqrt();
+        let bases: Vec<Complex> = bases.iter().map(|c| c / norm).collect();
+
+        let size = bases.len() as f64;
+        let expected: StateVector = (0..bases.len())
+            .map(|k| {
+                bases
+                    .iter()
+                    .enumerate()
+                    .map(|(x, amplitude)| amplitude * e_power_to(2.0 * PI * (x * k) as f64 / size))
+                    .sum::<Complex>()
+                    / size.sqrt()
+            })
+            .collect();
+
+        let mut v = StateVector::from_complex_bases_unchecked(bases);
+        v.qft(&[0, 1, 2]);
+        assert!(v.approx_eq(&expected, (1e-12, 4)));
+    }
+
+    #[test]
+    fn test_swap_registers_matches_swap_gates() {
+        let bases: Vec<Complex> = (0..32)
+            .map(|index| Complex::new(index as f64, 1.0 - index as f64))
+            .collect();
+        for (register_a, register_b) in [
+            (vec![0, 1], vec![3, 4]),
+            (vec![4, 0, 2], vec![1, 3, 0]),
+            (vec![2], vec![2]),
+        ] {
+            let mut expected = StateVector::from_complex_bases_unchecked(bases.clone());
+            for (&a, &b) in register_a.iter().zip(&register_b) {
+                if a != b {
+                    expected.cnot(a, b);
+                    expected.cnot(b, a);
+                    expected.cnot(a, b);
+                }
+            }
+            let mut v = StateVector::from_complex_bases_unchecked(bases.clone());
+            v.swap_registers(&register_a, &register_b).unwrap();
+            assert_eq!(v, expected);
+        }
+
+        let mut v = StateVector::new(3);
+        assert_eq!(
+            v.swap_registers(&[0, 1], &[2]),
+            Err(QasmSimError::InvalidQubits {
+                reason: "cannot swap a register of 2 qubits with a register of 1 qubits".into()
+            })
+        );
+        assert_eq!(
+            v.swap_registers(&[0], &[3]),
+            Err(QasmSimError::InvalidQubits {
+                reason: "qubit 3 is out of the state-vector of 3 qubits".into()
+            })
+        );
+    }
+
+    #[test]
+    fn test_swap_registers_across_chunks() {
+        let width = storage::CHUNK_BITS + 2;
+        let mut expected = StateVector::new(width);
+        let mut chunked = StateVector::new_chunked(width);
+        for v in [&mut expected, &mut chunked] {
+            v.u(PI / 2.0, 0.0, PI, 0);
+            v.u(0.3, 0.2, 0.1, 1);
+            v.u(PI, 0.0, PI, width - 1);
+        }
+        for (a, b) in [(0, width - 1), (1, 2)] {
+            expected.cnot(a, b);
+            expected.cnot(b, a);
+            expected.cnot(a, b);
+        }
+        chunked.swap_registers(&[0, 1], &[width - 1, 2]).unwrap();
+        assert!(chunked.approx_eq(&expected, (1e-12, 4)));
+    }
+
+    #[test]
+    fn test_entanglement_entropy_of_product_state() {
+        let p = Complex::from(0.5);
+        let v = StateVector::from_complex_bases_unchecked(vec![p, p, p, p]);
+        assert!(v.entanglement_entropy_bits(1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_entanglement_entropy_of_bell_state() {
+        let a = Complex::from(FRAC_1_SQRT_2);
+        let b = Complex::new(0.0, FRAC_1_SQRT_2);
+        let p = Complex::from(0.0);
+        let v = StateVector::from_complex_bases_unchecked(vec![a, p, p, b]);
+        assert!((v.entanglement_entropy_bits(1) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_entanglement_entropy_reduces_the_smallest_side() {
+        // A Bell pair between qubits 0 and 11 of a 12-qubit system: the cut
+        // keeping 11 qubits is computed from the single qubit left out.
+        let mut v = StateVector::new(12);
+        v.u(std::f64::consts::FRAC_PI_2, 0.0, std::f64::consts::PI, 0);
+        v.cnot(0, 11);
+        assert!((v.entanglement_entropy_bits(11) - 1.0).abs() < 1e-9);
+        assert!(v.entanglement_entropy_bits(1).abs() > 0.99);
+    }
+
+    #[test]
+    #[should_panic(expected = "the smallest side of the bipartition has 9 qubits")]
+    fn test_entanglement_entropy_of_too_wide_cut() {
+        StateVector::new(18).entanglement_entropy_bits(9);
+    }
+
+    #[test]
+    fn test_subsystem_entanglement_entropy_of_product_state() {
+        let mut v = StateVector::new(3);
+        v.u(PI / 2.0, 0.0, PI, 0);
+        v.u(0.3, 1.2, -0.4, 1);
+        v.u(PI / 3.0, 0.0, 0.0, 2);
+        for subsystem in [&[0][..], &[1], &[2], &[0, 2]] {
+            assert!(v.entanglement_entropy(subsystem).abs() < 1e-9);
+            let coefficients = v.schmidt_coefficients(subsystem);
+            assert!((coefficients[0] - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_subsystem_entanglement_entropy_of_bell_pair() {
+        let mut v = StateVector::new(2);
+        v.u(PI / 2.0, 0.0, PI, 0);
+        v.cnot(0, 1);
+        let ln_2 = std::f64::consts::LN_2;
+        assert!((v.entanglement_entropy(&[0]) - ln_2).abs() < 1e-9);
+        assert!((v.entanglement_entropy(&[1]) - ln_2).abs() < 1e-9);
+        assert!((v.entanglement_entropy_bits(1) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_subsystem_entanglement_entropy_of_ghz_state() {
+        let mut v = StateVector::new(4);
+        v.u(PI / 2.0, 0.0, PI, 0);
+        for target in 1..4 {
+            v.cnot(0, target);
+        }
+        let ln_2 = std::f64::consts::LN_2;
+        for qubit in 0..4 {
+            assert!((v.entanglement_entropy(&[qubit]) - ln_2).abs() < 1e-9);
+        }
+        assert!((v.entanglement_entropy(&[3, 1]) - ln_2).abs() < 1e-9);
+        let coefficients = v.schmidt_coefficients(&[1, 2, 3]);
+        assert_eq!(coefficients.len(), 2);
+        let norm: f64 = coefficients.iter().map(|c| c * c).sum();
+        assert!((norm - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    #[should_panic(expected = "qubit 2 is out of the state-vector of 2 qubits")]
+    fn test_schmidt_coefficients_reject_qubits_out_of_range() {
+        StateVector::new(2).schmidt_coefficients(&[2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "qubit 0 is repeated in the subsystem")]
+    fn test_entanglement_entropy_rejects_repeated_qubits() {
+        StateVector::new(2).entanglement_entropy(&[0, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "the smallest side of the bipartition has 9 qubits")]
+    fn test_schmidt_coefficients_reject_too_large_bipartitions() {
+        let subsystem: Vec<usize> = (0..MAX_SCHMIDT_QUBITS + 1).collect();
+        StateVector::new(2 * MAX_SCHMIDT_QUBITS + 2).schmidt_coefficients(&subsystem);
+    }
+
+    #[test]
+    fn test_density_matrix_of_bell_pair_qubit_is_maximally_mixed() {
+        let mut v = StateVector::new(2);
+        v.u(PI / 2.0, 0.0, PI, 0);
         v.cnot(0, 1);
+        let margin = ComplexMargin::default();
+        let half = Complex::from(0.5);
+        let zero = Complex::from(0.0);
+        for qubit in 0..2 {
+            let density = v.density_matrix(&[qubit]);
+            assert!(margin.approx_eq(density[0][0], half));
+            assert!(margin.approx_eq(density[1][1], half));
+            assert!(margin.approx_eq(density[0][1], zero));
+            assert!(margin.approx_eq(density[1][0], zero));
+        }
+    }
+
+    #[test]
+    fn test_density_matrix_orders_the_qubits() {
+        let mut v = StateVector::new(3);
+        v.u(PI, 0.0, 0.0, 2);
+        let margin = ComplexMargin::default();
+        let one = Complex::from(1.0);
+        // |100⟩ has q[2] = 1, the least significant bit of the rows of [2, 0].
+        assert!(margin.approx_eq(v.density_matrix(&[2, 0])[0b01][0b01], one));
+        assert!(margin.approx_eq(v.density_matrix(&[0, 2])[0b10][0b10], one));
+        assert_eq!(v.density_matrix(&[0, 1, 2]).len(), 8);
+    }
+
+    #[test]
+    fn test_bloch_vectors_of_basis_and_superposition_states() {
+        let close = |(x, y, z): (f64, f64, f64), expected: (f64, f64, f64)| {
+            (x - expected.0).abs() < 1e-10
+                && (y - expected.1).abs() < 1e-10
+                && (z - expected.2).abs() < 1e-10
+        };
+        let mut v = StateVector::new(2);
+        assert!(close(v.bloch_vector(0), (0.0, 0.0, 1.0)));
+        v.u(PI, 0.0, 0.0, 1);
+        assert!(close(v.bloch_vector(1), (0.0, 0.0, -1.0)));
+        v.u(PI / 2.0, 0.0, PI, 0);
+        assert!(close(v.bloch_vector(0), (1.0, 0.0, 0.0)));
+        v.u(0.0, 0.0, PI / 2.0, 0);
+        assert!(close(v.bloch_vector(0), (0.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn test_bloch_vector_of_bell_pair_qubit_is_null() {
+        let mut v = StateVector::new(2);
+        v.u(PI / 2.0, 0.0, PI, 0);
         v.cnot(0, 1);
-        assert_eq!(v, StateVector::from_complex_bases(vec!(p, a, p, b)));
+        for qubit in 0..2 {
+            let (x, y, z) = v.bloch_vector(qubit);
+            assert!((x * x + y * y + z * z).sqrt() < 1e-10);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "the density matrix of 11 qubits is more than the limit of 10")]
+    fn test_density_matrix_rejects_too_many_qubits() {
+        let qubits: Vec<usize> = (0..11).collect();
+        StateVector::new(11).density_matrix(&qubits);
+    }
+
+    #[test]
+    fn test_ket_string_of_single_base() {
+        let p = Complex::from(0.0);
+        let v = StateVector::from_complex_bases_unchecked(vec![p, Complex::from(1.0), p, p]);
+        assert_eq!(v.as_ket_string(1e-10), "|01⟩");
+        assert_eq!(format!("{}", v), "|01⟩");
+    }
+
+    #[test]
+    fn test_ket_string_of_complex_amplitudes() {
+        let p = Complex::from(0.0);
+        let a = Complex::new(0.5, 0.5);
+        let b = Complex::from(-FRAC_1_SQRT_2);
+        let tiny = Complex::from(1e-6);
+        let v = StateVector::from_complex_bases_unchecked(vec![tiny, a, p, b]);
+        assert_eq!(v.as_ket_string(1e-10), "(0.500+0.500i)|01⟩ - 0.707|11⟩");
     }
 
     #[test]
@@ -382,7 +2561,7 @@ mod tests {
         let size = 1000;
         let mut accum = 0;
         for _ in 0..size {
-            let mut v = StateVector::from_complex_bases(vec![
+            let mut v = StateVector::from_complex_bases_unchecked(vec![
                 Complex::from(FRAC_1_SQRT_2),
                 Complex::from(FRAC_1_SQRT_2),
             ]);
@@ -393,13 +2572,13 @@ mod tests {
             f64,
             (accum as f64) / (size as f64),
             0.5,
-            epsilon = std::f64::EPSILON
+            epsilon = f64::EPSILON
         );
     }
 
     #[test]
     fn test_state_vector_measurement_superposition() {
-        let mut v = StateVector::from_complex_bases(vec![
+        let mut v = StateVector::from_complex_bases_unchecked(vec![
             Complex::from(FRAC_1_SQRT_2),
             Complex::from(FRAC_1_SQRT_2),
         ]);
@@ -408,37 +2587,48 @@ mod tests {
         measurement.collapse(faked_random_value);
         assert_approx_eq(
             &v,
-            &StateVector::from_complex_bases(vec![Complex::from(1.0), Complex::from(0.0)]),
+            &StateVector::from_complex_bases_unchecked(vec![
+                Complex::from(1.0),
+                Complex::from(0.0),
+            ]),
         );
     }
 
     #[test]
     fn test_state_vector_measurement_0() {
-        let mut v = StateVector::from_complex_bases(vec![Complex::from(1.0), Complex::from(0.0)]);
+        let mut v =
+            StateVector::from_complex_bases_unchecked(vec![Complex::from(1.0), Complex::from(0.0)]);
         let mut measurement = Measurement::new(&mut v.bases, 0);
         let faked_random_value = 0.0;
         measurement.collapse(faked_random_value);
         assert_approx_eq(
             &v,
-            &StateVector::from_complex_bases(vec![Complex::from(1.0), Complex::from(0.0)]),
+            &StateVector::from_complex_bases_unchecked(vec![
+                Complex::from(1.0),
+                Complex::from(0.0),
+            ]),
         );
     }
 
     #[test]
     fn test_state_vector_measurement_1() {
-        let mut v = StateVector::from_complex_bases(vec![Complex::from(0.0), Complex::from(1.0)]);
+        let mut v =
+            StateVector::from_complex_bases_unchecked(vec![Complex::from(0.0), Complex::from(1.0)]);
         let mut measurement = Measurement::new(&mut v.bases, 0);
         let faked_random_value = 0.0;
         measurement.collapse(faked_random_value);
         assert_approx_eq(
             &v,
-            &StateVector::from_complex_bases(vec![Complex::from(0.0), Complex::from(1.0)]),
+            &StateVector::from_complex_bases_unchecked(vec![
+                Complex::from(0.0),
+                Complex::from(1.0),
+            ]),
         );
     }
 
     #[test]
     fn test_state_vector_measurement_2_qubit_superposition() {
-        let mut v = StateVector::from_complex_bases(vec![
+        let mut v = StateVector::from_complex_bases_unchecked(vec![
             Complex::from(0.5),
             Complex::from(0.5),
             Complex::from(0.5),
@@ -449,7 +2639,7 @@ mod tests {
         measurement.collapse(faked_random_value);
         assert_approx_eq(
             &v,
-            &StateVector::from_complex_bases(vec![
+            &StateVector::from_complex_bases_unchecked(vec![
                 Complex::from(FRAC_1_SQRT_2),
                 Complex::from(0.0),
                 Complex::from(FRAC_1_SQRT_2),
@@ -457,4 +2647,430 @@ mod tests {
             ]),
         );
     }
+
+    #[test]
+    fn test_measure_all_of_basis_state() {
+        let mut v = StateVector::from_complex_bases_unchecked(vec![
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(1.0),
+            Complex::from(0.0),
+        ]);
+        assert_eq!(v.measure_all(), vec![false, true]);
+    }
+
+    #[test]
+    fn test_measure_register_collapses_the_state() {
+        let mut v = StateVector::new(3);
+        v.u(PI / 2.0, 0.0, PI, 0);
+        v.cnot(0, 1);
+        v.cnot(0, 2);
+        let value = v.measure_register(&[2, 0]);
+        assert!(value == 0b00 || value == 0b11);
+
+        let mut expected = vec![Complex::from(0.0); 8];
+        expected[if value == 0 { 0 } else { 0b111 }] = Complex::from(1.0);
+        assert_approx_eq(&v, &StateVector::from_complex_bases_unchecked(expected));
+        assert_eq!(v.measure_register(&[1]), value & 1);
+    }
+
+    #[test]
+    fn test_assert_approx_eq_with_loosened_margin() {
+        let v1 =
+            StateVector::from_complex_bases_unchecked(vec![Complex::from(1.0), Complex::from(0.0)]);
+        let v2 = StateVector::from_complex_bases_unchecked(vec![
+            Complex::from(1.0 - 1e-9),
+            Complex::from(0.0),
+        ]);
+        let strict = std::panic::catch_unwind(|| assert_approx_eq(&v1, &v2));
+        assert!(strict.is_err());
+        assert_approx_eq_with(&v1, &v2, complex::ComplexMargin::epsilon(1e-6));
+    }
+
+    #[test]
+    fn test_state_vectors_of_different_widths_are_not_approx_eq() {
+        let v1 = StateVector::new(1);
+        let v2 = StateVector::new(2);
+        assert!(!v1.approx_eq_with_margin(&v2, ComplexMargin::epsilon(1.0)));
+        assert!(!v2.approx_eq(&v1, ComplexMargin::epsilon(1.0)));
+    }
+
+    #[test]
+    fn test_nonzero_of_bell_state() {
+        let mut v = StateVector::new(2);
+        v.u(PI / 2.0, 0.0, PI, 0);
+        v.cnot(0, 1);
+        let nonzero: Vec<(usize, Complex)> = v.nonzero().collect();
+        assert_eq!(nonzero.len(), 2);
+        assert_eq!(nonzero[0].0, 0b00);
+        assert_eq!(nonzero[1].0, 0b11);
+        for (_, amplitude) in nonzero {
+            assert!((amplitude.norm_sqr() - 0.5).abs() < 1e-9);
+        }
+        assert_eq!(v.nonzero_above(0.6).count(), 0);
+    }
+
+    #[test]
+    fn test_top_k() {
+        let mut v = StateVector::new(3);
+        v.u(PI / 3.0, 0.0, 0.0, 0);
+        v.u(PI / 2.0, 0.0, PI, 2);
+        let probabilities = v.probabilities();
+        let top = v.top_k(3);
+        let indices: Vec<usize> = top.iter().map(|(index, _)| *index).collect();
+        assert_eq!(indices, vec![0b000, 0b100, 0b001]);
+        for (index, probability) in top {
+            assert_eq!(probability, probabilities[index]);
+        }
+
+        let argmax = (0..probabilities.len()).fold(0, |best, index| {
+            if probabilities[index] > probabilities[best] {
+                index
+            } else {
+                best
+            }
+        });
+        assert_eq!(v.top_k(1), vec![(argmax, probabilities[argmax])]);
+        assert_eq!(v.top_k(100).len(), 8);
+    }
+
+    #[test]
+    fn test_top_k_outcomes_match_a_full_sort() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1106);
+        for _ in 0..50 {
+            let qubit_width = rng.gen_range(1..6);
+            let mut v = StateVector::new(qubit_width);
+            for _ in 0..rng.gen_range(0..10) {
+                if qubit_width > 1 && rng.gen_bool(0.3) {
+                    let control = rng.gen_range(0..qubit_width);
+                    let target = (control + rng.gen_range(1..qubit_width)) % qubit_width;
+                    v.cnot(control, target);
+                } else {
+                    let angles: [f64; 3] = rng.gen();
+                    let target = rng.gen_range(0..qubit_width);
+                    v.u(angles[0] * PI, angles[1] * PI, angles[2] * PI, target);
+                }
+            }
+
+            let mut reference: Vec<(usize, f64)> =
+                v.probabilities().into_iter().enumerate().collect();
+            reference.sort_by(|(i1, p1), (i2, p2)| p2.total_cmp(p1).then(i1.cmp(i2)));
+            let k = rng.gen_range(0..=reference.len() + 1);
+            reference.truncate(k);
+            assert_eq!(v.top_k_outcomes(k), reference);
+        }
+    }
+
+    #[test]
+    fn test_chunked_state_vector_matches_dense_on_random_circuits() {
+        use crate::random::SeededRandom;
+        use rand::{Rng, SeedableRng};
+
+        let qubit_width = 10;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1110);
+        for circuit in 0..20 {
+            let mut dense = StateVector::new(qubit_width);
+            let mut chunked = StateVector::new_chunked(qubit_width);
+            let mut dense_rng = SeededRandom::new(circuit);
+            let mut chunked_rng = SeededRandom::new(circuit);
+            for _ in 0..100 {
+                match rng.gen_range(0..10) {
+                    0..=2 => {
+                        let control = rng.gen_range(0..qubit_width);
+                        let target = (control + rng.gen_range(1..qubit_width)) % qubit_width;
+                        dense.cnot(control, target);
+                        chunked.cnot(control, target);
+                    }
+                    3 => {
+                        let target = rng.gen_range(0..qubit_width);
+                        assert_eq!(
+                            dense.measure_with(target, &mut dense_rng),
+                            chunked.measure_with(target, &mut chunked_rng)
+                        );
+                    }
+                    _ => {
+                        let angles: [f64; 3] = rng.gen();
+                        let target = rng.gen_range(0..qubit_width);
+                        dense.u(angles[0] * PI, angles[1] * PI, angles[2] * PI, target);
+                        chunked.u(angles[0] * PI, angles[1] * PI, angles[2] * PI, target);
+                    }
+                }
+            }
+            dense.qft(&[0, 3, 7, 9]);
+            chunked.qft(&[0, 3, 7, 9]);
+
+            assert_approx_eq(&dense, &chunked);
+            assert_eq!(dense.probabilities(), chunked.probabilities());
+            assert_eq!(dense.top_k_outcomes(5), chunked.top_k_outcomes(5));
+            assert!(approx_eq!(
+                f64,
+                dense.entanglement_entropy_bits(4),
+                chunked.entanglement_entropy_bits(4),
+                epsilon = 1e-10
+            ));
+        }
+    }
+
+    #[test]
+    fn test_chunked_state_vector_resets_to_zero() {
+        let mut chunked = StateVector::new_chunked(6);
+        chunked.u(PI / 2.0, 0.0, PI, 5);
+        chunked.reset();
+        assert_eq!(chunked, StateVector::new(6));
+        assert_eq!(chunked.amplitude_at(0), Some(Complex::from(1.0)));
+        assert_eq!(chunked.amplitude_at(64), None);
+    }
+
+    #[test]
+    fn test_amplitudes_of_state_vector_spanning_several_chunks() {
+        let width = storage::CHUNK_BITS + 2;
+        let mut dense = StateVector::new(width);
+        let mut chunked = StateVector::new_chunked(width);
+        for statevector in [&mut dense, &mut chunked] {
+            statevector.u(PI / 2.0, 0.0, PI, 0);
+            statevector.u(PI / 2.0, 0.0, PI, width - 1);
+        }
+        assert!(chunked.as_complex_bases().is_none());
+        assert_eq!(
+            chunked.amplitudes().collect::<Vec<_>>(),
+            dense.as_complex_bases().unwrap().iter().collect::<Vec<_>>()
+        );
+        assert_eq!(chunked.amplitudes().count(), exp2(width));
+    }
+
+    #[test]
+    fn test_gate_sequence_matches_applying_the_gates_one_by_one() {
+        let gates = [
+            (PI / 2.0, 0.0, PI),
+            (0.3, 1.2, -0.4),
+            (PI, PI / 2.0, PI / 2.0),
+            (0.0, 0.0, PI / 4.0),
+        ];
+        let mut expected = StateVector::new(3);
+        expected.u(PI / 2.0, 0.0, PI, 0);
+        expected.cnot(0, 2);
+        let mut v = expected.clone();
+        for &(theta, phi, lambda) in &gates {
+            expected.u(theta, phi, lambda, 2);
+        }
+        v.apply_gate_sequence(&gates, 2);
+        assert_approx_eq(&v, &expected);
+
+        let untouched = v.clone();
+        v.apply_gate_sequence(&[], 1);
+        assert_eq!(v, untouched);
+    }
+
+    #[test]
+    fn test_z_on_plus_yields_minus() {
+        let mut v = StateVector::new(1);
+        v.u(PI / 2.0, 0.0, PI, 0);
+        v.z(0);
+        assert_approx_eq(
+            &v,
+            &StateVector::from_complex_bases_unchecked(vec![
+                Complex::from(FRAC_1_SQRT_2),
+                Complex::from(-FRAC_1_SQRT_2),
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_y_on_zero_yields_i_one() {
+        let mut v = StateVector::new(1);
+        v.y(0);
+        assert_eq!(
+            v,
+            StateVector::from_complex_bases_unchecked(vec![
+                Complex::from(0.0),
+                Complex::new(0.0, 1.0),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_pauli_fast_paths_match_the_general_rotation() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1111);
+        let mut v = StateVector::new(3);
+        for target in 0..3 {
+            let angles: [f64; 3] = rng.gen();
+            v.u(angles[0] * PI, angles[1] * PI, angles[2] * PI, target);
+        }
+        for target in 0..3 {
+            let (mut fast, mut general) = (v.clone(), v.clone());
+            fast.y(target);
+            general.u(PI, PI / 2.0, PI / 2.0, target);
+            assert_approx_eq(&fast, &general);
+
+            let (mut fast, mut general) = (v.clone(), v.clone());
+            fast.z(target);
+            general.u(0.0, 0.0, PI, target);
+            assert_approx_eq(&fast, &general);
+        }
+    }
+
+    #[test]
+    fn test_top_k_outcomes_break_ties_by_index() {
+        let mut v = StateVector::new(2);
+        v.u(PI / 2.0, 0.0, PI, 0);
+        v.u(PI / 2.0, 0.0, PI, 1);
+        let indices: Vec<usize> = v
+            .top_k_outcomes(3)
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_top_k_outcomes_with_k_larger_than_the_state() {
+        let mut v = StateVector::new(2);
+        v.u(PI / 2.0, 0.0, PI, 0);
+        assert_eq!(v.top_k_outcomes(usize::MAX), v.top_k_outcomes(4));
+        assert_eq!(v.top_k_outcomes(usize::MAX).len(), 4);
+    }
+
+    #[test]
+    fn test_marginal_probabilities() {
+        let mut v = StateVector::new(3);
+        v.u(PI / 2.0, 0.0, PI, 0);
+        v.cnot(0, 1);
+        v.u(PI, 0.0, PI, 2);
+        let marginals = v.marginal_probabilities(0, 2);
+        assert_eq!(marginals.len(), 4);
+        assert!((marginals[0b00] - 0.5).abs() < 1e-9);
+        assert!(marginals[0b01].abs() < 1e-9);
+        assert!(marginals[0b10].abs() < 1e-9);
+        assert!((marginals[0b11] - 0.5).abs() < 1e-9);
+        let marginals = v.marginal_probabilities(2, 1);
+        assert!(marginals[0].abs() < 1e-9);
+        assert!((marginals[1] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_u_cache_capacity_can_be_changed() {
+        set_u_cache_capacity(64);
+        assert_eq!(u_cache_capacity(), 64);
+        set_u_cache_capacity(DEFAULT_U_CACHE_CAPACITY);
+        assert_eq!(u_cache_capacity(), DEFAULT_U_CACHE_CAPACITY);
+    }
+
+    #[test]
+    fn test_u_cache_hit_rate() {
+        assert_eq!(UCacheStats::default().hit_rate(), 0.0);
+        let stats = UCacheStats { hits: 3, misses: 1 };
+        assert_eq!(stats.hit_rate(), 0.75);
+    }
+
+    fn rotated_by_pi_over_3(v: &StateVector) -> StateVector {
+        let phase = Complex::from_polar(1.0, PI / 3.0);
+        v.amplitudes().map(|a| a * phase).collect()
+    }
+
+    #[test]
+    fn test_global_phase_is_ignored_when_comparing_up_to_phase() {
+        let mut psi = StateVector::new(3);
+        psi.u(0.3, 1.2, -0.4, 0);
+        psi.cnot(0, 2);
+        psi.u(PI / 2.0, 0.0, PI, 1);
+        let rotated = rotated_by_pi_over_3(&psi);
+        assert!(!psi.approx_eq_with_margin(&rotated, ComplexMargin::default()));
+        assert!(psi.approx_eq_up_to_global_phase(&rotated, ComplexMargin::default()));
+        assert_approx_eq_phase(&rotated, &psi);
+
+        let mut other = psi.clone();
+        other.u(0.1, 0.0, 0.0, 1);
+        assert!(!psi.approx_eq_up_to_global_phase(&other, ComplexMargin::epsilon(1e-6)));
+        assert!(!psi.approx_eq_up_to_global_phase(&StateVector::new(2), ComplexMargin::default()));
+    }
+
+    #[test]
+    fn test_normalize_global_phase_skips_negligible_amplitudes() {
+        let tiny = Complex::new(0.0, 1e-12);
+        let a = Complex::from(FRAC_1_SQRT_2);
+        let b = Complex::new(0.0, FRAC_1_SQRT_2);
+        let psi = StateVector::from_complex_bases_unchecked(vec![tiny, a, Complex::from(0.0), b]);
+        let mut rotated = rotated_by_pi_over_3(&psi);
+        rotated.normalize_global_phase();
+        assert_approx_eq_with(&rotated, &psi, ComplexMargin::epsilon(1e-12));
+    }
+
+    #[test]
+    #[should_panic(expected = "up to a global phase")]
+    fn test_assert_approx_eq_phase_fails_for_different_states() {
+        let mut plus = StateVector::new(1);
+        plus.u(PI / 2.0, 0.0, PI, 0);
+        assert_approx_eq_phase(&plus, &StateVector::new(1));
+    }
+
+    #[test]
+    fn test_bell_states_match_their_circuits() {
+        for bell_index in 0..4_u8 {
+            let mut v = StateVector::new(2);
+            if bell_index >= 2 {
+                v.u(PI, 0.0, PI, 1);
+            }
+            v.u(PI / 2.0, 0.0, PI, 0);
+            if !bell_index.is_multiple_of(2) {
+                v.u(0.0, 0.0, PI, 0);
+            }
+            v.cnot(0, 1);
+            // The circuit prepares the last state with a global phase of -1.
+            assert_approx_eq_phase(&v, &StateVector::bell_state(bell_index));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "there are 4 Bell states")]
+    fn test_bell_index_out_of_range() {
+        StateVector::bell_state(4);
+    }
+
+    #[test]
+    fn test_ghz_and_w_states() {
+        let ghz = StateVector::ghz_state(3);
+        assert_eq!(ghz.qubit_width(), 3);
+        let probabilities = ghz.probabilities();
+        assert!((probabilities[0b000] - 0.5).abs() < 1e-12);
+        assert!((probabilities[0b111] - 0.5).abs() < 1e-12);
+        assert_approx_eq(&StateVector::ghz_state(2), &StateVector::bell_state(0));
+
+        let w = StateVector::w_state(3);
+        let probabilities = w.probabilities();
+        for (index, probability) in probabilities.iter().enumerate() {
+            let expected = if index.count_ones() == 1 {
+                1.0 / 3.0
+            } else {
+                0.0
+            };
+            assert!((probability - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_composed_sequence_equals_sequential_application() {
+        let ops = [
+            (0.3, 1.2, -0.4, 1),
+            (1.1, -0.7, 2.5, 1),
+            (PI / 2.0, 0.0, PI, 0),
+            (0.9, 0.1, 0.2, 1),
+            (-0.6, 0.8, 1.4, 0),
+            (2.2, -1.3, 0.5, 0),
+        ];
+        let mut initial = StateVector::new(2);
+        initial.u(0.5, 0.4, 0.3, 0);
+        initial.cnot(0, 1);
+
+        let mut composed = initial.clone();
+        composed.apply_sequence_1q(&ops);
+        let mut sequential = initial;
+        for &(theta, phi, lambda, target) in &ops {
+            sequential.u(theta, phi, lambda, target);
+        }
+        assert_approx_eq_with(&composed, &sequential, ComplexMargin::epsilon(1e-12));
+    }
 }
@@ -0,0 +1,301 @@
+//! A state-vector storing the amplitudes in single precision.
+
+use super::cached_fns::build_u;
+use super::storage::insert_zero_bit;
+use super::{
+    check_bit, check_density_qubits, check_entanglement_cut, entropy_in_bits, exp2,
+    hermitian_eigenvalues, marginals_of, reduced_density_matrix, smallest_side, top_k_of, Complex,
+    QuantumState, StateVector,
+};
+use crate::random::RandomSource;
+
+/// Alias for the float-32-based complex used by
+/// [`SinglePrecisionStateVector`].
+///
+/// [`SinglePrecisionStateVector`]: ./struct.SinglePrecisionStateVector.html
+pub type Complex32 = num::Complex<f32>;
+
+/// Represent the state of a quantum system with amplitudes in single
+/// precision, taking half the memory of a [`StateVector`] at the cost of
+/// accumulating rounding errors about 1e-7 per gate instead of 1e-16.
+///
+/// Gates and measurements are computed in single precision. Probabilities,
+/// density matrices and entropies are returned in double precision, so the
+/// results of the simulation keep their types, but only the amplitudes
+/// involved in each of them are converted.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::statevector::{QuantumState, SinglePrecisionStateVector};
+///
+/// let mut state = SinglePrecisionStateVector::new(2);
+/// state.u(std::f64::consts::FRAC_PI_2, 0.0, std::f64::consts::PI, 0);
+/// state.cnot(0, 1);
+/// let probabilities = state.probabilities();
+/// assert!((probabilities[0b11] - 0.5).abs() < 1e-6);
+/// ```
+///
+/// [`StateVector`]: ./struct.StateVector.html
+#[derive(Debug, Clone, PartialEq)]
+
+pub struct SinglePrecisionStateVector {
+    bases: Vec<Complex32>,
+    qubit_width: usize,
+}
+
+impl SinglePrecisionStateVector {
+    /// Create a new state-vector of `qubit_width` qubits with all the
+    /// amplitude concentrated in the all-zeroes outcome.
+    pub fn new(qubit_width: usize) -> Self {
+        let mut statevector = SinglePrecisionStateVector {
+            bases: vec![Complex32::new(0.0, 0.0); exp2(qubit_width)],
+            qubit_width,
+        };
+        statevector.reset();
+        statevector
+    }
+
+    /// Create a single precision state-vector rounding the amplitudes of
+    /// `statevector`.
+    pub fn from_dense(statevector: &StateVector) -> Self {
+        SinglePrecisionStateVector {
+            bases: statevector
+                .bases
+                .iter()
+                .map(|amplitude| to_single(*amplitude))
+                .collect(),
+            qubit_width: statevector.qubit_width(),
+        }
+    }
+
+    /// Return the equivalent double precision state-vector, taking twice
+    /// the memory.
+    pub fn to_dense(&self) -> StateVector {
+        self.bases
+            .iter()
+            .map(|amplitude| to_double(*amplitude))
+            .collect()
+    }
+
+    /// Return the amplitudes corresponding to the bases of the system.
+    pub fn as_complex_bases(&self) -> &[Complex32] {
+        &self.bases
+    }
+
+    /// Return the number of amplitudes the state-vector has room for.
+    pub fn capacity(&self) -> usize {
+        self.bases.capacity()
+    }
+
+    /// Return the reduced density matrix of the `qubits`, tracing out the
+    /// rest of the system. See [`StateVector::density_matrix()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases [`StateVector::density_matrix()`] does.
+    ///
+    /// [`StateVector::density_matrix()`]: ./struct.StateVector.html#method.density_matrix
+    pub fn density_matrix(&self, qubits: &[usize]) -> Vec<Vec<Complex>> {
+        if let Err(reason) = check_density_qubits(qubits, self.qubit_width) {
+            panic!("{}", reason);
+        }
+        let traced: Vec<usize> = (0..self.qubit_width)
+            .filter(|qubit| !qubits.contains(qubit))
+            .collect();
+        reduced_density_matrix(qubits, &traced, |index| to_double(self.bases[index]))
+    }
+
+    /// Return the entanglement entropy, in bits, of the bipartition between
+    /// the `cut` least significant qubits and the rest of the system. See
+    /// [`StateVector::entanglement_entropy_bits()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases [`StateVector::entanglement_entropy_bits()`]
+    /// does.
+    ///
+    /// [`StateVector::entanglement_entropy_bits()`]: ./struct.StateVector.html#method.entanglement_entropy_bits
+    pub fn entanglement_entropy_bits(&self, cut: usize) -> f64 {
+        if cut == 0 || cut >= self.qubit_width {
+            return 0.0;
+        }
+        if let Err(reason) = check_entanglement_cut(cut, self.qubit_width) {
+            panic!("{}", reason);
+        }
+        let (kept, traced) = smallest_side(cut, self.qubit_width);
+        let matrix = reduced_density_matrix(&kept, &traced, |index| to_double(self.bases[index]));
+        entropy_in_bits(&hermitian_eigenvalues(&matrix))
+    }
+
+    fn probabilities_iter(&self) -> impl Iterator<Item = (usize, f64)> + '_ {
+        self.bases
+            .iter()
+            .map(|amplitude| f64::from(amplitude.norm_sqr()))
+            .enumerate()
+    }
+}
+
+impl QuantumState for SinglePrecisionStateVector {
+    fn qubit_width(&self) -> usize {
+        self.qubit_width
+    }
+
+    fn cnot(&mut self, control: usize, target: usize) {
+        let (low, high) = (control.min(target), control.max(target));
+        for n in 0..exp2(self.qubit_width - 2) {
+            let index_a = insert_zero_bit(insert_zero_bit(n, low), high) | exp2(control);
+            self.bases.swap(index_a, index_a | exp2(target));
+        }
+    }
+
+    fn u(&mut self, theta: f64, phi: f64, lambda: f64, target: usize) {
+        let u_matrix = build_u(theta, phi, lambda);
+        let u_matrix = (
+            to_single(u_matrix.0),
+            to_single(u_matrix.1),
+            to_single(u_matrix.2),
+            to_single(u_matrix.3),
+        );
+        for n in 0..exp2(self.qubit_width - 1) {
+            let index_0 = insert_zero_bit(n, target);
+            let index_1 = index_0 | exp2(target);
+            let selected = (self.bases[index_0], self.bases[index_1]);
+            self.bases[index_0] = u_matrix.0 * selected.0 + u_matrix.1 * selected.1;
+            self.bases[index_1] = u_matrix.2 * selected.0 + u_matrix.3 * selected.1;
+        }
+    }
+
+    fn measure_with(&mut self, target: usize, rng: &mut dyn RandomSource) -> bool {
+        let chance_universe_0: f32 = self
+            .bases
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| check_bit(*index, target) == 0)
+            .map(|(_, amplitude)| amplitude.norm_sqr())
+            .sum();
+        let chances = [chance_universe_0, 1.0 - chance_universe_0];
+        let value = (rng.next_f64() >= f64::from(chances[0])) as usize;
+        let normalization_factor = chances[value].sqrt();
+        for (index, amplitude) in self.bases.iter_mut().enumerate() {
+            if check_bit(index, target) == value {
+                *amplitude /= normalization_factor;
+            } else {
+                *amplitude = Complex32::new(0.0, 0.0);
+            }
+        }
+        value != 0
+    }
+
+    fn probabilities(&self) -> Vec<f64> {
+        self.probabilities_iter()
+            .map(|(_, probability)| probability)
+            .collect()
+    }
+
+    fn marginal_outcomes(&self, qubits: &[usize]) -> Vec<(usize, f64)> {
+        marginals_of(self.probabilities_iter(), qubits)
+    }
+
+    fn top_k_outcomes(&self, k: usize) -> Vec<(usize, f64)> {
+        top_k_of(self.probabilities_iter(), k.min(self.bases.len()))
+    }
+
+    fn reset(&mut self) {
+        for amplitude in self.bases.iter_mut() {
+            *amplitude = Complex32::new(0.0, 0.0);
+        }
+        self.bases[0].re = 1.0;
+    }
+}
+
+#[inline]
+fn to_single(amplitude: Complex) -> Complex32 {
+    Complex32::new(amplitude.re as f32, amplitude.im as f32)
+}
+
+#[inline]
+fn to_double(amplitude: Complex32) -> Complex {
+    Complex::new(amplitude.re.into(), amplitude.im.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_2, PI};
+    use std::mem::size_of;
+
+    use super::*;
+    use crate::complex::ComplexMargin;
+    use crate::statevector::assert_approx_eq_with;
+
+    #[test]
+    fn test_bell_state_matches_double_precision() {
+        let mut single = SinglePrecisionStateVector::new(2);
+        let mut double = StateVector::new(2);
+        fn bell(state: &mut dyn QuantumState) {
+            state.u(FRAC_PI_2, 0.0, PI, 0);
+            state.cnot(0, 1);
+            state.u(0.3, 1.2, -0.4, 1);
+        }
+        bell(&mut single);
+        bell(&mut double);
+        assert_approx_eq_with(&single.to_dense(), &double, ComplexMargin::epsilon(1e-6));
+        for (p1, p2) in single.probabilities().iter().zip(double.probabilities()) {
+            assert!((p1 - p2).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_amplitudes_take_half_the_memory() {
+        let single = SinglePrecisionStateVector::new(10);
+        let double = StateVector::new(10);
+        assert_eq!(
+            single.capacity() * size_of::<Complex32>() * 2,
+            double.len() * size_of::<Complex>()
+        );
+    }
+
+    #[test]
+    fn test_reductions_match_double_precision() {
+        let mut single = SinglePrecisionStateVector::new(3);
+        let mut double = StateVector::new(3);
+        fn apply(state: &mut dyn QuantumState) {
+            state.u(FRAC_PI_2, 0.0, PI, 0);
+            state.cnot(0, 2);
+            state.u(0.3, 1.2, -0.4, 1);
+        }
+        apply(&mut single);
+        apply(&mut double);
+        let (single_density, double_density) = (
+            single.density_matrix(&[2, 0]),
+            double.density_matrix(&[2, 0]),
+        );
+        for (single_row, double_row) in single_density.iter().zip(&double_density) {
+            for (s, d) in single_row.iter().zip(double_row) {
+                assert!((s - d).norm() < 1e-6);
+            }
+        }
+        assert!(
+            (single.entanglement_entropy_bits(1) - double.entanglement_entropy_bits(1)).abs()
+                < 1e-6
+        );
+        let marginals = single.marginal_outcomes(&[1]);
+        for ((o1, p1), (o2, p2)) in marginals.iter().zip(double.marginal_outcomes(&[1])) {
+            assert_eq!(*o1, o2);
+            assert!((p1 - p2).abs() < 1e-6);
+        }
+        assert!((single.top_k_outcomes(1)[0].1 - double.top_k_outcomes(1)[0].1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_measurement_normalizes_the_state() {
+        let mut single = SinglePrecisionStateVector::new(2);
+        single.u(FRAC_PI_2, 0.0, PI, 0);
+        single.cnot(0, 1);
+        let outcome = single.measure(0);
+        let expected = if outcome { 0b11 } else { 0b00 };
+        let probabilities = single.probabilities();
+        assert!((probabilities[expected] - 1.0).abs() < 1e-6);
+        assert!((probabilities.iter().sum::<f64>() - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,361 @@
+//! A state-vector storing only the amplitudes which are not zero.
+
+use std::collections::HashMap;
+
+use super::cached_fns::build_u;
+use super::{
+    check_bit, check_density_qubits, check_entanglement_cut, entropy_in_bits, exp2, gather_bits,
+    hermitian_eigenvalues, smallest_side, top_k_of, Complex, QuantumState, StateVector,
+};
+use crate::random::RandomSource;
+
+/// Amplitudes whose probability falls below this value after a gate are
+/// considered zero and stop being stored. Otherwise, rounding errors in
+/// interferences would keep filling the map.
+const PRUNE_THRESHOLD: f64 = 1e-24;
+
+/// Represent the state of a quantum system as a map from the bases with
+/// non-zero amplitude to their amplitudes.
+///
+/// Memory usage grows with the number of non-zero amplitudes instead of with
+/// the number of qubits, which makes `SparseStateVector` suitable for states
+/// with little superposition.
+///
+/// # Examples
+///
+/// ```
+/// use qasmsim::statevector::{QuantumState, SparseStateVector};
+///
+/// let mut state = SparseStateVector::new(30);
+/// state.u(std::f64::consts::FRAC_PI_2, 0.0, std::f64::consts::PI, 0);
+/// state.cnot(0, 29);
+/// assert_eq!(state.nonzero_len(), 2);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+
+pub struct SparseStateVector {
+    amplitudes: HashMap<usize, Complex>,
+    qubit_width: usize,
+}
+
+impl SparseStateVector {
+    /// Create a new state-vector of `qubit_width` qubits with all the
+    /// amplitude concentrated in the all-zeroes outcome.
+    pub fn new(qubit_width: usize) -> Self {
+        let mut statevector = SparseStateVector {
+            amplitudes: HashMap::new(),
+            qubit_width,
+        };
+        statevector.reset();
+        statevector
+    }
+
+    /// Create a sparse state-vector with the non-zero amplitudes of
+    /// `statevector`.
+    pub fn from_dense(statevector: &StateVector) -> Self {
+        SparseStateVector {
+            amplitudes: statevector.nonzero_above(0.0).collect(),
+            qubit_width: statevector.qubit_width(),
+        }
+    }
+
+    /// Return the equivalent dense state-vector.
+    pub fn to_dense(&self) -> StateVector {
+        let mut bases = vec![Complex::new(0.0, 0.0); exp2(self.qubit_width)];
+        for (index, amplitude) in &self.amplitudes {
+            bases[*index] = *amplitude;
+        }
+        StateVector::from_complex_bases_unchecked(bases)
+    }
+
+    /// Return the number of amplitudes stored, which are those not being
+    /// zero.
+    pub fn nonzero_len(&self) -> usize {
+        self.amplitudes.len()
+    }
+
+    /// Return the amplitude of the basis `index`.
+    pub fn amplitude(&self, index: usize) -> Complex {
+        self.amplitudes
+            .get(&index)
+            .copied()
+            .unwrap_or_else(|| Complex::new(0.0, 0.0))
+    }
+
+    /// Return the reduced density matrix of the `qubits`, tracing out the
+    /// rest of the system. See [`StateVector::density_matrix()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases [`StateVector::density_matrix()`] does.
+    ///
+    /// [`StateVector::density_matrix()`]: ./struct.StateVector.html#method.density_matrix
+    pub fn density_matrix(&self, qubits: &[usize]) -> Vec<Vec<Complex>> {
+        if let Err(reason) = check_density_qubits(qubits, self.qubit_width) {
+            panic!("{}", reason);
+        }
+        self.reduced_density_matrix(qubits)
+    }
+
+    /// Return the entanglement entropy, in bits, of the bipartition between
+    /// the `cut` least significant qubits and the rest of the system. See
+    /// [`StateVector::entanglement_entropy_bits()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases [`StateVector::entanglement_entropy_bits()`]
+    /// does.
+    ///
+    /// [`StateVector::entanglement_entropy_bits()`]: ./struct.StateVector.html#method.entanglement_entropy_bits
+    pub fn entanglement_entropy_bits(&self, cut: usize) -> f64 {
+        if cut == 0 || cut >= self.qubit_width {
+            return 0.0;
+        }
+        if let Err(reason) = check_entanglement_cut(cut, self.qubit_width) {
+            panic!("{}", reason);
+        }
+        let (kept, _) = smallest_side(cut, self.qubit_width);
+        entropy_in_bits(&hermitian_eigenvalues(&self.reduced_density_matrix(&kept)))
+    }
+
+    /// Return the reduced density matrix of the `kept` qubits. Only the
+    /// amplitudes sharing the values of the traced qubits contribute to the
+    /// same elements, so they are grouped by those values first.
+    fn reduced_density_matrix(&self, kept: &[usize]) -> Vec<Vec<Complex>> {
+        let kept_mask: usize = kept.iter().map(|qubit| exp2(*qubit)).sum();
+        let mut groups: HashMap<usize, Vec<(usize, Complex)>> = HashMap::new();
+        for (index, amplitude) in &self.amplitudes {
+            groups
+                .entry(index & !kept_mask)
+                .or_default()
+                .push((gather_bits(*index, kept), *amplitude));
+        }
+        let size = exp2(kept.len());
+        let mut matrix = vec![vec![Complex::new(0.0, 0.0); size]; size];
+        for group in groups.values() {
+            for (row, row_amplitude) in group {
+                for (column, column_amplitude) in group {
+                    matrix[*row][*column] += row_amplitude * column_amplitude.conj();
+                }
+            }
+        }
+        matrix
+    }
+}
+
+impl QuantumState for SparseStateVector {
+    fn qubit_width(&self) -> usize {
+        self.qubit_width
+    }
+
+    fn cnot(&mut self, control: usize, target: usize) {
+        self.amplitudes = self
+            .amplitudes
+            .drain()
+            .map(|(index, amplitude)| {
+                if check_bit(index, control) == 1 {
+                    (index ^ (1 << target), amplitude)
+                } else {
+                    (index, amplitude)
+                }
+            })
+            .collect();
+    }
+
+    fn u(&mut self, theta: f64, phi: f64, lambda: f64, target: usize) {
+        let u_matrix = build_u(theta, phi, lambda);
+        let mask = 1 << target;
+        let mut pairs: Vec<usize> = self.amplitudes.keys().map(|index| index & !mask).collect();
+        pairs.sort_unstable();
+        pairs.dedup();
+        for index_0 in pairs {
+            let index_1 = index_0 | mask;
+            let selected = (self.amplitude(index_0), self.amplitude(index_1));
+            let new_amplitudes = [
+                (index_0, u_matrix.0 * selected.0 + u_matrix.1 * selected.1),
+                (index_1, u_matrix.2 * selected.0 + u_matrix.3 * selected.1),
+            ];
+            for (index, amplitude) in new_amplitudes {
+                if amplitude.norm_sqr() < PRUNE_THRESHOLD {
+                    self.amplitudes.remove(&index);
+                } else {
+                    self.amplitudes.insert(index, amplitude);
+                }
+            }
+        }
+    }
+
+    fn measure_with(&mut self, target: usize, rng: &mut dyn RandomSource) -> bool {
+        let chance_universe_0: f64 = self
+            .amplitudes
+            .iter()
+            .filter(|(index, _)| check_bit(**index, target) == 0)
+            .map(|(_, amplitude)| amplitude.norm_sqr())
+            .sum();
+        let chances = [chance_universe_0, 1.0 - chance_universe_0];
+        let value = (rng.next_f64() >= chances[0]) as usize;
+        let normalization_factor = chances[value].sqrt();
+        self.amplitudes
+            .retain(|index, _| check_bit(*index, target) == value);
+        for amplitude in self.amplitudes.values_mut() {
+            *amplitude /= normalization_factor;
+        }
+        value != 0
+    }
+
+    /// Return the probabilities of all the bases of the system, taking 8
+    /// bytes per basis state. Prefer [`marginal_outcomes()`] or
+    /// [`top_k_outcomes()`], which only visit the non-zero amplitudes.
+    ///
+    /// [`marginal_outcomes()`]: #method.marginal_outcomes
+    /// [`top_k_outcomes()`]: #method.top_k_outcomes
+    fn probabilities(&self) -> Vec<f64> {
+        let mut probabilities = vec![0.0; exp2(self.qubit_width)];
+        for (index, amplitude) in &self.amplitudes {
+            probabilities[*index] = amplitude.norm_sqr();
+        }
+        probabilities
+    }
+
+    fn marginal_outcomes(&self, qubits: &[usize]) -> Vec<(usize, f64)> {
+        let mut marginals: HashMap<usize, f64> = HashMap::new();
+        for (index, amplitude) in &self.amplitudes {
+            *marginals.entry(gather_bits(*index, qubits)).or_insert(0.0) += amplitude.norm_sqr();
+        }
+        let mut marginals: Vec<(usize, f64)> = marginals.into_iter().collect();
+        marginals.sort_unstable_by_key(|(outcome, _)| *outcome);
+        marginals
+    }
+
+    /// Return the `k` bases with the highest probabilities. Bases with zero
+    /// amplitude are not stored, so fewer than `k` bases are returned if
+    /// there are fewer non-zero amplitudes.
+    fn top_k_outcomes(&self, k: usize) -> Vec<(usize, f64)> {
+        top_k_of(
+            self.amplitudes
+                .iter()
+                .map(|(index, amplitude)| (*index, amplitude.norm_sqr())),
+            k,
+        )
+    }
+
+    fn reset(&mut self) {
+        self.amplitudes.clear();
+        self.amplitudes.insert(0, Complex::new(1.0, 0.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    use super::*;
+    use crate::statevector::assert_approx_eq;
+
+    #[test]
+    fn test_gates_match_the_dense_state_vector() {
+        let mut sparse = SparseStateVector::new(3);
+        let mut dense = StateVector::new(3);
+        fn apply(state: &mut dyn QuantumState, step: usize) {
+            match step {
+                0 | 4 => state.u(FRAC_PI_2, 0.0, PI, 0),
+                1 => state.cnot(0, 2),
+                2 => state.u(0.3, 1.2, -0.4, 1),
+                _ => state.cnot(1, 0),
+            }
+        }
+        for step in 0..5 {
+            apply(&mut sparse, step);
+            apply(&mut dense, step);
+            assert_approx_eq(&sparse.to_dense(), &dense);
+        }
+    }
+
+    #[test]
+    fn test_interference_removes_amplitudes() {
+        let mut sparse = SparseStateVector::new(2);
+        sparse.u(FRAC_PI_2, 0.0, PI, 1);
+        assert_eq!(sparse.nonzero_len(), 2);
+        sparse.u(FRAC_PI_2, 0.0, PI, 1);
+        assert_eq!(sparse.nonzero_len(), 1);
+        assert!((sparse.probabilities()[0] - 1.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_measurement_collapses_the_state() {
+        let mut sparse = SparseStateVector::new(2);
+        sparse.u(FRAC_PI_2, 0.0, PI, 0);
+        sparse.cnot(0, 1);
+        let outcome = sparse.measure(0);
+        let expected = if outcome { 3 } else { 0 };
+        assert_eq!(sparse.nonzero_len(), 1);
+        assert!((sparse.amplitude(expected).re - 1.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_outcomes_match_the_dense_state_vector() {
+        let mut sparse = SparseStateVector::new(4);
+        let mut dense = StateVector::new(4);
+        fn apply(state: &mut dyn QuantumState) {
+            state.u(FRAC_PI_2, 0.0, PI, 0);
+            state.u(0.3, 1.2, -0.4, 2);
+            state.cnot(0, 3);
+        }
+        apply(&mut sparse);
+        apply(&mut dense);
+        for qubits in [vec![0], vec![3, 0], vec![1, 2, 3]] {
+            let (sparse, dense) = (
+                sparse.marginal_outcomes(&qubits),
+                QuantumState::marginal_outcomes(&dense, &qubits),
+            );
+            assert_eq!(sparse.len(), dense.len());
+            for ((o1, p1), (o2, p2)) in sparse.iter().zip(&dense) {
+                assert_eq!(o1, o2);
+                assert!((p1 - p2).abs() < 1e-10);
+            }
+        }
+        let top: Vec<usize> = sparse.top_k_outcomes(2).iter().map(|(i, _)| *i).collect();
+        let expected: Vec<usize> = dense.top_k_outcomes(2).iter().map(|(i, _)| *i).collect();
+        assert_eq!(top, expected);
+    }
+
+    #[test]
+    fn test_density_and_entropy_match_the_dense_state_vector() {
+        let mut sparse = SparseStateVector::new(3);
+        let mut dense = StateVector::new(3);
+        fn apply(state: &mut dyn QuantumState) {
+            state.u(FRAC_PI_2, 0.0, PI, 0);
+            state.cnot(0, 2);
+            state.u(0.3, 1.2, -0.4, 1);
+        }
+        apply(&mut sparse);
+        apply(&mut dense);
+        for qubits in [vec![0], vec![2, 1]] {
+            let (sparse, dense) = (
+                sparse.density_matrix(&qubits),
+                dense.density_matrix(&qubits),
+            );
+            for (sparse_row, dense_row) in sparse.iter().zip(&dense) {
+                for (s, d) in sparse_row.iter().zip(dense_row) {
+                    assert!((s - d).norm() < 1e-10);
+                }
+            }
+        }
+        for cut in 0..4 {
+            assert!(
+                (sparse.entanglement_entropy_bits(cut) - dense.entanglement_entropy_bits(cut))
+                    .abs()
+                    < 1e-10
+            );
+        }
+    }
+
+    #[test]
+    fn test_conversions_between_dense_and_sparse() {
+        let mut dense = StateVector::new(2);
+        dense.u(FRAC_PI_2, 0.0, PI, 0);
+        let sparse = SparseStateVector::from_dense(&dense);
+        assert_eq!(sparse.nonzero_len(), 2);
+        assert_eq!(sparse.to_dense(), dense);
+    }
+}
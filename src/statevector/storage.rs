@@ -0,0 +1,170 @@
+//! The layouts of the amplitudes of a state-vector in memory.
+
+use std::fmt;
+use std::ops::{Index, IndexMut};
+
+use super::Complex;
+
+/// Base-2 logarithm of the number of amplitudes in a chunk. Chunks of 2^20
+/// amplitudes take 16 MiB each.
+#[cfg(not(test))]
+pub const CHUNK_BITS: usize = 20;
+
+/// Tests use small chunks so small state-vectors span several of them.
+#[cfg(test)]
+pub const CHUNK_BITS: usize = 4;
+
+const CHUNK_MASK: usize = (1 << CHUNK_BITS) - 1;
+
+/// Store the amplitudes of a state-vector either in a single contiguous
+/// allocation or split in fixed-size chunks, so big state-vectors do not
+/// need to find a contiguous block of memory for all their amplitudes.
+#[derive(Clone)]
+pub enum Storage {
+    Dense(Vec<Complex>),
+    Chunked(Vec<Box<[Complex]>>),
+}
+
+impl Storage {
+    /// Create a chunked storage of `len` amplitudes set to zero.
+    pub fn zeroed_chunked(len: usize) -> Self {
+        let chunk_len = len.min(1 << CHUNK_BITS);
+        let chunk_count = len.div_ceil(chunk_len.max(1));
+        Storage::Chunked(
+            (0..chunk_count)
+                .map(|_| vec![Complex::new(0.0, 0.0); chunk_len].into_boxed_slice())
+                .collect(),
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Storage::Dense(bases) => bases.len(),
+            Storage::Chunked(chunks) => chunks.iter().map(|chunk| chunk.len()).sum(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Complex> {
+        match self {
+            Storage::Dense(bases) => bases.get(index),
+            Storage::Chunked(chunks) => chunks
+                .get(index >> CHUNK_BITS)
+                .and_then(|chunk| chunk.get(index & CHUNK_MASK)),
+        }
+    }
+
+    /// Return the amplitudes as a single slice, if they are stored
+    /// contiguously.
+    pub fn as_slice(&self) -> Option<&[Complex]> {
+        match self {
+            Storage::Dense(bases) => Some(bases),
+            Storage::Chunked(chunks) if chunks.len() == 1 => Some(&chunks[0]),
+            Storage::Chunked(_) => None,
+        }
+    }
+
+    fn slices(&self) -> Vec<&[Complex]> {
+        match self {
+            Storage::Dense(bases) => vec![bases.as_slice()],
+            Storage::Chunked(chunks) => chunks.iter().map(|chunk| &chunk[..]).collect(),
+        }
+    }
+
+    fn slices_mut(&mut self) -> Vec<&mut [Complex]> {
+        match self {
+            Storage::Dense(bases) => vec![bases.as_mut_slice()],
+            Storage::Chunked(chunks) => chunks.iter_mut().map(|chunk| &mut chunk[..]).collect(),
+        }
+    }
+
+    /// Return an iterator over the amplitudes, in increasing order of basis.
+    pub fn iter(&self) -> impl Iterator<Item = &Complex> + '_ {
+        self.slices().into_iter().flatten()
+    }
+
+    /// Return an iterator over mutable references to the amplitudes, in
+    /// increasing order of basis.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Complex> + '_ {
+        self.slices_mut().into_iter().flatten()
+    }
+}
+
+impl Index<usize> for Storage {
+    type Output = Complex;
+
+    #[inline]
+    fn index(&self, index: usize) -> &Complex {
+        match self {
+            Storage::Dense(bases) => &bases[index],
+            Storage::Chunked(chunks) => &chunks[index >> CHUNK_BITS][index & CHUNK_MASK],
+        }
+    }
+}
+
+impl IndexMut<usize> for Storage {
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut Complex {
+        match self {
+            Storage::Dense(bases) => &mut bases[index],
+            Storage::Chunked(chunks) => &mut chunks[index >> CHUNK_BITS][index & CHUNK_MASK],
+        }
+    }
+}
+
+impl PartialEq for Storage {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for Storage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Return `n` with a zero bit inserted at `position`, shifting the bits
+/// from `position` on one place to the left.
+#[inline]
+pub fn insert_zero_bit(n: usize, position: usize) -> usize {
+    let low = n & ((1 << position) - 1);
+    ((n >> position) << (position + 1)) | low
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunked_storage_indexes_across_chunks() {
+        let len = 1 << (CHUNK_BITS + 1);
+        let mut storage = Storage::zeroed_chunked(len);
+        assert_eq!(storage.len(), len);
+        assert!(storage.as_slice().is_none());
+
+        storage[CHUNK_MASK] = Complex::new(1.0, 0.0);
+        storage[CHUNK_MASK + 1] = Complex::new(0.0, 1.0);
+        assert_eq!(storage.get(CHUNK_MASK), Some(&Complex::new(1.0, 0.0)));
+        assert_eq!(storage.get(CHUNK_MASK + 1), Some(&Complex::new(0.0, 1.0)));
+        assert_eq!(storage.get(len), None);
+
+        let nonzero: Vec<usize> = storage
+            .iter()
+            .enumerate()
+            .filter(|(_, amplitude)| amplitude.norm_sqr() > 0.0)
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(nonzero, vec![CHUNK_MASK, CHUNK_MASK + 1]);
+    }
+
+    #[test]
+    fn test_insert_zero_bit() {
+        assert_eq!(insert_zero_bit(0b111, 0), 0b1110);
+        assert_eq!(insert_zero_bit(0b111, 1), 0b1101);
+        assert_eq!(insert_zero_bit(0b111, 3), 0b0111);
+    }
+}
@@ -2,9 +2,19 @@
 
 extern crate qasmsim;
 
-use std::{f64::consts::FRAC_1_SQRT_2, vec};
+use std::{collections::HashMap, f64::consts::FRAC_1_SQRT_2, vec};
 
-use qasmsim::statevector::{assert_approx_eq, Complex, StateVector};
+use qasmsim::error::RuntimeError;
+use qasmsim::grammar::ast::{InversionError, Statement};
+use qasmsim::statevector::{
+    assert_approx_eq, assert_approx_eq_phase, assert_approx_eq_with, Complex, ComplexMargin,
+    StateVector,
+};
+
+#[cfg(feature = "memory-tracking")]
+#[global_allocator]
+static ALLOCATOR: qasmsim::alloc_tracking::TrackingAllocator =
+    qasmsim::alloc_tracking::TrackingAllocator;
 
 #[test]
 fn endianess() {
@@ -16,12 +26,13 @@ fn endianess() {
   ";
     assert_approx_eq(
         qasmsim::run(source, None).unwrap().statevector(),
-        &StateVector::from_complex_bases(vec![
+        &StateVector::from_amplitudes_with_validation(vec![
             Complex::from(FRAC_1_SQRT_2),
             Complex::from(0.0),
             Complex::from(FRAC_1_SQRT_2),
             Complex::from(0.0),
-        ]),
+        ])
+        .unwrap(),
     )
 }
 
@@ -37,10 +48,11 @@ fn call_custom_gate_on_qubit() {
   ";
     assert_approx_eq(
         qasmsim::run(source, None).unwrap().statevector(),
-        &StateVector::from_complex_bases(vec![
+        &StateVector::from_amplitudes_with_validation(vec![
             Complex::from(FRAC_1_SQRT_2),
             Complex::from(FRAC_1_SQRT_2),
-        ]),
+        ])
+        .unwrap(),
     )
 }
 
@@ -56,12 +68,13 @@ fn call_custom_gate_on_register() {
   ";
     assert_approx_eq(
         qasmsim::run(source, None).unwrap().statevector(),
-        &StateVector::from_complex_bases(vec![
+        &StateVector::from_amplitudes_with_validation(vec![
             Complex::from(0.5),
             Complex::from(0.5),
             Complex::from(0.5),
             Complex::from(0.5),
-        ]),
+        ])
+        .unwrap(),
     )
 }
 
@@ -80,12 +93,13 @@ fn call_custom_gate_inside_custom_gate() {
   ";
     assert_approx_eq(
         qasmsim::run(source, None).unwrap().statevector(),
-        &StateVector::from_complex_bases(vec![
+        &StateVector::from_amplitudes_with_validation(vec![
             Complex::from(0.5),
             Complex::from(0.5),
             Complex::from(0.5),
             Complex::from(0.5),
-        ]),
+        ])
+        .unwrap(),
     )
 }
 
@@ -99,12 +113,7 @@ fn test_one_register_bell_circuit() {
   ";
     assert_approx_eq(
         qasmsim::run(source, None).unwrap().statevector(),
-        &StateVector::from_complex_bases(vec![
-            Complex::from(FRAC_1_SQRT_2),
-            Complex::from(0.0),
-            Complex::from(0.0),
-            Complex::from(FRAC_1_SQRT_2),
-        ]),
+        &StateVector::bell_state(0),
     )
 }
 
@@ -119,12 +128,7 @@ fn test_two_registers_bell_circuit() {
   ";
     assert_approx_eq(
         qasmsim::run(source, None).unwrap().statevector(),
-        &StateVector::from_complex_bases(vec![
-            Complex::from(FRAC_1_SQRT_2),
-            Complex::from(0.0),
-            Complex::from(0.0),
-            Complex::from(FRAC_1_SQRT_2),
-        ]),
+        &StateVector::bell_state(0),
     )
 }
 
@@ -139,12 +143,7 @@ fn test_no_indices_bell_circuit() {
   ";
     assert_approx_eq(
         qasmsim::run(source, None).unwrap().statevector(),
-        &StateVector::from_complex_bases(vec![
-            Complex::from(FRAC_1_SQRT_2),
-            Complex::from(0.0),
-            Complex::from(0.0),
-            Complex::from(FRAC_1_SQRT_2),
-        ]),
+        &StateVector::bell_state(0),
     )
 }
 
@@ -157,7 +156,7 @@ fn test_no_indices_superposition() {
   ";
     assert_approx_eq(
         qasmsim::run(source, None).unwrap().statevector(),
-        &StateVector::from_complex_bases(vec![Complex::from(0.25); 16]),
+        &StateVector::from_amplitudes_with_validation(vec![Complex::from(0.25); 16]).unwrap(),
     )
 }
 
@@ -171,13 +170,13 @@ fn test_quantum_experience_header_is_included() {
   ";
     assert_approx_eq(
         qasmsim::run(source, None).unwrap().statevector(),
-        &StateVector::from_complex_bases(vec![Complex::from(0.25); 16]),
+        &StateVector::from_amplitudes_with_validation(vec![Complex::from(0.25); 16]).unwrap(),
     )
 }
 
 #[test]
 fn test_measurements() {
-    let subtests = vec![
+    let subtests = [
         (
             "
      OPENQASM 2.0;
@@ -186,7 +185,7 @@ fn test_measurements() {
      creg c[2];
      measure q -> c;
      ",
-            0b00_u64,
+            0b00_u128,
         ),
         (
             "
@@ -197,7 +196,7 @@ fn test_measurements() {
      x q[0];
      measure q -> c;
      ",
-            0b01_u64,
+            0b01_u128,
         ),
         (
             "
@@ -208,7 +207,7 @@ fn test_measurements() {
      x q[1];
      measure q -> c;
      ",
-            0b10_u64,
+            0b10_u128,
         ),
         (
             "
@@ -219,7 +218,7 @@ fn test_measurements() {
      x q;
      measure q -> c;
      ",
-            0b11_u64,
+            0b11_u128,
         ),
     ];
     for (index, (source, expected_result)) in subtests.iter().enumerate() {
@@ -270,6 +269,59 @@ fn test_conditional() {
     assert_eq!(*result.memory().get("d").unwrap(), (0b01, 2, 69));
 }
 
+#[test]
+fn test_conditional_sees_measurements_from_previous_statements() {
+    let source = "
+  OPENQASM 2.0;
+  include \"qelib1.inc\";
+  qreg q[3];
+  qreg r[1];
+  creg c[3];
+  creg d[1];
+  x q[0];
+  x q[2];
+  measure q[0] -> c[0];
+  measure q[1] -> c[1];
+  if (c==1) x r[0];
+  measure q[2] -> c[2];
+  if (c==5) x q[1];
+  if (c==5) measure q[1] -> c[1];
+  if (c==7) measure r[0] -> d[0];
+  ";
+    let result = qasmsim::run(source, Some(20)).unwrap();
+    assert_eq!(result.memory().get("c").unwrap().0, 0b111);
+    assert_eq!(result.memory().get("d").unwrap().0, 0b1);
+    assert_eq!(
+        result.histogram().as_ref().unwrap()["c"],
+        (vec![(0b111, 20)], 3)
+    );
+    assert_eq!(
+        result.histogram().as_ref().unwrap()["d"],
+        (vec![(0b1, 20)], 1)
+    );
+}
+
+#[test]
+fn test_measuring_a_bit_again_overwrites_it() {
+    let source = "
+  OPENQASM 2.0;
+  include \"qelib1.inc\";
+  qreg q[1];
+  creg c[2];
+  x q[0];
+  measure q[0] -> c[0];
+  measure q[0] -> c[0];
+  if (c==1) x q[0];
+  measure q[0] -> c[0];
+  ";
+    let result = qasmsim::run(source, Some(10)).unwrap();
+    assert_eq!(result.memory().get("c").unwrap().0, 0b00);
+    assert_eq!(
+        result.histogram().as_ref().unwrap()["c"],
+        (vec![(0b00, 10)], 2)
+    );
+}
+
 #[test]
 fn test_print_json_1() {
     let source = "
@@ -289,7 +341,7 @@ fn test_print_json_1() {
     };
 
     let result = qasmsim::run(source, option.shots).unwrap();
-    let output = qasmsim::print_result(&result, &option);
+    let output = qasmsim::print_result(&result, &option).unwrap();
     assert_eq!(
         output,
         r#"{
@@ -323,6 +375,36 @@ fn test_print_json_1() {
     );
 }
 
+#[test]
+fn test_print_json_only_most_probable_states() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    x q[1];
+    ";
+
+    let option = qasmsim::options::Options {
+        format: qasmsim::options::Format::Json,
+        statevector: false,
+        max_states: Some(1),
+        ..Default::default()
+    };
+
+    let result = qasmsim::run(source, option.shots).unwrap();
+    let output = qasmsim::print_result(&result, &option).unwrap();
+    assert_eq!(
+        output,
+        r#"{
+  "State": {
+    "2": {
+      "Probability": "1.000000"
+    }
+  }
+}"#
+    );
+}
+
 #[test]
 fn test_print_json_2() {
     let source = "
@@ -341,7 +423,7 @@ fn test_print_json_2() {
     };
 
     let result = qasmsim::run(source, option.shots).unwrap();
-    let output = qasmsim::print_result(&result, &option);
+    let output = qasmsim::print_result(&result, &option).unwrap();
     assert_eq!(
         output,
         r#"{
@@ -375,6 +457,80 @@ fn test_print_json_2() {
     );
 }
 
+#[test]
+fn test_print_json_split_registers() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    qreg r[1];
+    h q[0];
+    cx q[0], q[1];
+    x r[0];
+    ";
+
+    let option = qasmsim::options::Options {
+        format: qasmsim::options::Format::Json,
+        statevector: false,
+        split_registers: true,
+        ..Default::default()
+    };
+
+    let result = qasmsim::run(source, option.shots).unwrap();
+    let output = qasmsim::print_result(&result, &option).unwrap();
+    assert_eq!(
+        output,
+        r#"{
+  "State": {
+    "q": {
+      "00": {
+        "Probability": "0.500000"
+      },
+      "01": {
+        "Probability": "0.000000"
+      },
+      "10": {
+        "Probability": "0.000000"
+      },
+      "11": {
+        "Probability": "0.500000"
+      }
+    },
+    "r": {
+      "0": {
+        "Probability": "0.000000"
+      },
+      "1": {
+        "Probability": "1.000000"
+      }
+    }
+  }
+}"#
+    );
+}
+
+#[test]
+fn test_register_order_matches_stats_layout() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg a[1];
+    creg b[2];
+    creg c[3];
+    x q[0];
+    measure q[0] -> a[0];
+    measure q[0] -> b[1];
+    measure q[0] -> c[2];
+    measure q[1] -> c[0];
+    ";
+
+    let result = qasmsim::run(source, Some(10)).unwrap();
+    assert_eq!(result.register_order(), vec!["c", "b", "a"]);
+    let expected_stats = HashMap::from_iter(vec![("100101".to_string(), 10)]);
+    assert_eq!(result.stats(), &Some(expected_stats));
+}
+
 #[test]
 fn test_print_json_shots() {
     let source = "
@@ -395,7 +551,7 @@ fn test_print_json_shots() {
     };
 
     let result = qasmsim::run(source, option.shots).unwrap();
-    let output = qasmsim::print_result(&result, &option);
+    let output = qasmsim::print_result(&result, &option).unwrap();
     assert_eq!(
         output,
         r#"{
@@ -422,13 +578,21 @@ fn test_print_json_shots_sequence() {
         format: qasmsim::options::Format::Json,
         shots: Some(5),
         times: false,
-        mode: "sequence".to_string(),
+        mode: qasmsim::options::SimulationMode::Sequence,
         ..Default::default()
     };
 
-    let result = qasmsim::run_mode(source, option.shots, option.mode.clone()).unwrap();
-    println!("{:?}", result);
-    let output = qasmsim::print_result(&result, &option);
+    let result = qasmsim::run_mode(source, option.shots, option.mode).unwrap();
+    assert_eq!(result.sequences().as_ref().unwrap().len(), 5);
+    assert_eq!(
+        result.shot(4),
+        Some(&HashMap::from([
+            ("c".to_string(), 1),
+            ("c1".to_string(), 0)
+        ]))
+    );
+    assert_eq!(result.shot(5), None);
+    let output = qasmsim::print_result(&result, &option).unwrap();
     assert_eq!(
         output,
         r#"{
@@ -444,6 +608,7 @@ fn test_print_json_shots_sequence() {
 }
 
 #[test]
+#[allow(unused_variables, clippy::useless_vec)]
 fn test_observe() {
     let source = "
   OPENQASM 2.0;
@@ -510,12 +675,12 @@ fn test_observe() {
 //         format: qasmsim::options::Format::Json,
 //         shots: Some(1000),
 //         times: false,
-//         mode: "max".to_string(),
+//         mode: qasmsim::options::SimulationMode::Max,
 //         ..Default::default()
 //     };
 
-//     let result = qasmsim::run_mode(source, option.shots, option.mode.clone()).unwrap();
-//     let output = qasmsim::print_result(&result, &option);
+//     let result = qasmsim::run_mode(source, option.shots, option.mode).unwrap();
+//     let output = qasmsim::print_result(&result, &option).unwrap();
 //     assert_eq!(
 //         output,
 //         r#"{
@@ -525,3 +690,2266 @@ fn test_observe() {
 // }"#
 //     )
 // }
+
+#[test]
+fn test_print_bits_of_partially_written_register() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg c[3];
+    x q[1];
+    measure q[1] -> c[1];
+    ";
+
+    let option = qasmsim::options::Options {
+        binary: false,
+        hexadecimal: false,
+        statevector: false,
+        probabilities: false,
+        show_bits: true,
+        ..Default::default()
+    };
+
+    let result = qasmsim::run(source, option.shots).unwrap();
+    let output = qasmsim::print_result(&result, &option).unwrap();
+    assert_eq!(
+        output,
+        "Memory:
++------+-----------------+-----------+----------------------+
+| Name | Register length | Int value |         Bits         |
++------+-----------------+-----------+----------------------+
+|    c |               3 |         2 | c[2]=0 c[1]=1 c[0]=0 |
++------+-----------------+-----------+----------------------+
+
+"
+    );
+}
+
+#[test]
+fn test_print_json_bits_of_partially_written_register() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg c[3];
+    x q[1];
+    measure q[1] -> c[1];
+    ";
+
+    let option = qasmsim::options::Options {
+        format: qasmsim::options::Format::Json,
+        binary: false,
+        hexadecimal: false,
+        statevector: false,
+        probabilities: false,
+        show_bits: true,
+        ..Default::default()
+    };
+
+    let result = qasmsim::run(source, option.shots).unwrap();
+    let output = qasmsim::print_result(&result, &option).unwrap();
+    assert_eq!(
+        output,
+        r#"{
+  "Memory": {
+    "c": {
+      "0": {
+        "Bits": "c[2]=0 c[1]=1 c[0]=0",
+        "Int value": 2,
+        "Register length": 3
+      }
+    }
+  }
+}"#
+    );
+}
+
+#[test]
+fn test_register_filter_limits_the_printed_registers() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg a[1];
+    creg result[2];
+    creg b[1];
+    x q[1];
+    measure q[0] -> a[0];
+    measure q[1] -> result[0];
+    measure q[2] -> b[0];
+    ";
+
+    let result = qasmsim::run(source, None).unwrap();
+    let option = qasmsim::options::Options::builder()
+        .statevector(false)
+        .probabilities(false)
+        .register("result")
+        .build();
+    let output = qasmsim::print_result(&result, &option).unwrap();
+    assert!(output.contains("result"));
+    assert!(!output.contains("|    a |"));
+    assert!(!output.contains("|    b |"));
+
+    let result = qasmsim::run_mode(
+        source,
+        Some(10),
+        qasmsim::options::SimulationMode::Aggregation,
+    )
+    .unwrap();
+    assert_eq!(result.stats().as_ref().unwrap()["0010"], 10);
+    let option = qasmsim::options::Options::builder()
+        .format(qasmsim::options::Format::Json)
+        .statevector(false)
+        .probabilities(false)
+        .register("result")
+        .shots(10)
+        .build();
+    let output = qasmsim::print_result(&result, &option).unwrap();
+    assert_eq!(
+        output,
+        r#"{
+  "Memory": {
+    "01": 10
+  }
+}"#
+    );
+
+    let result =
+        qasmsim::run_mode(source, Some(3), qasmsim::options::SimulationMode::Sequence).unwrap();
+    let filtered = qasmsim::options::Options::builder()
+        .format(qasmsim::options::Format::Json)
+        .register("b")
+        .register("a")
+        .shots(3)
+        .mode(qasmsim::options::SimulationMode::Sequence)
+        .build();
+    let output = qasmsim::print_result(&result, &filtered).unwrap();
+    assert!(output.contains(
+        r#""Sequences": [
+    "00",
+    "00",
+    "00"
+  ]"#
+    ));
+}
+
+#[test]
+fn test_written_bits_of_classical_registers() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg a[2];
+    creg b[2];
+    creg c[2];
+    measure q[1] -> b[1];
+    ";
+
+    let result = qasmsim::run(source, None).unwrap();
+    assert_eq!(result.written_bits().len(), 3);
+    assert_eq!(*result.written_bits().get("a").unwrap(), 0b00);
+    assert_eq!(*result.written_bits().get("b").unwrap(), 0b10);
+    assert_eq!(*result.written_bits().get("c").unwrap(), 0b00);
+
+    let option = qasmsim::options::Options {
+        hexadecimal: false,
+        statevector: false,
+        probabilities: false,
+        mark_unwritten: true,
+        ..Default::default()
+    };
+    let output = qasmsim::print_result(&result, &option).unwrap();
+    assert!(output.contains("|    a |               2 |         - |         - |"));
+    assert!(output.contains("|    b |               2 |         0 |      0b00 |"));
+    assert!(output.contains("|    c |               2 |         - |         - |"));
+
+    let option = qasmsim::options::Options {
+        format: qasmsim::options::Format::Json,
+        hexadecimal: false,
+        statevector: false,
+        probabilities: false,
+        mark_unwritten: true,
+        ..Default::default()
+    };
+    let output = qasmsim::print_result(&result, &option).unwrap();
+    assert_eq!(
+        output,
+        r#"{
+  "Memory": {
+    "a": {
+      "0": {
+        "Bin value": null,
+        "Int value": null,
+        "Register length": 2
+      }
+    },
+    "b": {
+      "0": {
+        "Bin value": "0b00",
+        "Int value": 0,
+        "Register length": 2
+      }
+    },
+    "c": {
+      "0": {
+        "Bin value": null,
+        "Int value": null,
+        "Register length": 2
+      }
+    }
+  }
+}"#
+    );
+}
+
+#[test]
+fn test_written_bits_accumulate_over_shots() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[1];
+    creg d[1];
+    h q[0];
+    measure q[0] -> c[0];
+    if (c==1) measure q[1] -> d[0];
+    ";
+    let execution = qasmsim::QasmSim::new()
+        .with_shots(100)
+        .with_seed(7)
+        .run(source)
+        .unwrap();
+    assert_eq!(execution.written_bits()["c"], 1);
+    assert_eq!(execution.written_bits()["d"], 1);
+
+    // The histogram of `d` only counts the shots measuring into it.
+    let histogram = execution.histogram().as_ref().unwrap();
+    let ones = histogram["c"]
+        .0
+        .iter()
+        .find(|(value, _)| *value == 1)
+        .map_or(0, |(_, count)| *count);
+    let measured: usize = histogram["d"].0.iter().map(|(_, count)| count).sum();
+    assert!(0 < ones && ones < 100);
+    assert_eq!(measured, ones);
+}
+
+#[test]
+fn test_max_entanglement_of_ghz_preparation() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[4];
+    h q[0];
+    cx q[0], q[1];
+    cx q[1], q[2];
+    cx q[2], q[3];
+    ";
+
+    let options = qasmsim::options::SimulationOptions {
+        track_entanglement: Some(2),
+        ..Default::default()
+    };
+    let result = qasmsim::run_with_options(source, None, &options).unwrap();
+    let max_entanglement = result.max_entanglement().unwrap();
+    assert!((max_entanglement - 1.0).abs() < 1e-9);
+
+    let result = qasmsim::run(source, None).unwrap();
+    assert_eq!(result.max_entanglement(), None);
+    assert_approx_eq(result.statevector(), &StateVector::ghz_state(4));
+}
+
+#[test]
+fn test_treat_opaque_gates_as_identity() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    opaque gate magic(theta) a, b;
+    qreg q[2];
+    x q[0];
+    magic(pi) q[0], q[1];
+    ";
+
+    let options = qasmsim::options::SimulationOptions {
+        treat_opaque_as_identity: true,
+        ..Default::default()
+    };
+    let result = qasmsim::run_with_options(source, None, &options).unwrap();
+    let probabilities = result.probabilities();
+    assert!((probabilities[1] - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_substitute_gate_only_accepts_declarations_of_the_same_gate() {
+    use qasmsim::error::SubstitutionError;
+    use qasmsim::grammar::parse_statement;
+
+    let source = "
+    OPENQASM 2.0;
+    opaque gate flip q;
+    qreg q[1];
+    flip q[0];
+    ";
+    let mut program = qasmsim::parse_and_link(source).unwrap();
+    let original = program.clone();
+
+    let register = parse_statement("qreg r[1];").unwrap();
+    assert_eq!(
+        qasmsim::substitute_gate(&mut program, "flip", register),
+        Err(SubstitutionError::NotAGateDeclaration)
+    );
+    let other = parse_statement("gate flop q { U(pi, 0, pi) q; }").unwrap();
+    assert_eq!(
+        qasmsim::substitute_gate(&mut program, "flip", other.clone()),
+        Err(SubstitutionError::NameMismatch {
+            expected: "flip".into(),
+            found: "flop".into()
+        })
+    );
+    assert_eq!(
+        qasmsim::substitute_gate(&mut program, "flop", other),
+        Err(SubstitutionError::GateNotFound("flop".into()))
+    );
+    assert_eq!(program, original);
+
+    let definition = parse_statement("gate flip q { U(pi, 0, pi) q; }").unwrap();
+    assert_eq!(
+        qasmsim::substitute_gate(&mut program, "flip", definition),
+        Ok(())
+    );
+    assert_eq!(qasmsim::simulate(&program).unwrap().probabilities()[1], 1.0);
+}
+
+#[test]
+fn test_teleportation_with_bit_conditionals() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg c[2];
+    creg d[1];
+    ry(1.2) q[0];
+    h q[1];
+    cx q[1], q[2];
+    cx q[0], q[1];
+    h q[0];
+    measure q[0] -> c[0];
+    measure q[1] -> c[1];
+    if (c[1]==1) x q[2];
+    if (c[0]==1) z q[2];
+    ry(-1.2) q[2];
+    measure q[2] -> d[0];
+    ";
+
+    let options = qasmsim::options::SimulationOptions {
+        extensions: true,
+        ..Default::default()
+    };
+    let result = qasmsim::run_with_options(source, Some(100), &options).unwrap();
+    let histogram = result.histogram().as_ref().unwrap();
+    assert_eq!(histogram["d"].0, vec![(0, 100)]);
+    assert_eq!(histogram["c"].0.len(), 4);
+}
+
+#[test]
+fn test_gate_info_reports_the_origin_of_the_gate() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    // Apply H twice
+    gate hh q {
+      h q;
+      h q;
+    }
+    ";
+
+    let info = qasmsim::get_gate_info(source, "h").unwrap();
+    assert_eq!(info.origin, "qelib1.inc");
+    assert_eq!(info.lineno, 29);
+
+    let info = qasmsim::get_gate_info(source, "hh").unwrap();
+    assert_eq!(info.origin, "<source>");
+    assert_eq!(info.lineno, 5);
+}
+
+#[test]
+fn test_empty_program() {
+    let source = "OPENQASM 2.0;\n";
+    let result = qasmsim::run(source, None).unwrap();
+    assert_approx_eq(
+        result.statevector(),
+        &StateVector::from_amplitudes_with_validation(vec![Complex::from(1.0)]).unwrap(),
+    );
+    assert!(result.memory().is_empty());
+    qasmsim::print_result(&result, &Default::default()).unwrap();
+
+    let result = qasmsim::run(source, Some(10)).unwrap();
+    assert_eq!(result.histogram(), &Some(HashMap::new()));
+}
+
+#[test]
+fn test_program_without_gates() {
+    let source = "
+    OPENQASM 2.0;
+    qreg q[2];
+    creg c[2];
+    ";
+    let result = qasmsim::run(source, None).unwrap();
+    assert_approx_eq(result.statevector(), &StateVector::new(2));
+    assert_eq!(result.memory()["c"].0, 0);
+}
+
+#[test]
+fn test_run_with_timeout_matches_run() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    h q[0];
+    cx q[0], q[1];
+    ";
+    let timeout = std::time::Duration::from_secs(60);
+    let result = qasmsim::run_with_timeout(source, None, timeout).unwrap();
+    let expected = qasmsim::run(source, None).unwrap();
+    assert_approx_eq(result.statevector(), expected.statevector());
+}
+
+#[test]
+fn test_gate_info_reads_block_comments() {
+    let source = "OPENQASM 2.0;\r\n/*\r\n * Apply U twice\r\n */\r\ngate uu q {\r\n  U(0, 0, 0) q;\r\n  U(0, 0, 0) q;\r\n}\r\n";
+
+    let docstring = qasmsim::get_gate_info(source, "uu").unwrap().docstring;
+    assert_eq!(docstring, " Apply U twice\n");
+}
+
+#[test]
+fn test_gate_info_returns_line_comments_verbatim() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[1]; // Not documentation
+    gate undocumented a { x a; }
+    // Flip the qubit
+    //   twice.
+    gate xx a {
+      x a;
+      x a;
+    }
+    ";
+
+    let docstring = qasmsim::get_gate_info(source, "xx").unwrap().docstring;
+    assert_eq!(docstring, " Flip the qubit\n   twice.\n");
+
+    let docstring = qasmsim::get_gate_info(source, "undocumented")
+        .unwrap()
+        .docstring;
+    assert_eq!(docstring, "");
+
+    let docstring = qasmsim::get_gate_info(source, "h").unwrap().docstring;
+    assert_eq!(docstring, " Clifford gate: Hadamard\n");
+}
+
+#[test]
+fn test_uniform_weights_reproduce_ordinary_counts() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg c[2];
+    creg d[1];
+    x q[1];
+    h q[2];
+    measure q[0] -> c[0];
+    measure q[1] -> c[1];
+    measure q[2] -> d[0];
+    ";
+    let program = qasmsim::parse_and_link(source).unwrap();
+
+    let weighted = qasmsim::simulate_weighted(&program, 100, |_| 1.0).unwrap();
+    let computation = qasmsim::simulate_with_shots(&program, 100).unwrap();
+    let histogram = computation.histogram().as_ref().unwrap();
+    let as_weights = |(value, count): &(u128, usize)| (*value, *count as f64);
+    assert_eq!(
+        weighted["c"].0,
+        histogram["c"].0.iter().map(as_weights).collect::<Vec<_>>()
+    );
+    assert_eq!(weighted["c"].1, 2);
+    let total: f64 = weighted["d"].0.iter().map(|(_, weight)| weight).sum();
+    assert_eq!(total, 100.0);
+}
+
+#[test]
+fn test_gate_application_count_includes_broadcasts_and_expansions() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg c[3];
+    gate hh a {
+      h a;
+      h a;
+    }
+    hh q;
+    cx q[0], q[1];
+    if (c == 7) x q[0];
+    measure q -> c;
+    ";
+
+    let execution = qasmsim::run(source, None).unwrap();
+    let count = execution.gate_application_count();
+    assert_eq!(count["hh"], 3);
+    assert_eq!(count["h"], 6);
+    assert_eq!(count["u2"], 6);
+    assert_eq!(count["cx"], 1);
+    assert_eq!(count["CX"], 1);
+    assert!(!count.contains_key("x"));
+
+    let execution = qasmsim::run(source, Some(10)).unwrap();
+    assert_eq!(execution.gate_application_count()["hh"], 30);
+}
+
+#[test]
+fn test_print_bloch_vectors() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[1];
+    qreg r[2];
+    h q[0];
+    x r[1];
+    ";
+    let execution = qasmsim::run(source, None).unwrap();
+
+    let json_options = qasmsim::options::Options::builder()
+        .format(qasmsim::options::Format::Json)
+        .statevector(false)
+        .probabilities(false)
+        .bloch(true)
+        .build();
+    let output = qasmsim::print_result(&execution, &json_options).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let bloch: Vec<Vec<f64>> = serde_json::from_value(json["Bloch"].clone()).unwrap();
+    let expected = [[1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 0.0, -1.0]];
+    for (vector, expected) in bloch.iter().zip(expected) {
+        for (coordinate, expected) in vector.iter().zip(expected) {
+            assert!((coordinate - expected).abs() < 1e-10);
+        }
+    }
+
+    let tabular_options = qasmsim::options::Options::builder()
+        .statevector(false)
+        .probabilities(false)
+        .bloch(true)
+        .build();
+    let output = qasmsim::print_result(&execution, &tabular_options).unwrap();
+    assert!(output.contains("Bloch vectors:"));
+    assert!(output.contains("r[1]"));
+    assert!(output.contains("-1.000000"));
+
+    let with_shots = qasmsim::options::Options::builder()
+        .format(qasmsim::options::Format::Json)
+        .shots(10)
+        .bloch(true)
+        .build();
+    let execution = qasmsim::run(source, Some(10)).unwrap();
+    let output = qasmsim::print_result(&execution, &with_shots).unwrap();
+    assert!(!output.contains("Bloch"));
+}
+
+#[test]
+fn test_density_matrix_of_bell_pair_qubit_is_maximally_mixed() {
+    use qasmsim::options::SimulationOptions;
+
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    h q[0];
+    cx q[0], q[1];
+    ";
+    let execution = qasmsim::QasmSim::new()
+        .with_options(SimulationOptions {
+            density_qubits: Some(vec![1]),
+            ..Default::default()
+        })
+        .run(source)
+        .unwrap();
+    let density = execution.density_matrix().unwrap();
+    let margin = ComplexMargin::default();
+    assert!(margin.approx_eq(density[0][0], Complex::from(0.5)));
+    assert!(margin.approx_eq(density[0][1], Complex::from(0.0)));
+    assert!(margin.approx_eq(density[1][0], Complex::from(0.0)));
+    assert!(margin.approx_eq(density[1][1], Complex::from(0.5)));
+
+    let option = qasmsim::options::Options {
+        format: qasmsim::options::Format::Json,
+        ..Default::default()
+    };
+    let output = qasmsim::print_result(&execution, &option).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(json["DensityMatrix"][0][1], serde_json::json!([0.0, 0.0]));
+    assert!((json["DensityMatrix"][1][1][0].as_f64().unwrap() - 0.5).abs() < 1e-10);
+}
+
+#[test]
+fn test_density_matrix_is_averaged_over_shots() {
+    use qasmsim::options::SimulationOptions;
+
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[1];
+    creg c[1];
+    h q[0];
+    measure q[0] -> c[0];
+    ";
+    let execution = qasmsim::QasmSim::new()
+        .with_shots(2000)
+        .with_seed(7)
+        .with_options(SimulationOptions {
+            density_qubits: Some(vec![0]),
+            ..Default::default()
+        })
+        .run(source)
+        .unwrap();
+    let density = execution.density_matrix().unwrap();
+    let ones = execution.histogram().as_ref().unwrap()["c"]
+        .0
+        .iter()
+        .find(|(value, _)| *value == 1)
+        .map_or(0, |(_, count)| *count);
+    assert!((density[1][1].re - ones as f64 / 2000.0).abs() < 1e-10);
+    assert!((density[0][0].re + density[1][1].re - 1.0).abs() < 1e-10);
+    assert!(density[0][1].norm() < 1e-10);
+
+    let error = qasmsim::simulate_with_options(
+        &qasmsim::parse_and_link(source).unwrap(),
+        None,
+        &SimulationOptions {
+            density_qubits: Some(vec![1]),
+            ..Default::default()
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        error,
+        RuntimeError::InvalidDensityQubits {
+            reason: "qubit 1 is out of the state-vector of 1 qubits".into()
+        }
+    );
+}
+
+#[test]
+fn test_probabilities_are_computed_on_demand() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    h q[0];
+    ry(pi / 3) q[1];
+    ";
+    let program = qasmsim::parse_and_link(source).unwrap();
+    let computation = qasmsim::simulate(&program).unwrap();
+    let expected = computation.statevector().probabilities();
+    assert_eq!(computation.probabilities(), &expected[..]);
+    assert_eq!(computation, qasmsim::simulate(&program).unwrap());
+
+    let execution = qasmsim::run(source, None).unwrap();
+    assert_eq!(execution.probabilities(), &expected);
+    assert_eq!(execution.clone().probabilities(), &expected);
+}
+
+#[test]
+fn test_padding_a_program_to_more_qubits() {
+    use qasmsim::options::SimulationOptions;
+
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[1];
+    x q[0];
+    ";
+    let options = SimulationOptions {
+        pad_qubits: Some(3),
+        ..Default::default()
+    };
+    let program = qasmsim::parse_and_link(source).unwrap();
+    let computation = qasmsim::simulate_with_options(&program, None, &options).unwrap();
+    let mut expected = vec![Complex::new(0.0, 0.0); 8];
+    expected[1] = Complex::new(1.0, 0.0);
+    assert_eq!(computation.statevector().qubit_width(), 3);
+    assert_approx_eq(
+        computation.statevector(),
+        &StateVector::from_complex_bases_unchecked(expected),
+    );
+
+    let execution = qasmsim::QasmSim::new()
+        .with_pad_qubits(3)
+        .run(source)
+        .unwrap();
+    assert_eq!(execution.probabilities().len(), 8);
+
+    let error = qasmsim::simulate_with_options(
+        &program,
+        None,
+        &SimulationOptions {
+            pad_qubits: Some(0),
+            ..Default::default()
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        error,
+        RuntimeError::PaddingTooNarrow {
+            qubits: 1,
+            pad_qubits: 0
+        }
+    );
+}
+
+#[test]
+fn test_peak_memory_bytes_of_dense_simulation() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[4];
+    h q[0];
+    cx q[0], q[1];
+    h q[0];
+    ";
+
+    let execution = qasmsim::run(source, None).unwrap();
+    let amplitudes = 16 * 16;
+    // One table of 8 row pairs for the `U` on q[0], and one of 4 row pairs
+    // for the `CX`, of 16 bytes per pair.
+    let row_tables = 8 * 16 + 4 * 16;
+    // The matrix of the only distinct `U` along with its 48-byte cache key.
+    let matrices = 64 + 48;
+    assert_eq!(
+        execution.peak_memory_bytes(),
+        amplitudes + row_tables + matrices
+    );
+
+    let option = qasmsim::options::Options {
+        format: qasmsim::options::Format::Json,
+        times: true,
+        ..Default::default()
+    };
+    let output = qasmsim::print_result(&execution, &option).unwrap();
+    assert!(output.contains(&format!(
+        "\"EstimatedPeakMemoryBytes\": {}",
+        amplitudes + row_tables + matrices
+    )));
+}
+
+#[test]
+fn test_replay_source_reproduces_recorded_shots() {
+    use qasmsim::random::{RecordingRandom, ReplayRandom, ThreadRandom};
+
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg c[3];
+    h q;
+    measure q -> c;
+    ";
+    let program = qasmsim::parse_and_link(source).unwrap();
+    let options = qasmsim::options::SimulationOptions::default();
+
+    let mut recording = RecordingRandom::new(ThreadRandom);
+    let recorded: Vec<u128> = (0..20)
+        .map(|_| {
+            let computation =
+                qasmsim::simulate_with_rng(&program, None, &options, &mut recording).unwrap();
+            computation.memory()["c"].0
+        })
+        .collect();
+    assert_eq!(recording.recorded().len(), 60);
+
+    let mut replay = ReplayRandom::new(recording.into_recorded());
+    let replayed: Vec<u128> = (0..20)
+        .map(|_| {
+            let computation =
+                qasmsim::simulate_with_rng(&program, None, &options, &mut replay).unwrap();
+            computation.memory()["c"].0
+        })
+        .collect();
+    assert_eq!(recorded, replayed);
+    assert_eq!(replay.remaining(), 0);
+}
+
+#[test]
+#[should_panic(expected = "the random number at 1 is 1.5, outside [0.0, 1.0)")]
+fn test_replay_source_rejects_numbers_out_of_range() {
+    qasmsim::random::ReplayRandom::new(vec![0.5, 1.5]);
+}
+
+#[test]
+fn test_replay_source_try_new_returns_the_number_out_of_range() {
+    use qasmsim::random::{RandomNumberOutOfRange, ReplayRandom};
+
+    assert_eq!(
+        ReplayRandom::try_new(vec![0.5, 1.5]),
+        Err(RandomNumberOutOfRange {
+            index: 1,
+            value: 1.5
+        })
+    );
+    assert_eq!(ReplayRandom::try_new(vec![0.5]).unwrap().remaining(), 1);
+}
+
+#[test]
+fn test_running_out_of_replayed_numbers_is_an_error() {
+    use qasmsim::random::ReplayRandom;
+
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[2];
+    h q;
+    measure q -> c;
+    ";
+    let program = qasmsim::parse_and_link(source).unwrap();
+    let options = qasmsim::options::SimulationOptions::default();
+    let mut replay = ReplayRandom::new(vec![0.25]);
+    assert_eq!(
+        qasmsim::simulate_with_rng(&program, None, &options, &mut replay),
+        Err(RuntimeError::RandomSourceExhausted)
+    );
+
+    let mut replay = ReplayRandom::new(vec![0.25, 0.75, 0.5]);
+    assert_eq!(
+        qasmsim::simulate_with_rng(&program, Some(2), &options, &mut replay),
+        Err(RuntimeError::RandomSourceExhausted)
+    );
+}
+
+#[test]
+fn test_seeded_source_is_deterministic() {
+    use qasmsim::random::SeededRandom;
+
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg c[3];
+    h q;
+    measure q -> c;
+    ";
+    let program = qasmsim::parse_and_link(source).unwrap();
+    let options = qasmsim::options::SimulationOptions::default();
+    let simulate = |seed| {
+        qasmsim::simulate_with_rng(&program, Some(200), &options, &mut SeededRandom::new(seed))
+            .unwrap()
+    };
+    assert_eq!(simulate(1107).stats(), simulate(1107).stats());
+    assert_ne!(simulate(1107).stats(), simulate(7011).stats());
+}
+
+#[test]
+fn test_sampling_terminal_measurements_matches_running_every_shot() {
+    use qasmsim::random::SeededRandom;
+
+    let circuit = "
+    u3(0.3, 0.2, 0.1) q[0];
+    h q[1];
+    cx q[0], q[2];
+    u3(1.1, 0.0, 0.5) q[2];
+    cx q[1], q[0];
+    measure q[0] -> c[1];
+    barrier q;
+    measure q[2] -> c[0];
+    measure q[0] -> c[2];
+    ";
+    let preamble = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg c[3];
+    ";
+    let fast = format!("{}{}", preamble, circuit);
+    // The conditional never holds but disables the sampling.
+    let slow = format!("{}if (c == 1) x q[0];{}", preamble, circuit);
+
+    let options = qasmsim::options::SimulationOptions::default();
+    let simulate = |source: &str| {
+        let program = qasmsim::parse_and_link(source).unwrap();
+        qasmsim::simulate_with_rng(&program, Some(500), &options, &mut SeededRandom::new(1107))
+            .unwrap()
+    };
+    let fast = simulate(&fast);
+    let slow = simulate(&slow);
+    assert_eq!(fast.histogram(), slow.histogram());
+    assert_eq!(fast.stats(), slow.stats());
+    assert_eq!(fast.memory(), slow.memory());
+    assert_eq!(fast.statevector(), slow.statevector());
+    assert_eq!(
+        fast.gate_application_count()["u3"],
+        slow.gate_application_count()["u3"]
+    );
+}
+
+#[test]
+fn test_replaying_a_trace_reproduces_the_computation() {
+    use qasmsim::random::Trace;
+
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg c[3];
+    h q;
+    measure q[0] -> c[0];
+    cx q[1], q[2];
+    if (c == 1) u3(0.4, 0.2, 0.1) q[1];
+    measure q[1] -> c[1];
+    ry(0.7) q[2];
+    measure q[2] -> c[2];
+    ";
+    let program = qasmsim::parse_and_link(source).unwrap();
+    let (computation, trace) = qasmsim::simulate_traced(&program).unwrap();
+    assert_eq!(trace.draws.len(), 3);
+
+    let trace = Trace::from_json(&trace.to_json()).unwrap();
+    let replayed = qasmsim::simulate_replay(&program, &trace).unwrap();
+    assert!(replayed
+        .statevector()
+        .amplitudes()
+        .eq(computation.statevector().amplitudes()));
+    assert_eq!(replayed.memory(), computation.memory());
+
+    let short = Trace::new(trace.draws[..2].to_vec());
+    assert_eq!(
+        qasmsim::simulate_replay(&program, &short),
+        Err(RuntimeError::TraceMismatch {
+            recorded: 2,
+            consumed: 3
+        })
+    );
+}
+
+#[test]
+fn test_traces_round_trip_random_numbers_exactly() {
+    use qasmsim::random::{RandomSource, SeededRandom, Trace};
+
+    let mut rng = SeededRandom::new(1108);
+    let draws: Vec<f64> = (0..100_000).map(|_| rng.next_f64()).collect();
+    let trace = Trace::from_json(&Trace::new(draws.clone()).to_json()).unwrap();
+    assert!(draws
+        .iter()
+        .zip(&trace.draws)
+        .all(|(draw, read)| draw.to_bits() == read.to_bits()));
+    assert_eq!(trace.draws.len(), draws.len());
+}
+
+#[test]
+fn test_traces_with_numbers_out_of_range_are_rejected() {
+    use qasmsim::random::{Trace, TraceError};
+
+    assert_eq!(
+        Trace::from_json(r#"{"version": 1, "draws": [1.5]}"#),
+        Err(TraceError::InvalidFormat)
+    );
+    assert_eq!(
+        Trace::from_json(r#"{"version": 1, "draws": [0.5, -0.25]}"#),
+        Err(TraceError::InvalidFormat)
+    );
+
+    let program = qasmsim::parse_and_link(
+        "
+    OPENQASM 2.0;
+    qreg q[1];
+    creg c[1];
+    measure q[0] -> c[0];
+    ",
+    )
+    .unwrap();
+    assert_eq!(
+        qasmsim::simulate_replay(&program, &Trace::new(vec![1.0])),
+        Err(RuntimeError::InvalidTrace {
+            reason: "the random number at 0 is 1, outside [0.0, 1.0)".into()
+        })
+    );
+}
+
+#[test]
+fn test_classical_registers_in_parameters_with_extensions() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[1];
+    x q[0];
+    measure q[0] -> c[0];
+    rx(c * pi) q[1];
+    ";
+    let options = qasmsim::options::SimulationOptions {
+        extensions: true,
+        ..Default::default()
+    };
+    let execution = qasmsim::QasmSim::new()
+        .with_options(options)
+        .run(source)
+        .unwrap();
+    assert!((execution.probabilities()[0b11] - 1.0).abs() < 1e-10);
+
+    let gate_definition = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[1];
+    creg c[1];
+    gate g a { rx(c) a; }
+    g q[0];
+    ";
+    let error = qasmsim::QasmSim::new()
+        .with_options(qasmsim::options::SimulationOptions {
+            extensions: true,
+            ..Default::default()
+        })
+        .run(gate_definition)
+        .expect_err("registers are not visible inside gate definitions");
+    assert!(matches!(
+        error,
+        qasmsim::QasmSimError::SymbolNotFound { .. }
+    ));
+
+    assert!(matches!(
+        qasmsim::run(source, None),
+        Err(qasmsim::QasmSimError::ClassicalRegisterInExpression { .. })
+    ));
+}
+
+#[test]
+fn test_conditional_block_is_equivalent_to_single_conditionals() {
+    let preamble = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[2];
+    x q[0];
+    h q[1];
+    measure q[0] -> c[0];
+    ";
+    let block_source = format!("{}if (c==1) {{ x q[0]; z q[1]; }}", preamble);
+    let single_source = format!("{}if (c==1) x q[0];\nif (c==1) z q[1];", preamble);
+
+    let options = qasmsim::options::SimulationOptions {
+        extensions: true,
+        ..Default::default()
+    };
+    let block_result = qasmsim::run_with_options(&block_source, None, &options).unwrap();
+    let single_result = qasmsim::run(&single_source, None).unwrap();
+    assert_approx_eq(block_result.statevector(), single_result.statevector());
+    assert_approx_eq(
+        block_result.statevector(),
+        &StateVector::from_amplitudes_with_validation(vec![
+            Complex::from(FRAC_1_SQRT_2),
+            Complex::from(0.0),
+            Complex::from(-FRAC_1_SQRT_2),
+            Complex::from(0.0),
+        ])
+        .unwrap(),
+    );
+}
+
+#[test]
+fn test_conditional_on_binary_literal() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg c[2];
+    x q[1];
+    measure q[0] -> c[0];
+    measure q[1] -> c[1];
+    if (c==0b10) x q[2];
+    ";
+    assert_approx_eq(
+        qasmsim::run(source, None).unwrap().statevector(),
+        &StateVector::from_amplitudes_with_validation(vec![
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(1.0),
+            Complex::from(0.0),
+        ])
+        .unwrap(),
+    );
+}
+
+#[test]
+fn test_run_and_sample_returns_every_shot() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[1];
+    creg d[1];
+    h q[0];
+    cx q[0], q[1];
+    measure q[0] -> c[0];
+    measure q[1] -> d[0];
+    ";
+    let samples = qasmsim::run_and_sample(source, 50).unwrap();
+    assert_eq!(samples.len(), 50);
+    for shot in samples {
+        assert_eq!(shot.len(), 2);
+        assert_eq!(shot["c"], shot["d"]);
+    }
+}
+
+#[test]
+fn test_streaming_shots_match_the_seeded_simulation() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[1];
+    creg d[1];
+    h q[0];
+    cx q[0], q[1];
+    measure q[0] -> c[0];
+    measure q[1] -> d[0];
+    ";
+    let shots: Vec<HashMap<String, u128>> = qasmsim::streaming_shots(source, Some(11))
+        .take(50)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(shots.len(), 50);
+    assert!(shots.iter().all(|shot| shot["c"] == shot["d"]));
+    assert!(shots.iter().any(|shot| shot["c"] == 0));
+    assert!(shots.iter().any(|shot| shot["c"] == 1));
+
+    let replayed: Vec<HashMap<String, u128>> = qasmsim::streaming_shots(source, Some(11))
+        .take(50)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(shots, replayed);
+}
+
+#[test]
+fn test_streaming_shots_end_after_an_error() {
+    let source = "
+    OPENQASM 2.0;
+    qreg q[1];
+    xxx q[0];
+    ";
+    let mut shots = qasmsim::streaming_shots(source, None);
+    assert!(shots.next().unwrap().is_err());
+    assert!(shots.next().is_none());
+
+    let mut shots = qasmsim::streaming_shots("OPENQASM 2.0; qreg", None);
+    assert!(shots.next().unwrap().is_err());
+    assert!(shots.next().is_none());
+}
+
+#[test]
+fn test_simulate_body_threads_the_state_across_calls() {
+    use qasmsim::{simulate_body, Session};
+
+    let mut session = Session::new();
+    simulate_body(
+        &mut session,
+        "
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[2];
+    h q[0];
+    ",
+    )
+    .unwrap();
+    let computation = simulate_body(&mut session, "cx q[0],q[1];").unwrap();
+    assert_approx_eq(
+        computation.statevector(),
+        &StateVector::from_complex_bases_unchecked(vec![
+            Complex::from(FRAC_1_SQRT_2),
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(FRAC_1_SQRT_2),
+        ]),
+    );
+    assert_eq!(computation.gate_application_count()["cx"], 1);
+
+    let computation = simulate_body(
+        &mut session,
+        "
+    include \"qelib1.inc\";
+    qreg r[1];
+    measure q -> c;
+    x r[0];
+    ",
+    )
+    .unwrap();
+    let value = computation.memory()["c"].0;
+    assert!(value == 0 || value == 3);
+    assert_eq!(computation.statevector().qubit_width(), 3);
+    let outcome = value as usize | 0b100;
+    assert!((computation.probabilities()[outcome] - 1.0).abs() < 1e-9);
+
+    let computation = simulate_body(&mut session, "if (c==3) x q[0];").unwrap();
+    assert_eq!(computation.memory()["c"].0, value);
+}
+
+#[test]
+fn test_simulate_body_failures_leave_the_session_untouched() {
+    use qasmsim::{simulate_body, Session};
+
+    let mut session = Session::new();
+    simulate_body(&mut session, "qreg q[1];\nU(pi, 0, pi) q[0];").unwrap();
+    let before = session.statevector().clone();
+    let statements = session.program().program.len();
+
+    assert!(simulate_body(&mut session, "U(pi, 0, pi) q[0];\nU(0, 0, 0) r[0];").is_err());
+    assert_eq!(session.statevector(), &before);
+    assert_eq!(session.program().program.len(), statements);
+}
+
+#[test]
+fn test_compile_custom_gate_inside_custom_gate() {
+    use qasmsim::PrimitiveOp;
+    use std::f64::consts::PI;
+
+    let source = "
+  OPENQASM 2.0;
+  gate u2(phi, lambda) q {
+    U(pi/2, phi, lambda) q;
+  }
+  gate h q {
+    u2(0, pi) q;
+  }
+  qreg q[2];
+  h q;
+  ";
+    let program = qasmsim::parse_and_link(source).unwrap();
+    let hadamard = |target| PrimitiveOp::U {
+        theta: PI / 2.0,
+        phi: 0.0,
+        lambda: PI,
+        target,
+    };
+    assert_eq!(
+        qasmsim::compile(&program).unwrap(),
+        vec![hadamard(0), hadamard(1)]
+    );
+}
+
+#[test]
+fn test_compile_keeps_conditionals_structured() {
+    use qasmsim::PrimitiveOp;
+
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[2];
+    measure q[0] -> c[1];
+    if (c==2) { CX q[0], q[1]; reset q[0]; }
+    ";
+    let program = qasmsim::parse_and_link_with_extensions(source).unwrap();
+    let circuit = qasmsim::compile(&program).unwrap();
+    assert_eq!(
+        circuit,
+        vec![
+            PrimitiveOp::Measure {
+                qubit: 0,
+                register: "c".into(),
+                bit: 1
+            },
+            PrimitiveOp::Conditional {
+                register: "c".into(),
+                bit: None,
+                value: 2,
+                body: vec![
+                    PrimitiveOp::Cx {
+                        control: 0,
+                        target: 1
+                    },
+                    PrimitiveOp::Reset { qubit: 0 },
+                ],
+            },
+        ]
+    );
+    let listing: Vec<String> = circuit.iter().map(|op| op.to_string()).collect();
+    assert_eq!(
+        listing,
+        vec!["measure 0 -> c[1]", "if (c==2) {\n  CX 0, 1\n  reset 0\n}"]
+    );
+}
+
+#[test]
+fn test_decomposed_program_simulates_as_the_original() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    gate entangle(theta) a, b {
+      ry(theta) a;
+      cx a, b;
+      t b;
+    }
+    qreg q[3];
+    creg c[2];
+    h q;
+    entangle(pi / 3) q[0], q[1];
+    u3(0.3, 1.2, -0.4) q[2];
+    barrier q[0], q[2];
+    if (c==0) ccx q[0], q[1], q[2];
+    rz(sqrt(2) / 7) q[1];
+    ";
+    let program = qasmsim::parse_and_link(source).unwrap();
+    let decomposed = qasmsim::decompose_to_qasm(&program).unwrap();
+    assert!(decomposed.starts_with("OPENQASM 2.0;\nqreg q[3];\ncreg c[2];\n"));
+    assert!(decomposed.contains("barrier q[0], q[2];\n"));
+    assert!(decomposed.contains("if (c==0) CX q[1], q[2];\n"));
+
+    let reparsed = qasmsim::parse_and_link(&decomposed).unwrap();
+    assert!(reparsed.gate_names().is_empty());
+    let gates: Vec<String> = reparsed
+        .program
+        .iter()
+        .filter_map(|span| match &*span.node {
+            Statement::QuantumOperation(qasmsim::grammar::ast::QuantumOperation::Unitary(
+                unitary,
+            )) => Some(unitary.0.to_string()),
+            _ => None,
+        })
+        .collect();
+    assert!(gates.iter().all(|name| name == "U" || name == "CX"));
+
+    let original = qasmsim::run(source, None).unwrap();
+    let roundtrip = qasmsim::run(&decomposed, None).unwrap();
+    assert_approx_eq(original.statevector(), roundtrip.statevector());
+}
+
+#[test]
+fn test_decomposed_program_keeps_measurements_and_resets() {
+    use qasmsim::QasmSim;
+
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[2];
+    h q[0];
+    cx q[0], q[1];
+    measure q -> c;
+    reset q[1];
+    if (c==3) x q[1];
+    ";
+    let program = qasmsim::parse_and_link(source).unwrap();
+    let decomposed = qasmsim::decompose_to_qasm(&program).unwrap();
+    let histogram = |source: &str| {
+        QasmSim::new()
+            .with_seed(5)
+            .with_shots(100)
+            .run(source)
+            .unwrap()
+            .histogram()
+            .clone()
+    };
+    assert_eq!(histogram(source), histogram(&decomposed));
+}
+
+#[test]
+fn test_measure_into_high_bits_of_wide_register() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[1];
+    creg c[70];
+    x q[0];
+    measure q[0] -> c[69];
+    ";
+
+    let result = qasmsim::run(source, None).unwrap();
+    assert_eq!(result.memory()["c"].0, 1_u128 << 69);
+
+    let option = qasmsim::options::Options {
+        format: qasmsim::options::Format::Json,
+        statevector: false,
+        probabilities: false,
+        show_bits: true,
+        ..Default::default()
+    };
+    let output = qasmsim::print_result(&result, &option).unwrap();
+    assert!(output.contains(r#""Int value": "590295810358705651712""#));
+    assert!(output.contains(r#""Hex value": "0x200000000000000000""#));
+    assert!(output.contains(&format!(r#""Bin value": "0b1{}""#, "0".repeat(69))));
+}
+
+#[test]
+fn test_condition_on_values_above_64_bits() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[70];
+    x q[0];
+    measure q[0] -> c[66];
+    if (c==0x40000000000000000) x q[1];
+    if (c==0x400000000000000000) x q[1];
+    ";
+
+    let result = qasmsim::run(source, None).unwrap();
+    assert_eq!(result.memory()["c"].0, 0x40000000000000000_u128);
+    assert_approx_eq(
+        result.statevector(),
+        &StateVector::from_complex_bases_unchecked(vec![
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(1.0),
+        ]),
+    );
+}
+
+#[test]
+fn test_conditional_on_wide_register() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[70];
+    x q[0];
+    measure q[0] -> c[0];
+    if (c==1) x q[1];
+    ";
+    let result = qasmsim::run(source, None).unwrap();
+    assert_approx_eq(
+        result.statevector(),
+        &StateVector::from_amplitudes_with_validation(vec![
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(1.0),
+        ])
+        .unwrap(),
+    );
+}
+
+#[test]
+fn test_missing_header_is_a_warning_when_not_strict() {
+    let source = "
+    include \"qelib1.inc\";
+    qreg q[1];
+    x q[0];
+    ";
+
+    let options = qasmsim::options::SimulationOptions::default();
+    let result = qasmsim::run_with_options(source, None, &options).unwrap();
+    assert_eq!(result.warnings().len(), 1);
+    assert_eq!(
+        result.warnings()[0].message,
+        "missing `OPENQASM 2.0;` header"
+    );
+    assert_approx_eq(
+        result.statevector(),
+        &StateVector::from_amplitudes_with_validation(vec![Complex::from(0.0), Complex::from(1.0)])
+            .unwrap(),
+    );
+
+    let options = qasmsim::options::SimulationOptions {
+        strict: true,
+        ..Default::default()
+    };
+    assert!(qasmsim::run_with_options(source, None, &options).is_err());
+}
+
+#[test]
+fn test_openqasm3_bell_circuit_matches_openqasm2() {
+    let openqasm3 = "
+    OPENQASM 3.0;
+    include \"qelib1.inc\";
+    qubit[2] q;
+    bit[2] c;
+    bit d;
+    h q[0];
+    cx q[0], q[1];
+    c = measure q;
+    d[0] = measure q[1];
+    reset q;
+    ";
+    let openqasm2 = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[2];
+    creg d[1];
+    h q[0];
+    cx q[0], q[1];
+    measure q -> c;
+    measure q[1] -> d[0];
+    reset q;
+    ";
+
+    let result3 = qasmsim::run(openqasm3, Some(100)).unwrap();
+    let result2 = qasmsim::run(openqasm2, Some(100)).unwrap();
+
+    let outcomes = |result: &qasmsim::Execution| {
+        let mut outcomes = HashMap::new();
+        for (name, (values, width)) in result.histogram().as_ref().unwrap() {
+            let values: Vec<u128> = values.iter().map(|(value, _)| *value).collect();
+            outcomes.insert(name.clone(), (values, *width));
+        }
+        outcomes
+    };
+    assert_eq!(outcomes(&result3), outcomes(&result2));
+    assert_eq!(outcomes(&result3)["c"], (vec![0b00, 0b11], 2));
+}
+
+#[test]
+fn test_openqasm3_measurement_assignments_are_warnings() {
+    let source = "
+    OPENQASM 3.0;
+    include \"qelib1.inc\";
+    qubit[2] q;
+    bit[2] c;
+    bit[2] d;
+    h q[0];
+    c = measure q;
+    measure q -> d;
+    ";
+    let declarations = "
+    OPENQASM 3.0;
+    include \"qelib1.inc\";
+    qubit[2] q;
+    bit[2] c;
+    h q[0];
+    ";
+
+    let result = qasmsim::run(source, None).unwrap();
+    assert_eq!(result.warnings().len(), 1);
+    assert_eq!(
+        result.warnings()[0].severity,
+        qasmsim::grammar::lint::LintSeverity::Warning
+    );
+    assert!(result.warnings()[0].message.contains("OPENQASM 3"));
+    assert!(qasmsim::run(declarations, None)
+        .unwrap()
+        .warnings()
+        .is_empty());
+}
+
+#[test]
+fn test_simulator_builder_honors_every_option() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    include \"coin.inc\";
+    qreg q[2];
+    creg c[2];
+    coin q;
+    measure q -> c;
+    ";
+    let includes = HashMap::from([("coin.inc".to_string(), "gate coin a { h a; }".to_string())]);
+    let simulator = qasmsim::QasmSim::new()
+        .with_shots(50)
+        .with_seed(7)
+        .with_mode(qasmsim::options::SimulationMode::Sequence)
+        .with_includes(includes)
+        .with_max_qubits(2);
+    let result = simulator.run(source).unwrap();
+    let sequences = result.sequences().as_ref().unwrap();
+    assert_eq!(sequences.len(), 50);
+    assert_eq!(result.histogram().as_ref().unwrap()["c"].0.len(), 4);
+    let again = simulator.run(source).unwrap();
+    assert_eq!(again.sequences(), result.sequences());
+
+    let error = simulator.with_max_qubits(1).run(source).unwrap_err();
+    assert!(matches!(
+        error,
+        qasmsim::QasmSimError::TooManyQubits {
+            qubits: 2,
+            max_qubits: 1,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn test_simulator_builder_options_and_aggregation_mode() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[1];
+    creg c[1];
+    if (c==0) {
+      x q[0];
+    }
+    measure q -> c;
+    ";
+    let options = qasmsim::options::SimulationOptions {
+        extensions: true,
+        ..Default::default()
+    };
+    let simulator = qasmsim::QasmSim::new().with_options(options).with_shots(10);
+    let result = simulator
+        .clone()
+        .with_mode(qasmsim::options::SimulationMode::Aggregation)
+        .run(source)
+        .unwrap();
+    assert_eq!(result.histogram().as_ref().unwrap()["c"].0, vec![(1, 10)]);
+    assert!(result.sequences().is_none());
+    assert!(qasmsim::QasmSim::new().run(source).is_err());
+    assert_eq!(
+        "unknown".parse::<qasmsim::options::SimulationMode>(),
+        Err(qasmsim::QasmSimError::InvalidMode("unknown".to_string()))
+    );
+}
+
+#[test]
+fn test_circuit_followed_by_its_inverse_is_the_identity() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg c[3];
+    gate entangle(theta) a, b {
+      ry(theta) a;
+      cx a, b;
+      t b;
+    }
+    h q;
+    entangle(pi / 3) q[0], q[1];
+    u3(0.3, 1.2, -0.4) q[2];
+    if (c==0) ccx q[0], q[1], q[2];
+    s q[1];
+    ";
+    let program = qasmsim::parse_and_link(source).unwrap();
+    let inverse = program.inverse().unwrap();
+    let declared = program.gate_names();
+    let mut combined = program.clone();
+    combined.program.extend(
+        inverse
+            .program
+            .into_iter()
+            .filter(|span| match &*span.node {
+                Statement::QRegDecl(..) | Statement::CRegDecl(..) => false,
+                Statement::GateDecl { signature, .. } => !declared.contains(&signature.0.as_str()),
+                _ => true,
+            }),
+    );
+    let computation = qasmsim::simulate(&combined).unwrap();
+    assert_approx_eq(computation.statevector(), &StateVector::new(3));
+}
+
+#[test]
+fn test_measurements_cannot_be_inverted() {
+    let source = "
+    OPENQASM 2.0;
+    qreg q[1];
+    creg c[1];
+    measure q -> c;
+    ";
+    let program = qasmsim::parse_and_link(source).unwrap();
+    assert!(matches!(
+        program.inverse(),
+        Err(InversionError::NonUnitaryOperation { symbol_name, .. }) if symbol_name == "measure"
+    ));
+}
+
+#[test]
+fn test_execution_summary_of_bell_state() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[2];
+    h q[0];
+    cx q[0], q[1];
+    ";
+    let execution = qasmsim::run(source, None).unwrap();
+    assert_eq!(
+        execution.to_string(),
+        "qubits: 2\nmost probable states:\n  |00⟩ 0.500000\n  |11⟩ 0.500000\nmemory:\n  c[2] = 0\n"
+    );
+}
+
+#[test]
+fn test_backends_produce_the_same_results() {
+    use qasmsim::options::{Backend, SimulationOptions};
+
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[5];
+    creg c[5];
+    h q[0];
+    cx q[0], q[4];
+    u3(0.3, 1.2, -0.4) q[2];
+    ccx q[0], q[2], q[3];
+    h q[0];
+    ";
+    let dense = qasmsim::run(source, None).unwrap();
+    for backend in [Backend::Sparse, Backend::Auto] {
+        let options = SimulationOptions {
+            backend,
+            ..Default::default()
+        };
+        let result = qasmsim::run_with_options(source, None, &options).unwrap();
+        assert_approx_eq(result.statevector(), dense.statevector());
+    }
+
+    let measured = format!("{}measure q -> c;\nif (c==1) x q[1];\n", source);
+    let simulate = |backend| {
+        qasmsim::QasmSim::new()
+            .with_options(SimulationOptions {
+                backend,
+                strict: true,
+                ..Default::default()
+            })
+            .with_seed(11)
+            .with_shots(20)
+            .run(&measured)
+            .unwrap()
+            .histogram()
+            .clone()
+    };
+    let expected = simulate(Backend::Dense);
+    assert_eq!(simulate(Backend::Sparse), expected);
+    assert_eq!(simulate(Backend::Auto), expected);
+}
+
+#[test]
+fn test_sparse_backend_simulates_wide_ghz_states() {
+    use qasmsim::options::{Backend, SimulationOptions};
+    use qasmsim::statevector::{QuantumState, State};
+
+    // A GHZ state of 36 qubits, whose dense state-vector takes 1 TiB.
+    let mut program =
+        String::from("OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[36];\ncreg c[36];\nh q[0];\n");
+    for qubit in 0..35 {
+        program.push_str(&format!("cx q[{}], q[{}];\n", qubit, qubit + 1));
+    }
+    let options = SimulationOptions {
+        backend: Backend::Sparse,
+        track_entanglement: Some(1),
+        density_qubits: Some(vec![0, 35]),
+        ..Default::default()
+    };
+    let result = qasmsim::run_with_options(&program, None, &options).unwrap();
+    match result.state() {
+        State::Sparse(state) => assert_eq!(state.nonzero_len(), 2),
+        _ => panic!("the state is not sparse"),
+    }
+    let marginals = result.state().marginal_outcomes(&[0, 35]);
+    assert_eq!(marginals.len(), 2);
+    assert_eq!((marginals[0].0, marginals[1].0), (0b00, 0b11));
+    assert!((marginals[0].1 - 0.5).abs() < 1e-10);
+    assert!((result.max_entanglement().unwrap() - 1.0).abs() < 1e-10);
+    let density = result.density_matrix().unwrap();
+    // Tracing out the qubits in between leaves a classical mixture.
+    assert!((density[0][0].re - 0.5).abs() < 1e-10);
+    assert!((density[3][3].re - 0.5).abs() < 1e-10);
+    assert!(density[0][3].norm() < 1e-10);
+    assert!(result.peak_memory_bytes() < 1 << 20);
+    assert!(format!("{}", result).contains(&format!("|{}⟩ 0.500000", "1".repeat(36))));
+
+    let measured = format!("{}measure q -> c;\n", program);
+    let result = qasmsim::QasmSim::new()
+        .with_options(SimulationOptions {
+            backend: Backend::Sparse,
+            ..Default::default()
+        })
+        .with_seed(5)
+        .with_shots(100)
+        .run(&measured)
+        .unwrap();
+    let (values, _) = &result.histogram().as_ref().unwrap()["c"];
+    let all_ones = (1 << 36) - 1;
+    assert!(values
+        .iter()
+        .all(|(value, _)| *value == 0 || *value == all_ones));
+    assert_eq!(values.iter().map(|(_, count)| count).sum::<usize>(), 100);
+}
+
+#[test]
+fn test_native_pauli_gates_match_their_definitions() {
+    use qasmsim::options::{Backend, SimulationOptions};
+
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    h q[0];
+    z q[0];
+    y q[1];
+    h q[2];
+    y q[2];
+    z q[2];
+    ";
+    // The Pauli gates are rotations of pi up to a global phase.
+    let rotations = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    h q[0];
+    rz(pi) q[0];
+    ry(pi) q[1];
+    h q[2];
+    ry(pi) q[2];
+    rz(pi) q[2];
+    ";
+    for backend in [Backend::Dense, Backend::Sparse, Backend::Auto] {
+        let options = SimulationOptions {
+            backend,
+            ..Default::default()
+        };
+        let native = qasmsim::run_with_options(source, None, &options).unwrap();
+        let reference = qasmsim::run_with_options(rotations, None, &options).unwrap();
+        assert_approx_eq_phase(native.statevector(), reference.statevector());
+
+        let count = native.gate_application_count();
+        assert_eq!(count["y"], 2);
+        assert_eq!(count["z"], 2);
+        assert_eq!(count["u3"], 2);
+        assert_eq!(count["u1"], 2);
+        assert_eq!(count["U"], 6);
+    }
+}
+
+#[test]
+fn test_single_precision_matches_double_precision() {
+    use qasmsim::options::{Precision, SimulationOptions};
+
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[3];
+    creg c[3];
+    h q[0];
+    cx q[0], q[1];
+    rx(0.3) q[2];
+    cu3(0.2, 1.1, -0.5) q[1], q[2];
+    ";
+    let single = SimulationOptions {
+        precision: Precision::Single,
+        ..Default::default()
+    };
+    let single_result = qasmsim::run_with_options(source, None, &single).unwrap();
+    let double_result = qasmsim::run(source, None).unwrap();
+    assert_approx_eq_with(
+        single_result.statevector(),
+        double_result.statevector(),
+        ComplexMargin::epsilon(1e-6),
+    );
+    for (p1, p2) in single_result
+        .probabilities()
+        .iter()
+        .zip(double_result.probabilities())
+    {
+        assert!((p1 - p2).abs() < 1e-6);
+    }
+
+    let measured = format!("{}measure q -> c;\n", source);
+    let histogram = |options: &SimulationOptions| {
+        qasmsim::QasmSim::new()
+            .with_options(options.clone())
+            .with_seed(3)
+            .with_shots(50)
+            .run(&measured)
+            .unwrap()
+            .histogram()
+            .clone()
+    };
+    assert_eq!(histogram(&single), histogram(&SimulationOptions::default()));
+}
+
+#[test]
+fn test_simulate_with_registered_library() {
+    use qasmsim::{register_library, GateLibrary};
+
+    let library = GateLibrary::from_qasm_string(
+        "
+    gate my_h a { U(pi/2, 0, pi) a; }
+    gate bell a, b { my_h a; CX a, b; }
+    ",
+    )
+    .unwrap();
+    let source = "
+    OPENQASM 2.0;
+    qreg q[2];
+    bell q[0], q[1];
+    ";
+    let mut program = qasmsim::parse_and_link(source).unwrap();
+    register_library(&mut program, library).unwrap();
+    let computation = qasmsim::simulate(&program).unwrap();
+    assert_approx_eq(
+        computation.statevector(),
+        &StateVector::from_complex_bases_unchecked(vec![
+            Complex::from(FRAC_1_SQRT_2),
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(FRAC_1_SQRT_2),
+        ]),
+    );
+}
+
+/// Return a random program of `length` gates on 3 qubits, likely to have
+/// gates undoing each other.
+fn random_circuit(seed: u64, length: usize) -> String {
+    let mut state = seed
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+    let mut next = move |bound: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % bound as u64) as usize
+    };
+    let gates = [
+        ("h", "h"),
+        ("x", "x"),
+        ("y", "y"),
+        ("s", "sdg"),
+        ("tdg", "t"),
+        ("U(0.3, 1.1, -0.7)", "U(-0.3, 0.7, -1.1)"),
+        ("U(pi/2, 0, pi)", "U(-pi/2, -pi, 0)"),
+    ];
+    let mut source = String::from("OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[3];\n");
+    let mut pending = Vec::new();
+    for _ in 0..length {
+        match next(4) {
+            0 if !pending.is_empty() => {
+                let line: String = pending.pop().unwrap();
+                source.push_str(&line);
+            }
+            1 => {
+                let (control, target) = (next(3), next(3));
+                if control != target {
+                    source.push_str(&format!("cx q[{}], q[{}];\n", control, target));
+                    pending.push(format!("CX q[{}], q[{}];\n", control, target));
+                }
+            }
+            _ => {
+                let (gate, inverse) = gates[next(gates.len())];
+                let qubit = next(3);
+                source.push_str(&format!("{} q[{}];\n", gate, qubit));
+                pending.push(format!("{} q[{}];\n", inverse, qubit));
+            }
+        }
+    }
+    source
+}
+
+#[test]
+fn test_optimized_circuits_simulate_as_the_original() {
+    let mut cancelled = 0;
+    for seed in 0..50 {
+        let source = random_circuit(seed, 40);
+        let program = qasmsim::parse_and_link(&source).unwrap();
+        let optimized = qasmsim::optimize_circuit(&program);
+        assert!(optimized.program.len() <= program.program.len());
+        cancelled += program.program.len() - optimized.program.len();
+        assert_approx_eq_with(
+            qasmsim::simulate(&program).unwrap().statevector(),
+            qasmsim::simulate(&optimized).unwrap().statevector(),
+            ComplexMargin::epsilon(1e-12),
+        );
+    }
+    assert!(cancelled > 0);
+}
+
+#[test]
+fn test_optimization_does_not_cancel_across_other_operations() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    gate h a { U(0, 0, 0) a; }
+    qreg q[2];
+    creg c[2];
+    x q[0];
+    measure q[0] -> c[0];
+    x q[0];
+    h q[1];
+    h q[1];
+    cx q[0], q[1];
+    if (c==1) x q[1];
+    cx q[0], q[1];
+    CX q[0], q[1];
+    CX q[1], q[0];
+    ";
+    let program = qasmsim::parse_and_link(source).unwrap();
+    let optimized = qasmsim::optimize_circuit(&program);
+    assert_eq!(optimized, program);
+}
+
+#[test]
+fn test_json_output_with_shots_is_a_single_document() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[2];
+    h q[0];
+    cx q[0], q[1];
+    measure q -> c;
+    ";
+    let options = qasmsim::options::Options::builder()
+        .format(qasmsim::options::Format::Json)
+        .shots(20)
+        .build();
+    let execution = qasmsim::run(source, Some(20)).unwrap();
+    let output = qasmsim::print_result(&execution, &options).unwrap();
+    assert!(output.starts_with('{'));
+    let document: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert!(document["Memory"].is_object());
+}
+
+#[test]
+fn test_program_registers_in_declaration_order() {
+    let program = qasmsim::parse_and_link(
+        "
+    OPENQASM 2.0;
+    qreg q[2];
+    creg c[2];
+    creg d[3];
+    ",
+    )
+    .unwrap();
+    assert_eq!(
+        qasmsim::program_registers(&program),
+        (
+            vec![("q".to_string(), 2)],
+            vec![("c".to_string(), 2), ("d".to_string(), 3)]
+        )
+    );
+}
+
+#[test]
+fn test_run_mode_keeps_the_results_of_each_mode() {
+    use qasmsim::options::SimulationMode;
+
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[2];
+    x q[1];
+    measure q -> c;
+    ";
+    for mode in [
+        SimulationMode::Aggregation,
+        SimulationMode::Min,
+        SimulationMode::Max,
+    ] {
+        let result = qasmsim::run_mode(source, Some(5), mode).unwrap();
+        assert_eq!(
+            result.stats(),
+            &Some(HashMap::from([("10".to_string(), 5)]))
+        );
+        assert_eq!(result.sequences(), &None);
+    }
+
+    let result = qasmsim::run_mode(source, Some(3), SimulationMode::Sequence).unwrap();
+    assert_eq!(
+        result.sequences(),
+        &Some(vec![HashMap::from([("c".to_string(), 0b10)]); 3])
+    );
+
+    let result = qasmsim::run_mode(source, None, SimulationMode::Sequence).unwrap();
+    assert_eq!(result.sequences(), &None);
+    assert_eq!(result.memory()["c"].0, 0b10);
+}
+
+#[test]
+fn test_postselecting_one_qubit_of_a_bell_pair_collapses_the_other() {
+    let program = qasmsim::parse_and_link(
+        "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    h q[0];
+    cx q[0], q[1];
+    ",
+    )
+    .unwrap();
+    let statevector =
+        qasmsim::simulate_postselected(&program, &[("q".to_string(), 0, false)]).unwrap();
+    assert_approx_eq(
+        &statevector,
+        &StateVector::from_complex_bases_unchecked(vec![
+            Complex::from(1.0),
+            Complex::from(0.0),
+            Complex::from(0.0),
+            Complex::from(0.0),
+        ]),
+    );
+}
+
+#[test]
+fn test_peak_memory_is_measured_only_with_memory_tracking() {
+    let source = "
+    OPENQASM 2.0;
+    qreg q[12];
+    ";
+    let peak_memory_bytes = qasmsim::run(source, None)
+        .unwrap()
+        .times()
+        .peak_memory_bytes();
+    if cfg!(feature = "memory-tracking") {
+        let amplitudes_bytes = (1 << 12) * std::mem::size_of::<Complex>();
+        assert!(peak_memory_bytes.unwrap() >= amplitudes_bytes);
+    } else {
+        assert_eq!(peak_memory_bytes, None);
+    }
+}
+
+#[cfg(feature = "memory-tracking")]
+#[test]
+fn test_single_precision_halves_the_peak_memory() {
+    use qasmsim::options::{Precision, SimulationOptions};
+    use qasmsim::statevector::{Complex32, State};
+
+    let source = "
+    OPENQASM 2.0;
+    qreg q[22];
+    U(pi/2, 0, pi) q[0];
+    CX q[0], q[21];
+    ";
+    let run = |precision| {
+        qasmsim::QasmSim::new()
+            .with_options(SimulationOptions {
+                precision,
+                ..Default::default()
+            })
+            .run(source)
+            .unwrap()
+    };
+    let amplitudes_bytes = (1 << 22) * std::mem::size_of::<Complex32>();
+
+    let single = run(Precision::Single);
+    assert!(matches!(single.state(), State::Single(_)));
+    let single_peak = single.times().peak_memory_bytes().unwrap();
+    // No copy of the state in double precision is made.
+    assert!(single_peak >= amplitudes_bytes);
+    assert!(single_peak < amplitudes_bytes * 5 / 4);
+    drop(single);
+
+    let double_peak = run(Precision::Double).times().peak_memory_bytes().unwrap();
+    assert!(double_peak >= 2 * amplitudes_bytes);
+}
+
+#[test]
+fn test_inv_modifier_on_t_is_tdg() {
+    let modified = qasmsim::grammar::expand_modifiers(
+        "
+  OPENQASM 3;
+  include \"qelib1.inc\";
+  qreg q[1];
+  h q[0];
+  inv @ t q[0];
+  ",
+    )
+    .unwrap();
+    let expected = "
+  OPENQASM 3;
+  include \"qelib1.inc\";
+  qreg q[1];
+  h q[0];
+  tdg q[0];
+  ";
+    assert_approx_eq(
+        qasmsim::run(&modified, None).unwrap().statevector(),
+        qasmsim::run(expected, None).unwrap().statevector(),
+    )
+}
+
+#[test]
+fn test_ctrl_modifier_on_x_entangles() {
+    let modified = qasmsim::grammar::expand_modifiers(
+        "
+  OPENQASM 3;
+  include \"qelib1.inc\";
+  qreg q[2];
+  h q[0];
+  ctrl @ x q[0], q[1];
+  ",
+    )
+    .unwrap();
+    assert_approx_eq(
+        qasmsim::run(&modified, None).unwrap().statevector(),
+        &StateVector::bell_state(0),
+    )
+}
+
+#[test]
+fn test_print_frequencies_of_a_bell_circuit() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[2];
+    h q[0];
+    cx q[0], q[1];
+    measure q -> c;
+    ";
+    let execution = qasmsim::QasmSim::new()
+        .with_shots(1000)
+        .with_seed(7)
+        .run(source)
+        .unwrap();
+
+    let options = qasmsim::options::Options::builder()
+        .format(qasmsim::options::Format::Json)
+        .shots(1000)
+        .frequencies(true)
+        .build();
+    let output = qasmsim::print_result(&execution, &options).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(json["Memory"], serde_json::json!({ "00": 540, "11": 460 }));
+    let interval = |key: &str| {
+        let frequency = &json["Frequencies"][key];
+        [
+            frequency["Frequency"].as_f64().unwrap(),
+            frequency["Lower"].as_f64().unwrap(),
+            frequency["Upper"].as_f64().unwrap(),
+        ]
+    };
+    for (actual, expected) in interval("00").iter().zip([0.54, 0.509015, 0.570679]) {
+        assert!((actual - expected).abs() < 1e-6);
+    }
+    for (actual, expected) in interval("11").iter().zip([0.46, 0.429321, 0.490985]) {
+        assert!((actual - expected).abs() < 1e-6);
+    }
+
+    let options = qasmsim::options::Options::builder()
+        .shots(1000)
+        .frequencies(true)
+        .build();
+    let output = qasmsim::print_result(&execution, &options).unwrap();
+    assert!(output.contains("| Count | Frequency |      95% CI      |"));
+    assert!(output.contains("|   540 |    0.5400 | [0.5090, 0.5707] |"));
+    assert!(output.contains("|   460 |    0.4600 | [0.4293, 0.4910] |"));
+}
+
+#[test]
+fn test_tee_writes_the_same_result_into_both_sinks() {
+    let source = "
+    OPENQASM 2.0;
+    include \"qelib1.inc\";
+    qreg q[2];
+    creg c[2];
+    h q[0];
+    cx q[0], q[1];
+    measure q -> c;
+    ";
+    let options = qasmsim::options::Options::builder().shots(10).build();
+    let result = qasmsim::run(source, options.shots).unwrap();
+
+    let mut sink = qasmsim::Tee::new(Vec::new(), Vec::new());
+    qasmsim::write_result(&mut sink, &result, &options).unwrap();
+    let (file, stdout) = sink.into_inner();
+    assert_eq!(file, stdout);
+    assert_eq!(
+        file,
+        qasmsim::print_result(&result, &options)
+            .unwrap()
+            .into_bytes()
+    );
+}
@@ -2,6 +2,9 @@
 
 extern crate qasmsim;
 
+use std::collections::HashMap;
+use std::time::Duration;
+
 use indoc::indoc;
 
 use qasmsim::{QasmSimError, QasmType};
@@ -21,9 +24,56 @@ fn test_calling_a_non_existing_gate() {
         QasmSimError::UndefinedGate {
             source: "xxx q;\n",
             symbol_name: "xxx".into(),
-            lineno: 3
+            lineno: 3,
+            suggestion: None
+        }
+    );
+}
+
+#[test]
+fn test_calling_a_qelib1_gate_without_including_it() {
+    let source = indoc!(
+        "
+  OPENQASM 2.0;
+  qreg q[2];
+  cx q[0], q[1];
+  "
+    );
+    let error = qasmsim::run(source, None).expect_err("should fail");
+    let suggestion = "gate 'cx' is defined in \"qelib1.inc\"; add `include \"qelib1.inc\";`";
+    assert_eq!(
+        error,
+        QasmSimError::UndefinedGate {
+            source: "cx q[0], q[1];\n",
+            symbol_name: "cx".into(),
+            lineno: 3,
+            suggestion: Some(suggestion.into())
         }
     );
+    assert!(format!("{}", error).contains(suggestion));
+}
+
+#[test]
+fn test_calling_a_gate_by_its_common_name() {
+    let source = indoc!(
+        "
+  OPENQASM 2.0;
+  include \"qelib1.inc\";
+  qreg q[2];
+  hadamard q[0];
+  "
+    );
+    let error = qasmsim::run(source, None).expect_err("should fail");
+    assert_eq!(
+        error,
+        QasmSimError::UndefinedGate {
+            source: "hadamard q[0];\n",
+            symbol_name: "hadamard".into(),
+            lineno: 4,
+            suggestion: Some("did you mean `h`?".into())
+        }
+    );
+    assert!(format!("{}", error).contains("did you mean `h`?"));
 }
 
 #[test]
@@ -229,6 +279,30 @@ fn test_index_out_of_bounds() {
     );
 }
 
+#[test]
+fn test_bit_conditional_index_out_of_bounds() {
+    let source = indoc!(
+        r#"
+  OPENQASM 2.0;
+  include "qelib1.inc";
+  qreg q[2];
+  creg c[2];
+  if (c[2]==1) x q[0];
+  "#
+    );
+    let error = qasmsim::parse_and_link_with_extensions(source).expect_err("should fail");
+    assert_eq!(
+        error,
+        QasmSimError::IndexOutOfBounds {
+            source: "if (c[2]==1) x q[0];\n",
+            symbol_name: "c".into(),
+            lineno: 5,
+            size: 2,
+            index: 2
+        }
+    );
+}
+
 #[test]
 fn test_argument_expansion_with_different_size_registers() {
     let source = indoc!(
@@ -339,3 +413,437 @@ fn test_include_non_existent_lib() {
         }
     );
 }
+
+#[test]
+fn test_include_library_redefining_a_gate() {
+    let source = indoc!(
+        r#"
+  OPENQASM 2.0;
+  include "qelib1.inc";
+  include "mygates.inc";
+  qreg q[1];
+  h q[0];
+  "#
+    );
+    let redefining = HashMap::from_iter(vec![(
+        "mygates.inc".to_owned(),
+        "gate h a { U(pi/2, 0, pi) a; }".to_owned(),
+    )]);
+    let error = qasmsim::QasmSim::new()
+        .with_includes(redefining)
+        .run(source)
+        .expect_err("should fail");
+    assert_eq!(
+        error,
+        QasmSimError::GateRedefinition {
+            source: "include \"mygates.inc\";\n",
+            lineno: 3,
+            symbol_name: "h".into(),
+            libpath: "mygates.inc".into(),
+            previous_libpath: "qelib1.inc".into()
+        }
+    );
+    assert_eq!(
+        format!("{}", error),
+        "error: library `mygates.inc` redefines gate `h`, already defined in `qelib1.inc`\n  |\n3 | include \"mygates.inc\";\n  | ^ help: rename the gate in one of the libraries or define it as in `qelib1.inc`\n"
+    );
+
+    let repeating = HashMap::from_iter(vec![(
+        "mygates.inc".to_owned(),
+        "// The same Hadamard.\ngate h a { u2(0,pi) a; }".to_owned(),
+    )]);
+    let execution = qasmsim::QasmSim::new()
+        .with_includes(repeating)
+        .run(source)
+        .expect("identical definitions are merged");
+    assert!((execution.probabilities()[1] - 0.5).abs() < 1e-10);
+}
+
+#[test]
+fn test_include_malformed_library() {
+    let source = indoc!(
+        r#"
+  OPENQASM 2.0;
+  include "mylib.inc";
+  qreg q[1];
+  "#
+    );
+    let malformed = HashMap::from_iter(vec![(
+        "mylib.inc".to_owned(),
+        "gate broken q { U(0,0,0) q".to_owned(),
+    )]);
+    let error = qasmsim::QasmSim::new()
+        .with_includes(malformed)
+        .run(source)
+        .expect_err("should fail");
+    assert_eq!(
+        error,
+        QasmSimError::InvalidLibrary {
+            source: "include \"mylib.inc\";\n",
+            lineno: 2,
+            libpath: "mylib.inc".into(),
+            library_lineno: 1,
+            library_column: 27,
+            reason: "expected \";\", or \"[\", found EOF".into()
+        }
+    );
+    assert_eq!(
+        format!("{}", error),
+        "error: cannot parse library `mylib.inc`: expected \";\", or \"[\", found EOF at line 1, column 27\n  |\n2 | include \"mylib.inc\";\n  | ^ help: fix the syntax of `mylib.inc`\n"
+    );
+}
+
+#[test]
+fn test_apply_opaque_gate() {
+    let source = indoc!(
+        r#"
+  OPENQASM 2.0;
+  opaque gate magic q;
+  qreg q[2];
+  magic q[0];
+  "#
+    );
+    let error = qasmsim::run(source, None).expect_err("should fail");
+    assert_eq!(
+        error,
+        QasmSimError::OpaqueGateNotSimulatable {
+            source: "magic q[0];\n",
+            lineno: 4,
+            symbol_name: "magic".into()
+        }
+    );
+}
+
+#[test]
+fn test_cx_on_the_same_qubit_fails_on_every_backend() {
+    use qasmsim::options::{Backend, Precision, SimulationOptions};
+
+    let source = indoc!(
+        r#"
+  OPENQASM 2.0;
+  include "qelib1.inc";
+  qreg q[2];
+  h q[0];
+  cx q[0], q[0];
+  "#
+    );
+    for (backend, precision) in [
+        (Backend::Dense, Precision::Double),
+        (Backend::Sparse, Precision::Double),
+        (Backend::Auto, Precision::Double),
+        (Backend::Dense, Precision::Single),
+    ] {
+        let options = SimulationOptions {
+            backend,
+            precision,
+            ..Default::default()
+        };
+        let error = qasmsim::run_with_options(source, None, &options).expect_err("should fail");
+        assert_eq!(
+            error,
+            QasmSimError::RepeatedQubit {
+                source: "cx q[0], q[0];\n",
+                lineno: 5,
+                symbol_name: "CX".into()
+            }
+        );
+    }
+}
+
+#[test]
+fn test_division_by_zero_in_parameters() {
+    let source = indoc!(
+        r#"
+  OPENQASM 2.0;
+  include "qelib1.inc";
+  qreg q[2];
+  u1(pi/(1-1)) q;
+  "#
+    );
+    let error = qasmsim::run(source, None).expect_err("should fail");
+    assert_eq!(
+        error,
+        QasmSimError::DivisionByZero {
+            source: "u1(pi/(1-1)) q;\n",
+            lineno: 4
+        }
+    );
+}
+
+#[test]
+fn test_classical_register_too_wide() {
+    let source = indoc!(
+        "
+    OPENQASM 2.0;
+    creg c[129];
+    "
+    );
+    let error = qasmsim::run(source, None).expect_err("should fail");
+    assert_eq!(
+        error,
+        QasmSimError::RegisterTooWide {
+            source: "creg c[129];\n",
+            symbol_name: "c".into(),
+            lineno: 2,
+            size: 129,
+            max_size: 128,
+        }
+    );
+}
+
+#[test]
+fn test_simulation_timeout() {
+    let source = indoc!(
+        "
+  OPENQASM 2.0;
+  include \"qelib1.inc\";
+  qreg q[16];
+  h q;
+  "
+    );
+    let error = qasmsim::run_with_timeout(source, Some(10_000), Duration::from_millis(10))
+        .expect_err("should time out");
+    match error {
+        QasmSimError::SimulationTimeout { elapsed_ms } => assert!(elapsed_ms >= 10),
+        _ => panic!("expected a timeout, got {:?}", error),
+    }
+    assert!(format!("{}", error).starts_with("error: simulation timed out after"));
+}
+
+#[test]
+fn test_runtime_errors_are_reported_before_the_timeout() {
+    let source = indoc!(
+        "
+  OPENQASM 2.0;
+  qreg q[2];
+  xxx q;
+  "
+    );
+    let error =
+        qasmsim::run_with_timeout(source, None, Duration::from_secs(60)).expect_err("should fail");
+    assert_eq!(
+        error,
+        QasmSimError::UndefinedGate {
+            source: "xxx q;\n",
+            symbol_name: "xxx".into(),
+            lineno: 3,
+            suggestion: None
+        }
+    );
+}
+
+#[test]
+fn test_zero_width_register() {
+    let source = indoc!(
+        "
+  OPENQASM 2.0;
+  qreg q[0];
+  "
+    );
+    let error = qasmsim::run(source, None).expect_err("should fail");
+    assert_eq!(
+        error,
+        QasmSimError::EmptyRegister {
+            source: "qreg q[0];\n",
+            symbol_name: "q".into(),
+            lineno: 2
+        }
+    );
+    assert_eq!(
+        format!("{}", error),
+        "error: register `q` has size 0\n  |\n2 | qreg q[0];\n  | ^ help: registers must have at least one element\n"
+    );
+}
+
+#[test]
+fn test_too_many_qubits() {
+    let source = indoc!(
+        "
+  OPENQASM 2.0;
+  qreg q[2];
+  qreg r[2];
+  "
+    );
+    let error = qasmsim::QasmSim::new()
+        .with_max_qubits(3)
+        .run(source)
+        .expect_err("should fail");
+    assert_eq!(
+        error,
+        QasmSimError::TooManyQubits {
+            source: "qreg r[2];\n",
+            lineno: 3,
+            qubits: 4,
+            max_qubits: 3
+        }
+    );
+    assert_eq!(
+        format!("{}", error),
+        "error: the program declares 4 qubits but the limit is 3\n  |\n3 | qreg r[2];\n  | ^ help: reduce the size of the quantum registers or raise the limit\n"
+    );
+}
+
+#[test]
+fn test_classical_register_in_parameter_expression() {
+    let source = indoc!(
+        "
+  OPENQASM 2.0;
+  qreg q[1];
+  creg c[2];
+  U(c * pi, 0, 0) q[0];
+  "
+    );
+    let error = qasmsim::run(source, None).expect_err("should fail");
+    assert_eq!(
+        error,
+        QasmSimError::ClassicalRegisterInExpression {
+            source: "U(c * pi, 0, 0) q[0];\n",
+            lineno: 4,
+            symbol_name: "c".into()
+        }
+    );
+    assert_eq!(
+        format!("{}", error),
+        "error: cannot use the classical register `c` in a parameter expression\n  |\n4 | U(c * pi, 0, 0) q[0];\n  | ^ help: OPENQASM 2.0 does not allow it; enable the extensions to use the value of the register\n"
+    );
+}
+
+#[test]
+fn test_owned_errors_chain_the_layer_errors() {
+    use std::error::Error;
+
+    use qasmsim::error::{OwnedQasmSimError, RuntimeError, SemanticError};
+
+    let source = indoc!(
+        "
+  OPENQASM 2.0;
+  qreg q[2];
+  qreg q[1];
+  "
+    );
+    let program = qasmsim::parse_and_link(source).unwrap();
+    let runtime_error = qasmsim::simulate(&program).expect_err("should fail");
+    let stored: Box<dyn Error + Send + Sync> =
+        Box::new(OwnedQasmSimError::from((source, runtime_error)));
+
+    let mut chain = vec![stored.to_string()];
+    let mut cause = stored.source();
+    while let Some(error) = cause {
+        chain.push(error.to_string());
+        cause = error.source();
+    }
+    assert_eq!(chain.len(), 3);
+    assert!(chain[0].contains("qreg q[1];"));
+    assert!(stored
+        .source()
+        .unwrap()
+        .downcast_ref::<RuntimeError>()
+        .is_some());
+    assert!(stored
+        .source()
+        .and_then(Error::source)
+        .unwrap()
+        .downcast_ref::<SemanticError>()
+        .is_some());
+}
+
+#[test]
+fn test_run_errors_outlive_the_source() {
+    let stored: Box<dyn std::error::Error + Send + Sync> = {
+        let source = String::from("OPENQASM 2.0;\nqreg q[2];\nxxx q;\n");
+        Box::new(qasmsim::run(&source, None).unwrap_err().into_owned())
+    };
+    assert!(stored.to_string().contains("xxx q;"));
+}
+
+#[test]
+fn test_zero_shots_are_rejected() {
+    let source = "OPENQASM 2.0;\nqreg q[1];\ncreg c[1];\nmeasure q -> c;\n";
+    assert_eq!(
+        qasmsim::run(source, Some(0)).unwrap_err(),
+        QasmSimError::InvalidShots { shots: 0 }
+    );
+
+    let program = qasmsim::parse_and_link(source).unwrap();
+    assert_eq!(
+        qasmsim::simulate_with_shots(&program, 0).unwrap_err(),
+        qasmsim::error::RuntimeError::InvalidShots { shots: 0 }
+    );
+    assert_eq!(
+        qasmsim::simulate_weighted(&program, 0, |_| 1.0).unwrap_err(),
+        qasmsim::error::RuntimeError::InvalidShots { shots: 0 }
+    );
+    assert_eq!(
+        qasmsim::run_and_sample(source, 0).unwrap_err(),
+        QasmSimError::InvalidShots { shots: 0 }
+    );
+}
+
+#[test]
+fn test_postselecting_impossible_outcomes() {
+    use qasmsim::error::RuntimeError;
+
+    let program = qasmsim::parse_and_link("OPENQASM 2.0;\nqreg q[2];\n").unwrap();
+    assert_eq!(
+        qasmsim::simulate_postselected(&program, &[("q".to_string(), 1, true)]).unwrap_err(),
+        RuntimeError::InvalidPostselection {
+            reason: "outcome 1 of `q[1]` has probability 0".to_string()
+        }
+    );
+    assert_eq!(
+        qasmsim::simulate_postselected(&program, &[("q".to_string(), 2, false)]).unwrap_err(),
+        RuntimeError::InvalidPostselection {
+            reason: "qubit 2 is out of the 2 qubits of register `q`".to_string()
+        }
+    );
+    assert_eq!(
+        qasmsim::simulate_postselected(&program, &[("r".to_string(), 0, false)]).unwrap_err(),
+        RuntimeError::InvalidPostselection {
+            reason: "there is no quantum register `r`".to_string()
+        }
+    );
+}
+
+#[test]
+fn test_printing_shots_of_a_simulation_without_shots_is_an_error() {
+    use qasmsim::options::{Format, Options, SimulationMode};
+
+    let execution = qasmsim::run("OPENQASM 2.0;\nqreg q[1];\ncreg c[1];\n", None).unwrap();
+    for format in [Format::Tabular, Format::Json] {
+        for mode in [SimulationMode::Aggregation, SimulationMode::Sequence] {
+            let options = Options::builder()
+                .format(format.clone())
+                .shots(10)
+                .mode(mode)
+                .build();
+            assert!(matches!(
+                qasmsim::print_result(&execution, &options),
+                Err(QasmSimError::MalformedResult { .. })
+            ));
+        }
+    }
+}
+
+#[test]
+fn test_tracking_the_entanglement_of_too_wide_cuts() {
+    use qasmsim::error::RuntimeError;
+    use qasmsim::options::SimulationOptions;
+
+    let program = qasmsim::parse_and_link("OPENQASM 2.0;\nqreg q[20];\n").unwrap();
+    let options = SimulationOptions {
+        track_entanglement: Some(10),
+        ..Default::default()
+    };
+    assert_eq!(
+        qasmsim::simulate_with_options(&program, None, &options).unwrap_err(),
+        RuntimeError::InvalidEntanglementCut {
+            reason: "the smallest side of the bipartition has 10 qubits, more than 8".to_string()
+        }
+    );
+
+    let options = SimulationOptions {
+        track_entanglement: Some(15),
+        ..Default::default()
+    };
+    assert!(qasmsim::simulate_with_options(&program, None, &options).is_ok());
+}
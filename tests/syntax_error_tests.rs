@@ -186,3 +186,264 @@ fn test_missing_arrow() {
         }
     );
 }
+
+#[test]
+fn test_bit_conditional_requires_extensions() {
+    let source = indoc!(
+        "
+    OPENQASM 2.0;
+    qreg q[1];
+    creg c[2];
+    if (c[1]==1) U(pi, 0, pi) q[0];
+  "
+    );
+    let err = qasmsim::run(source, None).unwrap_err();
+    assert_eq!(
+        err,
+        QasmSimError::UnexpectedToken {
+            source: "if (c[1]==1) U(pi, 0, pi) q[0];\n",
+            lineno: 4,
+            startpos: 5,
+            endpos: Some(6),
+            token: Some(Tok::LBracket),
+            expected: vec!["\"==\"".into()]
+        }
+    );
+}
+
+#[test]
+fn test_conditional_block_requires_extensions() {
+    let source = indoc!(
+        "
+    OPENQASM 2.0;
+    qreg q[1];
+    creg c[1];
+    if (c==1) { U(pi, 0, pi) q[0]; }
+  "
+    );
+    let err = qasmsim::run(source, None).unwrap_err();
+    assert_eq!(
+        err,
+        QasmSimError::UnexpectedToken {
+            source: "if (c==1) { U(pi, 0, pi) q[0]; }\n",
+            lineno: 4,
+            startpos: 10,
+            endpos: Some(11),
+            token: Some(Tok::LBrace),
+            expected: vec![
+                "\"CX\"".into(),
+                "\"U\"".into(),
+                "\"measure\"".into(),
+                "\"reset\"".into(),
+                "id".into()
+            ]
+        }
+    );
+}
+
+#[test]
+fn test_conditional_block_rejects_nested_conditionals() {
+    let source = indoc!(
+        "
+    OPENQASM 2.0;
+    qreg q[1];
+    creg c[1];
+    if (c==1) { if (c==1) U(pi, 0, pi) q[0]; }
+  "
+    );
+    let options = qasmsim::options::SimulationOptions {
+        extensions: true,
+        ..Default::default()
+    };
+    let err = qasmsim::run_with_options(source, None, &options).unwrap_err();
+    assert_eq!(
+        err,
+        QasmSimError::NestedConditional {
+            source: "if (c==1) { if (c==1) U(pi, 0, pi) q[0]; }\n",
+            lineno: 4,
+            startpos: 12,
+            endpos: Some(14),
+        }
+    );
+    assert!(err
+        .to_string()
+        .contains("conditionals cannot be nested inside a conditional block"));
+}
+
+#[test]
+fn test_openqasm3_unsupported_feature() {
+    let source = indoc!(
+        "
+    OPENQASM 3;
+    qubit[2] q;
+    for i in [0:1] { U(pi, 0, pi) q[i]; }
+  "
+    );
+    let err = qasmsim::run(source, None).unwrap_err();
+    assert_eq!(
+        err,
+        QasmSimError::UnsupportedFeature {
+            source: "for i in [0:1] { U(pi, 0, pi) q[i]; }\n",
+            lineno: 3,
+            startpos: 0,
+            endpos: Some(3),
+            feature: "for".into(),
+        }
+    );
+    assert_eq!(
+        format!("{}", err),
+        "error: `for` is not yet supported\n  |\n3 | for i in [0:1] { U(pi, 0, pi) q[i]; }\n  | ^^^ help: only OPENQASM 3 declarations and measurements are supported\n"
+    );
+}
+
+#[test]
+fn test_openqasm3_syntax_is_rejected_in_openqasm2() {
+    let source = indoc!(
+        "
+    OPENQASM 2.0;
+    qubit[2] q;
+  "
+    );
+    let err = qasmsim::run(source, None).unwrap_err();
+    assert!(matches!(
+        err,
+        QasmSimError::UnexpectedToken { lineno: 2, .. }
+    ));
+}
+
+#[test]
+fn test_unterminated_block_comment() {
+    let source = indoc!(
+        "
+    OPENQASM 2.0;
+    qreg q[1]; /* start
+    of an unterminated comment
+  "
+    );
+    let err = qasmsim::run(source, None).unwrap_err();
+    assert_eq!(
+        err,
+        QasmSimError::UnterminatedComment {
+            source: "qreg q[1]; /* start\n",
+            lineno: 2,
+            startpos: 11,
+        }
+    );
+    assert_eq!(
+        format!("{}", err),
+        "error: unterminated block comment\n  |\n2 | qreg q[1]; /* start\n  |            ^^ help: close the comment with `*/`\n"
+    );
+}
+
+#[test]
+fn test_diagnostic_points_to_the_unexpected_token() {
+    use qasmsim::error::{Diagnostic, Severity};
+
+    let source = indoc!(
+        "
+    OPENQASM 2.0;
+    qreg q[10];
+    qreg r[10] creg c[1];
+  "
+    );
+    let err = qasmsim::run(source, None).unwrap_err();
+    assert_eq!(
+        err.to_diagnostic(),
+        Diagnostic {
+            line: 3,
+            column: 12,
+            length: 4,
+            message: "expected \";\", found \"keyword `creg`\"".into(),
+            help: Some("consider adding \";\" before this".into()),
+            severity: Severity::Error,
+        }
+    );
+}
+
+#[test]
+fn test_integer_literals_out_of_range() {
+    let source = indoc!(
+        "
+    OPENQASM 2.0;
+    qreg q[1];
+    creg c[4];
+    if (c==0x100000000000000000000000000000000) U(0, 0, 0) q[0];
+    "
+    );
+    let err = qasmsim::run(source, None).unwrap_err();
+    assert_eq!(
+        err,
+        QasmSimError::IntegerOutOfRange {
+            source: "if (c==0x100000000000000000000000000000000) U(0, 0, 0) q[0];\n",
+            lineno: 4,
+            startpos: 7,
+            endpos: Some(42),
+            literal: "0x100000000000000000000000000000000".into()
+        }
+    );
+
+    let sources = [
+        format!("OPENQASM 2.0;\nqreg q[0b1{}];\n", "0".repeat(200)),
+        String::from("OPENQASM 2.0;\nqreg q[99999999999999999999999];\n"),
+        String::from(
+            "OPENQASM 2.0;\nqreg q[1];\nU(0x100000000000000000000000000000000, 0, 0) q[0];\n",
+        ),
+    ];
+    for source in &sources {
+        assert!(matches!(
+            qasmsim::run(source, None).unwrap_err(),
+            QasmSimError::IntegerOutOfRange { .. }
+        ));
+    }
+}
+
+#[test]
+fn test_empty_source() {
+    let options = qasmsim::options::SimulationOptions::default();
+    let err = qasmsim::run_with_options("", None, &options).unwrap_err();
+    assert_eq!(
+        err,
+        QasmSimError::UnexpectedEOF {
+            source: "",
+            lineno: 1,
+            startpos: 0,
+            endpos: None,
+            token: None,
+            expected: vec![
+                "\"CX\"".into(),
+                "\"U\"".into(),
+                "\"barrier\"".into(),
+                "\"bit\"".into(),
+                "\"creg\"".into(),
+                "\"gate\"".into(),
+                "\"if\"".into(),
+                "\"include\"".into(),
+                "\"measure\"".into(),
+                "\"opaque\"".into(),
+                "\"qreg\"".into(),
+                "\"qubit\"".into(),
+                "\"reset\"".into(),
+                "docstring".into(),
+                "id".into()
+            ]
+        }
+    );
+}
+
+#[test]
+fn test_syntax_error_after_non_ascii_comment() {
+    let source = "OPENQASM 2.0;\n// ñañaña\nfoo q[0]\n";
+    let err = qasmsim::run(source, None).unwrap_err();
+    assert_eq!(
+        err,
+        QasmSimError::UnexpectedEOF {
+            source: "foo q[0]\n",
+            lineno: 3,
+            startpos: 8,
+            endpos: None,
+            token: None,
+            expected: vec!["\",\"".into(), "\";\"".into()]
+        }
+    );
+    assert!(format!("{}", err).contains("foo q[0]"));
+}